@@ -0,0 +1,37 @@
+use std::{path::Path, process::Command};
+
+/// Capture the git commit and build date for `!version`. Both can be supplied through the environment
+/// instead, for builds without a git checkout such as in Docker.
+fn main() {
+    let commit = std::env::var("GIT_COMMIT")
+        .ok()
+        .or_else(|| output("git", &["rev-parse", "--short", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+    let date = std::env::var("BUILD_DATE")
+        .ok()
+        .or_else(|| output("date", &["-u", "+%Y-%m-%d"]))
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=GIT_COMMIT={commit}");
+    println!("cargo:rustc-env=BUILD_DATE={date}");
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=BUILD_DATE");
+    // HEAD itself only changes when switching branches, commits move the branch it points to instead.
+    let branch = output("git", &["symbolic-ref", "-q", "HEAD"]);
+    for file in ["HEAD", "logs/HEAD", "packed-refs"]
+        .into_iter()
+        .chain(branch.as_deref())
+    {
+        if let Some(path) = output("git", &["rev-parse", "--git-path", file]).filter(|path| Path::new(path).exists()) {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+}
+
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
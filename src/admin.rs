@@ -0,0 +1,108 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use axum::{
+    Json, Router,
+    extract::{Path, Request, State as AxumState},
+    http::{StatusCode, header::AUTHORIZATION},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use matrix_appservice::exports::matrix_sdk::ruma::OwnedRoomId;
+use serde_json::{Map, Value};
+use subtle::ConstantTimeEq;
+
+use crate::{server::AppState, settings::RoomSettings, usage::RoomStats};
+
+/// Operator API under `/admin`, authenticated with a bearer token.
+pub fn router(token: &str) -> Router<AppState> {
+    Router::new()
+        .route("/admin/rooms", get(list_rooms))
+        .route(
+            "/admin/rooms/{room_id}/settings",
+            get(get_settings).patch(update_settings),
+        )
+        .route("/admin/rooms/{room_id}/stats", get(get_stats))
+        .route_layer(middleware::from_fn_with_state(Arc::<str>::from(token), require_token))
+}
+
+async fn require_token(AxumState(token): AxumState<Arc<str>>, request: Request, next: Next) -> Response {
+    let authorized = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| bool::from(given.as_bytes().ct_eq(token.as_bytes())));
+
+    match authorized {
+        true => next.run(request).await,
+        false => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Internal failures are logged and reported as a bare 500.
+struct AdminError(anyhow::Error);
+
+impl<E: Into<anyhow::Error>> From<E> for AdminError {
+    fn from(error: E) -> Self {
+        Self(error.into())
+    }
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        tracing::warn!("Admin API request failed: {}", self.0);
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    }
+}
+
+/// Rooms the bot keeps any state for.
+async fn list_rooms(AxumState(appservice): AxumState<AppState>) -> Result<Json<Vec<String>>, AdminError> {
+    let rooms = appservice
+        .state()
+        .store()
+        .keys("room/")
+        .await?
+        .iter()
+        .filter_map(|key| key.strip_prefix("room/")?.split('/').next().map(str::to_string))
+        .collect::<BTreeSet<_>>();
+
+    Ok(Json(rooms.into_iter().collect()))
+}
+
+async fn get_settings(
+    AxumState(appservice): AxumState<AppState>,
+    Path(room_id): Path<OwnedRoomId>,
+) -> Result<Json<RoomSettings>, AdminError> {
+    Ok(Json(RoomSettings::load(appservice.state().store(), &room_id).await?))
+}
+
+/// Update the settings given in a JSON object, `null` restores a setting's default.
+async fn update_settings(
+    AxumState(appservice): AxumState<AppState>,
+    Path(room_id): Path<OwnedRoomId>,
+    Json(patch): Json<Map<String, Value>>,
+) -> Result<Response, AdminError> {
+    let store = appservice.state().store();
+    let _lock = RoomSettings::lock(store, &room_id).await;
+    let mut settings = RoomSettings::load(store, &room_id).await?;
+    let kept_content = settings.keeps_content();
+    for (key, value) in patch {
+        if let Err(error) = settings.apply(&key, value) {
+            return Ok((StatusCode::BAD_REQUEST, error.to_string()).into_response());
+        }
+    }
+
+    settings.save(store, &room_id).await?;
+    if kept_content && !settings.keeps_content() {
+        appservice.state().forget_content(&room_id).await?;
+    }
+    Ok(Json(settings).into_response())
+}
+
+async fn get_stats(
+    AxumState(appservice): AxumState<AppState>,
+    Path(room_id): Path<OwnedRoomId>,
+) -> Result<Json<RoomStats>, AdminError> {
+    Ok(Json(RoomStats::load(appservice.state().store(), &room_id).await?))
+}
@@ -0,0 +1,67 @@
+use base64::{Engine, prelude::BASE64_STANDARD};
+use matrix_appservice::{
+    Device,
+    exports::matrix_sdk::ruma::{
+        RoomId,
+        events::{
+            relation::Thread,
+            room::message::{ImageMessageEventContent, OriginalSyncRoomMessageEvent, Relation},
+        },
+    },
+};
+
+use crate::{
+    media,
+    openai::{ChatRequest, ContentPart, ConversationStore, MessageContent, OpenAIChoice, OpenAIMessage, Role},
+};
+
+/// Describe a posted image for people using a screen reader, and reply with the description in a thread on the
+/// image. Enabled per room with `!set alt_text true`.
+pub async fn describe(
+    state: &ConversationStore,
+    device: &Device,
+    room_id: &RoomId,
+    event: &OriginalSyncRoomMessageEvent,
+    image: &ImageMessageEventContent,
+    locale: &str,
+) -> anyhow::Result<()> {
+    let config = state.config();
+    let data = media::download(state.homeserver(), &image.source, config.media.max_size).await?;
+    let mimetype = image
+        .info
+        .as_ref()
+        .and_then(|info| info.mimetype.as_deref())
+        .unwrap_or_else(|| media::image_type(&data).0);
+
+    let prompt = format!(
+        "Write alt text for this image, for someone using a screen reader. Describe what matters in one or two \
+        sentences, including any text shown in it. Reply with only the alt text, in the language of locale {locale}."
+    );
+    let content = MessageContent::Parts(vec![
+        ContentPart::text(prompt),
+        ContentPart::image_url(format!("data:{mimetype};base64,{}", BASE64_STANDARD.encode(data))),
+    ]);
+    let model = config.openai.vision_model.as_ref().unwrap_or(&config.openai.model);
+    let request = ChatRequest::new(model.clone(), vec![OpenAIMessage::new(Role::User, content)]);
+    let response = state.post_completion(&request).await?;
+    let Some(description) = response
+        .choices
+        .first()
+        .map(OpenAIChoice::text)
+        .filter(|text| !text.is_empty())
+    else {
+        return Ok(());
+    };
+
+    let description = state
+        .locales()
+        .text(locale, "alt_text.reply", &[("description", &description)]);
+    let root = match &event.content.relates_to {
+        Some(Relation::Thread(thread)) => thread.event_id.clone(),
+        _ => event.event_id.clone(),
+    };
+    let mut content = config.behavior.notice(description);
+    content.relates_to = Some(Relation::Thread(Thread::plain(root, event.event_id.clone())));
+    device.send_message(room_id, content).await?;
+    Ok(())
+}
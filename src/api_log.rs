@@ -0,0 +1,206 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use anyhow::Context;
+use chrono::Utc;
+use matrix_appservice::{
+    ApplicationService, State,
+    exports::matrix_sdk::ruma::{OwnedRoomId, OwnedUserId, UserId, events::room::message::RoomMessageEventContent},
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::{
+    fs,
+    io::AsyncWriteExt,
+    sync::{Mutex, mpsc},
+};
+use url::Url;
+
+use crate::{moderation::escape_html, openai::ConversationStore};
+
+/// Largest message content posted to the debug room, in bytes of JSON. Events are limited to 64 KiB, and encryption
+/// adds a third. Longer exchanges are cut off there, the log files keep all of it.
+const MAX_ROOM_CONTENT: usize = 40_000;
+/// Exchanges waiting to be posted to the debug room. More are dropped rather than held up.
+const ROOM_QUEUE: usize = 100;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ApiLogConfig {
+    /// Log API requests and responses from startup. Can be switched at runtime with `!debug api on|off`.
+    pub enabled: bool,
+    /// Directory to write `api.log` to, as one JSON document per line.
+    pub directory: Option<PathBuf>,
+    /// Room to post each exchange to.
+    pub room: Option<OwnedRoomId>,
+    /// Size in bytes after which the log file is rotated.
+    pub max_size: u64,
+    /// Number of rotated files kept next to the current one.
+    pub max_files: usize,
+    /// Users allowed to switch logging on and off.
+    pub admins: Vec<OwnedUserId>,
+}
+
+impl Default for ApiLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: None,
+            room: None,
+            max_size: 10 * 1024 * 1024,
+            max_files: 5,
+            admins: Vec::new(),
+        }
+    }
+}
+
+/// Full request and response bodies of model API calls, for diagnosing provider incompatibilities.
+pub struct ApiLog {
+    config: ApiLogConfig,
+    api_key: String,
+    enabled: AtomicBool,
+    /// Held while writing, so concurrent exchanges don't interleave or rotate underneath each other.
+    file: Mutex<()>,
+    /// Exchanges to post to the debug room. They're posted by [`post`] through the bot's device, so they're
+    /// encrypted in encrypted rooms.
+    queue: mpsc::Sender<String>,
+    queued: Mutex<Option<mpsc::Receiver<String>>>,
+}
+
+impl ApiLog {
+    pub fn new(config: &ApiLogConfig, api_key: &str) -> Self {
+        let (queue, queued) = mpsc::channel(ROOM_QUEUE);
+        Self {
+            config: config.clone(),
+            api_key: api_key.to_string(),
+            enabled: AtomicBool::new(config.enabled),
+            file: Mutex::new(()),
+            queue,
+            queued: Mutex::new(Some(queued)),
+        }
+    }
+
+    pub fn is_admin(&self, user_id: &UserId) -> bool {
+        self.config.admins.iter().any(|admin| admin == user_id)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Record an exchange, if logging is on. Failures are only logged, they shouldn't break the conversation.
+    pub async fn record(&self, endpoint: &Url, request: &Value, response: &Value) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let entry = json!({
+            "time": Utc::now().to_rfc3339(),
+            "endpoint": endpoint,
+            "request": request,
+            "response": response,
+        });
+        // The key is sent in a header rather than the body, but providers sometimes echo it back in errors.
+        let mut entry = entry.to_string();
+        if !self.api_key.is_empty() {
+            entry = entry.replace(&self.api_key, "[redacted]");
+        }
+
+        if let Some(directory) = &self.config.directory
+            && let Err(error) = self.write(directory, &entry).await
+        {
+            tracing::warn!("Writing API log failed: {error}");
+        }
+        if self.config.room.is_some() && self.queue.try_send(entry).is_err() {
+            tracing::warn!("Dropping an API log entry, the debug room is falling behind");
+        }
+    }
+
+    async fn write(&self, directory: &Path, entry: &str) -> anyhow::Result<()> {
+        let _lock = self.file.lock().await;
+        let path = directory.join("api.log");
+        if fs::metadata(&path)
+            .await
+            .is_ok_and(|metadata| metadata.len() >= self.config.max_size)
+        {
+            self.rotate(directory).await?;
+        }
+
+        fs::create_dir_all(directory).await?;
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(&path).await?;
+        file.write_all(format!("{entry}\n").as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Shift `api.log.1` to `api.log.2` and so on, dropping the oldest, and move `api.log` to `api.log.1`.
+    async fn rotate(&self, directory: &Path) -> anyhow::Result<()> {
+        let name = |index: usize| match index {
+            0 => directory.join("api.log"),
+            index => directory.join(format!("api.log.{index}")),
+        };
+        let _ = fs::remove_file(name(self.config.max_files)).await;
+        for index in (0..self.config.max_files).rev() {
+            if fs::try_exists(name(index)).await? {
+                fs::rename(name(index), name(index + 1)).await?;
+            }
+        }
+        // Without rotated files to keep, the full log is simply started over.
+        let _ = fs::remove_file(name(0)).await;
+        Ok(())
+    }
+}
+
+/// Post logged exchanges to the debug room as they come in. Runs until the process stops.
+pub async fn post(appservice: ApplicationService<State<Arc<ConversationStore>>>) -> anyhow::Result<()> {
+    let api_log = appservice.state().api_log();
+    let Some(room_id) = &api_log.config.room else {
+        return Ok(());
+    };
+    let mut queued = api_log
+        .queued
+        .lock()
+        .await
+        .take()
+        .context("API log is already being posted")?;
+    let device = appservice
+        .get_bot()
+        .await?
+        .get_device()
+        .await
+        .context("Device not found")?;
+
+    while let Some(entry) = queued.recv().await {
+        if let Err(error) = device.send_message(room_id, room_content(&entry)).await {
+            tracing::warn!("Posting API log to {room_id} failed: {error}");
+        }
+    }
+    Ok(())
+}
+
+/// A notice with the entry as a code block, cut off so the event stays within the size limit.
+fn room_content(entry: &str) -> RoomMessageEventContent {
+    let mut end = entry.len();
+    loop {
+        while !entry.is_char_boundary(end) {
+            end -= 1;
+        }
+        let body = &entry[..end];
+        let html = format!("<pre><code class=\"language-json\">{}</code></pre>", escape_html(body));
+        let content = RoomMessageEventContent::notice_html(body, html);
+        // Escaping grows text by a varying amount, so shrink in proportion until it fits.
+        let size = serde_json::to_vec(&content).map_or(usize::MAX, |json| json.len());
+        if size <= MAX_ROOM_CONTENT {
+            return content;
+        }
+        end = end * MAX_ROOM_CONTENT / size;
+    }
+}
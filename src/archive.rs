@@ -0,0 +1,38 @@
+use matrix_appservice::{
+    Device,
+    exports::matrix_sdk::ruma::{EventId, OwnedRoomId},
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::webhooks::ExchangeRecord;
+
+/// Event type of the exchanges mirrored into the archive room.
+pub const EXCHANGE_EVENT_TYPE: &str = "nl.spacebased.openai.exchange";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    /// Room every completed exchange is mirrored into, as an audit trail. Invite the bot to it first.
+    pub room: Option<OwnedRoomId>,
+}
+
+/// Post an exchange to the archive room, if one is configured. The record has already been stripped according to
+/// the retention policy of the room it came from.
+pub async fn mirror(
+    device: &Device,
+    config: &ArchiveConfig,
+    record: &ExchangeRecord,
+    prompt_id: &EventId,
+    response_id: &EventId,
+) -> anyhow::Result<()> {
+    let Some(room_id) = &config.room else {
+        return Ok(());
+    };
+
+    let mut content = serde_json::to_value(record)?;
+    content["prompt_event_id"] = json!(prompt_id);
+    content["response_event_id"] = json!(response_id);
+    device.send_raw(room_id, EXCHANGE_EVENT_TYPE, content).await?;
+    Ok(())
+}
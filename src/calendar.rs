@@ -0,0 +1,308 @@
+use std::sync::LazyLock;
+
+use anyhow::{Context, anyhow, ensure};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use chacha20poly1305::{
+    KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, Payload},
+};
+use chrono::{DateTime, Days, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use matrix_appservice::exports::matrix_sdk::ruma::UserId;
+use regex::Regex;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::{
+    client::{self, Identified},
+    store::Store,
+};
+
+/// Longest range `get_agenda` reads at once.
+const MAX_AGENDA_DAYS: u64 = 31;
+
+static CALENDAR_DATA: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?s)<(?:[\w-]+:)?calendar-data[^>]*>(.*?)</(?:[\w-]+:)?calendar-data>").expect("valid regex")
+});
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CalendarConfig {
+    /// Secret the stored CalDAV passwords are encrypted with. Calendars can't be connected without it.
+    pub key: Option<String>,
+}
+
+/// CalDAV calendar a user connected in a DM with `!calendar connect`.
+#[derive(Debug, Clone)]
+pub struct CalendarAccount {
+    pub url: Url,
+    pub username: String,
+    pub password: String,
+}
+
+/// A calendar account as stored, with the password encrypted.
+#[derive(Serialize, Deserialize)]
+struct StoredAccount {
+    url: Url,
+    username: String,
+    /// Nonce followed by the ciphertext, base64-encoded.
+    password: String,
+}
+
+/// Cipher for stored passwords. The user ID is authenticated along with each password, so a record can't be copied
+/// to another user.
+fn cipher(key: &str) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new(&Sha256::digest(key.as_bytes()))
+}
+
+fn seal(key: &str, user_id: &UserId, password: &str) -> anyhow::Result<String> {
+    let nonce: [u8; 24] = rand::random();
+    let payload = Payload {
+        msg: password.as_bytes(),
+        aad: user_id.as_bytes(),
+    };
+    let ciphertext = cipher(key)
+        .encrypt(XNonce::from_slice(&nonce), payload)
+        .map_err(|_| anyhow!("Encrypting the password failed"))?;
+    Ok(BASE64_STANDARD.encode([&nonce[..], &ciphertext].concat()))
+}
+
+fn open(key: &str, user_id: &UserId, sealed: &str) -> anyhow::Result<String> {
+    let sealed = BASE64_STANDARD.decode(sealed)?;
+    ensure!(sealed.len() > 24, "Stored password is too short");
+    let (nonce, ciphertext) = sealed.split_at(24);
+    let payload = Payload {
+        msg: ciphertext,
+        aad: user_id.as_bytes(),
+    };
+    let password = cipher(key)
+        .decrypt(XNonce::from_slice(nonce), payload)
+        .map_err(|_| anyhow!("Decrypting the password failed"))?;
+    Ok(String::from_utf8(password)?)
+}
+
+fn account_key(user_id: &UserId) -> String {
+    format!("user/{user_id}/calendar")
+}
+
+impl CalendarAccount {
+    /// The user's account, or none if there is none or its password can't be decrypted, e.g. because the key
+    /// changed.
+    pub async fn load(store: &dyn Store, key: &str, user_id: &UserId) -> anyhow::Result<Option<Self>> {
+        let Some(stored) = store.load::<StoredAccount>(&account_key(user_id)).await? else {
+            return Ok(None);
+        };
+        match open(key, user_id, &stored.password) {
+            Ok(password) => Ok(Some(Self {
+                url: stored.url,
+                username: stored.username,
+                password,
+            })),
+            Err(error) => {
+                tracing::warn!("Reading the calendar account of {user_id} failed: {error}");
+                Ok(None)
+            }
+        }
+    }
+
+    pub async fn save(&self, store: &dyn Store, key: &str, user_id: &UserId) -> anyhow::Result<()> {
+        let stored = StoredAccount {
+            url: self.url.clone(),
+            username: self.username.clone(),
+            password: seal(key, user_id, &self.password)?,
+        };
+        store.save(&account_key(user_id), &stored).await
+    }
+
+    pub async fn forget(store: &dyn Store, user_id: &UserId) -> anyhow::Result<()> {
+        store.delete(&account_key(user_id)).await
+    }
+
+    /// Events between `start` and `end`, with recurring events expanded by the server. The URL comes from the
+    /// user, so `web` should be the client that refuses hosts that aren't public.
+    pub async fn events(
+        &self,
+        web: &reqwest::Client,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<Event>> {
+        let range = format!(
+            r#"start="{}" end="{}""#,
+            start.format("%Y%m%dT%H%M%SZ"),
+            end.format("%Y%m%dT%H%M%SZ")
+        );
+        let query = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<C:calendar-query xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <D:prop><C:calendar-data><C:expand {range}/></C:calendar-data></D:prop>
+  <C:filter>
+    <C:comp-filter name="VCALENDAR">
+      <C:comp-filter name="VEVENT"><C:time-range {range}/></C:comp-filter>
+    </C:comp-filter>
+  </C:filter>
+</C:calendar-query>"#
+        );
+
+        client::check_public(&self.url)?;
+        let response = web
+            .request(Method::from_bytes(b"REPORT")?, self.url.clone())
+            .basic_auth(&self.username, Some(&self.password))
+            .header("Depth", "1")
+            .header("Content-Type", "application/xml; charset=utf-8")
+            .body(query)
+            .send_identified()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        let mut events = CALENDAR_DATA
+            .captures_iter(&response)
+            .flat_map(|captures| parse_events(&unescape(&captures[1])))
+            .collect::<Vec<_>>();
+        events.sort_by_key(|event| event.start);
+        Ok(events)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub summary: String,
+    pub location: Option<String>,
+    pub start: DateTime<Utc>,
+    pub end: Option<DateTime<Utc>>,
+    pub all_day: bool,
+}
+
+impl Event {
+    pub fn describe(&self, timezone: Tz) -> String {
+        let start = self.start.with_timezone(&timezone);
+        let when = match (self.all_day, self.end.map(|end| end.with_timezone(&timezone))) {
+            // All-day events are dates, not times, so they aren't shifted to the timezone.
+            (true, _) => format!("{} (all day)", self.start.format("%a %Y-%m-%d")),
+            (false, Some(end)) if end.date_naive() == start.date_naive() => {
+                format!("{}–{}", start.format("%a %Y-%m-%d %H:%M"), end.format("%H:%M"))
+            }
+            (false, Some(end)) => format!(
+                "{} – {}",
+                start.format("%a %Y-%m-%d %H:%M"),
+                end.format("%a %Y-%m-%d %H:%M")
+            ),
+            (false, None) => start.format("%a %Y-%m-%d %H:%M").to_string(),
+        };
+        match &self.location {
+            Some(location) => format!("{when}: {} ({location})", self.summary),
+            None => format!("{when}: {}", self.summary),
+        }
+    }
+}
+
+/// Parse a range such as `2025-03-01` or `2025-03-01/2025-03-07` into its start and end in the given timezone.
+/// Both days are included.
+pub fn parse_range(range: &str, timezone: Tz) -> anyhow::Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let (first, last) = range.split_once('/').unwrap_or((range, range));
+    let parse = |date: &str| {
+        NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").with_context(|| format!("Invalid date '{date}'"))
+    };
+    let (first, last) = (parse(first)?, parse(last)?);
+    ensure!(first <= last, "The range ends before it starts");
+    ensure!(
+        (last - first).num_days() < MAX_AGENDA_DAYS as i64,
+        "Ranges are limited to {MAX_AGENDA_DAYS} days"
+    );
+
+    let midnight = |date: NaiveDate| {
+        timezone
+            .from_local_datetime(&date.and_time(NaiveTime::MIN))
+            .earliest()
+            .map(|start| start.with_timezone(&Utc))
+            .context("Invalid local date")
+    };
+    Ok((midnight(first)?, midnight(last + Days::new(1))?))
+}
+
+fn unescape(xml: &str) -> String {
+    let xml = xml.trim();
+    let xml = xml
+        .strip_prefix("<![CDATA[")
+        .and_then(|xml| xml.strip_suffix("]]>"))
+        .unwrap_or(xml);
+    xml.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#13;", "\r")
+        .replace("&amp;", "&")
+}
+
+/// Events in an iCalendar document. Only the properties shown in the agenda are read.
+fn parse_events(ics: &str) -> Vec<Event> {
+    // Long lines are folded by continuing them on a line starting with whitespace.
+    let unfolded = ics.replace("\r\n", "\n").replace("\n ", "").replace("\n\t", "");
+
+    let mut events = Vec::new();
+    let mut current: Option<Vec<(&str, &str, &str)>> = None;
+    for line in unfolded.lines() {
+        match line {
+            "BEGIN:VEVENT" => current = Some(Vec::new()),
+            "END:VEVENT" => {
+                if let Some(event) = current.take().and_then(|properties| build_event(&properties)) {
+                    events.push(event);
+                }
+            }
+            _ => {
+                if let Some(properties) = &mut current
+                    && let Some((key, value)) = line.split_once(':')
+                {
+                    let (name, parameters) = key.split_once(';').unwrap_or((key, ""));
+                    properties.push((name, parameters, value));
+                }
+            }
+        }
+    }
+
+    events
+}
+
+fn build_event(properties: &[(&str, &str, &str)]) -> Option<Event> {
+    let property = |name: &str| properties.iter().find(|(key, _, _)| *key == name);
+    let text = |name: &str| {
+        property(name).map(|(_, _, value)| value.replace("\\,", ",").replace("\\;", ";").replace("\\n", " "))
+    };
+
+    let (_, parameters, value) = property("DTSTART")?;
+    let (start, all_day) = parse_time(parameters, value)?;
+    let end = property("DTEND").and_then(|(_, parameters, value)| parse_time(parameters, value).map(|(end, _)| end));
+
+    Some(Event {
+        summary: text("SUMMARY").unwrap_or_else(|| "(no title)".to_string()),
+        location: text("LOCATION").filter(|location| !location.is_empty()),
+        start,
+        end,
+        all_day,
+    })
+}
+
+/// Parse a DTSTART or DTEND value. Returns the time and whether it is a date without a time.
+fn parse_time(parameters: &str, value: &str) -> Option<(DateTime<Utc>, bool)> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y%m%d") {
+        return Some((date.and_time(NaiveTime::MIN).and_utc(), true));
+    }
+    if let Some(utc) = value.strip_suffix('Z') {
+        return Some((
+            NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?.and_utc(),
+            false,
+        ));
+    }
+
+    let local = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+    let timezone = parameters
+        .split(';')
+        .find_map(|parameter| parameter.strip_prefix("TZID="))
+        .and_then(|tzid| tzid.trim_matches('"').parse::<Tz>().ok())
+        .unwrap_or(Tz::UTC);
+    let start = timezone.from_local_datetime(&local).earliest()?.with_timezone(&Utc);
+    Some((start, false))
+}
@@ -0,0 +1,91 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use matrix_appservice::exports::matrix_sdk::ruma::{
+    MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, RoomId, events::room::message::OriginalSyncRoomMessageEvent,
+};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+/// How long to wait for newer missed messages in a room before answering the latest one.
+const LATEST_DEBOUNCE: Duration = Duration::from_secs(3);
+
+/// What to do with messages sent while the bot was offline, delivered in bulk after a restart.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CatchUpPolicy {
+    /// Answer every missed message.
+    Process,
+    /// Don't answer missed messages at all.
+    Ignore,
+    /// Only answer the most recent missed message in each room.
+    #[default]
+    Latest,
+    /// Post a single notice per room that the bot was away, without answering.
+    Notice,
+}
+
+pub enum CatchUpDecision {
+    Process,
+    Skip,
+    Notice,
+}
+
+pub struct CatchUp {
+    policy: CatchUpPolicy,
+    started: MilliSecondsSinceUnixEpoch,
+    latest: Mutex<HashMap<OwnedRoomId, (MilliSecondsSinceUnixEpoch, OwnedEventId)>>,
+    noticed: Mutex<HashSet<OwnedRoomId>>,
+}
+
+impl CatchUp {
+    pub fn new(policy: CatchUpPolicy) -> Self {
+        Self {
+            policy,
+            started: MilliSecondsSinceUnixEpoch::now(),
+            latest: Mutex::new(HashMap::new()),
+            noticed: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Whether the event was sent before the bot started, as part of the backlog it is catching up on.
+    pub fn is_backlog(&self, event: &OriginalSyncRoomMessageEvent) -> bool {
+        event.origin_server_ts < self.started
+    }
+
+    pub async fn decide(&self, room_id: &RoomId, event: &OriginalSyncRoomMessageEvent) -> CatchUpDecision {
+        if !self.is_backlog(event) {
+            return CatchUpDecision::Process;
+        }
+
+        match self.policy {
+            CatchUpPolicy::Process => CatchUpDecision::Process,
+            CatchUpPolicy::Ignore => CatchUpDecision::Skip,
+            CatchUpPolicy::Notice => match self.noticed.lock().await.insert(room_id.to_owned()) {
+                true => CatchUpDecision::Notice,
+                false => CatchUpDecision::Skip,
+            },
+            CatchUpPolicy::Latest => {
+                {
+                    let mut latest = self.latest.lock().await;
+                    let entry = latest
+                        .entry(room_id.to_owned())
+                        .or_insert_with(|| (event.origin_server_ts, event.event_id.clone()));
+                    if event.origin_server_ts >= entry.0 {
+                        *entry = (event.origin_server_ts, event.event_id.clone());
+                    }
+                }
+
+                // Give the rest of the backlog a chance to arrive, then only the newest message wins.
+                tokio::time::sleep(LATEST_DEBOUNCE).await;
+                let latest = self.latest.lock().await;
+                match latest.get(room_id) {
+                    Some((_, event_id)) if *event_id == event.event_id => CatchUpDecision::Process,
+                    _ => CatchUpDecision::Skip,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::Mutex;
+
+use matrix_appservice::exports::matrix_sdk::ruma::{EventId, RoomId};
+use url::Url;
+
+/// A numbered source the model was given, referenced as `[n]` in its reply.
+#[derive(Debug, Clone)]
+pub struct Citation {
+    pub number: usize,
+    pub url: String,
+}
+
+/// Sources handed to the model while answering a prompt, numbered in the order they were first seen.
+#[derive(Default)]
+pub struct Citations {
+    urls: Mutex<Vec<String>>,
+    /// What tools read without citing it by number, such as fetched pages and Home Assistant entities.
+    consulted: Mutex<Vec<String>>,
+}
+
+impl Citations {
+    /// Number to cite the URL by, reusing the number if it was cited before.
+    pub fn cite(&self, url: String) -> usize {
+        let mut urls = self.urls.lock().expect("citations lock poisoned");
+        match urls.iter().position(|cited| *cited == url) {
+            Some(index) => index + 1,
+            None => {
+                urls.push(url);
+                urls.len()
+            }
+        }
+    }
+
+    /// Citations the reply actually refers to.
+    pub fn referenced(&self, reply: &str) -> Vec<Citation> {
+        self.urls
+            .lock()
+            .expect("citations lock poisoned")
+            .iter()
+            .enumerate()
+            .map(|(index, url)| Citation {
+                number: index + 1,
+                url: url.clone(),
+            })
+            .filter(|citation| reply.contains(&format!("[{}]", citation.number)))
+            .collect()
+    }
+
+    /// Record what a tool read, e.g. a URL or an identifier, to list among the sources of the reply.
+    pub fn consult(&self, source: String) {
+        let mut consulted = self.consulted.lock().expect("citations lock poisoned");
+        if !consulted.contains(&source) {
+            consulted.push(source);
+        }
+    }
+
+    /// What tools read that isn't already among the cited sources.
+    pub fn consulted(&self, cited: &[Citation]) -> Vec<String> {
+        self.consulted
+            .lock()
+            .expect("citations lock poisoned")
+            .iter()
+            .filter(|source| !cited.iter().any(|citation| citation.url == **source))
+            .cloned()
+            .collect()
+    }
+}
+
+pub fn permalink(room_id: &RoomId, event_id: &EventId) -> String {
+    format!("https://matrix.to/#/{room_id}/{event_id}")
+}
+
+/// Markdown footer linking the cited sources, followed by what else tools read: URLs linked by host, other
+/// identifiers as code.
+pub fn footer(citations: &[Citation], sources: &[String]) -> String {
+    let links = citations
+        .iter()
+        .map(|citation| format!("[[{}]]({})", citation.number, citation.url))
+        .chain(sources.iter().map(|source| match Url::parse(source) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {
+                format!("[{}]({source})", url.host_str().unwrap_or(source))
+            }
+            _ => format!("`{source}`"),
+        }))
+        .collect::<Vec<_>>();
+    format!("{FOOTER_START}{}</sub>", links.join(" · "))
+}
+
+/// Start of the footer listing the sources of a reply.
+pub const FOOTER_START: &str = "\n\n<sub>Sources: ";
+
+/// A reply without the footer listing its sources.
+pub fn strip_footer(body: &str) -> &str {
+    body.rsplit_once(FOOTER_START).map_or(body, |(reply, _)| reply)
+}
@@ -0,0 +1,184 @@
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use rand::Rng;
+use reqwest::{
+    ClientBuilder, RequestBuilder, Response,
+    dns::{Addrs, Name, Resolve, Resolving},
+    header::{HeaderMap, HeaderName, HeaderValue},
+    redirect::Policy,
+};
+use serde::Deserialize;
+use tracing::Instrument;
+use url::{Host, Url};
+
+use crate::version::VERSION;
+
+/// Redirects followed for URLs from the model or users.
+const MAX_REDIRECTS: usize = 10;
+
+/// Header carrying a unique ID for each outgoing request, also in the tracing span and logs of the request.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ClientConfig {
+    /// `User-Agent` of requests to the model provider and tools, so gateways can attribute the traffic.
+    pub user_agent: String,
+    /// Extra headers sent with each request to the model provider, e.g. a deployment name or gateway credentials.
+    /// Requests to other hosts, such as URLs picked by the model, don't carry them.
+    pub headers: BTreeMap<String, String>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: format!("{}/{VERSION}", env!("CARGO_PKG_NAME")),
+            headers: BTreeMap::new(),
+        }
+    }
+}
+
+/// Builder for HTTP clients identifying the bot as configured, sending `headers` with each request.
+pub fn builder(config: &ClientConfig, headers: HeaderMap) -> ClientBuilder {
+    reqwest::Client::builder()
+        .use_rustls_tls()
+        .user_agent(&config.user_agent)
+        .default_headers(headers)
+}
+
+/// Like [`builder`], for clients of the model provider, which also send the configured headers.
+pub fn provider_builder(config: &ClientConfig, mut headers: HeaderMap) -> anyhow::Result<ClientBuilder> {
+    for (name, value) in &config.headers {
+        headers.insert(HeaderName::try_from(name)?, HeaderValue::from_str(value)?);
+    }
+    Ok(builder(config, headers))
+}
+
+/// Limit a client to publicly routable hosts, for URLs picked by the model or users, so they can't be used to
+/// reach services on the bot's own network. Hostnames are checked as they are resolved, redirects included.
+pub fn public_only(builder: ClientBuilder) -> ClientBuilder {
+    builder
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(Policy::custom(|attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error("too many redirects");
+            }
+            match check_public(attempt.url()) {
+                Ok(()) => attempt.follow(),
+                Err(error) => attempt.error(error.to_string()),
+            }
+        }))
+}
+
+/// Refuse URLs other than http(s) and IP addresses that aren't publicly routable. Hostnames are left to
+/// [`public_only`] clients, which check every address they resolve to.
+pub fn check_public(url: &Url) -> anyhow::Result<()> {
+    if !matches!(url.scheme(), "http" | "https") {
+        anyhow::bail!("Only http and https URLs can be fetched");
+    }
+    let ip = match url.host() {
+        Some(Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(Host::Ipv6(ip)) => IpAddr::V6(ip),
+        Some(Host::Domain(_)) => return Ok(()),
+        None => anyhow::bail!("URL has no host"),
+    };
+    match is_public(ip) {
+        true => Ok(()),
+        false => anyhow::bail!("{ip} is not a public address"),
+    }
+}
+
+/// Whether an address is reachable from the internet, rather than loopback, private, link-local (including
+/// cloud metadata at 169.254.169.254), shared, multicast or otherwise reserved.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || first == 0
+                || first >= 240
+                || (first == 100 && second & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80
+                    || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+            }
+        },
+    }
+}
+
+/// System resolver that drops addresses which aren't public, failing when none are left.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(resolve_public(name))
+    }
+}
+
+async fn resolve_public(name: Name) -> Result<Addrs, Box<dyn std::error::Error + Send + Sync>> {
+    let addresses = tokio::net::lookup_host((name.as_str(), 0))
+        .await?
+        .filter(|address| is_public(address.ip()))
+        .collect::<Vec<SocketAddr>>();
+    if addresses.is_empty() {
+        return Err(format!("{} doesn't resolve to a public address", name.as_str()).into());
+    }
+    Ok(Box::new(addresses.into_iter()))
+}
+
+/// Read a response body up to `limit` bytes, without downloading the rest. Also returns whether it was cut off.
+pub async fn read_limited(mut response: Response, limit: usize) -> anyhow::Result<(Vec<u8>, bool)> {
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > limit {
+            body.extend_from_slice(&chunk[..limit - body.len()]);
+            return Ok((body, true));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok((body, false))
+}
+
+/// A new random request ID.
+pub fn request_id() -> String {
+    format!("{:032x}", rand::rng().random::<u128>())
+}
+
+pub trait Identified {
+    /// Send the request tagged with a new [`REQUEST_ID_HEADER`]. The ID goes into the tracing span of the request and
+    /// into the log when it fails, so it can be matched with the upstream's logs.
+    fn send_identified(self) -> impl Future<Output = reqwest::Result<Response>> + Send;
+}
+
+impl Identified for RequestBuilder {
+    fn send_identified(self) -> impl Future<Output = reqwest::Result<Response>> + Send {
+        let request_id = request_id();
+        let span = tracing::info_span!("request", request_id);
+        async move {
+            tracing::debug!("Sending request");
+            self.header(REQUEST_ID_HEADER, &request_id)
+                .send()
+                .await
+                .inspect_err(|error| tracing::warn!(request_id, "Request failed: {error}"))
+        }
+        .instrument(span)
+    }
+}
@@ -0,0 +1,135 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use matrix_appservice::exports::matrix_sdk::ruma::RoomId;
+use serde::Deserialize;
+use tokio::sync::RwLock;
+
+use crate::store::Store;
+
+const INSTANCE_PREFIX: &str = "cluster/instances/";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ClusterConfig {
+    /// Split rooms between all instances sharing the store, which must be Redis. Every instance must receive all
+    /// appservice transactions, for example through a fan-out proxy, and ignores rooms it doesn't own.
+    pub enabled: bool,
+    /// Unique name of this instance, defaults to the hostname.
+    pub instance_id: Option<String>,
+    /// Seconds between heartbeats. Instances missing three heartbeats are dropped from the ring.
+    pub heartbeat_interval: u64,
+    /// Points per instance on the hash ring, more points spread rooms more evenly.
+    pub virtual_nodes: usize,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            instance_id: None,
+            heartbeat_interval: 10,
+            virtual_nodes: 64,
+        }
+    }
+}
+
+/// Membership of this instance in a group of instances sharing a store, assigning each room to
+/// exactly one live instance through consistent hashing.
+pub struct Cluster {
+    store: Arc<dyn Store>,
+    instance_id: String,
+    config: ClusterConfig,
+    ring: RwLock<Vec<(u64, String)>>,
+}
+
+impl Cluster {
+    pub async fn join(store: Arc<dyn Store>, config: &ClusterConfig) -> anyhow::Result<Arc<Self>> {
+        let instance_id = config
+            .instance_id
+            .clone()
+            .or_else(|| std::env::var("HOSTNAME").ok())
+            .unwrap_or_else(|| format!("instance-{}", std::process::id()));
+
+        let cluster = Arc::new(Self {
+            store,
+            instance_id,
+            config: config.clone(),
+            ring: RwLock::new(Vec::new()),
+        });
+
+        cluster.heartbeat().await?;
+        tracing::info!("Joined cluster as {}", cluster.instance_id);
+
+        let heartbeat = Arc::clone(&cluster);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(heartbeat.config.heartbeat_interval.max(1)));
+            loop {
+                interval.tick().await;
+                if let Err(error) = heartbeat.heartbeat().await {
+                    tracing::warn!("Cluster heartbeat failed // {error}");
+                }
+            }
+        });
+
+        Ok(cluster)
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Whether this instance is responsible for processing events in the room.
+    pub async fn owns(&self, room_id: &RoomId) -> bool {
+        let ring = self.ring.read().await;
+        let hash = fnv1a(room_id.as_bytes());
+        let index = ring.partition_point(|(point, _)| *point < hash);
+        match ring.get(index).or_else(|| ring.first()) {
+            Some((_, owner)) => *owner == self.instance_id,
+            None => true,
+        }
+    }
+
+    async fn heartbeat(&self) -> anyhow::Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let ttl = Duration::from_secs(self.config.heartbeat_interval.max(1) * 3);
+        self.store
+            .save_expiring(&format!("{INSTANCE_PREFIX}{}", self.instance_id), &now, ttl)
+            .await?;
+
+        let mut instances: Vec<String> = self
+            .store
+            .keys(INSTANCE_PREFIX)
+            .await?
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(INSTANCE_PREFIX).map(str::to_string))
+            .collect();
+        instances.sort();
+
+        let mut ring: Vec<(u64, String)> = instances
+            .iter()
+            .flat_map(|instance| {
+                (0..self.config.virtual_nodes.max(1))
+                    .map(move |node| (fnv1a(format!("{instance}#{node}").as_bytes()), instance.clone()))
+            })
+            .collect();
+        ring.sort();
+
+        let mut lock = self.ring.write().await;
+        if lock.len() != ring.len() {
+            tracing::info!("Cluster membership changed, {} instance(s) active", instances.len());
+        }
+        *lock = ring;
+
+        Ok(())
+    }
+}
+
+/// Hash that is stable across processes and builds, unlike the standard library hasher.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
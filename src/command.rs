@@ -1,25 +1,101 @@
+use std::{fmt::Display, sync::Arc};
+
+use chrono::{TimeDelta, Utc};
+
 use matrix_appservice::{
-    Device,
+    ApplicationService, Device, Room, State, User,
     exports::matrix_sdk::ruma::{
-        OwnedEventId,
-        events::room::message::{RoomMessageEventContent, RoomMessageEventContentWithoutRelation},
+        OwnedEventId, UserId,
+        events::{
+            relation::Thread,
+            room::message::{OriginalSyncRoomMessageEvent, Relation},
+        },
     },
 };
 use serde_json::json;
+use url::Url;
 
-use crate::openai::Processed;
+use crate::{
+    calendar::CalendarAccount,
+    consent, debate,
+    directives::InlineDirectives,
+    images::{self, ImageOptions},
+    memory::Memories,
+    moderation,
+    openai::{ConversationStore, MessageContent, Processed, load_message},
+    paste,
+    relation::BotResponse,
+    scheduler::Schedule,
+    settings::{MODERATOR_POWER_LEVEL, RoomSettings},
+    store::StorageBackend,
+    usage::RoomStats,
+    version,
+};
 
 pub enum Command {
     Reset,
     Help,
     Version,
+    Consent(String),
+    Set(String),
+    Settings,
+    Stats,
+    Debug(String),
+    Schedule(String),
+    Pause(String),
+    Resume,
+    WhoAmI,
+    Dm(String),
+    Calendar(String),
+    Fork(String),
+    Debate(String),
+    Preview,
+    Memories,
+    Forget(String),
+    Cancel,
+    Translate(String),
+    Explain,
+    Image(String),
     Unknown(String),
 }
 
+/// Everything a command may need to act on the room it was sent in.
+pub struct CommandContext<'a> {
+    pub appservice: &'a ApplicationService<State<Arc<ConversationStore>>>,
+    pub user: &'a Arc<User>,
+    pub room: &'a Arc<Room>,
+    pub device: &'a Device,
+    pub sender: &'a UserId,
+    pub event: &'a OriginalSyncRoomMessageEvent,
+    /// Locale of the room, for the text of responses.
+    pub locale: &'a str,
+}
+
+impl CommandContext<'_> {
+    pub fn state(&self) -> &ConversationStore {
+        self.appservice.state()
+    }
+
+    /// Response text in the room's locale.
+    pub fn text(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        self.state().locales().text(self.locale, key, args)
+    }
+
+    async fn is_moderator(&self) -> anyhow::Result<bool> {
+        let power_level = self
+            .state()
+            .homeserver()
+            .power_level(self.room.id(), self.sender)
+            .await?;
+        Ok(power_level >= MODERATOR_POWER_LEVEL)
+    }
+}
+
 impl Command {
     pub fn parse(input: &str) -> Option<Self> {
         let trimmed = input.trim();
-        if !trimmed.starts_with('!') {
+        // `!!` introduces inline directives for a prompt rather than a command.
+        if !trimmed.starts_with('!') || trimmed.starts_with("!!") {
             return None;
         }
 
@@ -31,34 +107,638 @@ impl Command {
             "reset" => Command::Reset,
             "help" => Command::Help,
             "version" => Command::Version,
+            "consent" => Command::Consent(args.to_string()),
+            "set" => Command::Set(args.to_string()),
+            "settings" => Command::Settings,
+            "stats" => Command::Stats,
+            "debug" => Command::Debug(args.to_string()),
+            "schedule" => Command::Schedule(args.to_string()),
+            "pause" => Command::Pause(args.to_string()),
+            "resume" => Command::Resume,
+            "whoami" => Command::WhoAmI,
+            "dm" => Command::Dm(args.to_string()),
+            "calendar" => Command::Calendar(args.to_string()),
+            "fork" => Command::Fork(args.to_string()),
+            "debate" => Command::Debate(args.to_string()),
+            "preview" => Command::Preview,
+            "memories" => Command::Memories,
+            "forget" => Command::Forget(args.to_string()),
+            "cancel" => Command::Cancel,
+            "translate" => Command::Translate(args.to_string()),
+            "explain" => Command::Explain,
+            "image" => Command::Image(args.to_string()),
             other => Command::Unknown(other.to_string()),
         })
     }
 
-    pub async fn send_message(&self, device: &Device, relates_to: OwnedEventId) -> anyhow::Result<()> {
-        let content = RoomMessageEventContentWithoutRelation::text_markdown(self.as_str());
-        let relation_body = json!({
-            "rel_type": "nl.spacebased.matrix-openai-bot.bot_response",
-            "relates_to": relates_to,
-        });
+    /// Run the command, returning the notice to post in response, if any.
+    pub async fn execute(self, context: &CommandContext<'_>) -> anyhow::Result<Option<String>> {
+        let state = context.state();
+        let room_id = context.room.id();
 
-        // content.with_relation(Some(serde_json::from_value(relation_body)?));
-        Ok(())
-    }
-
-    pub fn as_str(&self) -> &'static str {
         match self {
-            Command::Reset => "",
-            Command::Help => "Help text",
-            Command::Version => "Matrix AI Bot, v0.10",
-            Command::Unknown(_) => "Unknown command",
+            Command::Reset => {
+                state.clear(context.user.id(), room_id).await?;
+                Ok(None)
+            }
+            Command::Consent(args) => Ok(Some(match args.trim() {
+                "revoke" => {
+                    consent::revoke(state.store(), context.sender).await?;
+                    context.text("consent.revoked", &[])
+                }
+                "" | "yes" => {
+                    consent::grant(state.store(), context.sender).await?;
+                    context.text("consent.granted", &[])
+                }
+                _ => context.text("consent.usage", &[]),
+            })),
+            Command::Set(args) => {
+                if !context.is_moderator().await? {
+                    return Ok(Some(context.text("settings.moderators_only", &[])));
+                }
+
+                let (key, value) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+                // Rooms may only pick from the configured models, which the operator knows the cost of.
+                let openai = &state.config().openai;
+                let models = || std::iter::once(&openai.model).chain(&openai.room_models);
+                if key == "model"
+                    && !matches!(value.trim(), "" | "unset")
+                    && !models().any(|model| model == value.trim())
+                {
+                    let models = models().map(|model| format!("`{model}`")).collect::<Vec<_>>();
+                    return Ok(Some(
+                        context.text("settings.unknown_model", &[("models", &models.join(", "))]),
+                    ));
+                }
+                let _lock = RoomSettings::lock(state.store(), room_id).await;
+                let mut settings = RoomSettings::load(state.store(), room_id).await?;
+                let kept_content = settings.keeps_content();
+                if let Err(error) = settings.set(key, value) {
+                    return Ok(Some(error.to_string()));
+                }
+                settings.save(state.store(), room_id).await?;
+                if kept_content && !settings.keeps_content() {
+                    state.forget_content(room_id).await?;
+                }
+                Ok(Some(context.text("settings.updated", &[("key", &key)])))
+            }
+            Command::Settings => Ok(Some(RoomSettings::load(state.store(), room_id).await?.describe()?)),
+            Command::Debug(args) if args.trim().starts_with("api") => {
+                if !state.api_log().is_admin(context.sender) {
+                    return Ok(Some(context.text("debug.api_admins_only", &[])));
+                }
+                let enabled = match args.trim().trim_start_matches("api").trim() {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Ok(Some(context.text("debug.api_usage", &[]))),
+                };
+                state.api_log().set_enabled(enabled);
+                Ok(Some(match enabled {
+                    true => context.text("debug.api_enabled", &[]),
+                    false => context.text("debug.api_disabled", &[]),
+                }))
+            }
+            Command::Debug(args) => {
+                if !context.is_moderator().await? {
+                    return Ok(Some(context.text("settings.moderators_only", &[])));
+                }
+                let enabled = match args.trim() {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Ok(Some(context.text("debug.usage", &[]))),
+                };
+
+                let _lock = RoomSettings::lock(state.store(), room_id).await;
+                let mut settings = RoomSettings::load(state.store(), room_id).await?;
+                settings.debug = Some(enabled);
+                settings.save(state.store(), room_id).await?;
+                Ok(Some(match enabled {
+                    true => context.text("debug.enabled", &[]),
+                    false => context.text("debug.disabled", &[]),
+                }))
+            }
+            Command::Stats => {
+                let stats = RoomStats::load(state.store(), room_id).await?;
+                let length = state.event_ids(context.user.id(), room_id).await?.len();
+                Ok(Some(stats.to_markdown(length)))
+            }
+            Command::Schedule(args) => schedule(context, &args).await.map(Some),
+            Command::WhoAmI => whoami(context).await.map(Some),
+            Command::Version => Ok(Some(version::describe())),
+            Command::Dm(question) => direct_message(context, &question).await,
+            Command::Translate(language) => translate(context, &language).await,
+            Command::Explain => explain(context).await,
+            Command::Image(args) => image(context, &args).await,
+            Command::Calendar(args) => calendar(context, &args).await.map(Some),
+            Command::Fork(topic) => fork(context, &topic).await.map(Some),
+            Command::Debate(topic) => debate(context, &topic).await,
+            Command::Preview if !context.is_moderator().await? => {
+                Ok(Some(context.text("preview.moderators_only", &[])))
+            }
+            Command::Preview => preview(context).await,
+            Command::Memories if !context.room.is_direct().await => Ok(Some(context.text("memories.dm_only", &[]))),
+            Command::Memories => {
+                let memories = Memories::load(state.store(), &state.config().memory, context.sender).await?;
+                Ok(Some(match memories.facts.is_empty() {
+                    true => context.text("memories.empty", &[]),
+                    false => context.text("memories.list", &[("memories", &memories.to_markdown())]),
+                }))
+            }
+            Command::Forget(args) => forget(context, &args).await.map(Some),
+            // The progress notice of the history being read reports the cancellation.
+            Command::Cancel => Ok((!state.cancel_backfill(room_id)).then(|| context.text("cancel.nothing", &[]))),
+            Command::Pause(_) | Command::Resume if !context.is_moderator().await? => {
+                Ok(Some(context.text("pause.moderators_only", &[])))
+            }
+            Command::Pause(args) => {
+                let record = args.trim() == "record";
+                let _lock = RoomSettings::lock(state.store(), room_id).await;
+                let mut settings = RoomSettings::load(state.store(), room_id).await?;
+                settings.paused = Some(true);
+                settings.record_while_paused = Some(record);
+                settings.save(state.store(), room_id).await?;
+                Ok(Some(match record {
+                    true => context.text("pause.recording", &[]),
+                    false => context.text("pause.paused", &[]),
+                }))
+            }
+            Command::Resume => {
+                let _lock = RoomSettings::lock(state.store(), room_id).await;
+                let mut settings = RoomSettings::load(state.store(), room_id).await?;
+                settings.paused = None;
+                settings.record_while_paused = None;
+                settings.save(state.store(), room_id).await?;
+                Ok(Some(context.text("pause.resumed", &[])))
+            }
+            Command::Help => Ok(Some(context.text("command.help", &[]))),
+            Command::Unknown(_) => Ok(Some(context.text("command.unknown", &[]))),
         }
     }
 
+    /// Post the response to a command, related to the command so backfills can leave it out of the conversation.
+    pub async fn send_message(context: &CommandContext<'_>, response: String) -> anyhow::Result<OwnedEventId> {
+        let state = context.state();
+        let relation = BotResponse {
+            event_id: context.event.event_id.clone(),
+            model: None,
+            epoch: state.epoch(context.user.id(), context.room.id()).await?,
+        };
+        relation
+            .send(
+                context.device,
+                context.room.id(),
+                state.config().behavior.notice(response),
+            )
+            .await
+    }
+
+    /// Whether the command sends the sender's messages to the model, so it waits for their consent like prompts do.
+    pub fn needs_consent(&self) -> bool {
+        matches!(
+            self,
+            Command::Dm(_) | Command::Translate(_) | Command::Explain | Command::Image(_)
+        )
+    }
+
     pub fn into_processed(&self) -> Option<Processed> {
         match self {
-            Command::Reset => Some(Processed::Stop),
+            Command::Reset => Some(Processed::Boundary),
             _ => None,
         }
     }
 }
+
+async fn schedule(context: &CommandContext<'_>, args: &str) -> anyhow::Result<String> {
+    let state = context.state();
+    let room_id = context.room.id();
+    let _lock = RoomSettings::lock(state.store(), room_id).await;
+    let mut settings = RoomSettings::load(state.store(), room_id).await?;
+
+    let (action, rest) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+    match action {
+        "list" | "" => {
+            if settings.schedules.is_empty() {
+                return Ok(context.text("schedule.empty", &[]));
+            }
+            let lines = settings
+                .schedules
+                .iter()
+                .enumerate()
+                .map(|(index, schedule)| format!("{}. `{}` {}", index + 1, schedule.cron, schedule.prompt))
+                .collect::<Vec<_>>();
+            Ok(format!(
+                "{}\n{}",
+                context.text("schedule.heading", &[]),
+                lines.join("\n")
+            ))
+        }
+        "add" | "remove" if !context.is_moderator().await? => Ok(context.text("schedule.moderators_only", &[])),
+        "add" => {
+            let Some((cron, prompt)) = rest
+                .trim()
+                .strip_prefix('"')
+                .and_then(|rest| rest.split_once('"'))
+                .filter(|(_, prompt)| !prompt.trim().is_empty())
+            else {
+                return Ok(context.text("schedule.usage", &[]));
+            };
+
+            let schedule = match Schedule::new(cron, prompt.trim()) {
+                Ok(schedule) => schedule,
+                Err(error) => return Ok(error.to_string()),
+            };
+            settings.schedules.push(schedule);
+            settings.save(state.store(), room_id).await?;
+            Ok(context.text("schedule.added", &[("number", &settings.schedules.len())]))
+        }
+        "remove" => match rest.trim().parse::<usize>() {
+            Ok(number) if (1..=settings.schedules.len()).contains(&number) => {
+                settings.schedules.remove(number - 1);
+                settings.save(state.store(), room_id).await?;
+                Ok(context.text("schedule.removed", &[("number", &number)]))
+            }
+            _ => Ok(context.text("schedule.usage", &[])),
+        },
+        _ => Ok(context.text("schedule.usage", &[])),
+    }
+}
+
+/// Describe what the bot runs on in this room, and where prompts end up.
+async fn whoami(context: &CommandContext<'_>) -> anyhow::Result<String> {
+    let state = context.state();
+    let config = state.config();
+    let settings = RoomSettings::load(state.store(), context.room.id()).await?;
+
+    let model = settings.model.as_deref().unwrap_or(&config.openai.model);
+    let provider = state.chat_provider(model);
+    let endpoint = provider.base_url();
+    let data = context.text(
+        "whoami.provider",
+        &[
+            ("provider", &provider.name()),
+            ("host", &endpoint.host_str().unwrap_or_default()),
+        ],
+    );
+    let storage = match config.storage.backend {
+        StorageBackend::Memory => context.text("whoami.storage_memory", &[]),
+        StorageBackend::AccountData => context.text("whoami.storage_account_data", &[]),
+        StorageBackend::Redis => context.text("whoami.storage_redis", &[]),
+    };
+    let scrubbing = match settings.pii_scrubbing.unwrap_or(config.pii.enabled) {
+        true => context.text("whoami.masked", &[]),
+        false => context.text("whoami.unmasked", &[]),
+    };
+
+    let mut lines = vec![
+        format!("**{}**", version::describe()),
+        format!(
+            "- {}",
+            context.text("whoami.model", &[("model", &model), ("endpoint", endpoint)])
+        ),
+    ];
+    if let Some(vision_model) = &config.openai.vision_model {
+        lines.push(format!(
+            "- {}",
+            context.text("whoami.images", &[("model", vision_model)])
+        ));
+    }
+    if let Some(persona) = &settings.persona {
+        lines.push(format!("- {}", context.text("whoami.persona", &[("persona", persona)])));
+    }
+    let tools = state
+        .tools()
+        .names(state.config(), context.room.id(), Some(context.sender))?
+        .join(", ");
+    lines.push(format!("- {}", context.text("whoami.tools", &[("tools", &tools)])));
+    lines.push(format!("- {data} {scrubbing}"));
+    lines.push(format!("- {storage}"));
+
+    Ok(lines.join("\n"))
+}
+
+/// Continue in a new DM with the sender. The question is taken from the arguments, or from the message
+/// the command replies to.
+async fn direct_message(context: &CommandContext<'_>, question: &str) -> anyhow::Result<Option<String>> {
+    let state = context.state();
+    let question = match question.trim() {
+        "" => match replied_to(context).await? {
+            Some(event) if !author_consented(context, &event).await? => {
+                return Ok(Some(context.text("consent.author", &[])));
+            }
+            Some(event) => event.content.body().to_string(),
+            None => return Ok(Some(context.text("dm.usage", &[]))),
+        },
+        question => question.to_string(),
+    };
+
+    // Answered in the room it was asked in, so the answer knows what the room was talking about.
+    let completion = state
+        .get_conversation(context.appservice, context.user, context.room)
+        .await?
+        .with_sender(context.sender)
+        .with_prompt(&context.event.event_id)
+        .send_prompt(MessageContent::Text(question.clone()), &InlineDirectives::default())
+        .await?;
+
+    let homeserver = state.homeserver();
+    let room_id = match homeserver.direct_room(context.sender).await? {
+        Some(room_id) => room_id,
+        None => homeserver.create_direct_room(context.sender).await?,
+    };
+    let epoch = state.epoch(context.user.id(), &room_id).await?;
+    // The seed quotes the question, but it's the bot posting it, so it's marked as a command response to keep it
+    // from being read back as something the bot said.
+    let seed_text = context.text("dm.seed", &[("room", &context.room.id()), ("question", &question)]);
+    let seed = BotResponse {
+        event_id: context.event.event_id.clone(),
+        model: None,
+        epoch,
+    };
+    let seed_id = seed
+        .send(context.device, &room_id, state.config().behavior.notice(seed_text))
+        .await?;
+
+    let mut answer = completion.content.clone();
+    answer.push_str(completion.truncation_notice());
+    let relation = BotResponse {
+        event_id: seed_id,
+        model: Some(completion.model.clone()),
+        epoch,
+    };
+    let answer_id = relation
+        .send(context.device, &room_id, state.config().behavior.reply(answer))
+        .await?;
+    // The DM picks up from the answer.
+    state
+        .insert_events(context.user.id(), &room_id, [answer_id], None)
+        .await?;
+
+    Ok(Some(context.text("dm.sent", &[])))
+}
+
+/// Translate the message the command replies to, answering in its thread. The exchange is kept out of the
+/// conversation.
+async fn translate(context: &CommandContext<'_>, language: &str) -> anyhow::Result<Option<String>> {
+    let language = language.trim();
+    let Some(event) = replied_to(context).await?.filter(|_| !language.is_empty()) else {
+        return Ok(Some(context.text("translate.usage", &[])));
+    };
+    if !author_consented(context, &event).await? {
+        return Ok(Some(context.text("consent.author", &[])));
+    }
+
+    let prompt = format!(
+        "Translate the following message into {language}. Keep the formatting, and reply with only the \
+        translation.\n\n{}",
+        event.content.body()
+    );
+    let settings = RoomSettings::load(context.state().store(), context.room.id()).await?;
+    let translation = context.state().complete_in(&settings, prompt).await?;
+    reply_in_thread(context, &event, translation).await?;
+    Ok(None)
+}
+
+/// Explain the code, stack trace or other message the command replies to, answering in its thread. The exchange is
+/// kept out of the conversation.
+async fn explain(context: &CommandContext<'_>) -> anyhow::Result<Option<String>> {
+    let Some(event) = replied_to(context).await? else {
+        return Ok(Some(context.text("explain.usage", &[])));
+    };
+    if !author_consented(context, &event).await? {
+        return Ok(Some(context.text("consent.author", &[])));
+    }
+
+    let prompt = format!(
+        "Explain the following message concisely. If it contains code, describe what it does and point out any \
+        bugs. If it contains an error or stack trace, explain the likely cause and how to fix it. Answer in Markdown, \
+        in the language of locale {}.\n\n{}",
+        context.locale,
+        event.content.body()
+    );
+    let settings = RoomSettings::load(context.state().store(), context.room.id()).await?;
+    let explanation = context.state().complete_in(&settings, prompt).await?;
+    reply_in_thread(context, &event, explanation).await?;
+    Ok(None)
+}
+
+/// Generate an image from the prompt directly, without going through the chat model. Leading `size=WIDTHxHEIGHT`
+/// and `quality=<quality>` arguments override the configured image settings.
+async fn image(context: &CommandContext<'_>, args: &str) -> anyhow::Result<Option<String>> {
+    let state = context.state();
+    let mut options = ImageOptions::default();
+    let mut prompt = args.trim();
+    while let Some((argument, rest)) = prompt.split_once(char::is_whitespace) {
+        match argument.split_once('=') {
+            Some(("size", size)) if state.config().images.allows_size(size) => options.size = Some(size.to_string()),
+            Some(("size", _)) => return Ok(Some(context.text("image.usage", &[]))),
+            Some(("quality", quality)) => options.quality = Some(quality.to_string()),
+            _ => break,
+        }
+        prompt = rest.trim_start();
+    }
+    if prompt.is_empty() {
+        return Ok(Some(context.text("image.usage", &[])));
+    }
+
+    let config = &state.config().images;
+    let _permit = state.tool_limiter().acquire().await?;
+    let generated =
+        images::generate_daily(state.store(), config, state.images(), context.sender, prompt, &options).await?;
+    let Some(image) = generated else {
+        return Ok(Some(context.text("image.limit", &[("limit", &config.daily_limit)])));
+    };
+    images::post(
+        state.homeserver(),
+        context.device,
+        context.room.id(),
+        image,
+        "generated.png",
+    )
+    .await?;
+    Ok(None)
+}
+
+/// Whether the author of a message the command sends to the model consented to that, when consent is required.
+async fn author_consented(context: &CommandContext<'_>, event: &OriginalSyncRoomMessageEvent) -> anyhow::Result<bool> {
+    let state = context.state();
+    Ok(!state.config().consent.required || consent::has_consented(state.store(), &event.sender).await?)
+}
+
+/// The message the command was sent in reply to, if any, including explicit replies within a thread.
+async fn replied_to(context: &CommandContext<'_>) -> anyhow::Result<Option<OriginalSyncRoomMessageEvent>> {
+    let event_id = match &context.event.content.relates_to {
+        Some(Relation::Reply { in_reply_to }) => &in_reply_to.event_id,
+        Some(Relation::Thread(thread)) if !thread.is_falling_back => match &thread.in_reply_to {
+            Some(in_reply_to) => &in_reply_to.event_id,
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(
+        load_message(context.room, context.device, event_id, context.state()).await?,
+    ))
+}
+
+/// Answer in the thread `event` is part of, starting one from it if it isn't in a thread yet.
+async fn reply_in_thread(
+    context: &CommandContext<'_>,
+    event: &OriginalSyncRoomMessageEvent,
+    markdown: String,
+) -> anyhow::Result<()> {
+    let root = match &event.content.relates_to {
+        Some(Relation::Thread(thread)) => thread.event_id.clone(),
+        _ => event.event_id.clone(),
+    };
+    let mut content = context.state().config().behavior.reply(markdown);
+    content.relates_to = Some(Relation::Thread(Thread::plain(root, event.event_id.clone())));
+    context.device.send_message(context.room.id(), content).await?;
+    Ok(())
+}
+
+/// Branch the conversation off into a new room with the sender, carrying over the messages so far.
+async fn fork(context: &CommandContext<'_>, topic: &str) -> anyhow::Result<String> {
+    let state = context.state();
+    let room_id = context.room.id();
+    if state.event_ids(context.user.id(), room_id).await?.is_empty() {
+        return Ok(context.text("fork.empty", &[]));
+    }
+
+    let fork_id = state.homeserver().create_direct_room(context.sender).await?;
+    let count = state
+        .fork(context.user.id(), context.room, context.device, &fork_id)
+        .await?;
+    let topic = topic.trim();
+    if !topic.is_empty() {
+        state
+            .homeserver()
+            .set_state(
+                &fork_id,
+                "m.room.name",
+                &json!({ "name": context.text("fork.room_name", &[("topic", &topic)]) }),
+            )
+            .await?;
+    }
+
+    let seed = context.text("fork.seed", &[("room", &room_id), ("count", &count)]);
+    context
+        .device
+        .send_message(&fork_id, state.config().behavior.notice(seed))
+        .await?;
+
+    Ok(context.text("fork.done", &[("room", &fork_id)]))
+}
+
+/// Let the configured personas debate a topic in the room. Any message in the room stops them.
+async fn debate(context: &CommandContext<'_>, topic: &str) -> anyhow::Result<Option<String>> {
+    let state = context.state();
+    let topic = topic.trim();
+    match topic {
+        "" => Ok(Some(context.text("debate.usage", &[]))),
+        "stop" => Ok(match debate::interrupt(state.scratch(), context.room.id()).await? {
+            true => Some(context.text("debate.stopped", &[])),
+            false => Some(context.text("debate.none", &[])),
+        }),
+        _ if state.config().debate.personas.len() < 2 => Ok(Some(context.text("debate.personas", &[]))),
+        _ if state.config().consent.required && !consent::has_consented(state.store(), context.sender).await? => {
+            Ok(Some(state.config().consent.notice.clone()))
+        }
+        topic => {
+            debate::start(
+                context.appservice,
+                context.room.id(),
+                context.event.event_id.clone(),
+                topic.to_string(),
+            )
+            .await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Show the messages the model would receive for the next prompt, hidden behind a spoiler, or pasted when long.
+async fn preview(context: &CommandContext<'_>) -> anyhow::Result<Option<String>> {
+    let state = context.state();
+    let conversation = state
+        .get_conversation(context.appservice, context.user, context.room)
+        .await?;
+    let request = conversation.preview().await?;
+    let json = serde_json::to_string_pretty(&request.messages)?;
+
+    let threshold = state.config().paste.threshold;
+    if threshold > 0 && json.chars().count() > threshold {
+        let location = paste::share(state, context.device, context.room.id(), &json, "prompt.json").await?;
+        return Ok(Some(context.text("preview.too_long", &[("location", &location)])));
+    }
+
+    let content = moderation::spoiler(&format!("```json\n{json}\n```"), &["prompt preview".to_string()]);
+    context.device.send_message(context.room.id(), content).await?;
+    Ok(None)
+}
+
+async fn forget(context: &CommandContext<'_>, args: &str) -> anyhow::Result<String> {
+    let state = context.state();
+    let _lock = Memories::lock(state.store(), context.sender).await;
+    let mut memories = Memories::load(state.store(), &state.config().memory, context.sender).await?;
+    let message = match args.trim() {
+        "all" => {
+            memories.forget_all();
+            context.text("forget.all", &[])
+        }
+        id => match id.parse() {
+            Ok(id) if memories.forget(id) => context.text("forget.done", &[("id", &id)]),
+            Ok(id) => return Ok(context.text("forget.missing", &[("id", &id)])),
+            Err(_) => return Ok(context.text("forget.usage", &[])),
+        },
+    };
+    memories.save(state.store(), context.sender).await?;
+    Ok(message)
+}
+
+/// Connect a CalDAV calendar for the `get_agenda` tool. Only available in DMs, and the message with the
+/// credentials is redacted right away.
+async fn calendar(context: &CommandContext<'_>, args: &str) -> anyhow::Result<String> {
+    let state = context.state();
+    if !context.room.is_direct().await {
+        return Ok(context.text("calendar.dm_only", &[]));
+    }
+
+    let Some(key) = state.config().calendar.key.as_deref() else {
+        return Ok(context.text("calendar.unavailable", &[]));
+    };
+
+    let parts = args.split_whitespace().collect::<Vec<_>>();
+    match parts.as_slice() {
+        [] => Ok(match CalendarAccount::load(state.store(), key, context.sender).await? {
+            Some(account) => context.text("calendar.connected", &[("url", &account.url)]),
+            None => context.text("calendar.none", &[("usage", &context.text("calendar.usage", &[]))]),
+        }),
+        ["disconnect"] => {
+            CalendarAccount::forget(state.store(), context.sender).await?;
+            Ok(context.text("calendar.disconnected", &[]))
+        }
+        ["connect", url, username, password] => {
+            state
+                .homeserver()
+                .redact(context.room.id(), &context.event.event_id, "Contains credentials")
+                .await?;
+
+            let Ok(url) = Url::parse(url) else {
+                return Ok(context.text("calendar.failed", &[]));
+            };
+            let account = CalendarAccount {
+                url,
+                username: username.to_string(),
+                password: password.to_string(),
+            };
+            let now = Utc::now();
+            // The server's response isn't repeated, as the URL may point anywhere.
+            if let Err(error) = account.events(state.web(), now, now + TimeDelta::days(1)).await {
+                tracing::warn!("Connecting the calendar of {} failed: {error}", context.sender);
+                return Ok(context.text("calendar.failed", &[]));
+            }
+            account.save(state.store(), key, context.sender).await?;
+            Ok(context.text("calendar.added", &[]))
+        }
+        _ => Ok(context.text("calendar.usage", &[])),
+    }
+}
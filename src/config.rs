@@ -0,0 +1,215 @@
+use matrix_appservice::exports::matrix_sdk::ruma::{
+    OwnedUserId, UserId, events::room::message::RoomMessageEventContent,
+};
+use serde::Deserialize;
+use url::Url;
+
+use crate::{
+    api_log::ApiLogConfig, archive::ArchiveConfig, calendar::CalendarConfig, catch_up::CatchUpPolicy,
+    client::ClientConfig, cluster::ClusterConfig, consent::ConsentConfig, debate::DebateConfig, email::EmailConfig,
+    filter::FilterConfig, home_assistant::HomeAssistantConfig, i18n::I18nConfig, images::ImagesConfig,
+    injection::InjectionConfig, issues::IssuesConfig, kubernetes::KubernetesConfig, limiter::LimitsConfig,
+    memory::MemoryConfig, moderation::ModerationConfig, onboarding::OnboardingConfig, openai::GeminiConfig,
+    openai::OpenAIConfig, output_filter::OutputFilterConfig, paste::PasteConfig, pii::PiiConfig,
+    prometheus::PrometheusConfig, prompt::PromptConfig, puppets::PuppetsConfig, recap::RecapConfig, retry::RetryConfig,
+    self_test::SelfTestConfig, server::HttpConfig, shell::ShellConfig, speech::SpeechConfig, sql::SqlConfig,
+    store::StorageConfig, style::StyleConfig, version::UpdatesConfig, webhooks::WebhooksConfig,
+};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    pub homeserver: HomeserverConfig,
+    pub appservice: AppserviceConfig,
+    pub openai: OpenAIConfig,
+    /// Google's Gemini API, used alongside `openai` for Gemini models.
+    #[serde(default)]
+    pub gemini: GeminiConfig,
+    #[serde(default)]
+    pub media: MediaConfig,
+    #[serde(default)]
+    pub behavior: BehaviorConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub cluster: ClusterConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub onboarding: OnboardingConfig,
+    #[serde(default)]
+    pub consent: ConsentConfig,
+    #[serde(default)]
+    pub pii: PiiConfig,
+    #[serde(default)]
+    pub style: StyleConfig,
+    #[serde(default)]
+    pub moderation: ModerationConfig,
+    #[serde(default)]
+    pub output_filter: OutputFilterConfig,
+    #[serde(default)]
+    pub injection: InjectionConfig,
+    #[serde(default)]
+    pub prompt: PromptConfig,
+    #[serde(default)]
+    pub images: ImagesConfig,
+    #[serde(default)]
+    pub speech: SpeechConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub client: ClientConfig,
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
+    #[serde(default)]
+    pub archive: ArchiveConfig,
+    #[serde(default)]
+    pub recap: RecapConfig,
+    #[serde(default)]
+    pub puppets: PuppetsConfig,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub self_test: SelfTestConfig,
+    #[serde(default)]
+    pub updates: UpdatesConfig,
+    #[serde(default)]
+    pub issues: IssuesConfig,
+    #[serde(default)]
+    pub home_assistant: HomeAssistantConfig,
+    #[serde(default)]
+    pub email: EmailConfig,
+    #[serde(default)]
+    pub calendar: CalendarConfig,
+    #[serde(default)]
+    pub sql: SqlConfig,
+    #[serde(default)]
+    pub shell: ShellConfig,
+    #[serde(default)]
+    pub kubernetes: KubernetesConfig,
+    #[serde(default)]
+    pub prometheus: PrometheusConfig,
+    #[serde(default)]
+    pub paste: PasteConfig,
+    #[serde(default)]
+    pub debate: DebateConfig,
+    #[serde(default)]
+    pub api_log: ApiLogConfig,
+    #[serde(default)]
+    pub memory: MemoryConfig,
+    #[serde(default)]
+    pub filter: FilterConfig,
+    #[serde(default)]
+    pub i18n: I18nConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HomeserverConfig {
+    pub server_name: String,
+    pub url: Url,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AppserviceConfig {
+    pub username: String,
+    pub as_token: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MediaConfig {
+    /// Maximum size in bytes of media downloaded and forwarded to the model.
+    pub max_size: u64,
+    /// Number of frames extracted from a posted video.
+    pub video_frames: usize,
+    /// Width in pixels extracted frames are scaled down to.
+    pub frame_width: u32,
+    pub ffmpeg_path: String,
+    pub ffprobe_path: String,
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 50 * 1024 * 1024,
+            video_frames: 4,
+            frame_width: 768,
+            ffmpeg_path: "ffmpeg".to_string(),
+            ffprobe_path: "ffprobe".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BehaviorConfig {
+    /// Emit a machine-readable `nl.spacebased.openai.response` event alongside each reply.
+    pub response_events: bool,
+    /// How to handle messages that arrived while the bot was offline.
+    pub catch_up: CatchUpPolicy,
+    /// Post a notice describing each tool call while it runs, rooms can override this.
+    pub announce_tools: bool,
+    /// Seconds to wait for the user to pick an option or answer a follow-up question the model asks.
+    pub choice_timeout: u64,
+    /// Post text the model sends alongside tool calls immediately, and edit it once the final answer is ready.
+    pub interim_replies: bool,
+    /// Name unnamed DMs after the first exchange, with a short title generated by the model.
+    pub dm_titles: bool,
+    /// Review pasted diffs and uploaded `.patch` files file by file, instead of prompting with the whole diff.
+    pub code_review: bool,
+    /// Message type of everything the bot sends: replies, command responses and error notices. When unset, replies
+    /// are `m.text` and the rest `m.notice`.
+    pub reply_msgtype: Option<ReplyMsgtype>,
+    /// List what tools read, such as fetched pages, below the reply next to the sources it cites.
+    pub sources: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplyMsgtype {
+    Text,
+    /// Other bots are expected to ignore notices, so they won't answer the bot.
+    Notice,
+}
+
+impl Default for BehaviorConfig {
+    fn default() -> Self {
+        Self {
+            response_events: false,
+            catch_up: CatchUpPolicy::default(),
+            announce_tools: false,
+            choice_timeout: 300,
+            interim_replies: false,
+            dm_titles: false,
+            code_review: false,
+            reply_msgtype: None,
+            sources: true,
+        }
+    }
+}
+
+impl BehaviorConfig {
+    /// Content of a reply to a prompt.
+    pub fn reply(&self, markdown: impl Into<String>) -> RoomMessageEventContent {
+        match self.reply_msgtype {
+            Some(ReplyMsgtype::Notice) => RoomMessageEventContent::notice_markdown(markdown),
+            _ => RoomMessageEventContent::text_markdown(markdown),
+        }
+    }
+
+    /// Content of a command response, status update or error notice.
+    pub fn notice(&self, markdown: impl Into<String>) -> RoomMessageEventContent {
+        match self.reply_msgtype {
+            Some(ReplyMsgtype::Text) => RoomMessageEventContent::text_markdown(markdown),
+            _ => RoomMessageEventContent::notice_markdown(markdown),
+        }
+    }
+}
+
+impl Config {
+    pub fn bot_user_id(&self) -> anyhow::Result<OwnedUserId> {
+        Ok(UserId::parse(format!(
+            "@{}:{}",
+            self.appservice.username, self.homeserver.server_name
+        ))?)
+    }
+}
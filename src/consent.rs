@@ -0,0 +1,48 @@
+use matrix_appservice::exports::matrix_sdk::ruma::{MilliSecondsSinceUnixEpoch, UserId};
+use serde::Deserialize;
+
+use crate::store::Store;
+
+const DEFAULT_NOTICE: &str = "\
+Before I can answer, please read this privacy notice.
+
+Messages you send me are forwarded to the OpenAI API, outside of this homeserver, to generate replies. \
+Conversation state is stored by the bot until you `!reset` it.
+
+Send `!consent` to agree, you can withdraw at any time with `!consent revoke`.";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ConsentConfig {
+    /// Require users to acknowledge the privacy notice before their messages are sent to the API.
+    pub required: bool,
+    /// Markdown privacy notice shown to users who haven't consented yet.
+    pub notice: String,
+}
+
+impl Default for ConsentConfig {
+    fn default() -> Self {
+        Self {
+            required: false,
+            notice: DEFAULT_NOTICE.to_string(),
+        }
+    }
+}
+
+fn consent_key(user_id: &UserId) -> String {
+    format!("user/{user_id}/consent")
+}
+
+pub async fn has_consented(store: &dyn Store, user_id: &UserId) -> anyhow::Result<bool> {
+    Ok(store.get(&consent_key(user_id)).await?.is_some())
+}
+
+pub async fn grant(store: &dyn Store, user_id: &UserId) -> anyhow::Result<()> {
+    store
+        .save(&consent_key(user_id), &MilliSecondsSinceUnixEpoch::now())
+        .await
+}
+
+pub async fn revoke(store: &dyn Store, user_id: &UserId) -> anyhow::Result<()> {
+    store.delete(&consent_key(user_id)).await
+}
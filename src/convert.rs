@@ -0,0 +1,227 @@
+use anyhow::{Context, bail};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dimension {
+    Length,
+    Mass,
+    Volume,
+    Area,
+    Speed,
+    Data,
+    Duration,
+    Temperature,
+}
+
+/// Units by name and symbol, with their dimension and size in that dimension's base unit (metre, kilogram,
+/// litre, square metre, metre per second, byte, second). Temperatures are handled separately, and so are data
+/// symbols such as `MB`, whose case matters, see [`data_symbol`].
+const UNITS: &[(&[&str], Dimension, f64)] = &[
+    (&["mm", "millimeter", "millimetre"], Dimension::Length, 0.001),
+    (&["cm", "centimeter", "centimetre"], Dimension::Length, 0.01),
+    (&["m", "meter", "metre"], Dimension::Length, 1.0),
+    (&["km", "kilometer", "kilometre"], Dimension::Length, 1000.0),
+    (&["in", "inch", "inches", "\""], Dimension::Length, 0.0254),
+    (&["ft", "foot", "feet", "'"], Dimension::Length, 0.3048),
+    (&["yd", "yard"], Dimension::Length, 0.9144),
+    (&["mi", "mile"], Dimension::Length, 1609.344),
+    (&["nmi", "nautical mile"], Dimension::Length, 1852.0),
+    (&["mg", "milligram"], Dimension::Mass, 0.000_001),
+    (&["g", "gram"], Dimension::Mass, 0.001),
+    (&["kg", "kilogram"], Dimension::Mass, 1.0),
+    (&["t", "tonne", "metric ton"], Dimension::Mass, 1000.0),
+    (&["oz", "ounce"], Dimension::Mass, 0.028_349_523_125),
+    (&["lb", "lbs", "pound"], Dimension::Mass, 0.453_592_37),
+    (&["st", "stone"], Dimension::Mass, 6.350_293_18),
+    (&["ml", "milliliter", "millilitre"], Dimension::Volume, 0.001),
+    (&["cl", "centiliter", "centilitre"], Dimension::Volume, 0.01),
+    (&["dl", "deciliter", "decilitre"], Dimension::Volume, 0.1),
+    (&["l", "liter", "litre"], Dimension::Volume, 1.0),
+    (&["m3", "cubic meter", "cubic metre"], Dimension::Volume, 1000.0),
+    (&["tsp", "teaspoon"], Dimension::Volume, 0.004_928_921_593_75),
+    (&["tbsp", "tablespoon"], Dimension::Volume, 0.014_786_764_781_25),
+    (&["fl oz", "fluid ounce"], Dimension::Volume, 0.029_573_529_562_5),
+    (&["cup"], Dimension::Volume, 0.236_588_236_5),
+    (&["pt", "pint"], Dimension::Volume, 0.473_176_473),
+    (&["qt", "quart"], Dimension::Volume, 0.946_352_946),
+    (&["gal", "gallon"], Dimension::Volume, 3.785_411_784),
+    (&["m2", "square meter", "square metre"], Dimension::Area, 1.0),
+    (
+        &["km2", "square kilometer", "square kilometre"],
+        Dimension::Area,
+        1_000_000.0,
+    ),
+    (&["ft2", "square foot", "square feet"], Dimension::Area, 0.092_903_04),
+    (&["ha", "hectare"], Dimension::Area, 10_000.0),
+    (&["ac", "acre"], Dimension::Area, 4046.856_422_4),
+    (
+        &["m/s", "meters per second", "metres per second"],
+        Dimension::Speed,
+        1.0,
+    ),
+    (
+        &["km/h", "kph", "kilometers per hour", "kilometres per hour"],
+        Dimension::Speed,
+        1.0 / 3.6,
+    ),
+    (&["mph", "miles per hour"], Dimension::Speed, 0.447_04),
+    (&["kn", "kt", "knot"], Dimension::Speed, 1852.0 / 3600.0),
+    (&["bit"], Dimension::Data, 0.125),
+    (&["kbit", "kilobit"], Dimension::Data, 1e3 / 8.0),
+    (&["mbit", "megabit"], Dimension::Data, 1e6 / 8.0),
+    (&["gbit", "gigabit"], Dimension::Data, 1e9 / 8.0),
+    (&["byte"], Dimension::Data, 1.0),
+    (&["kilobyte"], Dimension::Data, 1e3),
+    (&["megabyte"], Dimension::Data, 1e6),
+    (&["gigabyte"], Dimension::Data, 1e9),
+    (&["terabyte"], Dimension::Data, 1e12),
+    (&["kibibyte"], Dimension::Data, 1024.0),
+    (&["mebibyte"], Dimension::Data, 1024.0 * 1024.0),
+    (&["gibibyte"], Dimension::Data, 1024.0 * 1024.0 * 1024.0),
+    (&["tebibyte"], Dimension::Data, 1024.0 * 1024.0 * 1024.0 * 1024.0),
+    (&["ms", "millisecond"], Dimension::Duration, 0.001),
+    (&["s", "sec", "second"], Dimension::Duration, 1.0),
+    (&["min", "minute"], Dimension::Duration, 60.0),
+    (&["h", "hr", "hour"], Dimension::Duration, 3600.0),
+    (&["d", "day"], Dimension::Duration, 86_400.0),
+    (&["wk", "week"], Dimension::Duration, 604_800.0),
+    (&["c", "°c", "celsius"], Dimension::Temperature, 0.0),
+    (&["f", "°f", "fahrenheit"], Dimension::Temperature, 0.0),
+    (&["k", "kelvin"], Dimension::Temperature, 0.0),
+];
+
+/// Convert a value between units, or a time between timezones when both `from` and `to` are IANA timezone
+/// names such as `Europe/Amsterdam`.
+pub fn convert(value: &str, from: &str, to: &str) -> anyhow::Result<String> {
+    if let (Ok(from), Ok(to)) = (from.parse::<Tz>(), to.parse::<Tz>()) {
+        return convert_time(value, from, to);
+    }
+
+    let amount: f64 = value
+        .trim()
+        .replace(',', "")
+        .parse()
+        .with_context(|| format!("'{value}' is not a number"))?;
+    let (from_dimension, from_factor) = unit(from)?;
+    let (to_dimension, to_factor) = unit(to)?;
+    if from_dimension != to_dimension {
+        bail!("Can't convert {from} ({from_dimension:?}) to {to} ({to_dimension:?})");
+    }
+
+    let result = match from_dimension {
+        Dimension::Temperature => from_kelvin(to_kelvin(amount, from), to),
+        _ => amount * from_factor / to_factor,
+    };
+
+    Ok(format!("{amount} {from} = {} {to}", round(result)))
+}
+
+fn unit(name: &str) -> anyhow::Result<(Dimension, f64)> {
+    if let Some(size) = data_symbol(name.trim()) {
+        return Ok((Dimension::Data, size));
+    }
+    let name = name.trim().to_lowercase();
+    let find = |name: &str| UNITS.iter().find(|(names, _, _)| names.contains(&name));
+    // Only fall back to the singular for names like "miles", so "ms" stays milliseconds.
+    find(name.as_str())
+        .or_else(|| name.strip_suffix('s').and_then(find))
+        .map(|(_, dimension, factor)| (*dimension, *factor))
+        .with_context(|| format!("Unknown unit '{name}'"))
+}
+
+/// Size in bytes of a data symbol such as `kB`, `Mb` or `GiB`. A lowercase `b` is a bit and an uppercase `B` a
+/// byte, the case of the prefix doesn't matter.
+fn data_symbol(symbol: &str) -> Option<f64> {
+    let (prefix, size) = match (symbol.strip_suffix('B'), symbol.strip_suffix('b')) {
+        (Some(prefix), _) => (prefix, 1.0),
+        (_, Some(prefix)) => (prefix, 0.125),
+        _ => return None,
+    };
+    let scale = match prefix.to_lowercase().as_str() {
+        "" => 1.0,
+        "k" => 1e3,
+        "m" => 1e6,
+        "g" => 1e9,
+        "t" => 1e12,
+        "ki" => 1024.0,
+        "mi" => 1024.0 * 1024.0,
+        "gi" => 1024.0 * 1024.0 * 1024.0,
+        "ti" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    Some(size * scale)
+}
+
+fn to_kelvin(value: f64, unit: &str) -> f64 {
+    match unit.trim().to_lowercase().trim_start_matches('°') {
+        "c" | "celsius" => value + 273.15,
+        "f" | "fahrenheit" => (value - 32.0) * 5.0 / 9.0 + 273.15,
+        _ => value,
+    }
+}
+
+fn from_kelvin(value: f64, unit: &str) -> f64 {
+    match unit.trim().to_lowercase().trim_start_matches('°') {
+        "c" | "celsius" => value - 273.15,
+        "f" | "fahrenheit" => (value - 273.15) * 9.0 / 5.0 + 32.0,
+        _ => value,
+    }
+}
+
+/// Round away floating point noise, keeping six significant decimals.
+fn round(value: f64) -> f64 {
+    (value * 1e6).round() / 1e6
+}
+
+/// Convert a time such as `15:00`, `3pm` or `2025-03-01 15:00` from one timezone to another. A time without a
+/// date is taken to be today in the source timezone.
+fn convert_time(value: &str, from: Tz, to: Tz) -> anyhow::Result<String> {
+    let value = value.trim();
+    let naive = match parse_datetime(value) {
+        Some(datetime) => datetime,
+        None => {
+            let time = parse_time(value).with_context(|| format!("Can't parse time '{value}'"))?;
+            Utc::now().with_timezone(&from).date_naive().and_time(time)
+        }
+    };
+
+    let local = from
+        .from_local_datetime(&naive)
+        .earliest()
+        .with_context(|| format!("{naive} doesn't exist in {from}"))?;
+    let converted = local.with_timezone(&to);
+
+    Ok(format!(
+        "{} = {}",
+        local.format("%A %Y-%m-%d %H:%M %Z (%:z)"),
+        converted.format("%A %Y-%m-%d %H:%M %Z (%:z)")
+    ))
+}
+
+fn parse_datetime(value: &str) -> Option<NaiveDateTime> {
+    [
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .ok()
+            .map(|date| date.and_time(NaiveTime::MIN))
+    })
+}
+
+fn parse_time(value: &str) -> Option<NaiveTime> {
+    let mut compact = value.to_lowercase().replace(' ', "").replace('.', "");
+    // Times like "3pm" need minutes to parse.
+    if !compact.contains(':') && (compact.ends_with("am") || compact.ends_with("pm")) {
+        compact.insert_str(compact.len() - 2, ":00");
+    }
+    ["%H:%M", "%H:%M:%S", "%I:%M%p"]
+        .iter()
+        .find_map(|format| NaiveTime::parse_from_str(&compact, format).ok())
+}
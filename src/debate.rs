@@ -0,0 +1,179 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use matrix_appservice::{
+    ApplicationService, State,
+    exports::matrix_sdk::ruma::{OwnedEventId, OwnedRoomId, RoomId},
+};
+use serde::Deserialize;
+
+use crate::{
+    openai::{ConversationStore, MessageContent, OpenAIMessage, Role},
+    settings::RoomSettings,
+    store::{self, Store},
+};
+
+/// How long a debate is considered running without finishing, in case it gets stuck halfway.
+const DEBATE_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DebateConfig {
+    /// Personas taking turns in `!debate`, in speaking order. At least two are needed.
+    pub personas: Vec<Persona>,
+    /// Number of messages posted before the debate ends.
+    pub turns: usize,
+}
+
+impl Default for DebateConfig {
+    fn default() -> Self {
+        Self {
+            personas: vec![
+                Persona {
+                    name: "Proponent".to_string(),
+                    prompt: "You argue in favour of the topic, with concrete examples.".to_string(),
+                },
+                Persona {
+                    name: "Skeptic".to_string(),
+                    prompt: "You argue against the topic, questioning assumptions and pointing out risks.".to_string(),
+                },
+            ],
+            turns: 6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Persona {
+    pub name: String,
+    /// System prompt describing the persona's position and tone.
+    pub prompt: String,
+}
+
+fn debate_key(room_id: &RoomId) -> String {
+    store::room_key(room_id, "debate")
+}
+
+/// Stop the debate running in a room, if any. Returns whether one was stopped. Debates run in the process that
+/// started them, so `store` is the in-memory scratch store, cheap enough to check on every message.
+pub async fn interrupt(store: &dyn Store, room_id: &RoomId) -> anyhow::Result<bool> {
+    let key = debate_key(room_id);
+    let running = store.load::<OwnedEventId>(&key).await?.is_some();
+    if running {
+        store.delete(&key).await?;
+    }
+    Ok(running)
+}
+
+/// Run a debate about `topic` in the background. `id` identifies it, so a newer debate in the same room isn't
+/// mistaken for this one.
+pub async fn start(
+    appservice: &ApplicationService<State<Arc<ConversationStore>>>,
+    room_id: &RoomId,
+    id: OwnedEventId,
+    topic: String,
+) -> anyhow::Result<()> {
+    let store = appservice.state().scratch();
+    store.save_expiring(&debate_key(room_id), &id, DEBATE_TTL).await?;
+
+    let appservice = appservice.clone();
+    let room_id = room_id.to_owned();
+    tokio::spawn(async move {
+        if let Err(error) = run(&appservice, &room_id, &id, &topic).await {
+            tracing::warn!("Debate in {room_id} failed: {error}");
+        }
+        // Only clear our own marker, a new debate may have started in the meantime.
+        let store = appservice.state().scratch();
+        if let Ok(Some(current)) = store.load::<OwnedEventId>(&debate_key(&room_id)).await
+            && current == id
+        {
+            let _ = store.delete(&debate_key(&room_id)).await;
+        }
+    });
+
+    Ok(())
+}
+
+async fn run(
+    appservice: &ApplicationService<State<Arc<ConversationStore>>>,
+    room_id: &OwnedRoomId,
+    id: &OwnedEventId,
+    topic: &str,
+) -> anyhow::Result<()> {
+    let state = appservice.state();
+    let config = &state.config().debate;
+    let settings = RoomSettings::load(state.store(), room_id).await?;
+    let locale = state.locale(&settings);
+    let user = appservice.get_bot().await?;
+    let device = user.get_device().await.context("Device not found")?;
+    let puppets = state.puppets().active(room_id, &settings).await?;
+
+    // Each persona has its own conversation, in which the others' turns are user messages.
+    let names = config
+        .personas
+        .iter()
+        .map(|persona| persona.name.as_str())
+        .collect::<Vec<_>>();
+    let mut conversations = config
+        .personas
+        .iter()
+        .map(|persona| {
+            let system = format!(
+                "You are {}, debating \"{topic}\" with {}. {} Reply in one short paragraph, addressing the last \
+                argument directly.",
+                persona.name,
+                names.join(", "),
+                persona.prompt
+            );
+            vec![
+                OpenAIMessage::new(Role::System, MessageContent::Text(system)),
+                OpenAIMessage::new(Role::User, MessageContent::Text(format!("The topic is: {topic}"))),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    for turn in 0..config.turns {
+        if state
+            .scratch()
+            .load::<OwnedEventId>(&debate_key(room_id))
+            .await?
+            .as_ref()
+            != Some(id)
+        {
+            return Ok(());
+        }
+
+        let speaker = turn % conversations.len();
+        device.send_typing(room_id, true).await?;
+        let reply = state.chat(conversations[speaker].clone()).await?;
+        device.send_typing(room_id, false).await?;
+
+        let name = names[speaker];
+        for (index, conversation) in conversations.iter_mut().enumerate() {
+            conversation.push(match index == speaker {
+                true => OpenAIMessage::new(Role::Assistant, MessageContent::Text(reply.clone())),
+                false => OpenAIMessage::new(Role::User, MessageContent::Text(format!("{name}: {reply}"))),
+            });
+        }
+        // Ghosts show the persona's name themselves, otherwise the reply is prefixed with it.
+        if puppets {
+            let content = state.config().behavior.reply(reply);
+            state.puppets().send(room_id, name, &content).await?;
+        } else {
+            device
+                .send_message(room_id, state.config().behavior.reply(format!("**{name}:** {reply}")))
+                .await?;
+        }
+    }
+
+    device
+        .send_message(
+            room_id,
+            state
+                .config()
+                .behavior
+                .notice(state.locales().text(locale, "debate.end", &[])),
+        )
+        .await?;
+    Ok(())
+}
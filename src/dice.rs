@@ -0,0 +1,140 @@
+use anyhow::{Context, bail, ensure};
+use rand::Rng;
+
+/// Upper bounds keeping a single roll cheap and its output readable.
+const MAX_DICE: u32 = 100;
+const MAX_SIDES: u32 = 1000;
+const MAX_CONSTANT: i64 = 1_000_000;
+const MAX_TERMS: usize = 20;
+
+/// Which dice of a group count towards the total, as in `4d6kh3`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Keep {
+    All,
+    Highest(u32),
+    Lowest(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Term {
+    Dice { count: u32, sides: u32, keep: Keep },
+    Constant(i64),
+}
+
+/// Parse a dice expression such as `3d6+2`, `d20 - 1` or `4d6kh3 + 1d4`.
+fn parse(expression: &str) -> anyhow::Result<Vec<(i64, Term)>> {
+    let compact: String = expression.chars().filter(|c| !c.is_whitespace()).collect();
+    ensure!(!compact.is_empty(), "Empty dice expression");
+
+    let mut terms = Vec::new();
+    let mut sign = 1;
+    let mut start = 0;
+    for (index, c) in compact.char_indices().chain([(compact.len(), '+')]) {
+        if c != '+' && c != '-' {
+            continue;
+        }
+        let term = &compact[start..index];
+        match term.is_empty() {
+            // A leading sign, as in "-1+d6".
+            true if index == 0 => (),
+            true => bail!("Missing term in '{expression}'"),
+            false => terms.push((sign, parse_term(term)?)),
+        }
+        sign = if c == '-' { -1 } else { 1 };
+        start = index + 1;
+    }
+
+    ensure!(terms.len() <= MAX_TERMS, "Use at most {MAX_TERMS} terms in one roll");
+    Ok(terms)
+}
+
+fn parse_term(term: &str) -> anyhow::Result<Term> {
+    let lower = term.to_lowercase();
+    let Some((count, rest)) = lower.split_once('d') else {
+        return Ok(Term::Constant(
+            term.parse().with_context(|| format!("Invalid term '{term}'"))?,
+        ));
+    };
+
+    let count = match count {
+        "" => 1,
+        count => count
+            .parse()
+            .with_context(|| format!("Invalid dice count in '{term}'"))?,
+    };
+    let (sides, keep) = match rest.split_once("kl") {
+        Some((sides, keep)) => (sides, Keep::Lowest(keep.parse()?)),
+        None => match rest.split_once("kh").or_else(|| rest.split_once('k')) {
+            Some((sides, keep)) => (sides, Keep::Highest(keep.parse()?)),
+            None => (rest, Keep::All),
+        },
+    };
+    let sides = sides
+        .parse()
+        .with_context(|| format!("Invalid number of sides in '{term}'"))?;
+
+    ensure!(
+        (1..=MAX_DICE).contains(&count),
+        "Roll between 1 and {MAX_DICE} dice at once"
+    );
+    ensure!(
+        (2..=MAX_SIDES).contains(&sides),
+        "Dice need between 2 and {MAX_SIDES} sides"
+    );
+    if let Keep::Highest(keep) | Keep::Lowest(keep) = keep {
+        ensure!(keep <= count, "Can't keep {keep} of {count} dice");
+    }
+
+    Ok(Term::Dice { count, sides, keep })
+}
+
+/// Roll a dice expression with the thread-local generator, which is seeded from the operating system. Returns the
+/// individual rolls next to the total, e.g. `3d6+2: [4, 1, 6] + 2 = 13`.
+pub fn roll(expression: &str) -> anyhow::Result<String> {
+    let mut rng = rand::rng();
+    let mut total: i64 = 0;
+    let mut parts = Vec::new();
+
+    for (sign, term) in parse(expression)? {
+        let (value, description) = match term {
+            Term::Constant(value) => {
+                ensure!(
+                    value.abs() <= MAX_CONSTANT,
+                    "Keep constants between -{MAX_CONSTANT} and {MAX_CONSTANT}"
+                );
+                (value, value.to_string())
+            }
+            Term::Dice { count, sides, keep } => {
+                let rolls: Vec<u32> = (0..count).map(|_| rng.random_range(1..=sides)).collect();
+                let mut sorted = rolls.clone();
+                sorted.sort_unstable();
+                let kept = match keep {
+                    Keep::All => &sorted[..],
+                    Keep::Highest(keep) => &sorted[sorted.len() - keep as usize..],
+                    Keep::Lowest(keep) => &sorted[..keep as usize],
+                };
+                // At most MAX_DICE * MAX_SIDES, so this can't overflow.
+                let value = kept.iter().map(|roll| *roll as i64).sum::<i64>();
+                let description = match keep {
+                    Keep::All => format!("{rolls:?}"),
+                    _ => format!("{rolls:?} keeping {kept:?}"),
+                };
+                (value, description)
+            }
+        };
+
+        total = sign
+            .checked_mul(value)
+            .and_then(|value| total.checked_add(value))
+            .context("The total of the roll is too large")?;
+        let operator = match (parts.is_empty(), sign) {
+            (true, 1) => "",
+            (true, _) => "-",
+            (false, 1) => " + ",
+            (false, _) => " - ",
+        };
+        parts.push(format!("{operator}{description}"));
+    }
+
+    Ok(format!("{expression}: {} = {total}", parts.concat()))
+}
@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+
+use crate::openai::{ContentPart, MessageContent};
+
+/// Per-message parameter overrides given as `!!key=value` tokens at the start of a prompt, e.g.
+/// `!!model=gpt-4o !!temp=0.1 Summarize this`. They only apply to that one message.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InlineDirectives {
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+}
+
+impl InlineDirectives {
+    /// Parse leading directives, returning them along with the remaining prompt text. Parsing stops at
+    /// the first token that isn't a recognized directive.
+    pub fn parse(text: &str) -> (Self, &str) {
+        let mut directives = Self::default();
+        let mut rest = text.trim_start();
+
+        while let Some(token) = rest.split_whitespace().next()
+            && let Some((key, value)) = token.strip_prefix("!!").and_then(|token| token.split_once('='))
+        {
+            match key {
+                "model" if !value.is_empty() => directives.model = Some(value.to_string()),
+                "temp" | "temperature" => match value.parse() {
+                    Ok(value) => directives.temperature = Some(value),
+                    Err(_) => break,
+                },
+                "max_tokens" => match value.parse() {
+                    Ok(value) => directives.max_tokens = Some(value),
+                    Err(_) => break,
+                },
+                _ => break,
+            }
+            rest = rest[token.len()..].trim_start();
+        }
+
+        (directives, rest)
+    }
+
+    /// Split the directives off a prompt. For multipart prompts they are taken from the first text part.
+    pub fn extract(content: MessageContent) -> (Self, MessageContent) {
+        match content {
+            MessageContent::Text(text) => {
+                let (directives, rest) = Self::parse(&text);
+                let rest = rest.to_string();
+                (directives, MessageContent::Text(rest))
+            }
+            MessageContent::Parts(mut parts) => {
+                let mut directives = Self::default();
+                if let Some(ContentPart::Text { text }) =
+                    parts.iter_mut().find(|part| matches!(part, ContentPart::Text { .. }))
+                {
+                    let (parsed, rest) = Self::parse(text);
+                    let rest = rest.to_string();
+                    directives = parsed;
+                    *text = rest;
+                }
+                (directives, MessageContent::Parts(parts))
+            }
+        }
+    }
+}
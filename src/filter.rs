@@ -0,0 +1,65 @@
+use matrix_appservice::exports::matrix_sdk::ruma::{UserId, events::room::message::OriginalSyncRoomMessageEvent};
+use regex::Regex;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FilterConfig {
+    /// Message types the bot responds to. `m.notice` is left out by default, since bridges and other bots use it
+    /// for automated messages that aren't meant to be answered.
+    pub msgtypes: Vec<String>,
+    /// Regexes matched against the full user ID of senders to ignore, such as other bots or bridge puppets.
+    pub ignore_senders: Vec<String>,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            msgtypes: ["m.text", "m.emote", "m.image", "m.audio", "m.video", "m.file"]
+                .map(String::from)
+                .to_vec(),
+            ignore_senders: Vec::new(),
+        }
+    }
+}
+
+/// Decides which incoming messages the bot reacts to at all, commands included.
+pub struct MessageFilter {
+    msgtypes: Vec<String>,
+    ignore_senders: Vec<Regex>,
+}
+
+impl MessageFilter {
+    pub fn new(config: &FilterConfig) -> anyhow::Result<Self> {
+        let ignore_senders = config
+            .ignore_senders
+            .iter()
+            .map(|pattern| {
+                Regex::new(&format!("^(?:{pattern})$"))
+                    .map_err(|error| anyhow::anyhow!("Invalid sender pattern '{pattern}': {error}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            msgtypes: config.msgtypes.clone(),
+            ignore_senders,
+        })
+    }
+
+    pub fn accepts(&self, sender: &UserId, event: &OriginalSyncRoomMessageEvent) -> bool {
+        let msgtype = event.content.msgtype();
+        if !self.msgtypes.iter().any(|accepted| accepted == msgtype) {
+            tracing::debug!("Ignoring {msgtype} message {}", event.event_id);
+            return false;
+        }
+        if self
+            .ignore_senders
+            .iter()
+            .any(|pattern| pattern.is_match(sender.as_str()))
+        {
+            tracing::debug!("Ignoring message {} from {sender}", event.event_id);
+            return false;
+        }
+        true
+    }
+}
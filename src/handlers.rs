@@ -0,0 +1,822 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use matrix_appservice::{
+    ApplicationService, Device, EventContext, Room, State, User,
+    exports::matrix_sdk::ruma::{
+        EventId, OwnedEventId, OwnedUserId, RoomId,
+        events::{
+            reaction::OriginalSyncReactionEvent,
+            room::{
+                member::{MembershipChange, StrippedRoomMemberEvent},
+                message::{
+                    MessageType, OriginalSyncRoomMessageEvent, Relation, ReplacementMetadata, RoomMessageEventContent,
+                },
+                tombstone::OriginalSyncRoomTombstoneEvent,
+            },
+        },
+    },
+};
+use serde_json::json;
+
+use crate::{
+    alt_text, archive,
+    catch_up::CatchUpDecision,
+    citations,
+    command::{Command, CommandContext},
+    consent, debate,
+    directives::InlineDirectives,
+    limiter::{BUDGET_EXCEEDED, TOOL_FAILURES},
+    media, moderation, onboarding,
+    openai::{
+        Completion, Conversation, ConversationStore, MessageContent, OpenAIError, RESPONSE_EVENT_TYPE, frame_emote,
+        load_message,
+    },
+    output_filter, paste,
+    questions::{self, Question},
+    queue, recap,
+    relation::BotResponse,
+    retry::{self, retry},
+    review,
+    settings::RoomSettings,
+    speech,
+    usage::RoomStats,
+    webhooks::{self, ExchangeRecord},
+};
+
+pub async fn on_room_member(
+    event: StrippedRoomMemberEvent,
+    appservice: ApplicationService<State<Arc<ConversationStore>>>,
+    context: EventContext,
+) -> anyhow::Result<()> {
+    appservice.state().participants().invalidate(&context.room_id).await;
+
+    let user = appservice.get_bot().await?;
+    if event.state_key != user.id() || !appservice.state().owns_room(&context.room_id).await {
+        return Ok(());
+    }
+
+    // Auto-join on room invite, and introduce ourselves in new DMs.
+    match event.membership_change(None) {
+        MembershipChange::Invited => {
+            let (bot, room_id) = (&user, &context.room_id);
+            retry(appservice.state(), "join", || async move {
+                Ok(bot.join_room(room_id).await?)
+            })
+            .await?;
+            if event.content.is_direct.unwrap_or_default() {
+                let device = user.get_device().await.context("Device not found")?;
+                // A new room has no settings yet, so the default locale applies.
+                let locale = &appservice.state().config().prompt.locale;
+                onboarding::welcome(appservice.state(), &device, &context.room_id, locale).await?;
+            }
+        }
+        _ => (),
+    };
+
+    Ok(())
+}
+
+/// Follow a room to its replacement when it's upgraded, taking the conversation and settings along.
+pub async fn on_room_tombstone(
+    event: OriginalSyncRoomTombstoneEvent,
+    appservice: ApplicationService<State<Arc<ConversationStore>>>,
+    context: EventContext,
+) -> anyhow::Result<()> {
+    if !appservice.state().owns_room(&context.room_id).await {
+        return Ok(());
+    }
+
+    let replacement = &event.content.replacement_room;
+    let user = appservice.get_bot().await?;
+    let bot = &user;
+    retry(appservice.state(), "join", || async move {
+        Ok(bot.join_room(replacement).await?)
+    })
+    .await
+    .with_context(|| format!("Joining {replacement}, the replacement of {}", context.room_id))?;
+    let room = appservice.get_room(&context.room_id).await.context("Room not found")?;
+    let device = user.get_device().await.context("Device not found")?;
+    appservice.state().migrate_room(&room, &device, replacement).await?;
+    appservice.state().homeserver().leave(&context.room_id).await?;
+
+    Ok(())
+}
+
+pub async fn on_reaction(
+    event: OriginalSyncReactionEvent,
+    appservice: ApplicationService<State<Arc<ConversationStore>>>,
+    context: EventContext,
+) -> anyhow::Result<()> {
+    let user = appservice.get_bot().await?;
+    if &context.sender == user.id() {
+        return Ok(());
+    }
+
+    let relation = &event.content.relates_to;
+    appservice
+        .state()
+        .menus()
+        .select_by_reaction(&relation.event_id, &context.sender, &relation.key)
+        .await;
+
+    Ok(())
+}
+
+pub async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    appservice: ApplicationService<State<Arc<ConversationStore>>>,
+    context: EventContext,
+) -> anyhow::Result<()> {
+    let user = appservice.get_bot().await?;
+
+    // Don't process if bot sent this message itself or through one of its ghosts, or another instance is responsible
+    // for the room.
+    if &context.sender == user.id()
+        || appservice.state().puppets().is_puppet(&context.sender)
+        || !appservice.state().owns_room(&context.room_id).await
+    {
+        return Ok(());
+    }
+    // Notices and other bots are ignored entirely, so bots can't end up answering each other.
+    if !appservice.state().filter().accepts(&context.sender, &event) {
+        return Ok(());
+    }
+
+    let room = appservice.get_room(&context.room_id).await.context("Room not found")?;
+    let is_direct = room.is_direct().await;
+    // Only respond directly to DMs. Group chats require explicitely mentioning the bot.
+    let addressed = is_direct
+        || event
+            .content
+            .mentions
+            .as_ref()
+            .is_none_or(|mentions| mentions.user_ids.contains(user.id()));
+    let command = Command::parse(event.content.body());
+
+    // Anyone speaking up stops a debate between personas. Commands handle debates themselves.
+    if command.is_none() {
+        debate::interrupt(appservice.state().scratch(), &context.room_id).await?;
+    }
+
+    // Reading the settings takes a store request, so other messages are left alone before that: only messages
+    // addressed to the bot, images that may need a description and answers to open menus get here. Answers to
+    // follow-up questions reply to them, which addresses the bot.
+    let is_image = matches!(event.content.msgtype, MessageType::Image(_));
+    if !addressed && !is_image && !appservice.state().menus().is_pending(room.id()).await {
+        return Ok(());
+    }
+    let settings = RoomSettings::load(appservice.state().store(), room.id()).await?;
+    let locale = appservice.state().locale(&settings);
+
+    // A paused room is left alone entirely, apart from `!resume`.
+    if settings.paused.unwrap_or_default() && !matches!(command, Some(Command::Resume)) {
+        if settings.record_while_paused.unwrap_or_default() && addressed && command.is_none() {
+            appservice
+                .state()
+                .insert_events(user.id(), room.id(), [event.event_id.clone()], settings.retention_days)
+                .await?;
+        }
+        return Ok(());
+    }
+
+    // A number in reply to an open option menu selects an option rather than starting a new prompt.
+    if appservice
+        .state()
+        .menus()
+        .select_by_reply(&context.room_id, &context.sender, event.content.body())
+        .await
+    {
+        return Ok(());
+    }
+    // Likewise, a message answering a follow-up question of the model resumes the prompt that asked it. In group
+    // rooms the answer has to reply to the question, so other messages of the asker are left alone.
+    let reply_to = match &event.content.relates_to {
+        Some(Relation::Reply { in_reply_to }) => Some(&in_reply_to.event_id),
+        _ => None,
+    };
+    if command.is_none()
+        && (is_direct || reply_to.is_some())
+        && let Some(question) = questions::take(appservice.state(), room.id(), &context.sender, |question| {
+            is_direct || reply_to == Some(&question.question_id)
+        })
+        .await?
+    {
+        let device = user.get_device().await.context("Device not found")?;
+        let prompt = Prompt {
+            event: load_message(&room, &device, &question.prompt_id, appservice.state()).await?,
+            sender: context.sender,
+            is_direct,
+        };
+        let answer = event.content.body().to_string();
+        return queue_answer(appservice, user, room, device, prompt, Some((question, Some(answer)))).await;
+    }
+
+    // Images get a description in rooms that asked for one, whoever posted them. Images sent as prompts are answered
+    // instead, and the backlog after a restart is left alone.
+    let consent_config = &appservice.state().config().consent;
+    if let MessageType::Image(image) = &event.content.msgtype
+        && !addressed
+        && settings.alt_text.unwrap_or_default()
+        && !appservice.state().catch_up().is_backlog(&event)
+        && (!consent_config.required || consent::has_consented(appservice.state().store(), &context.sender).await?)
+    {
+        let device = user.get_device().await.context("Device not found")?;
+        let (state, room_id, event, image) = (
+            Arc::clone(appservice.state()),
+            room.id().to_owned(),
+            event.clone(),
+            image.clone(),
+        );
+        let locale = locale.to_string();
+        tokio::spawn(async move {
+            // Describing takes a model request like a tool run does, and shares their limit.
+            let result = match state.tool_limiter().acquire().await {
+                Ok(_permit) => alt_text::describe(&state, &device, &room_id, &event, &image, &locale).await,
+                Err(error) => Err(error),
+            };
+            if let Err(error) = result {
+                tracing::warn!("Describing image {} failed: {error}", event.event_id);
+            }
+        });
+    }
+
+    if !addressed {
+        return Ok(());
+    }
+
+    let device = user.get_device().await.context("Device not found")?;
+    match appservice.state().catch_up().decide(room.id(), &event).await {
+        CatchUpDecision::Process => (),
+        CatchUpDecision::Skip => return Ok(()),
+        CatchUpDecision::Notice => {
+            let notice = appservice.state().locales().text(locale, "catch_up.notice", &[]);
+            device
+                .send_message(room.id(), appservice.state().config().behavior.notice(notice))
+                .await?;
+            return Ok(());
+        }
+    }
+
+    device.send_receipt(room.id(), &event.event_id).await?;
+    onboarding::welcome(appservice.state(), &device, room.id(), locale).await?;
+
+    // Prompts, and commands sending the sender's messages to the model, need their consent first.
+    if command.as_ref().is_none_or(Command::needs_consent)
+        && consent_config.required
+        && !consent::has_consented(appservice.state().store(), &context.sender).await?
+    {
+        device
+            .send_message(
+                room.id(),
+                appservice.state().config().behavior.notice(&consent_config.notice),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    // Is input an appservice command?
+    if let Some(command) = command {
+        let command_context = CommandContext {
+            appservice: &appservice,
+            user: &user,
+            room: &room,
+            device: &device,
+            sender: &context.sender,
+            event: &event,
+            locale,
+        };
+
+        if let Some(response) = command.execute(&command_context).await? {
+            Command::send_message(&command_context, response).await?;
+        }
+
+        return Ok(());
+    }
+
+    let prompt = Prompt {
+        event,
+        sender: context.sender,
+        is_direct,
+    };
+    queue_answer(appservice, user, room, device, prompt, None).await
+}
+
+/// A prompt sent in a room: the message, who sent it and whether it was sent in a DM.
+#[derive(Clone)]
+struct Prompt {
+    event: OriginalSyncRoomMessageEvent,
+    sender: OwnedUserId,
+    is_direct: bool,
+}
+
+/// Answer a prompt, or resume one suspended on a follow-up question with its answer, on the room's actor. When the
+/// model asks a question, the prompt continues without an answer once it's left unanswered for too long.
+async fn queue_answer(
+    appservice: ApplicationService<State<Arc<ConversationStore>>>,
+    user: Arc<User>,
+    room: Arc<Room>,
+    device: Arc<Device>,
+    prompt: Prompt,
+    resumed: Option<(Question, Option<String>)>,
+) -> anyhow::Result<()> {
+    let state = Arc::clone(appservice.state());
+    let work = answer(
+        appservice.clone(),
+        Arc::clone(&user),
+        Arc::clone(&room),
+        Arc::clone(&device),
+        prompt.clone(),
+        resumed,
+    );
+    if let Some(question_id) = state.actors().run(room.id(), work).await?? {
+        tokio::spawn(expire_question(appservice, user, room, device, prompt, question_id));
+    }
+    Ok(())
+}
+
+/// Wait for the answer to a follow-up question, and continue the prompt without one when the question is still
+/// open after the timeout. Questions the model asks after that are waited on in turn.
+async fn expire_question(
+    appservice: ApplicationService<State<Arc<ConversationStore>>>,
+    user: Arc<User>,
+    room: Arc<Room>,
+    device: Arc<Device>,
+    prompt: Prompt,
+    mut question_id: OwnedEventId,
+) {
+    let state = Arc::clone(appservice.state());
+    let timeout = Duration::from_secs(state.config().behavior.choice_timeout);
+    loop {
+        tokio::time::sleep(timeout).await;
+        let taken = questions::take(&state, room.id(), &prompt.sender, |question| {
+            question.question_id == question_id
+        })
+        .await;
+        let question = match taken {
+            Ok(Some(question)) => question,
+            // Answered in time, the answer resumed the prompt.
+            Ok(None) => return,
+            Err(error) => {
+                tracing::warn!("Reading question {question_id} in {} failed: {error:#}", room.id());
+                return;
+            }
+        };
+
+        let work = answer(
+            appservice.clone(),
+            Arc::clone(&user),
+            Arc::clone(&room),
+            Arc::clone(&device),
+            prompt.clone(),
+            Some((question, None)),
+        );
+        match state.actors().run(room.id(), work).await.and_then(|result| result) {
+            Ok(Some(next)) => question_id = next,
+            Ok(None) => return,
+            Err(error) => {
+                tracing::warn!(
+                    "Continuing prompt {} without an answer failed: {error:#}",
+                    prompt.event.event_id
+                );
+                return;
+            }
+        }
+    }
+}
+
+/// Answer a prompt and post the reply. Runs on the room's actor, so prompts in a room are answered one at a time.
+/// Returns the follow-up question the prompt was suspended on instead, if the model asked one.
+async fn answer(
+    appservice: ApplicationService<State<Arc<ConversationStore>>>,
+    user: Arc<User>,
+    room: Arc<Room>,
+    device: Arc<Device>,
+    prompt: Prompt,
+    resumed: Option<(Question, Option<String>)>,
+) -> anyhow::Result<Option<OwnedEventId>> {
+    let Prompt {
+        event,
+        sender,
+        is_direct,
+    } = prompt;
+    device.send_typing(room.id(), true).await?;
+
+    let conversation = appservice
+        .state()
+        .get_conversation(&appservice, &user, &room)
+        .await?
+        .with_sender(&sender)
+        .with_prompt(&event.event_id);
+
+    let state = appservice.state();
+    let locale = state.locale(conversation.settings());
+    let (completion, started, first_exchange) = match resumed {
+        // A resumed prompt was set up before it was suspended, it only needs the answer.
+        Some((question, answer)) => {
+            let started = Instant::now();
+            let work = conversation.resume(question, answer);
+            (
+                queue::announce(state, &device, room.id(), locale, work).await?,
+                started,
+                false,
+            )
+        }
+        None => {
+            let fresh = conversation.is_empty().await;
+            if fresh && is_direct {
+                conversation.backfill().await?;
+            }
+            let first_exchange = fresh && conversation.is_empty().await;
+
+            if is_direct
+                && let Some(recap) = recap::recap(appservice.state(), &conversation, event.origin_server_ts).await?
+            {
+                device
+                    .send_message(room.id(), appservice.state().config().behavior.notice(recap))
+                    .await?;
+            }
+
+            // A review takes a request per file, so group rooms have to ask for one explicitly. Uploads carry no
+            // mentions, which would otherwise count as addressing the bot.
+            let mentioned = event
+                .content
+                .mentions
+                .as_ref()
+                .is_some_and(|mentions| mentions.user_ids.contains(user.id()));
+            if appservice.state().config().behavior.code_review
+                && (is_direct || mentioned)
+                && let Some((diff, request)) = review::extract(appservice.state(), &event).await?
+            {
+                // Like answers, the review requests mask personal data and pass the output filter.
+                let review = review::review(state, &diff, &request).await?;
+                let (review, _) = paste::shorten(state, &device, room.id(), review).await?;
+                let flagged = flagged(state, &conversation, room.id(), &review).await;
+                let content = match flagged.is_empty() {
+                    true => state.config().behavior.reply(review),
+                    false => moderation::spoiler(&review, &flagged),
+                };
+                let response_id = device.send_message(room.id(), content).await?;
+                conversation.insert_dialog(event.event_id.clone(), response_id).await?;
+                device.send_typing(room.id(), false).await?;
+                return Ok(None);
+            }
+
+            let (content, derived) = prompt_content(&appservice, &event).await?;
+            let (directives, prompt) = InlineDirectives::extract(content);
+            // Keep content derived from media, since it can't be rebuilt from the event body later.
+            if derived && conversation.settings().keeps_content() {
+                appservice
+                    .state()
+                    .insert_attachment(event.event_id.clone(), prompt.clone())
+                    .await;
+            }
+
+            let started = Instant::now();
+            let work = conversation.send_prompt(prompt, &directives);
+            (
+                queue::announce(state, &device, room.id(), locale, work).await?,
+                started,
+                first_exchange,
+            )
+        }
+    };
+    let completion = match completion {
+        Ok(completion) => completion,
+        Err(error) => return failed(state, &device, room.id(), locale, error).await.map(|()| None),
+    };
+    let latency = started.elapsed();
+
+    // The model asked a follow-up question instead of replying, the prompt is resumed once it's answered.
+    if let Some(question_id) = completion.question {
+        device.send_typing(room.id(), false).await?;
+        return Ok(Some(question_id));
+    }
+
+    let delivered = deliver(
+        state,
+        &device,
+        room.id(),
+        &conversation,
+        completion,
+        Some(&event.event_id),
+        latency,
+    )
+    .await?;
+    let Some((completion, response_id, abridged)) = delivered else {
+        return Ok(None);
+    };
+    conversation
+        .insert_dialog(event.event_id.clone(), response_id.clone())
+        .await?;
+    // The room only shows a preview of a pasted reply, or a voice message, the model should remember all of it.
+    if abridged && conversation.settings().keeps_content() {
+        state
+            .insert_attachment(response_id.clone(), MessageContent::Text(completion.content.clone()))
+            .await;
+    }
+
+    if first_exchange && is_direct && state.config().behavior.dm_titles {
+        let appservice = appservice.clone();
+        let (room_id, prompt, reply) = (
+            room.id().to_owned(),
+            event.content.body().to_string(),
+            completion.content.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(error) = name_room(appservice.state(), &room_id, &prompt, &reply).await {
+                tracing::warn!("Naming room {room_id} failed: {error}");
+            }
+        });
+    }
+
+    // The reply is out, failing to count it shouldn't look like a failed prompt.
+    let usage_days = match conversation.settings().retention_days {
+        Some(days) => days.min(state.config().limits.usage_days),
+        None => state.config().limits.usage_days,
+    };
+    if let Err(error) = RoomStats::record(
+        state.store(),
+        state.metrics(),
+        room.id(),
+        &sender,
+        &completion,
+        latency,
+        usage_days,
+    )
+    .await
+    {
+        tracing::warn!("Recording statistics of {} failed: {error:#}", room.id());
+    }
+
+    let hooks = state.config().webhooks.outbound.clone();
+    let archive = &state.config().archive;
+    if !hooks.is_empty() || archive.room.is_some() {
+        let record = ExchangeRecord::new(
+            room.id(),
+            &sender,
+            state.config().webhooks.user_key.as_deref(),
+            event.content.body(),
+            &completion.content,
+            &completion.model,
+            &completion.usage,
+        )
+        .retain(conversation.settings().retention.unwrap_or_default());
+        // Like webhook deliveries, a failure to archive doesn't fail the exchange.
+        if let Err(error) = archive::mirror(&device, archive, &record, &event.event_id, &response_id).await {
+            tracing::warn!("Archiving exchange from {} failed: {error}", room.id());
+        }
+        if !hooks.is_empty() {
+            let http = state.http().clone();
+            tokio::spawn(async move { webhooks::deliver(&http, &hooks, &record).await });
+        }
+    }
+
+    if state.config().behavior.response_events {
+        let conversation_id = appservice
+            .state()
+            .conversation_id(user.id(), room.id())
+            .await?
+            .unwrap_or_else(|| event.event_id.clone());
+        let content = completion.response_event(&response_id, &event.event_id, &conversation_id);
+        device.send_raw(room.id(), RESPONSE_EVENT_TYPE, content).await?;
+    }
+
+    device.send_typing(room.id(), false).await?;
+
+    Ok(None)
+}
+
+/// Tell the room a prompt failed, if the provider failed it. Other errors are passed on.
+async fn failed(
+    state: &ConversationStore,
+    device: &Device,
+    room_id: &RoomId,
+    locale: &str,
+    error: anyhow::Error,
+) -> anyhow::Result<()> {
+    let Some(openai_error) = error.downcast_ref::<OpenAIError>() else {
+        return Err(error);
+    };
+    tracing::warn!("Prompt in {room_id} failed: {openai_error}");
+    let notice = state.locales().text(locale, openai_error.message_key(), &[]);
+    retry::send(state, device, room_id, state.config().behavior.notice(notice)).await?;
+    device.send_typing(room_id, false).await?;
+    Ok(())
+}
+
+/// Post the reply to a completion, whether the prompt came from a message, a webhook or a schedule. Withheld replies
+/// become a notice instead, others are filtered, styled and put behind a spoiler when flagged, or spoken in voice
+/// mode. A reply to `prompt_id` is marked as the bot's response to it. Returns the completion as posted, the reply
+/// and whether the room shows less than all of it, or `None` if a notice was posted instead.
+async fn deliver(
+    state: &ConversationStore,
+    device: &Device,
+    room_id: &RoomId,
+    conversation: &Conversation,
+    mut completion: Completion,
+    prompt_id: Option<&EventId>,
+    latency: Duration,
+) -> anyhow::Result<Option<(Completion, OwnedEventId, bool)>> {
+    let locale = state.locale(conversation.settings());
+    if completion.finish_reason.as_deref() == Some("content_filter") {
+        let notice = state.locales().text(locale, "error.content_filter", &[]);
+        retry::send(state, device, room_id, state.config().behavior.notice(notice)).await?;
+        device.send_typing(room_id, false).await?;
+        return Ok(None);
+    }
+    if let Some(BUDGET_EXCEEDED | TOOL_FAILURES) = completion.finish_reason.as_deref() {
+        let notice = state.config().behavior.notice(&completion.content);
+        retry::send(state, device, room_id, notice).await?;
+        device.send_typing(room_id, false).await?;
+        return Ok(None);
+    }
+    match output_filter::enforce(state, conversation, completion.content).await? {
+        Some(content) => completion.content = content,
+        None => {
+            let notice = state.locales().text(locale, "output_filter.refusal", &[]);
+            retry::send(state, device, room_id, state.config().behavior.notice(notice)).await?;
+            device.send_typing(room_id, false).await?;
+            return Ok(None);
+        }
+    }
+
+    let reply = state.style().apply(&completion.content);
+    let (mut reply, pasted) = paste::shorten(state, device, room_id, reply).await?;
+    reply.push_str(completion.truncation_notice());
+    if !completion.citations.is_empty() || !completion.sources.is_empty() {
+        reply.push_str(&citations::footer(&completion.citations, &completion.sources));
+    }
+    if conversation.settings().debug.unwrap_or_default() {
+        reply.push_str(&completion.debug_footer(latency));
+    }
+
+    let flagged = flagged(state, conversation, room_id, &completion.content).await;
+    // In voice mode the answer is spoken, leaving out footers. Flagged answers stay text, behind a spoiler.
+    let spoken = conversation.settings().voice_mode.unwrap_or_default() && flagged.is_empty();
+    let response_id = if spoken {
+        let text = completion.content.strip_prefix("/me ").unwrap_or(&completion.content);
+        let audio = state.synthesizer().synthesize(text).await?;
+        let response_id = speech::post(state.homeserver(), device, room_id, audio).await?;
+        // A voice message can't replace the interim reply, which shows the spoken text instead.
+        if let Some(interim) = &completion.replaces {
+            let content = state
+                .config()
+                .behavior
+                .reply(reply.clone())
+                .make_replacement(ReplacementMetadata::new(interim.clone(), None));
+            retry::send(state, device, room_id, content).await?;
+        }
+        response_id
+    } else {
+        // Replies starting with /me are actions, sent as emotes.
+        let mut content = match (flagged.is_empty(), reply.strip_prefix("/me ")) {
+            (true, Some(action)) => RoomMessageEventContent::emote_markdown(action),
+            (true, None) => state.config().behavior.reply(reply),
+            (false, _) => moderation::spoiler(&reply, &flagged),
+        };
+        if let Some(interim) = &completion.replaces {
+            content = content.make_replacement(ReplacementMetadata::new(interim.clone(), None));
+        }
+        if let Some(prompt_id) = prompt_id {
+            let relation = BotResponse {
+                event_id: prompt_id.to_owned(),
+                model: Some(completion.model.clone()),
+                epoch: conversation.epoch().await?,
+            };
+            content = relation.attach(content)?;
+        }
+        retry::send(state, device, room_id, content).await?
+    };
+
+    Ok(Some((completion, response_id, pasted || spoken)))
+}
+
+/// Moderation categories a reply is flagged for, in rooms with spoilers. Spoilers are a courtesy, so a moderation
+/// outage lets replies through as they are rather than failing them.
+async fn flagged(state: &ConversationStore, conversation: &Conversation, room_id: &RoomId, reply: &str) -> Vec<String> {
+    if !conversation
+        .settings()
+        .spoilers
+        .unwrap_or(state.config().moderation.spoilers)
+    {
+        return Vec::new();
+    }
+    match state.moderation().flagged(reply).await {
+        Ok(categories) => categories,
+        Err(error) => {
+            tracing::warn!("Moderation of the reply in {room_id} failed: {error:#}");
+            state.metrics().increment("openai_bot_moderation_errors_total", &[]);
+            Vec::new()
+        }
+    }
+}
+
+/// Give a room without a name a short title describing its first exchange.
+async fn name_room(state: &ConversationStore, room_id: &RoomId, prompt: &str, reply: &str) -> anyhow::Result<()> {
+    let homeserver = state.homeserver();
+    if homeserver.state_event(room_id, "m.room.name").await?.is_some() {
+        return Ok(());
+    }
+
+    let title = state
+        .complete(format!(
+            "Write a title of at most six words for a conversation that starts like this. \
+            Reply with only the title, without quotes.\n\nUser: {prompt}\n\nAssistant: {reply}"
+        ))
+        .await?;
+    let title = title.trim().trim_matches('"');
+    if !title.is_empty() {
+        homeserver
+            .set_state(room_id, "m.room.name", &json!({ "name": title }))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Run a prompt in a room outside of any incoming message, such as from a schedule or webhook, and post the
+/// reply like the answer to a message. The exchange isn't added to the conversation.
+pub async fn prompt_room(
+    appservice: &ApplicationService<State<Arc<ConversationStore>>>,
+    room_id: &RoomId,
+    prompt: &str,
+) -> anyhow::Result<()> {
+    let settings = RoomSettings::load(appservice.state().store(), room_id).await?;
+    if settings.paused.unwrap_or_default() {
+        return Ok(());
+    }
+
+    let user = appservice.get_bot().await?;
+    let room = appservice.get_room(room_id).await.context("Room not found")?;
+    let device = user.get_device().await.context("Device not found")?;
+    let prompt = prompt.to_string();
+    let appservice = appservice.clone();
+    let state = Arc::clone(appservice.state());
+    state
+        .actors()
+        .run(room_id, async move {
+            let state = appservice.state();
+            let conversation = state.get_conversation(&appservice, &user, &room).await?;
+            device.send_typing(room.id(), true).await?;
+            let started = Instant::now();
+            let completion = conversation
+                .send_prompt(MessageContent::Text(prompt), &InlineDirectives::default())
+                .await;
+            let completion = match completion {
+                Ok(completion) => completion,
+                Err(error) => {
+                    return failed(state, &device, room.id(), state.locale(conversation.settings()), error).await;
+                }
+            };
+            // Not answering a message, the reply isn't marked as a response, nor added to the conversation.
+            let latency = started.elapsed();
+            deliver(state, &device, room.id(), &conversation, completion, None, latency).await?;
+            device.send_typing(room.id(), false).await?;
+            Ok(())
+        })
+        .await?
+}
+
+/// What the model is prompted with for a message, and whether it was derived from media rather than taken from the
+/// event body.
+async fn prompt_content(
+    appservice: &ApplicationService<State<Arc<ConversationStore>>>,
+    event: &OriginalSyncRoomMessageEvent,
+) -> anyhow::Result<(MessageContent, bool)> {
+    let content = match &event.content.msgtype {
+        #[cfg(feature = "video")]
+        MessageType::Video(video) => {
+            let state = appservice.state();
+            let content = media::video::prompt_content(state.homeserver(), &state.config().media, video).await?;
+            return Ok((content, true));
+        }
+        MessageType::Audio(audio) if appservice.state().config().speech.transcription.enabled => {
+            let state = appservice.state();
+            let mimetype = audio
+                .info
+                .as_ref()
+                .and_then(|info| info.mimetype.as_deref())
+                .unwrap_or("audio/ogg");
+            let data = media::download(state.homeserver(), &audio.source, state.config().media.max_size).await?;
+            let transcript = state.transcriber().transcribe(data, mimetype).await?;
+            return Ok((
+                MessageContent::Text(format!("(Transcribed voice message) {transcript}")),
+                true,
+            ));
+        }
+        // Mention the event replied to, so tools can act on e.g. a previously posted image.
+        MessageType::Text(text) => match &event.content.relates_to {
+            Some(Relation::Reply { in_reply_to }) => {
+                format!("{}\n\n(In reply to event {})", text.body, in_reply_to.event_id)
+            }
+            _ => text.body.clone(),
+        },
+        MessageType::Emote(emote) => frame_emote(&event.sender, &emote.body),
+        _ => event.content.body().to_string(),
+    };
+    Ok((MessageContent::Text(content), false))
+}
@@ -0,0 +1,173 @@
+use std::sync::LazyLock;
+
+use anyhow::{Context, bail, ensure};
+use matrix_appservice::exports::matrix_sdk::ruma::{OwnedRoomId, OwnedUserId, RoomId, UserId};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use url::Url;
+
+use crate::client::Identified;
+
+static ENTITY_ID: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[a-z_]+\.[a-z0-9_]+$").expect("valid regex"));
+static SERVICE_NAME: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[a-z0-9_]+$").expect("valid regex"));
+
+/// Service data keys that pick other targets than the entity the call was allowed for.
+const TARGET_KEYS: &[&str] = &["entity_id", "device_id", "area_id", "floor_id", "label_id", "target"];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HomeAssistantConfig {
+    /// Base URL of the Home Assistant instance, e.g. `http://homeassistant.local:8123/`. The tools are only offered
+    /// when this is set.
+    pub url: Option<Url>,
+    /// Rooms in which the home can be read and controlled, e.g. a DM with the bot.
+    pub rooms: Vec<OwnedRoomId>,
+    /// Users who can read and control the home in any room. The tools aren't offered unless rooms or users are set.
+    pub users: Vec<OwnedUserId>,
+    /// Long-lived access token.
+    pub token: String,
+    /// Entities the bot may read and control. Entries ending in `*` match by prefix, e.g. `light.*`.
+    pub entities: Vec<String>,
+    /// Services the bot may call, as `domain.service`, e.g. `light.turn_on`. Entries ending in `*` match by prefix,
+    /// e.g. `light.*`.
+    pub services: Vec<String>,
+    /// Ask the user to confirm before calling a service.
+    pub confirm: bool,
+}
+
+impl Default for HomeAssistantConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            rooms: Vec::new(),
+            users: Vec::new(),
+            token: String::new(),
+            entities: Vec::new(),
+            services: Vec::new(),
+            confirm: true,
+        }
+    }
+}
+
+impl HomeAssistantConfig {
+    pub fn enabled(&self) -> bool {
+        self.url.is_some() && (!self.rooms.is_empty() || !self.users.is_empty())
+    }
+
+    /// Whether the home can be read and controlled for a prompt in this room by this sender.
+    pub fn permits(&self, room_id: &RoomId, sender: Option<&UserId>) -> bool {
+        self.rooms.iter().any(|room| room == room_id)
+            || sender.is_some_and(|sender| self.users.iter().any(|user| user == sender))
+    }
+
+    pub fn allows(&self, entity_id: &str) -> bool {
+        ENTITY_ID.is_match(entity_id) && matches(&self.entities, entity_id)
+    }
+
+    pub fn allows_service(&self, domain: &str, service: &str) -> bool {
+        SERVICE_NAME.is_match(domain)
+            && SERVICE_NAME.is_match(service)
+            && matches(&self.services, &format!("{domain}.{service}"))
+    }
+}
+
+fn matches(patterns: &[String], name: &str) -> bool {
+    patterns.iter().any(|pattern| match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    })
+}
+
+/// Client for the Home Assistant REST API, limited to the allowed entities.
+pub struct HomeAssistant<'a> {
+    http: &'a reqwest::Client,
+    config: &'a HomeAssistantConfig,
+    url: &'a Url,
+}
+
+impl<'a> HomeAssistant<'a> {
+    pub fn new(http: &'a reqwest::Client, config: &'a HomeAssistantConfig) -> anyhow::Result<Self> {
+        let url = config.url.as_ref().context("Home Assistant is not configured")?;
+        Ok(Self { http, config, url })
+    }
+
+    fn allow(&self, entity_id: &str) -> anyhow::Result<()> {
+        ensure!(
+            self.config.allows(entity_id),
+            "Entity {entity_id} is not available to the bot"
+        );
+        Ok(())
+    }
+
+    /// Allowed entities with their current state.
+    pub async fn entities(&self) -> anyhow::Result<Vec<String>> {
+        let states: Vec<Value> = self.get("api/states").await?;
+        Ok(states
+            .iter()
+            .filter_map(|state| {
+                let entity_id = state["entity_id"].as_str()?;
+                self.config.allows(entity_id).then(|| describe(state))
+            })
+            .collect())
+    }
+
+    pub async fn state(&self, entity_id: &str) -> anyhow::Result<String> {
+        self.allow(entity_id)?;
+        let state: Value = self.get(&format!("api/states/{entity_id}")).await?;
+        Ok(format!("{}\nAttributes: {}", describe(&state), state["attributes"]))
+    }
+
+    pub async fn call_service(
+        &self,
+        domain: &str,
+        service: &str,
+        entity_id: &str,
+        data: Option<&Value>,
+    ) -> anyhow::Result<Vec<String>> {
+        self.allow(entity_id)?;
+        ensure!(
+            self.config.allows_service(domain, service),
+            "Service {domain}.{service} is not available to the bot"
+        );
+        let mut body = data.cloned().unwrap_or_else(|| json!({}));
+        let fields = body.as_object_mut().context("Service data must be an object")?;
+        if let Some(key) = TARGET_KEYS.iter().find(|key| fields.contains_key(**key)) {
+            bail!("Service data can't contain {key}, pass the entity as entity_id instead");
+        }
+        fields.insert("entity_id".to_string(), entity_id.into());
+
+        let changed: Vec<Value> = self
+            .http
+            .post(self.url.join(&format!("api/services/{domain}/{service}"))?)
+            .bearer_auth(&self.config.token)
+            .json(&body)
+            .send_identified()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(changed.iter().map(describe).collect())
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> anyhow::Result<T> {
+        Ok(self
+            .http
+            .get(self.url.join(path)?)
+            .bearer_auth(&self.config.token)
+            .send_identified()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
+
+fn describe(state: &Value) -> String {
+    let entity_id = state["entity_id"].as_str().unwrap_or_default();
+    let value = state["state"].as_str().unwrap_or_default();
+    match state["attributes"]["friendly_name"].as_str() {
+        Some(name) => format!("{entity_id} ({name}): {value}"),
+        None => format!("{entity_id}: {value}"),
+    }
+}
@@ -0,0 +1,512 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use anyhow::Context;
+use futures::StreamExt;
+use matrix_appservice::exports::matrix_sdk::ruma::{
+    EventId, MxcUri, OwnedEventId, OwnedMxcUri, OwnedRoomAliasId, OwnedRoomId, OwnedUserId, RoomAliasId, RoomId, UserId,
+};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use url::Url;
+
+use crate::{config::Config, metrics::Metrics};
+
+/// Counter making transaction IDs of messages sent through [`Homeserver::send_message`] unique.
+static NEXT_TRANSACTION: AtomicU64 = AtomicU64::new(0);
+
+/// A new transaction ID for sending an event.
+pub fn transaction_id() -> String {
+    format!(
+        "{}-{}",
+        chrono::Utc::now().timestamp_millis(),
+        NEXT_TRANSACTION.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+#[derive(Deserialize)]
+struct SendResponse {
+    event_id: OwnedEventId,
+}
+
+/// Thin client for Client-Server API endpoints not covered by the appservice library,
+/// authenticated with the appservice token and masquerading as the bot user.
+#[derive(Clone)]
+pub struct Homeserver {
+    client: Client,
+    url: Url,
+    as_token: String,
+    user_id: OwnedUserId,
+    metrics: Arc<Metrics>,
+}
+
+impl Homeserver {
+    pub fn new(config: &Config, metrics: Arc<Metrics>) -> anyhow::Result<Self> {
+        let client = Client::builder().use_rustls_tls().build()?;
+
+        Ok(Self {
+            client,
+            url: config.homeserver.url.clone(),
+            as_token: config.appservice.as_token.clone(),
+            user_id: config.bot_user_id()?,
+            metrics,
+        })
+    }
+
+    pub fn user_id(&self) -> &UserId {
+        &self.user_id
+    }
+
+    /// The same client masquerading as another user in the appservice's namespace, such as a ghost.
+    pub fn as_user(&self, user_id: &UserId) -> Self {
+        Self {
+            user_id: user_id.to_owned(),
+            ..self.clone()
+        }
+    }
+
+    /// The user the appservice token authenticates as, to check the homeserver is reachable and accepts it.
+    pub async fn whoami(&self) -> anyhow::Result<OwnedUserId> {
+        #[derive(Deserialize)]
+        struct WhoAmI {
+            user_id: OwnedUserId,
+        }
+
+        let request = self.request(Method::GET, "/_matrix/client/v3/account/whoami")?;
+        let whoami: WhoAmI = self.send("whoami", request).await?.json().await?;
+        Ok(whoami.user_id)
+    }
+
+    /// Build a request from individual path segments, percent-encoding each of them.
+    pub fn request_segments(&self, method: Method, segments: &[&str]) -> anyhow::Result<RequestBuilder> {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Homeserver URL can't be a base"))?
+            .clear()
+            .extend(segments);
+
+        Ok(self
+            .client
+            .request(method, url)
+            .bearer_auth(&self.as_token)
+            .query(&[("user_id", self.user_id.as_str())]))
+    }
+
+    pub fn request(&self, method: Method, path: &str) -> anyhow::Result<RequestBuilder> {
+        let url = self.url.join(path)?;
+        Ok(self
+            .client
+            .request(method, url)
+            .bearer_auth(&self.as_token)
+            .query(&[("user_id", self.user_id.as_str())]))
+    }
+
+    /// Send a request, failing on an error status. Failures are counted per endpoint, a short name such as
+    /// `upload`, so homeserver problems show up in the metrics.
+    pub async fn send(&self, endpoint: &'static str, request: RequestBuilder) -> anyhow::Result<Response> {
+        self.record(endpoint, request.send().await.and_then(Response::error_for_status))
+    }
+
+    /// Like [`Homeserver::send`], but a 404 response is `None` rather than a failure.
+    pub async fn send_optional(
+        &self,
+        endpoint: &'static str,
+        request: RequestBuilder,
+    ) -> anyhow::Result<Option<Response>> {
+        match request.send().await {
+            Ok(response) if response.status() == StatusCode::NOT_FOUND => Ok(None),
+            result => self
+                .record(endpoint, result.and_then(Response::error_for_status))
+                .map(Some),
+        }
+    }
+
+    fn record(&self, endpoint: &'static str, result: reqwest::Result<Response>) -> anyhow::Result<Response> {
+        result.map_err(|error| {
+            self.metrics
+                .increment("openai_bot_homeserver_errors_total", &[("endpoint", endpoint)]);
+            error.into()
+        })
+    }
+
+    /// Download a media file, refusing anything larger than `max_size` bytes.
+    pub async fn download(&self, uri: &MxcUri, max_size: u64) -> anyhow::Result<Vec<u8>> {
+        let (server_name, media_id) = uri.parts().context("Invalid MXC URI")?;
+        let path = format!("/_matrix/client/v1/media/download/{server_name}/{media_id}");
+        let response = self.send("download", self.request(Method::GET, &path)?).await?;
+
+        if response.content_length().is_some_and(|length| length > max_size) {
+            return Err(anyhow::anyhow!("Media exceeds the size limit of {max_size} bytes"));
+        }
+
+        let mut data = Vec::new();
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            data.extend_from_slice(&chunk?);
+            if data.len() as u64 > max_size {
+                return Err(anyhow::anyhow!("Media exceeds the size limit of {max_size} bytes"));
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Upload a file to the media repository, returning its MXC URI.
+    pub async fn upload(&self, data: Vec<u8>, content_type: &str, filename: &str) -> anyhow::Result<OwnedMxcUri> {
+        #[derive(Deserialize)]
+        struct UploadResponse {
+            content_uri: OwnedMxcUri,
+        }
+
+        let request = self
+            .request(Method::POST, "/_matrix/media/v3/upload")?
+            .query(&[("filename", filename)])
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(data);
+        let response: UploadResponse = self.send("upload", request).await?.json().await?;
+
+        Ok(response.content_uri)
+    }
+
+    /// Power level of a user in a room, from the room's power levels state event.
+    pub async fn power_level(&self, room_id: &RoomId, user_id: &UserId) -> anyhow::Result<i64> {
+        Ok(self.power_levels(room_id).await?.get(user_id))
+    }
+
+    pub async fn power_levels(&self, room_id: &RoomId) -> anyhow::Result<PowerLevels> {
+        let request = self.request_segments(
+            Method::GET,
+            &[
+                "_matrix",
+                "client",
+                "v3",
+                "rooms",
+                room_id.as_str(),
+                "state",
+                "m.room.power_levels",
+            ],
+        )?;
+        Ok(self.send("power_levels", request).await?.json().await?)
+    }
+
+    /// Content of a state event with an empty state key, `None` if the room doesn't have one.
+    pub async fn state_event(&self, room_id: &RoomId, event_type: &str) -> anyhow::Result<Option<Value>> {
+        let request = self.request_segments(
+            Method::GET,
+            &[
+                "_matrix",
+                "client",
+                "v3",
+                "rooms",
+                room_id.as_str(),
+                "state",
+                event_type,
+            ],
+        )?;
+        match self.send_optional("state", request).await? {
+            Some(response) => Ok(Some(response.json().await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up the room an alias points to, with servers that can be used to join it.
+    pub async fn resolve_alias(&self, alias: &RoomAliasId) -> anyhow::Result<Option<ResolvedAlias>> {
+        let request = self.request_segments(
+            Method::GET,
+            &["_matrix", "client", "v3", "directory", "room", alias.as_str()],
+        )?;
+        match self.send_optional("directory", request).await? {
+            Some(response) => Ok(Some(response.json().await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Search the homeserver's public room directory.
+    pub async fn public_rooms(&self, search: &str, limit: u32) -> anyhow::Result<Vec<PublicRoom>> {
+        #[derive(Deserialize)]
+        struct PublicRooms {
+            chunk: Vec<PublicRoom>,
+        }
+
+        let request = self
+            .request(Method::POST, "/_matrix/client/v3/publicRooms")?
+            .json(&json!({
+                "limit": limit,
+                "filter": { "generic_search_term": search },
+            }));
+        let rooms: PublicRooms = self.send("public_rooms", request).await?.json().await?;
+
+        Ok(rooms.chunk)
+    }
+
+    /// Send a state event with an empty state key.
+    pub async fn set_state(&self, room_id: &RoomId, event_type: &str, content: &Value) -> anyhow::Result<()> {
+        let request = self.request_segments(
+            Method::PUT,
+            &[
+                "_matrix",
+                "client",
+                "v3",
+                "rooms",
+                room_id.as_str(),
+                "state",
+                event_type,
+                "",
+            ],
+        )?;
+        self.send("state", request.json(content)).await?;
+        Ok(())
+    }
+
+    /// Redact an event, e.g. a message containing credentials.
+    pub async fn redact(&self, room_id: &RoomId, event_id: &EventId, reason: &str) -> anyhow::Result<()> {
+        // Redacting the same event twice is harmless, so the event ID doubles as transaction ID.
+        let transaction_id = format!("redact-{event_id}");
+        let request = self.request_segments(
+            Method::PUT,
+            &[
+                "_matrix",
+                "client",
+                "v3",
+                "rooms",
+                room_id.as_str(),
+                "redact",
+                event_id.as_str(),
+                &transaction_id,
+            ],
+        )?;
+        self.send("redact", request.json(&json!({ "reason": reason }))).await?;
+        Ok(())
+    }
+
+    /// Send an `m.room.message` event with raw content, for senders without a device at hand.
+    pub async fn send_message(&self, room_id: &RoomId, content: &Value) -> anyhow::Result<()> {
+        self.send_event(room_id, content, &transaction_id()).await?;
+        Ok(())
+    }
+
+    /// Send an `m.room.message` event with raw content under the given transaction ID. Sending again with the same
+    /// transaction ID returns the event sent the first time instead of posting it twice.
+    pub async fn send_event(
+        &self,
+        room_id: &RoomId,
+        content: &Value,
+        transaction: &str,
+    ) -> anyhow::Result<OwnedEventId> {
+        let request = self.request_segments(
+            Method::PUT,
+            &[
+                "_matrix",
+                "client",
+                "v3",
+                "rooms",
+                room_id.as_str(),
+                "send",
+                "m.room.message",
+                transaction,
+            ],
+        )?;
+        let response: SendResponse = self.send("send", request.json(content)).await?.json().await?;
+        Ok(response.event_id)
+    }
+
+    /// Register a user in the appservice's namespace. Registering a user that already exists succeeds.
+    pub async fn register(&self, localpart: &str) -> anyhow::Result<()> {
+        let request = self
+            .client
+            .post(self.url.join("/_matrix/client/v3/register")?)
+            .bearer_auth(&self.as_token)
+            .json(&json!({ "type": "m.login.application_service", "username": localpart }));
+        let response = request.send().await?;
+        if response.status() == StatusCode::BAD_REQUEST {
+            let error: Value = response.json().await?;
+            if error["errcode"] == "M_USER_IN_USE" {
+                return Ok(());
+            }
+            return Err(anyhow::anyhow!("Registering {localpart} failed: {error}"));
+        }
+        self.record("register", response.error_for_status())?;
+        Ok(())
+    }
+
+    /// Set the display name of the user masqueraded as.
+    pub async fn set_displayname(&self, displayname: &str) -> anyhow::Result<()> {
+        let request = self.request_segments(
+            Method::PUT,
+            &[
+                "_matrix",
+                "client",
+                "v3",
+                "profile",
+                self.user_id.as_str(),
+                "displayname",
+            ],
+        )?;
+        self.send("profile", request.json(&json!({ "displayname": displayname })))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn invite(&self, room_id: &RoomId, user_id: &UserId) -> anyhow::Result<()> {
+        let request = self.request_segments(
+            Method::POST,
+            &["_matrix", "client", "v3", "rooms", room_id.as_str(), "invite"],
+        )?;
+        self.send("invite", request.json(&json!({ "user_id": user_id })))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn leave(&self, room_id: &RoomId) -> anyhow::Result<()> {
+        let request = self.request_segments(
+            Method::POST,
+            &["_matrix", "client", "v3", "rooms", room_id.as_str(), "leave"],
+        )?;
+        self.send("leave", request.json(&json!({}))).await?;
+        Ok(())
+    }
+
+    /// Join a room the user masqueraded as was invited to.
+    pub async fn join(&self, room_id: &RoomId) -> anyhow::Result<()> {
+        let request = self.request_segments(Method::POST, &["_matrix", "client", "v3", "join", room_id.as_str()])?;
+        self.send("join", request.json(&json!({}))).await?;
+        Ok(())
+    }
+
+    /// Create a direct chat with a user, and record it in the bot's `m.direct` account data so it is
+    /// treated as a DM.
+    pub async fn create_direct_room(&self, user_id: &UserId) -> anyhow::Result<OwnedRoomId> {
+        #[derive(Deserialize)]
+        struct CreatedRoom {
+            room_id: OwnedRoomId,
+        }
+
+        let request = self
+            .request(Method::POST, "/_matrix/client/v3/createRoom")?
+            .json(&json!({
+                "preset": "trusted_private_chat",
+                "is_direct": true,
+                "invite": [user_id],
+            }));
+        let created: CreatedRoom = self.send("create_room", request).await?.json().await?;
+
+        let mut direct = self.direct_rooms().await?;
+        direct
+            .entry(user_id.to_owned())
+            .or_default()
+            .push(created.room_id.clone());
+        let request = self.request_segments(Method::PUT, &self.direct_path())?.json(&direct);
+        self.send("account_data", request).await?;
+
+        Ok(created.room_id)
+    }
+
+    /// The most recent direct chat with a user listed in the bot's `m.direct` account data that both are still in.
+    pub async fn direct_room(&self, user_id: &UserId) -> anyhow::Result<Option<OwnedRoomId>> {
+        let mut direct = self.direct_rooms().await?;
+        let Some(rooms) = direct.remove(user_id) else {
+            return Ok(None);
+        };
+        let joined = self.joined_rooms().await?;
+        for room_id in rooms.into_iter().rev().filter(|room_id| joined.contains(room_id)) {
+            if self.joined_members(&room_id).await?.contains_key(user_id) {
+                return Ok(Some(room_id));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn direct_rooms(&self) -> anyhow::Result<BTreeMap<OwnedUserId, Vec<OwnedRoomId>>> {
+        let request = self.request_segments(Method::GET, &self.direct_path())?;
+        Ok(match self.send_optional("account_data", request).await? {
+            Some(response) => response.json().await?,
+            None => BTreeMap::new(),
+        })
+    }
+
+    fn direct_path(&self) -> [&str; 7] {
+        [
+            "_matrix",
+            "client",
+            "v3",
+            "user",
+            self.user_id.as_str(),
+            "account_data",
+            "m.direct",
+        ]
+    }
+
+    /// Display names of the users currently joined to a room.
+    pub async fn joined_members(&self, room_id: &RoomId) -> anyhow::Result<BTreeMap<OwnedUserId, Option<String>>> {
+        #[derive(Deserialize)]
+        struct JoinedMembers {
+            joined: BTreeMap<OwnedUserId, Member>,
+        }
+
+        #[derive(Deserialize)]
+        struct Member {
+            display_name: Option<String>,
+        }
+
+        let request = self.request_segments(
+            Method::GET,
+            &["_matrix", "client", "v3", "rooms", room_id.as_str(), "joined_members"],
+        )?;
+        let members: JoinedMembers = self.send("joined_members", request).await?.json().await?;
+
+        Ok(members
+            .joined
+            .into_iter()
+            .map(|(user_id, member)| (user_id, member.display_name))
+            .collect())
+    }
+
+    /// Rooms the bot user is joined to.
+    pub async fn joined_rooms(&self) -> anyhow::Result<Vec<OwnedRoomId>> {
+        #[derive(Deserialize)]
+        struct JoinedRooms {
+            joined_rooms: Vec<OwnedRoomId>,
+        }
+
+        let request = self.request_segments(Method::GET, &["_matrix", "client", "v3", "joined_rooms"])?;
+        let rooms: JoinedRooms = self.send("joined_rooms", request).await?.json().await?;
+        Ok(rooms.joined_rooms)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResolvedAlias {
+    pub room_id: OwnedRoomId,
+    #[serde(default)]
+    pub servers: Vec<String>,
+}
+
+/// Entry of the public room directory.
+#[derive(Debug, Deserialize)]
+pub struct PublicRoom {
+    pub room_id: OwnedRoomId,
+    pub name: Option<String>,
+    pub topic: Option<String>,
+    pub canonical_alias: Option<OwnedRoomAliasId>,
+    pub num_joined_members: u64,
+}
+
+/// Content of a room's `m.room.power_levels` state event, as far as user levels go.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct PowerLevels {
+    users: BTreeMap<OwnedUserId, i64>,
+    users_default: i64,
+}
+
+impl PowerLevels {
+    pub fn get(&self, user_id: &UserId) -> i64 {
+        self.users.get(user_id).copied().unwrap_or(self.users_default)
+    }
+}
@@ -0,0 +1,70 @@
+use std::{collections::HashMap, fmt::Display, path::PathBuf};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+/// Bundles compiled into the binary. English is complete and used for any key missing from another locale.
+const BUILTIN: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.yaml")),
+    ("nl", include_str!("../locales/nl.yaml")),
+];
+const FALLBACK: &str = "en";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct I18nConfig {
+    /// Directory with `<locale>.yaml` bundles, e.g. `de.yaml`, adding locales or overriding built-in text key by key.
+    pub directory: Option<PathBuf>,
+}
+
+/// Text of command responses, notices and onboarding in each supported locale. The locale of a room is the one set
+/// with `!set locale`, or `prompt.locale` by default.
+pub struct Locales {
+    bundles: HashMap<String, HashMap<String, String>>,
+}
+
+impl Locales {
+    pub fn load(config: &I18nConfig) -> anyhow::Result<Self> {
+        let mut bundles = HashMap::new();
+        for (locale, source) in BUILTIN {
+            let bundle: HashMap<String, String> =
+                serde_yaml::from_str(source).with_context(|| format!("Invalid built-in locale {locale}"))?;
+            bundles.insert(locale.to_string(), bundle);
+        }
+
+        if let Some(directory) = &config.directory {
+            let entries = std::fs::read_dir(directory)
+                .with_context(|| format!("Reading locales from {} failed", directory.display()))?;
+            for entry in entries {
+                let path = entry?.path();
+                let Some(locale) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .filter(|_| path.extension().is_some_and(|extension| extension == "yaml"))
+                else {
+                    continue;
+                };
+                let bundle: HashMap<String, String> = serde_yaml::from_str(&std::fs::read_to_string(&path)?)
+                    .with_context(|| format!("Invalid locale file {}", path.display()))?;
+                bundles.entry(locale.to_string()).or_default().extend(bundle);
+            }
+        }
+
+        Ok(Self { bundles })
+    }
+
+    /// Text for `key` in `locale`, e.g. `nl-NL`, falling back to the language alone and then to English. Placeholders
+    /// like `{name}` are replaced with the matching argument.
+    pub fn text(&self, locale: &str, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        let language = locale.split(['-', '_']).next().unwrap_or(locale);
+        let template = [locale, language, FALLBACK]
+            .into_iter()
+            .find_map(|locale| self.bundles.get(locale)?.get(key))
+            .map(|text| text.trim_end())
+            .unwrap_or(key);
+
+        args.iter().fold(template.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{name}}}"), &value.to_string())
+        })
+    }
+}
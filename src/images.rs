@@ -0,0 +1,217 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::Utc;
+use matrix_appservice::{
+    Device,
+    exports::matrix_sdk::ruma::{
+        RoomId, UInt, UserId,
+        events::room::{
+            ImageInfo,
+            message::{ImageMessageEventContent, MessageType, RoomMessageEventContent},
+        },
+    },
+};
+use serde::Deserialize;
+use url::Url;
+
+use crate::{
+    homeserver::Homeserver,
+    limiter::LimitsConfig,
+    media,
+    openai::{Api, OpenAIConfig},
+    store::Store,
+};
+
+pub use self::{automatic1111::Automatic1111, comfyui::ComfyUi, openai::OpenAIImages};
+
+mod automatic1111;
+mod comfyui;
+mod openai;
+
+/// Sizes `!image` may ask for besides the configured one, those supported by DALL·E and common for Stable Diffusion.
+const SIZES: &[&str] = &[
+    "256x256",
+    "512x512",
+    "768x768",
+    "1024x1024",
+    "1024x1536",
+    "1536x1024",
+    "1024x1792",
+    "1792x1024",
+];
+
+/// Per-request overrides of the configured image settings, as given to `!image`.
+#[derive(Debug, Clone, Default)]
+pub struct ImageOptions {
+    /// Size as `WIDTHxHEIGHT`. ComfyUI uses the size set in its workflow.
+    pub size: Option<String>,
+    /// Quality as understood by the provider, e.g. `hd` for DALL·E 3. Ignored by Stable Diffusion backends.
+    pub quality: Option<String>,
+}
+
+/// A backend generating and editing images. All images are exchanged as PNG.
+#[async_trait]
+pub trait ImageProvider: Send + Sync {
+    async fn generate(&self, prompt: &str, options: &ImageOptions) -> anyhow::Result<Vec<u8>>;
+
+    /// Edit an image according to an instruction, or create a variation of it when the instruction is empty.
+    async fn edit(&self, image: Vec<u8>, instruction: &str) -> anyhow::Result<Vec<u8>> {
+        let _ = (image, instruction);
+        Err(anyhow::anyhow!(
+            "The configured image provider doesn't support editing images"
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageBackend {
+    /// OpenAI compatible images API, e.g. DALL·E.
+    #[default]
+    #[serde(rename = "openai")]
+    OpenAI,
+    /// Stable Diffusion through the AUTOMATIC1111 web UI API.
+    Automatic1111,
+    /// A ComfyUI server running a workflow exported in API format.
+    #[serde(rename = "comfyui")]
+    ComfyUi,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ImagesConfig {
+    pub provider: ImageBackend,
+    /// Base URL of the provider's API. Defaults to the images API below `openai.endpoint` for the OpenAI provider.
+    pub endpoint: Option<Url>,
+    pub model: String,
+    pub size: String,
+    /// Quality passed to the OpenAI images API, e.g. `hd` or `high`. The model's default when unset.
+    pub quality: Option<String>,
+    /// Sampling steps, for Stable Diffusion backends.
+    pub steps: u32,
+    /// ComfyUI workflow in API format, with `{prompt}` where the prompt text goes.
+    pub workflow: Option<PathBuf>,
+    /// Images each user may generate per day, with `!image` or through the model. 0 means no limit.
+    pub daily_limit: u32,
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        Self {
+            provider: ImageBackend::default(),
+            endpoint: None,
+            model: "dall-e-2".to_string(),
+            size: "1024x1024".to_string(),
+            quality: None,
+            steps: 25,
+            workflow: None,
+            daily_limit: 0,
+        }
+    }
+}
+
+impl ImagesConfig {
+    /// Width and height from the configured `WIDTHxHEIGHT` size.
+    pub fn dimensions(&self) -> anyhow::Result<(u32, u32)> {
+        parse_size(&self.size)
+    }
+
+    /// Whether a size requested with `!image` may be generated: the configured size or one of the common sizes.
+    pub fn allows_size(&self, size: &str) -> bool {
+        size == self.size || SIZES.contains(&size)
+    }
+}
+
+/// Width and height from a `WIDTHxHEIGHT` size.
+pub fn parse_size(size: &str) -> anyhow::Result<(u32, u32)> {
+    let (width, height) = size
+        .split_once('x')
+        .ok_or_else(|| anyhow::anyhow!("Invalid image size '{size}'"))?;
+    Ok((width.parse()?, height.parse()?))
+}
+
+/// Upload an image and post it in the room. The extension of `filename` is replaced to match the image format.
+pub async fn post(
+    homeserver: &Homeserver,
+    device: &Device,
+    room_id: &RoomId,
+    data: Vec<u8>,
+    filename: &str,
+) -> anyhow::Result<()> {
+    let (mimetype, extension) = media::image_type(&data);
+    let filename = PathBuf::from(filename)
+        .with_extension(extension)
+        .to_string_lossy()
+        .into_owned();
+    let mut info = ImageInfo::new();
+    info.mimetype = Some(mimetype.to_string());
+    info.size = UInt::new(data.len() as u64);
+
+    let source = media::upload(homeserver, room_id, data, mimetype, &filename).await?;
+    let mut content = ImageMessageEventContent::new(filename, source);
+    content.info = Some(Box::new(info));
+    device
+        .send_message(room_id, RoomMessageEventContent::new(MessageType::Image(content)))
+        .await?;
+    Ok(())
+}
+
+/// Generate an image for `user_id`, counting it towards their daily limit once it succeeded. Returns `None` instead
+/// when the limit is reached. A user's images are generated one at a time, so they can't run past the limit at once.
+pub async fn generate_daily(
+    store: &dyn Store,
+    config: &ImagesConfig,
+    provider: &dyn ImageProvider,
+    user_id: &UserId,
+    prompt: &str,
+    options: &ImageOptions,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    if config.daily_limit == 0 {
+        return provider.generate(prompt, options).await.map(Some);
+    }
+
+    let key = format!("user/{user_id}/images/{}", Utc::now().format("%Y-%m-%d"));
+    let _lock = store.lock(&key).await;
+    let count: u32 = store.load(&key).await?.unwrap_or_default();
+    if count >= config.daily_limit {
+        return Ok(None);
+    }
+    let image = provider.generate(prompt, options).await?;
+    store
+        .save_expiring(&key, &(count + 1), Duration::from_secs(2 * 24 * 60 * 60))
+        .await?;
+    Ok(Some(image))
+}
+
+/// Build the configured image provider. `client` carries the OpenAI credentials, `http` is a plain client
+/// for self-hosted backends.
+pub fn from_config(
+    config: &ImagesConfig,
+    openai: &OpenAIConfig,
+    limits: &LimitsConfig,
+    client: reqwest::Client,
+    http: reqwest::Client,
+) -> anyhow::Result<Box<dyn ImageProvider>> {
+    let endpoint = |name| {
+        config
+            .endpoint
+            .clone()
+            .with_context(|| format!("{name} requires images.endpoint"))
+    };
+    Ok(match config.provider {
+        ImageBackend::OpenAI => {
+            let endpoint = match &config.endpoint {
+                Some(endpoint) => endpoint.clone(),
+                None => openai.url(Api::Images)?,
+            };
+            Box::new(OpenAIImages::new(config, endpoint, client))
+        }
+        ImageBackend::Automatic1111 => Box::new(Automatic1111::new(config, endpoint("AUTOMATIC1111")?, http)?),
+        ImageBackend::ComfyUi => {
+            let timeout = (limits.tool_timeout > 0).then(|| Duration::from_secs(limits.tool_timeout));
+            Box::new(ComfyUi::new(config, endpoint("ComfyUI")?, http, timeout)?)
+        }
+    })
+}
@@ -0,0 +1,87 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use url::Url;
+
+use crate::{
+    client::Identified,
+    images::{ImageOptions, ImageProvider, ImagesConfig, parse_size},
+};
+
+/// Strength of img2img edits, lower values stay closer to the original image.
+const DENOISING_STRENGTH: f64 = 0.6;
+
+#[derive(Deserialize)]
+struct GenerationResponse {
+    images: Vec<String>,
+}
+
+/// Stable Diffusion through the AUTOMATIC1111 web UI, started with `--api`.
+pub struct Automatic1111 {
+    http: reqwest::Client,
+    endpoint: Url,
+    steps: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Automatic1111 {
+    pub fn new(config: &ImagesConfig, endpoint: Url, http: reqwest::Client) -> anyhow::Result<Self> {
+        let (width, height) = config.dimensions()?;
+        Ok(Self {
+            http,
+            endpoint,
+            steps: config.steps,
+            width,
+            height,
+        })
+    }
+
+    async fn request(&self, path: &str, body: Value) -> anyhow::Result<Vec<u8>> {
+        let response: GenerationResponse = self
+            .http
+            .post(self.endpoint.join(path)?)
+            .json(&body)
+            .send_identified()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let image = response
+            .images
+            .into_iter()
+            .next()
+            .context("AUTOMATIC1111 returned no image")?;
+        Ok(BASE64_STANDARD.decode(image)?)
+    }
+}
+
+#[async_trait]
+impl ImageProvider for Automatic1111 {
+    async fn generate(&self, prompt: &str, options: &ImageOptions) -> anyhow::Result<Vec<u8>> {
+        let (width, height) = match &options.size {
+            Some(size) => parse_size(size)?,
+            None => (self.width, self.height),
+        };
+        let body = json!({
+            "prompt": prompt,
+            "steps": self.steps,
+            "width": width,
+            "height": height,
+        });
+        self.request("sdapi/v1/txt2img", body).await
+    }
+
+    async fn edit(&self, image: Vec<u8>, instruction: &str) -> anyhow::Result<Vec<u8>> {
+        let body = json!({
+            "init_images": [BASE64_STANDARD.encode(image)],
+            "prompt": instruction,
+            "steps": self.steps,
+            "denoising_strength": DENOISING_STRENGTH,
+        });
+        self.request("sdapi/v1/img2img", body).await
+    }
+}
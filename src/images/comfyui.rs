@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use url::Url;
+
+use crate::{
+    client::Identified,
+    images::{ImageOptions, ImageProvider, ImagesConfig},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long to wait for an image when tools may run without a time limit.
+const MAX_WAIT: Duration = Duration::from_secs(300);
+
+#[derive(Deserialize)]
+struct QueuedPrompt {
+    prompt_id: String,
+}
+
+#[derive(Deserialize)]
+struct OutputImage {
+    filename: String,
+    #[serde(default)]
+    subfolder: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// A ComfyUI server running a workflow exported in API format. The first image output of the workflow
+/// is returned.
+pub struct ComfyUi {
+    http: reqwest::Client,
+    endpoint: Url,
+    workflow: String,
+    /// Polls of the history before giving up on a prompt.
+    max_polls: u32,
+}
+
+impl ComfyUi {
+    /// `timeout` is the time tools may run, so the image is given up on around when the tool would be cancelled.
+    pub fn new(
+        config: &ImagesConfig,
+        endpoint: Url,
+        http: reqwest::Client,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Self> {
+        let path = config.workflow.as_ref().context("ComfyUI requires a workflow file")?;
+        let workflow = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read ComfyUI workflow {}", path.display()))?;
+        validate(&workflow).with_context(|| format!("Invalid ComfyUI workflow {}", path.display()))?;
+
+        let wait = timeout.unwrap_or(MAX_WAIT);
+        Ok(Self {
+            http,
+            endpoint,
+            workflow,
+            max_polls: (wait.as_secs_f64() / POLL_INTERVAL.as_secs_f64()).ceil().max(1.0) as u32,
+        })
+    }
+
+    async fn wait_for_image(&self, prompt_id: &str) -> anyhow::Result<OutputImage> {
+        for _ in 0..self.max_polls {
+            let history: Value = self
+                .http
+                .get(self.endpoint.join(&format!("history/{prompt_id}"))?)
+                .send_identified()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            let image = history
+                .get(prompt_id)
+                .and_then(|entry| entry.get("outputs"))
+                .and_then(Value::as_object)
+                .into_iter()
+                .flat_map(|outputs| outputs.values())
+                .filter_map(|output| output.get("images")?.as_array()?.first())
+                .next();
+            if let Some(image) = image {
+                return Ok(serde_json::from_value(image.clone())?);
+            }
+
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+
+        Err(anyhow::anyhow!("ComfyUI didn't finish prompt {prompt_id} in time"))
+    }
+}
+
+/// Check the workflow is in API format and has a place for the prompt, so mistakes show at startup rather than
+/// on the first `!image`.
+fn validate(workflow: &str) -> anyhow::Result<()> {
+    if !workflow.contains("\"{prompt}\"") {
+        return Err(anyhow::anyhow!(
+            "The workflow has no \"{{prompt}}\" input for the prompt text"
+        ));
+    }
+    let workflow: Value = serde_json::from_str(&workflow.replace("\"{prompt}\"", "\"\""))?;
+    let nodes = workflow.as_object().context("The workflow isn't a JSON object")?;
+    if nodes.contains_key("nodes") || nodes.values().any(|node| node.get("class_type").is_none()) {
+        return Err(anyhow::anyhow!(
+            "The workflow isn't in API format, export it with \"Save (API Format)\" in ComfyUI"
+        ));
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl ImageProvider for ComfyUi {
+    async fn generate(&self, prompt: &str, _options: &ImageOptions) -> anyhow::Result<Vec<u8>> {
+        // Substitute the prompt as a JSON string, so quotes and newlines can't break the workflow.
+        let escaped = serde_json::to_string(prompt)?;
+        let workflow: Value = serde_json::from_str(&self.workflow.replace("\"{prompt}\"", &escaped))?;
+
+        let queued: QueuedPrompt = self
+            .http
+            .post(self.endpoint.join("prompt")?)
+            .json(&json!({ "prompt": workflow }))
+            .send_identified()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let image = self.wait_for_image(&queued.prompt_id).await?;
+        let data = self
+            .http
+            .get(self.endpoint.join("view")?)
+            .query(&[
+                ("filename", image.filename.as_str()),
+                ("subfolder", image.subfolder.as_str()),
+                ("type", image.kind.as_str()),
+            ])
+            .send_identified()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+
+        Ok(data.to_vec())
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
+use serde_json::json;
+use url::Url;
+
+use crate::{
+    client::Identified,
+    images::{ImageOptions, ImageProvider, ImagesConfig},
+    media,
+};
+
+#[derive(Deserialize)]
+struct ImagesResponse {
+    data: Vec<ImageData>,
+}
+
+#[derive(Deserialize)]
+struct ImageData {
+    b64_json: String,
+}
+
+impl ImagesResponse {
+    fn into_image(self) -> anyhow::Result<Vec<u8>> {
+        let image = self.data.into_iter().next().context("Images API returned no image")?;
+        Ok(BASE64_STANDARD.decode(image.b64_json)?)
+    }
+}
+
+/// OpenAI compatible images API, using `generations`, `edits` and `variations` below the endpoint.
+pub struct OpenAIImages {
+    client: reqwest::Client,
+    endpoint: Url,
+    model: String,
+    size: String,
+    quality: Option<String>,
+}
+
+impl OpenAIImages {
+    pub fn new(config: &ImagesConfig, endpoint: Url, client: reqwest::Client) -> Self {
+        Self {
+            client,
+            endpoint,
+            model: config.model.clone(),
+            size: config.size.clone(),
+            quality: config.quality.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl ImageProvider for OpenAIImages {
+    async fn generate(&self, prompt: &str, options: &ImageOptions) -> anyhow::Result<Vec<u8>> {
+        let mut body = json!({
+            "model": self.model,
+            "prompt": prompt,
+            "size": options.size.as_ref().unwrap_or(&self.size),
+            "response_format": "b64_json",
+        });
+        if let Some(quality) = options.quality.as_ref().or(self.quality.as_ref()) {
+            body["quality"] = json!(quality);
+        }
+
+        let response: ImagesResponse = self
+            .client
+            .post(self.endpoint.join("generations")?)
+            .json(&body)
+            .send_identified()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        response.into_image()
+    }
+
+    async fn edit(&self, image: Vec<u8>, instruction: &str) -> anyhow::Result<Vec<u8>> {
+        let (mimetype, extension) = media::image_type(&image);
+        let image = Part::bytes(image)
+            .file_name(format!("image.{extension}"))
+            .mime_str(mimetype)?;
+        let mut form = Form::new()
+            .text("model", self.model.clone())
+            .text("size", self.size.clone())
+            .text("response_format", "b64_json")
+            .part("image", image);
+
+        let path = match instruction.trim() {
+            "" => "variations",
+            instruction => {
+                form = form.text("prompt", instruction.to_string());
+                "edits"
+            }
+        };
+
+        let response: ImagesResponse = self
+            .client
+            .post(self.endpoint.join(path)?)
+            .multipart(form)
+            .send_identified()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        response.into_image()
+    }
+}
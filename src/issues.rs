@@ -0,0 +1,281 @@
+use std::sync::LazyLock;
+
+use anyhow::{Context, ensure};
+use regex::Regex;
+use reqwest::{RequestBuilder, StatusCode};
+use serde::Deserialize;
+use serde_json::Value;
+use url::Url;
+
+use crate::client::Identified;
+
+/// Number of matches `search_issues` returns.
+const SEARCH_RESULTS: usize = 10;
+
+static GITHUB_REPOSITORY: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[\w.-]+/[\w.-]+$").expect("valid regex"));
+/// GitLab projects can be nested in subgroups, as in `group/subgroup/project`.
+static GITLAB_PROJECT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[\w.-]+(/[\w.-]+)+$").expect("valid regex"));
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct IssuesConfig {
+    pub github: ForgeConfig,
+    pub gitlab: ForgeConfig,
+    /// Repositories the tools may read, written as for fetch_issue, e.g. `owner/name` or `gitlab:group/project`.
+    /// Empty allows every repository the tokens can see.
+    pub repositories: Vec<String>,
+}
+
+impl Default for IssuesConfig {
+    fn default() -> Self {
+        Self {
+            github: ForgeConfig {
+                endpoint: Url::parse("https://api.github.com/").expect("valid URL"),
+                token: None,
+            },
+            gitlab: ForgeConfig {
+                endpoint: Url::parse("https://gitlab.com/api/v4/").expect("valid URL"),
+                token: None,
+            },
+            repositories: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ForgeConfig {
+    /// API base URL, for self-hosted instances.
+    pub endpoint: Url,
+    /// API token, needed for private repositories and higher rate limits.
+    pub token: Option<String>,
+}
+
+/// An issue, pull request or merge request, as far as the model needs to know.
+#[derive(Debug, Clone)]
+pub struct Issue {
+    pub number: u64,
+    pub kind: &'static str,
+    pub title: String,
+    pub state: String,
+    pub url: String,
+    pub author: String,
+    pub labels: Vec<String>,
+    pub body: String,
+}
+
+impl Issue {
+    pub fn summary(&self) -> String {
+        format!("#{} ({}, {}) {}", self.number, self.kind, self.state, self.title)
+    }
+
+    pub fn describe(&self) -> String {
+        let mut lines = vec![self.summary(), format!("Author: {}", self.author)];
+        if !self.labels.is_empty() {
+            lines.push(format!("Labels: {}", self.labels.join(", ")));
+        }
+        lines.push(String::new());
+        lines.push(self.body.clone());
+        lines.join("\n")
+    }
+
+    fn from_github(value: &Value) -> anyhow::Result<Self> {
+        Ok(Self {
+            number: value["number"].as_u64().context("Issue without number")?,
+            kind: match value.get("pull_request") {
+                Some(_) => "pull request",
+                None => "issue",
+            },
+            title: string(&value["title"]),
+            state: string(&value["state"]),
+            url: string(&value["html_url"]),
+            author: string(&value["user"]["login"]),
+            labels: strings(&value["labels"], |label| label["name"].as_str()),
+            body: string(&value["body"]),
+        })
+    }
+
+    fn from_gitlab(value: &Value, kind: &'static str) -> anyhow::Result<Self> {
+        Ok(Self {
+            number: value["iid"].as_u64().context("Issue without number")?,
+            kind,
+            title: string(&value["title"]),
+            state: string(&value["state"]),
+            url: string(&value["web_url"]),
+            author: string(&value["author"]["username"]),
+            labels: strings(&value["labels"], Value::as_str),
+            body: string(&value["description"]),
+        })
+    }
+}
+
+fn string(value: &Value) -> String {
+    value.as_str().unwrap_or_default().to_string()
+}
+
+fn strings(value: &Value, name: impl Fn(&Value) -> Option<&str>) -> Vec<String> {
+    value
+        .as_array()
+        .map(|items| items.iter().filter_map(&name).map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Repository on a forge. GitHub repositories are written `owner/name`, GitLab projects `gitlab:group/project`.
+enum Repository<'a> {
+    GitHub(&'a str),
+    GitLab(&'a str),
+}
+
+impl<'a> Repository<'a> {
+    fn parse(repo: &'a str) -> anyhow::Result<Self> {
+        let repo = repo.trim().trim_matches('/');
+        let repository = match repo.strip_prefix("gitlab:") {
+            Some(project) => Repository::GitLab(project),
+            None => Repository::GitHub(repo.strip_prefix("github:").unwrap_or(repo)),
+        };
+        let (pattern, path) = match &repository {
+            Repository::GitHub(path) => (&GITHUB_REPOSITORY, path),
+            Repository::GitLab(path) => (&GITLAB_PROJECT, path),
+        };
+        // Dots are allowed in names, but a segment of only dots would walk up the API path.
+        ensure!(
+            pattern.is_match(path) && path.split('/').all(|segment| !segment.trim_matches('.').is_empty()),
+            "Expected a repository like owner/name, got '{repo}'"
+        );
+
+        Ok(repository)
+    }
+
+    /// The repository as written in `issues.repositories`.
+    fn name(&self) -> String {
+        match self {
+            Repository::GitHub(repo) => repo.to_string(),
+            Repository::GitLab(project) => format!("gitlab:{project}"),
+        }
+    }
+}
+
+pub struct Forges<'a> {
+    http: &'a reqwest::Client,
+    config: &'a IssuesConfig,
+}
+
+impl<'a> Forges<'a> {
+    pub fn new(http: &'a reqwest::Client, config: &'a IssuesConfig) -> Self {
+        Self { http, config }
+    }
+
+    /// Parse the repository and check that it's allowed.
+    fn repository<'r>(&self, repo: &'r str) -> anyhow::Result<Repository<'r>> {
+        let repository = Repository::parse(repo)?;
+        let name = repository.name();
+        ensure!(
+            self.config.repositories.is_empty()
+                || self.config.repositories.iter().any(|allowed| {
+                    let allowed = allowed.trim().trim_matches('/');
+                    allowed
+                        .strip_prefix("github:")
+                        .unwrap_or(allowed)
+                        .eq_ignore_ascii_case(&name)
+                }),
+            "Repository '{name}' is not in the list of allowed repositories"
+        );
+        Ok(repository)
+    }
+
+    pub async fn fetch(&self, repo: &str, number: u64) -> anyhow::Result<Issue> {
+        match self.repository(repo)? {
+            Repository::GitHub(repo) => {
+                let response = self
+                    .github(&format!("repos/{repo}/issues/{number}"))?
+                    .send_identified()
+                    .await?;
+                Issue::from_github(&response.error_for_status()?.json().await?)
+            }
+            Repository::GitLab(project) => {
+                let project = urlencode(project);
+                let response = self
+                    .gitlab(&format!("projects/{project}/issues/{number}"))?
+                    .send_identified()
+                    .await?;
+                if response.status() != StatusCode::NOT_FOUND {
+                    return Issue::from_gitlab(&response.error_for_status()?.json().await?, "issue");
+                }
+
+                // Issues and merge requests are numbered separately on GitLab.
+                let response = self
+                    .gitlab(&format!("projects/{project}/merge_requests/{number}"))?
+                    .send_identified()
+                    .await?;
+                Issue::from_gitlab(&response.error_for_status()?.json().await?, "merge request")
+            }
+        }
+    }
+
+    pub async fn search(&self, repo: &str, query: &str) -> anyhow::Result<Vec<Issue>> {
+        match self.repository(repo)? {
+            Repository::GitHub(repo) => {
+                let response: Value = self
+                    .github("search/issues")?
+                    .query(&[
+                        ("q", format!("{query} repo:{repo}")),
+                        ("per_page", SEARCH_RESULTS.to_string()),
+                    ])
+                    .send_identified()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                response["items"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(Issue::from_github)
+                    .collect()
+            }
+            Repository::GitLab(project) => {
+                let response: Value = self
+                    .gitlab(&format!("projects/{}/issues", urlencode(project)))?
+                    .query(&[("search", query.to_string()), ("per_page", SEARCH_RESULTS.to_string())])
+                    .send_identified()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                response
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|issue| Issue::from_gitlab(issue, "issue"))
+                    .collect()
+            }
+        }
+    }
+
+    fn github(&self, path: &str) -> anyhow::Result<RequestBuilder> {
+        let forge = &self.config.github;
+        let request = self
+            .http
+            .get(forge.endpoint.join(path)?)
+            .header("Accept", "application/vnd.github+json")
+            // GitHub rejects requests without a user agent.
+            .header("User-Agent", "matrix-openai-bot");
+        Ok(match &forge.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        })
+    }
+
+    fn gitlab(&self, path: &str) -> anyhow::Result<RequestBuilder> {
+        let forge = &self.config.gitlab;
+        let request = self.http.get(forge.endpoint.join(path)?);
+        Ok(match &forge.token {
+            Some(token) => request.header("PRIVATE-TOKEN", token),
+            None => request,
+        })
+    }
+}
+
+/// GitLab addresses projects by their URL-encoded path.
+fn urlencode(project: &str) -> String {
+    project.replace('%', "%25").replace('/', "%2F")
+}
@@ -0,0 +1,213 @@
+use std::path::PathBuf;
+
+use anyhow::ensure;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct KubernetesConfig {
+    /// Path to the kubeconfig used to reach the cluster. The Kubernetes tools are only offered when this is set.
+    /// Requires the `kubernetes` feature.
+    pub kubeconfig: Option<PathBuf>,
+    /// Context in the kubeconfig to use, instead of its current context.
+    pub context: Option<String>,
+    /// Namespaces the tools may read from.
+    pub namespaces: Vec<String>,
+}
+
+impl KubernetesConfig {
+    fn allow(&self, namespace: &str) -> anyhow::Result<()> {
+        ensure!(
+            self.namespaces.iter().any(|allowed| allowed == namespace),
+            "Namespace {namespace} is not available. Available namespaces: {}",
+            self.namespaces.join(", ")
+        );
+        Ok(())
+    }
+}
+
+/// Pods in a namespace with their phase, readiness and restart count.
+pub async fn list_pods(config: &KubernetesConfig, namespace: &str) -> anyhow::Result<String> {
+    config.allow(namespace)?;
+    imp::list_pods(config, namespace).await
+}
+
+/// Recent events in a namespace, optionally only those about one object.
+pub async fn events(config: &KubernetesConfig, namespace: &str, object: Option<&str>) -> anyhow::Result<String> {
+    config.allow(namespace)?;
+    imp::events(config, namespace, object).await
+}
+
+/// Replica counts, conditions and containers of a deployment.
+pub async fn describe_deployment(config: &KubernetesConfig, namespace: &str, name: &str) -> anyhow::Result<String> {
+    config.allow(namespace)?;
+    imp::describe_deployment(config, namespace, name).await
+}
+
+#[cfg(feature = "kubernetes")]
+mod imp {
+    use anyhow::Context;
+    use k8s_openapi::api::{
+        apps::v1::Deployment,
+        core::v1::{Event, Pod},
+    };
+    use kube::{
+        Api, Client,
+        api::ListParams,
+        config::{KubeConfigOptions, Kubeconfig},
+    };
+
+    use super::KubernetesConfig;
+
+    /// Number of events `events` returns, newest last.
+    const MAX_EVENTS: usize = 50;
+
+    async fn client(config: &KubernetesConfig) -> anyhow::Result<Client> {
+        let path = config.kubeconfig.as_ref().context("Kubernetes is not configured")?;
+        let options = KubeConfigOptions {
+            context: config.context.clone(),
+            ..Default::default()
+        };
+        let kubeconfig = kube::Config::from_custom_kubeconfig(Kubeconfig::read_from(path)?, &options).await?;
+        Ok(Client::try_from(kubeconfig)?)
+    }
+
+    pub async fn list_pods(config: &KubernetesConfig, namespace: &str) -> anyhow::Result<String> {
+        let pods: Api<Pod> = Api::namespaced(client(config).await?, namespace);
+        let lines = pods
+            .list(&ListParams::default())
+            .await?
+            .items
+            .iter()
+            .map(|pod| {
+                let name = pod.metadata.name.as_deref().unwrap_or_default();
+                let status = pod.status.as_ref();
+                let phase = status.and_then(|status| status.phase.as_deref()).unwrap_or("Unknown");
+                let containers = status
+                    .and_then(|status| status.container_statuses.as_ref())
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                let ready = containers.iter().filter(|container| container.ready).count();
+                let restarts: i32 = containers.iter().map(|container| container.restart_count).sum();
+                // A waiting reason such as CrashLoopBackOff says more than the phase.
+                let reason = containers
+                    .iter()
+                    .find_map(|container| container.state.as_ref()?.waiting.as_ref()?.reason.clone())
+                    .map(|reason| format!(", {reason}"))
+                    .unwrap_or_default();
+                format!(
+                    "{name}: {phase}{reason}, {ready}/{} ready, {restarts} restarts",
+                    containers.len()
+                )
+            })
+            .collect::<Vec<_>>();
+
+        Ok(match lines.is_empty() {
+            true => format!("No pods in {namespace}."),
+            false => lines.join("\n"),
+        })
+    }
+
+    pub async fn events(config: &KubernetesConfig, namespace: &str, object: Option<&str>) -> anyhow::Result<String> {
+        let events: Api<Event> = Api::namespaced(client(config).await?, namespace);
+        let params = match object {
+            Some(object) => ListParams::default().fields(&format!("involvedObject.name={object}")),
+            None => ListParams::default(),
+        };
+        let mut items = events.list(&params).await?.items;
+        items.sort_by_key(|event| event.last_timestamp.clone().map(|time| time.0));
+
+        let lines = items
+            .iter()
+            .rev()
+            .take(MAX_EVENTS)
+            .rev()
+            .map(|event| {
+                let time = event
+                    .last_timestamp
+                    .as_ref()
+                    .map(|time| time.0.to_string())
+                    .unwrap_or_default();
+                let object = &event.involved_object;
+                format!(
+                    "{time} {} {}/{}: {} {}",
+                    event.type_.as_deref().unwrap_or_default(),
+                    object.kind.as_deref().unwrap_or_default(),
+                    object.name.as_deref().unwrap_or_default(),
+                    event.reason.as_deref().unwrap_or_default(),
+                    event.message.as_deref().unwrap_or_default(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        Ok(match lines.is_empty() {
+            true => format!("No recent events in {namespace}."),
+            false => lines.join("\n"),
+        })
+    }
+
+    pub async fn describe_deployment(config: &KubernetesConfig, namespace: &str, name: &str) -> anyhow::Result<String> {
+        let deployments: Api<Deployment> = Api::namespaced(client(config).await?, namespace);
+        let deployment = deployments.get(name).await?;
+
+        let mut lines = vec![format!("Deployment {namespace}/{name}")];
+        if let Some(spec) = &deployment.spec {
+            lines.push(format!("Desired replicas: {}", spec.replicas.unwrap_or(1)));
+            if let Some(pod_spec) = &spec.template.spec {
+                for container in &pod_spec.containers {
+                    lines.push(format!(
+                        "Container {}: {}",
+                        container.name,
+                        container.image.as_deref().unwrap_or_default()
+                    ));
+                }
+            }
+        }
+        if let Some(status) = &deployment.status {
+            lines.push(format!(
+                "Replicas: {} ready, {} updated, {} available, {} unavailable",
+                status.ready_replicas.unwrap_or_default(),
+                status.updated_replicas.unwrap_or_default(),
+                status.available_replicas.unwrap_or_default(),
+                status.unavailable_replicas.unwrap_or_default(),
+            ));
+            for condition in status.conditions.iter().flatten() {
+                lines.push(format!(
+                    "Condition {}={}: {}",
+                    condition.type_,
+                    condition.status,
+                    condition.message.as_deref().unwrap_or_default()
+                ));
+            }
+        }
+
+        Ok(lines.join("\n"))
+    }
+}
+
+#[cfg(not(feature = "kubernetes"))]
+mod imp {
+    use super::KubernetesConfig;
+
+    fn unavailable() -> anyhow::Result<String> {
+        Err(anyhow::anyhow!(
+            "Kubernetes tools require building with the 'kubernetes' feature"
+        ))
+    }
+
+    pub async fn list_pods(_config: &KubernetesConfig, _namespace: &str) -> anyhow::Result<String> {
+        unavailable()
+    }
+
+    pub async fn events(_config: &KubernetesConfig, _namespace: &str, _object: Option<&str>) -> anyhow::Result<String> {
+        unavailable()
+    }
+
+    pub async fn describe_deployment(
+        _config: &KubernetesConfig,
+        _namespace: &str,
+        _name: &str,
+    ) -> anyhow::Result<String> {
+        unavailable()
+    }
+}
@@ -0,0 +1,200 @@
+//! OpenAI-Matrix bridging logic of the bot, usable as a library.
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! let bot = matrix_openai_bot::BotBuilder::new()
+//!     .configuration_file("config.yaml")
+//!     .build()
+//!     .await?;
+//!
+//! bot.run().await
+//! # }
+//! ```
+
+use std::sync::Arc;
+
+use matrix_appservice::{ApplicationService, ApplicationServiceBuilder, State};
+
+use crate::{
+    config::Config,
+    openai::{ConversationStore, CustomTool, ToolRegistry},
+};
+
+pub mod admin;
+pub mod alt_text;
+pub mod api_log;
+pub mod archive;
+pub mod calendar;
+pub mod catch_up;
+pub mod citations;
+pub mod client;
+pub mod cluster;
+pub mod command;
+pub mod config;
+pub mod consent;
+pub mod convert;
+pub mod debate;
+pub mod dice;
+pub mod directives;
+pub mod email;
+pub mod filter;
+pub mod handlers;
+pub mod home_assistant;
+pub mod homeserver;
+pub mod i18n;
+pub mod images;
+pub mod injection;
+pub mod issues;
+pub mod kubernetes;
+pub mod limiter;
+pub mod media;
+pub mod memory;
+pub mod menu;
+pub mod metrics;
+pub mod moderation;
+pub mod onboarding;
+pub mod openai;
+pub mod output_filter;
+pub mod participants;
+pub mod paste;
+pub mod pii;
+pub mod prometheus;
+pub mod prompt;
+pub mod puppets;
+pub mod questions;
+pub mod queue;
+pub mod recap;
+pub mod relation;
+pub mod report;
+pub mod retry;
+pub mod review;
+pub mod room_details;
+pub mod scheduler;
+pub mod self_test;
+pub mod server;
+pub mod settings;
+pub mod shell;
+pub mod speech;
+pub mod sql;
+pub mod store;
+pub mod style;
+pub mod usage;
+pub mod version;
+pub mod webhooks;
+
+pub struct BotBuilder {
+    config_path: String,
+    tools: ToolRegistry,
+    default_handlers: bool,
+}
+
+impl Default for BotBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BotBuilder {
+    pub fn new() -> Self {
+        Self {
+            config_path: "config.yaml".to_string(),
+            tools: ToolRegistry::default(),
+            default_handlers: true,
+        }
+    }
+
+    pub fn configuration_file(mut self, path: impl Into<String>) -> Self {
+        self.config_path = path.into();
+        self
+    }
+
+    /// Offer an additional tool to the model.
+    pub fn tool(mut self, tool: impl CustomTool + 'static) -> Self {
+        self.tools.register(Arc::new(tool));
+        self
+    }
+
+    /// Whether to register the built-in invite and message handlers. Disable to supply your own
+    /// through [`Bot::appservice`].
+    pub fn default_handlers(mut self, enabled: bool) -> Self {
+        self.default_handlers = enabled;
+        self
+    }
+
+    pub async fn build(self) -> anyhow::Result<Bot> {
+        let appservice = ApplicationServiceBuilder::new()
+            .configuration_file(&self.config_path)
+            .build()
+            .await?;
+
+        let config = appservice.get_user_fields::<Config>()?;
+        let state = ConversationStore::new(&config, self.tools).await?;
+        let appservice = appservice.with_state(state);
+
+        if self.default_handlers {
+            appservice.add_event_handler(handlers::on_room_member).await?;
+            appservice.add_event_handler(handlers::on_room_message).await?;
+            appservice.add_event_handler(handlers::on_reaction).await?;
+            appservice.add_event_handler(handlers::on_room_tombstone).await?;
+        }
+
+        Ok(Bot { appservice })
+    }
+}
+
+pub struct Bot {
+    appservice: ApplicationService<State<Arc<ConversationStore>>>,
+}
+
+impl Bot {
+    /// The underlying application service, for registering additional event handlers.
+    pub fn appservice(&self) -> &ApplicationService<State<Arc<ConversationStore>>> {
+        &self.appservice
+    }
+
+    pub async fn run(self) -> anyhow::Result<()> {
+        tokio::spawn(scheduler::run(self.appservice.clone()));
+        if self.appservice.state().config().api_log.room.is_some() {
+            let appservice = self.appservice.clone();
+            tokio::spawn(async move {
+                if let Err(error) = api_log::post(appservice).await {
+                    tracing::warn!("Posting the API log stopped: {error}");
+                }
+            });
+        }
+
+        let config = self.appservice.state().config();
+        if config.self_test.enabled {
+            let appservice = self.appservice.clone();
+            tokio::spawn(async move {
+                if let Err(error) = self_test::run(appservice).await {
+                    tracing::warn!("Posting the self-test results failed: {error}");
+                }
+            });
+        }
+        if config.updates.check {
+            let appservice = self.appservice.clone();
+            tokio::spawn(async move {
+                if let Err(error) = version::check(appservice).await {
+                    tracing::warn!("Checking for updates failed: {error}");
+                }
+            });
+        }
+
+        if let Some(listen) = config.http.listen {
+            let appservice = self.appservice.clone();
+            tokio::spawn(async move {
+                if let Err(error) = server::serve(appservice, listen).await {
+                    tracing::error!("HTTP server stopped: {error}");
+                }
+            });
+        }
+
+        if let Err(error) = self.appservice.run().await {
+            tracing::error!("Application service encountered an fatal error // {}", error);
+            return Err(error.into());
+        }
+
+        Ok(())
+    }
+}
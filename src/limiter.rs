@@ -0,0 +1,176 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use tokio::sync::{Semaphore, SemaphorePermit, oneshot};
+
+use crate::{metrics::Metrics, openai::Usage};
+
+/// Finish reason of a completion cut short because the prompt ran out of budget.
+pub const BUDGET_EXCEEDED: &str = "budget_exceeded";
+/// Finish reason of a completion given up on because its tool calls kept failing.
+pub const TOOL_FAILURES: &str = "tool_failures";
+/// Weight of the latest request in the running average of how long requests hold a slot.
+const RECENT_WEIGHT: f64 = 0.2;
+
+tokio::task_local! {
+    /// Signalled once work run through `Limiter::started` first gets a slot.
+    static STARTED: Mutex<Option<oneshot::Sender<()>>>;
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Maximum number of model requests in flight at once, across all rooms.
+    pub model_requests: usize,
+    /// Maximum number of tools running at once, across all rooms.
+    pub tool_runs: usize,
+    /// Approximate number of tokens of a tool result handed to the model at once. Longer results are
+    /// truncated, and the model can page through the rest.
+    pub tool_result_tokens: usize,
+    /// Maximum number of tool calls for a single prompt. 0 means no limit.
+    pub max_tool_calls: usize,
+    /// Seconds a tool may run before it's cancelled and the model is told it timed out. 0 means no limit.
+    pub tool_timeout: u64,
+    /// Failed tool calls in a row after which a prompt is given up on. 0 means no limit.
+    pub max_tool_failures: usize,
+    /// Maximum estimated cost in dollars of answering a single prompt, including all tool rounds.
+    pub max_cost: Option<f64>,
+    /// Dollars per million prompt tokens, to estimate cost.
+    pub prompt_price: f64,
+    /// Dollars per million completion tokens, to estimate cost.
+    pub completion_price: f64,
+    /// Tell people their prompt is queued, with the expected wait, when all model requests are in use.
+    pub queue_notices: bool,
+    /// Days the usage of a user in a room on a day is kept for usage reports. Rooms with a shorter retention
+    /// period keep it for that long instead.
+    pub usage_days: u32,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            model_requests: 8,
+            tool_runs: 8,
+            tool_result_tokens: 5000,
+            max_tool_calls: 10,
+            tool_timeout: 60,
+            max_tool_failures: 3,
+            max_cost: None,
+            prompt_price: 0.0,
+            completion_price: 0.0,
+            queue_notices: true,
+            usage_days: 400,
+        }
+    }
+}
+
+impl LimitsConfig {
+    /// Estimated cost in dollars of the given token usage.
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.prompt_tokens as f64 * self.prompt_price + usage.completion_tokens as f64 * self.completion_price)
+            / 1_000_000.0
+    }
+
+    /// Why a prompt may not go on after making `tool_calls` tool calls and using `usage`, if it may not.
+    pub fn exceeded(&self, tool_calls: usize, usage: &Usage) -> Option<String> {
+        if self.max_tool_calls > 0 && tool_calls > self.max_tool_calls {
+            return Some(format!("it needed more than {} tool calls", self.max_tool_calls));
+        }
+        match self.max_cost {
+            Some(max_cost) if self.cost(usage) > max_cost => Some(format!(
+                "it was about to cost more than ${max_cost:.2} (${:.2} so far)",
+                self.cost(usage)
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Global concurrency limit, so a burst of events (e.g. a large transaction after downtime)
+/// queues up instead of firing off hundreds of requests at once.
+pub struct Limiter {
+    kind: &'static str,
+    permits: usize,
+    semaphore: Semaphore,
+    metrics: Arc<Metrics>,
+    /// Running average of how long recent requests held a slot.
+    recent: Mutex<Option<Duration>>,
+}
+
+pub struct Permit<'a> {
+    limiter: &'a Limiter,
+    acquired: Instant,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl Limiter {
+    pub fn new(kind: &'static str, permits: usize, metrics: Arc<Metrics>) -> Self {
+        Self {
+            kind,
+            permits: permits.max(1),
+            semaphore: Semaphore::new(permits.max(1)),
+            metrics,
+            recent: Mutex::new(None),
+        }
+    }
+
+    /// Run `work`, signalling `started` once it first gets a slot of any limiter.
+    pub async fn started<F: Future>(started: oneshot::Sender<()>, work: F) -> F::Output {
+        STARTED.scope(Mutex::new(Some(started)), work).await
+    }
+
+    pub async fn acquire(&self) -> anyhow::Result<Permit<'_>> {
+        let labels = [("kind", self.kind)];
+        self.metrics.add("openai_bot_queue_depth", &labels, 1.0);
+        let permit = self.semaphore.acquire().await;
+        self.metrics.add("openai_bot_queue_depth", &labels, -1.0);
+        let permit = permit?;
+
+        self.metrics.add("openai_bot_in_flight", &labels, 1.0);
+        let _ = STARTED.try_with(|started| {
+            if let Some(started) = started.lock().expect("started lock poisoned").take() {
+                let _ = started.send(());
+            }
+        });
+        Ok(Permit {
+            limiter: self,
+            acquired: Instant::now(),
+            _permit: permit,
+        })
+    }
+
+    pub fn queue_depth(&self) -> usize {
+        self.metrics.get("openai_bot_queue_depth", &[("kind", self.kind)]) as usize
+    }
+
+    /// Whether all slots are in use, so new work has to wait.
+    pub fn saturated(&self) -> bool {
+        self.semaphore.available_permits() == 0
+    }
+
+    /// Expected wait for a slot, from the queue ahead and how long recent requests held one. Unknown until a request
+    /// has finished.
+    pub fn estimated_wait(&self) -> Option<Duration> {
+        let recent = (*self.recent.lock().expect("recent lock poisoned"))?;
+        let rounds = self.queue_depth() / self.permits + 1;
+        Some(recent * rounds as u32)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.limiter
+            .metrics
+            .add("openai_bot_in_flight", &[("kind", self.limiter.kind)], -1.0);
+
+        let held = self.acquired.elapsed();
+        let mut recent = self.limiter.recent.lock().expect("recent lock poisoned");
+        *recent = Some(match *recent {
+            Some(recent) => recent.mul_f64(1.0 - RECENT_WEIGHT) + held.mul_f64(RECENT_WEIGHT),
+            None => held,
+        });
+    }
+}
@@ -1,23 +1,12 @@
-use std::sync::Arc;
-
-use anyhow::Context;
+use chrono::NaiveDate;
 use clap::{Parser, Subcommand};
-use matrix_appservice::{
-    ApplicationService, ApplicationServiceBuilder, EventContext, State,
-    exports::matrix_sdk::ruma::events::room::{
-        member::{MembershipChange, StrippedRoomMemberEvent},
-        message::{OriginalSyncRoomMessageEvent, RoomMessageEventContent},
-    },
-};
-
-use crate::{
-    command::Command,
-    openai::{Config, ConversationStore},
+use matrix_appservice::ApplicationServiceBuilder;
+use matrix_openai_bot::{
+    BotBuilder,
+    config::Config,
+    report::{self, ReportFormat},
 };
 
-mod command;
-mod openai;
-
 #[derive(Debug, Parser)]
 #[command(name = "matrix-openai-bot", version, about)]
 struct Cli {
@@ -37,7 +26,7 @@ enum CliCommand {
             default_value = "config.yaml",
             help = "Path to the appservice configuration YAML file."
         )]
-        config: String,    
+        config: String,
     },
     /// Generate the YAML registration file for Synapse
     Generate {
@@ -49,7 +38,28 @@ enum CliCommand {
             default_value = "config.yaml",
             help = "Path to the appservice configuration YAML file."
         )]
-        config: String,    
+        config: String,
+        /// Output file, or "-" for stdout
+        #[arg(short, long, default_value = "-")]
+        output: String,
+    },
+    /// Report token usage and estimated cost per user and room
+    Report {
+        /// Configuration file path
+        #[arg(
+            short,
+            long,
+            env = "APPSERVICE_CONFIG_PATH",
+            default_value = "config.yaml",
+            help = "Path to the appservice configuration YAML file."
+        )]
+        config: String,
+        /// First day to include, e.g. 2024-01-01
+        #[arg(long)]
+        since: Option<NaiveDate>,
+        /// Output format
+        #[arg(short, long, value_enum, default_value = "csv")]
+        format: ReportFormat,
         /// Output file, or "-" for stdout
         #[arg(short, long, default_value = "-")]
         output: String,
@@ -64,28 +74,18 @@ async fn main() -> anyhow::Result<()> {
     match cli.command {
         CliCommand::Run { config } => run(&config).await,
         CliCommand::Generate { config, output } => generate(&config, &output).await,
+        CliCommand::Report {
+            config,
+            since,
+            format,
+            output,
+        } => usage_report(&config, since, format, &output).await,
     }
 }
 
 async fn run(config_path: &str) -> anyhow::Result<()> {
-    let appservice = ApplicationServiceBuilder::new()
-        .configuration_file(config_path)
-        .build()
-        .await?;
-
-    let config = appservice.get_user_fields::<Config>()?;
-    let state = ConversationStore::new(&config.openai)?;
-    let appservice = appservice.with_state(state);
-
-    appservice.add_event_handler(on_room_member).await?;
-    appservice.add_event_handler(on_room_message).await?;
-
-    if let Err(error) = appservice.run().await {
-        tracing::error!("Application service encountered an fatal error // {}", error);
-        return Err(error.into());
-    }
-
-    Ok(())
+    let bot = BotBuilder::new().configuration_file(config_path).build().await?;
+    bot.run().await
 }
 
 async fn generate(config_path: &str, output: &str) -> anyhow::Result<()> {
@@ -106,76 +106,27 @@ async fn generate(config_path: &str, output: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn on_room_member(
-    event: StrippedRoomMemberEvent,
-    appservice: ApplicationService<State<Arc<ConversationStore>>>,
-    context: EventContext,
+async fn usage_report(
+    config_path: &str,
+    since: Option<NaiveDate>,
+    format: ReportFormat,
+    output: &str,
 ) -> anyhow::Result<()> {
-    let user = appservice.get_bot().await?;
-    if event.state_key != user.id() {
-        return Ok(());
-    }
-
-    // Auto-join on room invite
-    match event.membership_change(None) {
-        MembershipChange::Invited => user.join_room(&context.room_id).await?,
-        _ => (),
-    };
-
-    Ok(())
-}
-
-async fn on_room_message(
-    event: OriginalSyncRoomMessageEvent,
-    appservice: ApplicationService<State<Arc<ConversationStore>>>,
-    context: EventContext,
-) -> anyhow::Result<()> {
-    let user = appservice.get_bot().await?;
-
-    // Don't process if bot sent this message itself.
-    if &context.sender == user.id() {
-        return Ok(());
-    }
-
-    let room = appservice.get_room(&context.room_id).await.context("Room not found")?;
-    let is_direct = room.is_direct().await;
-
-    // Only respond directly to DMs. Group chats require explicitely mentioning the bot.
-    if !is_direct
-        && let Some(mentions) = event.content.mentions.clone()
-        && !mentions.user_ids.contains(user.id())
-    {
-        return Ok(());
-    }
-
-    let device = user.get_device().await.context("Device not found")?;
-    device.send_receipt(room.id(), &event.event_id).await?;
+    let appservice = ApplicationServiceBuilder::new()
+        .configuration_file(config_path)
+        .build()
+        .await?;
 
-    // Is input an appservice command?
-    if let Some(command) = Command::parse(event.content.body()) {
-        match command {
-            Command::Reset => appservice.state().clear(user.id(), room.id()).await,
-            _ => (),
+    let config = appservice.get_user_fields::<Config>()?;
+    let rows = report::generate(&config, since).await?;
+    let report = report::render(&rows, format)?;
+    match output {
+        "-" => print!("{report}"),
+        path => {
+            std::fs::write(path, report)?;
+            tracing::info!("Report written to {path}");
         }
-
-        return Ok(());
     }
 
-    device.send_typing(room.id(), true).await?;
-
-    let conversation = appservice.state().get_conversation(&appservice, &user, &room).await?;
-
-    if conversation.is_empty().await && is_direct {
-        conversation.backfill().await?;
-    }
-
-    let response = conversation.send_prompt(event.content.body().to_string()).await?;
-    let response_id = device
-        .send_message(room.id(), RoomMessageEventContent::text_markdown(response))
-        .await?;
-    conversation.insert_dialog(event.event_id, response_id).await;
-
-    device.send_typing(room.id(), false).await?;
-
     Ok(())
 }
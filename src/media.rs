@@ -0,0 +1,81 @@
+use std::io::{Cursor, Read};
+
+use matrix_appservice::exports::matrix_sdk::{
+    crypto::{AttachmentDecryptor, AttachmentEncryptor},
+    ruma::{
+        RoomId,
+        events::room::{EncryptedFileInit, MediaSource},
+    },
+};
+
+use crate::homeserver::Homeserver;
+
+#[cfg(feature = "video")]
+pub mod video;
+
+/// Download a media attachment, decrypting it first when it was sent to an encrypted room.
+pub async fn download(homeserver: &Homeserver, source: &MediaSource, max_size: u64) -> anyhow::Result<Vec<u8>> {
+    match source {
+        MediaSource::Plain(uri) => homeserver.download(uri, max_size).await,
+        MediaSource::Encrypted(file) => {
+            let ciphertext = homeserver.download(&file.url, max_size).await?;
+            let mut cursor = Cursor::new(ciphertext);
+            let mut decryptor = AttachmentDecryptor::new(&mut cursor, file.as_ref().clone().into())?;
+
+            let mut data = Vec::new();
+            decryptor.read_to_end(&mut data)?;
+            Ok(data)
+        }
+    }
+}
+
+/// Whether the room has end-to-end encryption enabled.
+pub async fn is_encrypted(homeserver: &Homeserver, room_id: &RoomId) -> anyhow::Result<bool> {
+    Ok(homeserver.state_event(room_id, "m.room.encryption").await?.is_some())
+}
+
+/// Upload a media attachment for a room, encrypting it first when the room is encrypted.
+pub async fn upload(
+    homeserver: &Homeserver,
+    room_id: &RoomId,
+    data: Vec<u8>,
+    content_type: &str,
+    filename: &str,
+) -> anyhow::Result<MediaSource> {
+    if !is_encrypted(homeserver, room_id).await? {
+        return Ok(MediaSource::Plain(
+            homeserver.upload(data, content_type, filename).await?,
+        ));
+    }
+
+    let mut cursor = Cursor::new(data);
+    let mut encryptor = AttachmentEncryptor::new(&mut cursor);
+    let mut ciphertext = Vec::new();
+    encryptor.read_to_end(&mut ciphertext)?;
+    let keys = encryptor.finish();
+
+    // Encrypted attachments are uploaded as opaque data, the MIME type goes in the event info.
+    let url = homeserver
+        .upload(ciphertext, "application/octet-stream", filename)
+        .await?;
+    Ok(MediaSource::Encrypted(Box::new(
+        EncryptedFileInit {
+            url,
+            key: keys.key,
+            iv: keys.iv,
+            hashes: keys.hashes,
+            v: keys.version,
+        }
+        .into(),
+    )))
+}
+
+/// MIME type and file extension of an image, recognized by its leading bytes. Unknown data is taken to be PNG.
+pub fn image_type(data: &[u8]) -> (&'static str, &'static str) {
+    match data {
+        [0xff, 0xd8, 0xff, ..] => ("image/jpeg", "jpg"),
+        [b'G', b'I', b'F', b'8', ..] => ("image/gif", "gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => ("image/webp", "webp"),
+        _ => ("image/png", "png"),
+    }
+}
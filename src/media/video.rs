@@ -0,0 +1,118 @@
+use std::{
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use matrix_appservice::exports::matrix_sdk::ruma::events::room::message::VideoMessageEventContent;
+use tokio::process::Command;
+
+use crate::{
+    config::MediaConfig,
+    homeserver::Homeserver,
+    media,
+    openai::{ContentPart, MessageContent},
+};
+
+static NEXT_FILE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Turn a posted video into a prompt of evenly spaced keyframes a vision model can reason about.
+pub async fn prompt_content(
+    homeserver: &Homeserver,
+    config: &MediaConfig,
+    content: &VideoMessageEventContent,
+) -> anyhow::Result<MessageContent> {
+    if let Some(size) = content.info.as_ref().and_then(|info| info.size)
+        && u64::from(size) > config.max_size
+    {
+        return Err(anyhow::anyhow!(
+            "Video exceeds the size limit of {} bytes",
+            config.max_size
+        ));
+    }
+
+    let video = media::download(homeserver, &content.source, config.max_size).await?;
+    let frames = extract_frames(config, &video).await?;
+
+    let mut parts = vec![ContentPart::text(format!(
+        "The user posted a video ({}). These are {} frames sampled evenly across the clip.",
+        content.body,
+        frames.len()
+    ))];
+    parts.extend(
+        frames
+            .iter()
+            .map(|frame| ContentPart::image_url(format!("data:image/jpeg;base64,{}", BASE64_STANDARD.encode(frame)))),
+    );
+
+    Ok(MessageContent::Parts(parts))
+}
+
+pub async fn extract_frames(config: &MediaConfig, video: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+    // ffmpeg needs a seekable input for most containers, so go through a temporary file.
+    let file_id = NEXT_FILE_ID.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!("matrix-openai-bot-{}-{file_id}", std::process::id()));
+    tokio::fs::write(&path, video).await?;
+
+    let result = extract_from_file(config, &path).await;
+    if let Err(error) = tokio::fs::remove_file(&path).await {
+        tracing::warn!("Failed to remove temporary video file // {error}");
+    }
+
+    result
+}
+
+async fn extract_from_file(config: &MediaConfig, path: &Path) -> anyhow::Result<Vec<Vec<u8>>> {
+    let duration = probe_duration(config, path).await?;
+    let count = config.video_frames.max(1);
+
+    let mut frames = Vec::with_capacity(count);
+    for index in 0..count {
+        let timestamp = duration * (index as f64 + 0.5) / count as f64;
+        frames.push(extract_frame(config, path, timestamp).await?);
+    }
+
+    Ok(frames)
+}
+
+async fn probe_duration(config: &MediaConfig, path: &Path) -> anyhow::Result<f64> {
+    let output = Command::new(&config.ffprobe_path)
+        .args(["-v", "error", "-show_entries", "format=duration"])
+        .args(["-of", "default=noprint_wrappers=1:nokey=1"])
+        .arg(path)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().parse()?)
+}
+
+async fn extract_frame(config: &MediaConfig, path: &Path, timestamp: f64) -> anyhow::Result<Vec<u8>> {
+    let output = Command::new(&config.ffmpeg_path)
+        .args(["-v", "error", "-ss", &format!("{timestamp:.3}"), "-i"])
+        .arg(path)
+        .args([
+            "-frames:v",
+            "1",
+            "-vf",
+            &format!("scale='min({},iw)':-2", config.frame_width),
+        ])
+        .args(["-f", "image2pipe", "-vcodec", "mjpeg", "-"])
+        .output()
+        .await?;
+
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(anyhow::anyhow!(
+            "ffmpeg failed to extract frame at {timestamp:.1}s: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(output.stdout)
+}
@@ -0,0 +1,119 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use matrix_appservice::exports::matrix_sdk::ruma::UserId;
+use serde::{Deserialize, Serialize};
+use tokio::sync::OwnedMutexGuard;
+
+use crate::store::Store;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MemoryConfig {
+    /// Days after which remembered facts are forgotten. Kept until removed with `!forget` when unset.
+    pub expire_after_days: Option<u32>,
+    /// Most facts remembered per user, as they all go into the system prompt.
+    pub max_facts: usize,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        Self {
+            expire_after_days: None,
+            max_facts: 50,
+        }
+    }
+}
+
+/// Something a user asked the bot to remember about them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fact {
+    pub id: u64,
+    pub text: String,
+    /// Unix timestamp of when the fact was stored.
+    pub created: i64,
+}
+
+/// Facts remembered about one user, shared across all rooms.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Memories {
+    next_id: u64,
+    pub facts: Vec<Fact>,
+}
+
+fn memories_key(user_id: &UserId) -> String {
+    format!("user/{user_id}/memories")
+}
+
+impl Memories {
+    /// Load the facts remembered about a user, leaving out expired ones.
+    pub async fn load(store: &dyn Store, config: &MemoryConfig, user_id: &UserId) -> anyhow::Result<Self> {
+        let mut memories: Self = store.load(&memories_key(user_id)).await?.unwrap_or_default();
+        if let Some(days) = config.expire_after_days {
+            let cutoff = (Utc::now() - TimeDelta::days(days.into())).timestamp();
+            memories.facts.retain(|fact| fact.created >= cutoff);
+        }
+        Ok(memories)
+    }
+
+    /// Hold off other updates of the user's facts while loading, changing and saving them.
+    pub async fn lock(store: &dyn Store, user_id: &UserId) -> OwnedMutexGuard<()> {
+        store.lock(&memories_key(user_id)).await
+    }
+
+    pub async fn save(&self, store: &dyn Store, user_id: &UserId) -> anyhow::Result<()> {
+        store.save(&memories_key(user_id), self).await
+    }
+
+    /// Store a fact, returning its ID, or `None` when the user already has `max_facts` facts.
+    pub fn remember(&mut self, config: &MemoryConfig, text: &str) -> Option<u64> {
+        if self.facts.len() >= config.max_facts {
+            return None;
+        }
+        self.next_id += 1;
+        self.facts.push(Fact {
+            id: self.next_id,
+            text: text.trim().to_string(),
+            created: Utc::now().timestamp(),
+        });
+        Some(self.next_id)
+    }
+
+    /// Remove a fact by ID, returning whether it existed.
+    pub fn forget(&mut self, id: u64) -> bool {
+        let count = self.facts.len();
+        self.facts.retain(|fact| fact.id != id);
+        self.facts.len() < count
+    }
+
+    pub fn forget_all(&mut self) {
+        self.facts.clear();
+    }
+
+    /// The facts with their IDs and when they were stored, for the user.
+    pub fn to_markdown(&self) -> String {
+        let lines = self
+            .facts
+            .iter()
+            .map(|fact| {
+                let date = DateTime::from_timestamp(fact.created, 0)
+                    .map(|date| date.format("%Y-%m-%d").to_string())
+                    .unwrap_or_default();
+                format!("- **{}** {} ({date})", fact.id, fact.text)
+            })
+            .collect::<Vec<_>>();
+        format!("**What I remember about you**\n{}", lines.join("\n"))
+    }
+
+    /// The facts as a list, one per line, for the model.
+    pub fn describe(&self) -> String {
+        describe(self.facts.iter().map(|fact| fact.text.as_str()))
+    }
+}
+
+/// Facts as a list, one per line, the way the model is shown them both in the system prompt and by `recall_facts`.
+pub fn describe<'a>(facts: impl IntoIterator<Item = &'a str>) -> String {
+    facts
+        .into_iter()
+        .map(|fact| format!("- {fact}"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,141 @@
+use std::{collections::HashMap, time::Duration};
+
+use matrix_appservice::{
+    Device,
+    exports::matrix_sdk::ruma::{EventId, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId},
+};
+use tokio::sync::{Mutex, oneshot};
+
+use crate::config::BehaviorConfig;
+
+pub const NUMBER_EMOJI: [&str; 10] = ["1️⃣", "2️⃣", "3️⃣", "4️⃣", "5️⃣", "6️⃣", "7️⃣", "8️⃣", "9️⃣", "🔟"];
+
+struct Pending {
+    room_id: OwnedRoomId,
+    /// Who may pick an option, or anyone in the room when unset.
+    asker: Option<OwnedUserId>,
+    options: usize,
+    sender: oneshot::Sender<usize>,
+}
+
+impl Pending {
+    fn may_answer(&self, sender: &UserId) -> bool {
+        self.asker.as_deref().is_none_or(|asker| asker == sender)
+    }
+}
+
+/// Numbered option lists waiting for the user to pick an entry, either by reacting with a number
+/// emoji or by replying with the number.
+pub struct Menus {
+    behavior: BehaviorConfig,
+    pending: Mutex<HashMap<OwnedEventId, Pending>>,
+}
+
+impl Menus {
+    pub fn new(behavior: &BehaviorConfig) -> Self {
+        Self {
+            behavior: behavior.clone(),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Post the options and wait for `asker` to select one. Returns the zero-based index of the chosen option,
+    /// or `None` when nobody picked one in time.
+    pub async fn ask(
+        &self,
+        device: &Device,
+        room_id: &RoomId,
+        asker: Option<&UserId>,
+        question: &str,
+        options: &[String],
+        timeout: Duration,
+    ) -> anyhow::Result<Option<usize>> {
+        if options.is_empty() || options.len() > NUMBER_EMOJI.len() {
+            return Err(anyhow::anyhow!(
+                "Menus need between 1 and {} options",
+                NUMBER_EMOJI.len()
+            ));
+        }
+
+        let list = options
+            .iter()
+            .zip(NUMBER_EMOJI)
+            .map(|(option, emoji)| format!("{emoji} {option}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let body = format!("{question}\n\n{list}\n\nReact with a number or reply with it to choose.");
+        let event_id = device.send_message(room_id, self.behavior.reply(body)).await?;
+
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().await.insert(
+            event_id.clone(),
+            Pending {
+                room_id: room_id.to_owned(),
+                asker: asker.map(ToOwned::to_owned),
+                options: options.len(),
+                sender,
+            },
+        );
+
+        let selection = tokio::time::timeout(timeout, receiver).await;
+        self.pending.lock().await.remove(&event_id);
+
+        Ok(selection.ok().and_then(Result::ok))
+    }
+
+    /// Ask `asker` a yes or no question, for actions that need approval before they run. Only an explicit yes
+    /// within the timeout counts as approval.
+    pub async fn confirm(
+        &self,
+        device: &Device,
+        room_id: &RoomId,
+        asker: Option<&UserId>,
+        question: &str,
+        timeout: Duration,
+    ) -> anyhow::Result<bool> {
+        let options = ["Yes".to_string(), "No".to_string()];
+        Ok(self.ask(device, room_id, asker, question, &options, timeout).await? == Some(0))
+    }
+
+    /// Resolve a menu from a reaction to it. Returns whether the reaction selected an option.
+    pub async fn select_by_reaction(&self, relates_to: &EventId, sender: &UserId, key: &str) -> bool {
+        let Some(index) = NUMBER_EMOJI.iter().position(|emoji| *emoji == key) else {
+            return false;
+        };
+
+        let mut pending = self.pending.lock().await;
+        match pending.get(relates_to) {
+            Some(menu) if index < menu.options && menu.may_answer(sender) => {
+                let menu = pending.remove(relates_to).expect("menu is pending");
+                menu.sender.send(index).is_ok()
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether a menu is open in the room.
+    pub async fn is_pending(&self, room_id: &RoomId) -> bool {
+        self.pending.lock().await.values().any(|menu| menu.room_id == room_id)
+    }
+
+    /// Resolve the open menu in a room from a message consisting of just a number.
+    pub async fn select_by_reply(&self, room_id: &RoomId, sender: &UserId, body: &str) -> bool {
+        let Ok(number) = body.trim().parse::<usize>() else {
+            return false;
+        };
+
+        let mut pending = self.pending.lock().await;
+        let Some(event_id) = pending
+            .iter()
+            .find(|(_, menu)| {
+                menu.room_id == room_id && menu.may_answer(sender) && (1..=menu.options).contains(&number)
+            })
+            .map(|(event_id, _)| event_id.clone())
+        else {
+            return false;
+        };
+
+        let menu = pending.remove(&event_id).expect("menu is pending");
+        menu.sender.send(number - 1).is_ok()
+    }
+}
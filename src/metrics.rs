@@ -0,0 +1,53 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex};
+
+type Labels = Vec<(&'static str, String)>;
+
+/// In-process metrics registry, rendered in the Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    values: Mutex<BTreeMap<(&'static str, Labels), f64>>,
+}
+
+impl Metrics {
+    pub fn increment(&self, name: &'static str, labels: &[(&'static str, &str)]) {
+        self.add(name, labels, 1.0);
+    }
+
+    pub fn add(&self, name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+        let mut values = self.values.lock().unwrap();
+        *values.entry((name, to_labels(labels))).or_default() += value;
+    }
+
+    pub fn set(&self, name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+        let mut values = self.values.lock().unwrap();
+        values.insert((name, to_labels(labels)), value);
+    }
+
+    pub fn get(&self, name: &'static str, labels: &[(&'static str, &str)]) -> f64 {
+        let values = self.values.lock().unwrap();
+        values.get(&(name, to_labels(labels))).copied().unwrap_or_default()
+    }
+
+    pub fn render(&self) -> String {
+        let values = self.values.lock().unwrap();
+        let mut output = String::new();
+        for ((name, labels), value) in values.iter() {
+            let labels = labels
+                .iter()
+                .map(|(key, value)| format!("{key}=\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")))
+                .collect::<Vec<_>>()
+                .join(",");
+
+            let _ = match labels.is_empty() {
+                true => writeln!(output, "{name} {value}"),
+                false => writeln!(output, "{name}{{{labels}}} {value}"),
+            };
+        }
+
+        output
+    }
+}
+
+fn to_labels(labels: &[(&'static str, &str)]) -> Labels {
+    labels.iter().map(|(key, value)| (*key, value.to_string())).collect()
+}
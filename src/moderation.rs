@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+
+use matrix_appservice::exports::matrix_sdk::ruma::events::room::message::{FormattedBody, RoomMessageEventContent};
+use serde::Deserialize;
+use serde_json::json;
+use url::Url;
+
+use crate::{
+    client::Identified,
+    openai::{Api, OpenAIConfig},
+};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ModerationConfig {
+    /// Classify replies with the moderation API below `openai.endpoint`.
+    pub enabled: bool,
+    /// OpenAI compatible moderation endpoint to use instead, e.g. `https://api.openai.com/v1/moderations`. Replies
+    /// are classified when this is set, even if `enabled` is off.
+    pub endpoint: Option<Url>,
+    /// Hide flagged replies behind a spoiler by default, rooms can override this with `!set spoilers`.
+    pub spoilers: bool,
+}
+
+#[derive(Deserialize)]
+struct ModerationResponse {
+    results: Vec<ModerationResult>,
+}
+
+#[derive(Deserialize)]
+struct ModerationResult {
+    flagged: bool,
+    #[serde(default)]
+    categories: BTreeMap<String, bool>,
+}
+
+/// Classifies text with the moderation endpoint.
+pub struct Moderation {
+    endpoint: Option<Url>,
+    client: reqwest::Client,
+}
+
+impl Moderation {
+    pub fn new(config: &ModerationConfig, openai: &OpenAIConfig, client: reqwest::Client) -> anyhow::Result<Self> {
+        let endpoint = match (&config.endpoint, config.enabled) {
+            (Some(endpoint), _) => Some(endpoint.clone()),
+            (None, true) => Some(openai.url(Api::Moderations)?),
+            (None, false) => None,
+        };
+        Ok(Self { endpoint, client })
+    }
+
+    /// Categories the text was flagged for, empty when it wasn't flagged or no endpoint is configured.
+    pub async fn flagged(&self, text: &str) -> anyhow::Result<Vec<String>> {
+        let Some(endpoint) = &self.endpoint else {
+            return Ok(Vec::new());
+        };
+
+        let response: ModerationResponse = self
+            .client
+            .post(endpoint.clone())
+            .json(&json!({ "input": text }))
+            .send_identified()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(response
+            .results
+            .into_iter()
+            .filter(|result| result.flagged)
+            .flat_map(|result| result.categories)
+            .filter_map(|(category, flagged)| flagged.then_some(category))
+            .collect())
+    }
+}
+
+/// Message content with the whole reply hidden behind a spoiler, giving the flagged categories as reason.
+pub fn spoiler(body: &str, categories: &[String]) -> RoomMessageEventContent {
+    let reason = categories.join(", ");
+    let html = FormattedBody::markdown(body)
+        .map(|formatted| formatted.body)
+        .unwrap_or_else(|| escape_html(body));
+
+    RoomMessageEventContent::text_html(
+        format!("[Spoiler: {reason}] {body}"),
+        format!(r#"<span data-mx-spoiler="{}">{html}</span>"#, escape_html(&reason)),
+    )
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
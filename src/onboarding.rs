@@ -0,0 +1,48 @@
+use matrix_appservice::{
+    Device,
+    exports::matrix_sdk::ruma::{MilliSecondsSinceUnixEpoch, RoomId},
+};
+use serde::Deserialize;
+
+use crate::{openai::ConversationStore, store};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OnboardingConfig {
+    /// Send a welcome message when joining a DM or when first mentioned in a room.
+    pub enabled: bool,
+    /// Markdown welcome message, replacing the built-in one in the room's locale.
+    pub message: Option<String>,
+}
+
+impl Default for OnboardingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            message: None,
+        }
+    }
+}
+
+/// Send the welcome message, unless the room has been seen before.
+pub async fn welcome(state: &ConversationStore, device: &Device, room_id: &RoomId, locale: &str) -> anyhow::Result<()> {
+    let key = store::room_key(room_id, "seen");
+    if state.store().get(&key).await?.is_some() {
+        return Ok(());
+    }
+
+    state.store().save(&key, &MilliSecondsSinceUnixEpoch::now()).await?;
+
+    let config = &state.config().onboarding;
+    if config.enabled {
+        let message = match &config.message {
+            Some(message) => message.clone(),
+            None => state.locales().text(locale, "onboarding.welcome", &[]),
+        };
+        device
+            .send_message(room_id, state.config().behavior.reply(message))
+            .await?;
+    }
+
+    Ok(())
+}
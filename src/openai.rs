@@ -1,88 +1,197 @@
-use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use matrix_appservice::exports::matrix_sdk::ruma::{EventId, OwnedEventId};
+use serde::Deserialize;
+use serde_json::{Value, json};
 use url::Url;
 
-use crate::openai::tools::ToolCall;
+use crate::citations::Citation;
 
-pub use self::conversation::{ConversationStore, Processed};
+pub use self::{
+    actor::RoomActors,
+    api::{
+        ChatRequest, ContentPart, ImageUrl, MessageContent, OpenAIChoice, OpenAIMessage, OpenAIResponse, Role, Usage,
+    },
+    conversation::{
+        Conversation, ConversationStore, Processed, Progress, frame_emote, into_actions, load_message, parse_message,
+    },
+    error::OpenAIError,
+    gemini::{Gemini, GeminiConfig},
+    openrouter::{OpenRouterConfig, ProviderPreferences},
+    provider::{ChatProvider, OpenAICompatible},
+    tools::{AssistantAction, CustomTool, Invocation, Tool, ToolContext, ToolOutput, ToolRegistry},
+};
 
+mod actor;
+pub mod api;
 mod conversation;
+mod error;
+mod gemini;
+mod openrouter;
+mod provider;
 mod tools;
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct Config {
-    pub openai: OpenAIConfig,
-}
-
 #[derive(Debug, Clone, Deserialize)]
 pub struct OpenAIConfig {
+    /// Base URL of the provider's API, e.g. `https://api.openai.com/v1`. The full chat completions URL is accepted
+    /// as well.
     pub endpoint: Url,
     pub api_key: String,
     pub model: String,
+    /// Models rooms may switch to with `!set model`, besides `model`. Rooms can't pick another model when empty.
+    #[serde(default)]
+    pub room_models: Vec<String>,
+    /// Model used instead of `model` when a prompt contains images.
+    #[serde(default)]
+    pub vision_model: Option<String>,
+    /// Which paths the provider serves its APIs on.
+    #[serde(default)]
+    pub flavor: ApiFlavor,
+    #[serde(default)]
+    pub openrouter: OpenRouterConfig,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct OpenAIChoice {
-    pub index: u16,
-    pub message: OpenAIMessage,
-    // pub logprobs: Option<String>,
-    // pub finish_reason: String,
+/// APIs the bot uses below the provider's base URL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Api {
+    ChatCompletions,
+    /// Base of `generations`, `edits` and `variations`.
+    Images,
+    /// Base of `transcriptions` and `speech`.
+    Audio,
+    Embeddings,
+    Moderations,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct OpenAIResponse {
-    // pub id: String,
-    pub object: String,
-    pub created: u32,
-    pub model: String,
-    pub choices: Vec<OpenAIChoice>,
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiFlavor {
+    /// OpenAI and servers following its layout, such as vLLM, LocalAI and Ollama.
+    #[default]
+    #[serde(rename = "openai")]
+    OpenAI,
+    /// OpenRouter, sending the headers and routing preferences under `openai.openrouter`.
+    #[serde(rename = "openrouter")]
+    OpenRouter,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct OpenAIMessage {
-    pub role: String,
-    pub content: Option<MessageContent>,
-    #[serde(default)]
-    #[serde(skip_serializing_if = "Vec::is_empty")]
-    pub tool_calls: Vec<ToolCall>,
-    // pub refusal: Option<String>,
-    // pub annotations: Option<Vec<String>>,
+impl ApiFlavor {
+    /// Path of an API relative to the base URL.
+    pub fn path(self, api: Api) -> &'static str {
+        match (self, api) {
+            (Self::OpenAI, Api::ChatCompletions) => "chat/completions",
+            (Self::OpenAI, Api::Images) => "images/",
+            (Self::OpenAI, Api::Audio) => "audio/",
+            (Self::OpenAI, Api::Embeddings) => "embeddings",
+            (Self::OpenAI, Api::Moderations) => "moderations",
+            // OpenRouter follows OpenAI's layout, though it only serves chat completions and embeddings.
+            (Self::OpenRouter, api) => Self::OpenAI.path(api),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct OpenAIImageContent {
-    #[serde(rename = "type")]
-    kind: String,
-    image_url: ImageUrl,
-}
+impl OpenAIConfig {
+    /// The provider's base URL, ending in a slash, whether `endpoint` is the base URL or the full chat completions
+    /// URL.
+    pub fn base_url(&self) -> Url {
+        let path = self.endpoint.path().trim_end_matches('/');
+        let base = path
+            .strip_suffix(self.flavor.path(Api::ChatCompletions))
+            .unwrap_or(path)
+            .trim_end_matches('/');
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ImageUrl {
-    url: String,
+        let mut url = self.endpoint.clone();
+        url.set_path(&format!("{base}/"));
+        url
+    }
+
+    /// URL of one of the provider's APIs.
+    pub fn url(&self, api: Api) -> anyhow::Result<Url> {
+        Ok(self.base_url().join(self.flavor.path(api))?)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(untagged)]
-pub enum MessageContent {
-    Text(String),
-    Images(Vec<OpenAIImageContent>),
+/// Event type of the structured metadata event sent alongside each reply.
+pub const RESPONSE_EVENT_TYPE: &str = "nl.spacebased.openai.response";
+
+/// Start of the footer added to replies in debug mode.
+pub const DEBUG_FOOTER_START: &str = "\n\n---\n<sub>";
+
+/// A reply as the model wrote it, without the debug footer added when it was sent.
+pub fn strip_debug_footer(body: &str) -> &str {
+    body.rsplit_once(DEBUG_FOOTER_START).map_or(body, |(reply, _)| reply)
 }
 
-pub enum Role {
-    User,
-    Assistant,
+/// Final outcome of a prompt, including the tool round-trips it took to get there.
+#[derive(Debug)]
+pub struct Completion {
+    pub content: String,
+    pub model: String,
+    pub usage: Usage,
+    pub finish_reason: Option<String>,
+    pub tool_calls: Vec<String>,
+    /// Interim reply already posted while tools were running, which this completion should edit.
+    pub replaces: Option<OwnedEventId>,
+    /// Sources handed to the model by tools that the reply refers to.
+    pub citations: Vec<Citation>,
+    /// Other URLs and identifiers tools read while answering, listed after the citations.
+    pub sources: Vec<String>,
+    /// Follow-up requests made to finish a reply cut off at the token limit.
+    pub continuations: usize,
+    /// Follow-up question the prompt was suspended on instead of replying, resumed once it's answered.
+    pub question: Option<OwnedEventId>,
 }
 
-impl Role {
-    fn as_str(&self) -> &'static str {
-        match self {
-            Role::User => "user",
-            Role::Assistant => "assistant",
+impl Completion {
+    /// Whether the reply is still cut off at the token limit, after any follow-up requests.
+    pub fn is_truncated(&self) -> bool {
+        self.finish_reason.as_deref() == Some("length")
+    }
+
+    /// Note appended to a reply that is still cut off, so it doesn't read as complete.
+    pub fn truncation_notice(&self) -> &'static str {
+        match self.is_truncated() {
+            true => "\n\n*…(reply cut off at the length limit, ask me to continue for the rest)*",
+            false => "",
         }
     }
-}
 
-impl std::fmt::Display for Role {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(self.as_str())
+    /// Footer with request details, appended to replies in rooms with debug mode enabled. Starts with
+    /// [`DEBUG_FOOTER_START`], so it can be left out when the reply is read back into a conversation.
+    pub fn debug_footer(&self, latency: Duration) -> String {
+        let tools = match self.tool_calls.is_empty() {
+            true => "no tools".to_string(),
+            false => format!("tools: {}", self.tool_calls.join(", ")),
+        };
+        let continued = match self.continuations {
+            0 => String::new(),
+            count => format!(" · continued {count}×"),
+        };
+
+        format!(
+            "{DEBUG_FOOTER_START}{} · {} prompt + {} completion tokens · {:.2} s · {}{continued}</sub>",
+            self.model,
+            self.usage.prompt_tokens,
+            self.usage.completion_tokens,
+            latency.as_secs_f64(),
+            tools,
+        )
+    }
+
+    /// Content of the `nl.spacebased.openai.response` event describing this completion,
+    /// referencing the reply it belongs to.
+    pub fn response_event(&self, response_id: &EventId, prompt_id: &EventId, conversation_id: &EventId) -> Value {
+        json!({
+            "m.relates_to": {
+                "rel_type": "m.reference",
+                "event_id": response_id,
+            },
+            "prompt_event_id": prompt_id,
+            "conversation_id": conversation_id,
+            "model": self.model,
+            "usage": self.usage,
+            "tool_calls": self.tool_calls,
+            "finish_reason": self.finish_reason,
+        })
     }
 }
@@ -0,0 +1,88 @@
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use matrix_appservice::exports::matrix_sdk::ruma::{OwnedRoomId, RoomId};
+use tokio::sync::{mpsc, oneshot};
+
+/// How long a room's actor waits for more work before exiting. It is started again by the next prompt.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Jobs a room's actor holds before more work for the room is refused.
+const MAILBOX_SIZE: usize = 32;
+
+type Job = Pin<Box<dyn Future<Output = ()> + Send>>;
+type Mailboxes = Arc<Mutex<HashMap<OwnedRoomId, mpsc::Sender<Job>>>>;
+
+/// One task per active room, running the work sent to it strictly in order. Prompts and scheduled or webhook prompts
+/// for a room go through its actor, so tool loops never interleave and each prompt sees the exchanges before it in the
+/// store. It is a queue only: conversation state stays in the store.
+///
+/// Work running on an actor must not [`run`](Self::run) more work on the same room, it would wait for itself.
+#[derive(Default)]
+pub struct RoomActors {
+    mailboxes: Mailboxes,
+}
+
+impl RoomActors {
+    /// Queue `work` on the room's actor, after anything already queued, and wait for its result.
+    pub async fn run<T, F>(&self, room_id: &RoomId, work: F) -> anyhow::Result<T>
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        self.send(
+            room_id,
+            Box::pin(async move {
+                let _ = sender.send(work.await);
+            }),
+        )?;
+        receiver
+            .await
+            .with_context(|| format!("Work queued for {room_id} was dropped"))
+    }
+
+    fn send(&self, room_id: &RoomId, job: Job) -> anyhow::Result<()> {
+        // Sending under the lock means an actor can't exit between being looked up and receiving the job.
+        let mut mailboxes = self.mailboxes.lock().unwrap();
+        let job = match mailboxes.get(room_id) {
+            Some(mailbox) => match mailbox.try_send(job) {
+                Ok(()) => return Ok(()),
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    return Err(anyhow::anyhow!("Too much work queued for {room_id}"));
+                }
+                // The actor stopped, for instance because a job panicked. Start a new one.
+                Err(mpsc::error::TrySendError::Closed(job)) => job,
+            },
+            None => job,
+        };
+
+        let (mailbox, receiver) = mpsc::channel(MAILBOX_SIZE);
+        let _ = mailbox.try_send(job);
+        mailboxes.insert(room_id.to_owned(), mailbox);
+        tokio::spawn(actor(Arc::clone(&self.mailboxes), room_id.to_owned(), receiver));
+        Ok(())
+    }
+}
+
+async fn actor(mailboxes: Mailboxes, room_id: OwnedRoomId, mut receiver: mpsc::Receiver<Job>) {
+    loop {
+        match tokio::time::timeout(IDLE_TIMEOUT, receiver.recv()).await {
+            Ok(Some(job)) => job.await,
+            Ok(None) => return,
+            Err(_) => {
+                let mut mailboxes = mailboxes.lock().unwrap();
+                // Work may have arrived right as the timeout fired.
+                if receiver.is_empty() {
+                    mailboxes.remove(&room_id);
+                    tracing::debug!("Actor for {room_id} exited after being idle");
+                    return;
+                }
+            }
+        }
+    }
+}
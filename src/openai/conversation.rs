@@ -1,16 +1,24 @@
-use std::{borrow::Cow, collections::HashMap, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
 use anyhow::Context;
-use futures::{StreamExt, TryStreamExt, future};
+use futures::{StreamExt, TryStreamExt};
 use matrix_appservice::{
     ApplicationService, Device, Direction, Room, State, User,
     exports::matrix_sdk::ruma::{
-        OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
+        EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
         events::{
             AnySyncTimelineEvent,
             room::{
                 member::{MembershipChange, StrippedRoomMemberEvent},
-                message::OriginalSyncRoomMessageEvent,
+                message::{MessageType, OriginalSyncRoomMessageEvent, Relation, ReplacementMetadata},
             },
         },
         serde::Raw,
@@ -20,28 +28,162 @@ use reqwest::{
     Client,
     header::{AUTHORIZATION, HeaderMap, HeaderValue},
 };
-use serde::Deserialize;
-use serde_json::{Value, json};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tokio::sync::{Mutex, RwLock};
+use tracing::Instrument;
 
 use crate::{
+    api_log::ApiLog,
+    catch_up::CatchUp,
+    citations::{self, Citations},
+    client::{self, REQUEST_ID_HEADER},
+    cluster::Cluster,
     command::Command,
+    config::{Config, ReplyMsgtype},
+    directives::InlineDirectives,
+    filter::MessageFilter,
+    homeserver::Homeserver,
+    i18n::Locales,
+    images::{self, ImageProvider},
+    injection::InjectionGuard,
+    limiter::{BUDGET_EXCEEDED, Limiter, TOOL_FAILURES},
+    memory::Memories,
+    menu::Menus,
+    metrics::Metrics,
+    moderation::Moderation,
     openai::{
-        Config, MessageContent, OpenAIConfig, OpenAIMessage, OpenAIResponse, Role,
-        tools::{AssistantAction, Tool},
+        ApiFlavor, ChatProvider, ChatRequest, Completion, Gemini, MessageContent, OpenAIChoice, OpenAICompatible,
+        OpenAIError, OpenAIMessage, OpenAIResponse, Role, Usage,
+        actor::RoomActors,
+        strip_debug_footer,
+        tools::{AssistantAction, ToolContext, ToolOutput, ToolRegistry},
     },
+    output_filter::OutputFilter,
+    participants::Participants,
+    pii::{Pii, Scrubber},
+    prompt::{self, RoomContext},
+    puppets::Puppets,
+    questions::{self, Question},
+    relation::BotResponse,
+    retry::{self, retry},
+    room_details::RoomDetails,
+    settings::{ChoiceSelection, RoomSettings},
+    speech::{self, Synthesizer, Transcriber},
+    store::{self, MemoryStore, StorageBackend, Store},
+    style::Style,
 };
 
+/// Upper bound on model round-trips for a single prompt, so a model stuck calling tools can't loop forever.
+const MAX_TOOL_ROUNDS: usize = 8;
+
+/// Follow-up requests made when a reply is cut off at the token limit, before giving up and sending what we have.
+const MAX_CONTINUATIONS: usize = 3;
+const CONTINUE_PROMPT: &str = "Continue exactly where you left off, without repeating anything.";
+
+/// How long reading history may take before a progress notice is posted, and how often it is updated after that.
+const BACKFILL_NOTICE_DELAY: Duration = Duration::from_secs(3);
+const BACKFILL_NOTICE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Room state under fixed names, moved to the new room after an upgrade even when the store can't list it.
+const MIGRATED_KEYS: &[&str] = &["settings", "stats", "seen"];
+
+/// How far the tool loop of a prompt got, kept while it's suspended on a follow-up question.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Progress {
+    usage: Usage,
+    tool_calls: Vec<String>,
+    interim: Option<OwnedEventId>,
+    continuations: usize,
+    stitched: String,
+    rounds: usize,
+}
+
+/// Where a forked conversation came from: the room it was forked from and a copy of the messages it held at the
+/// time, so the fork doesn't depend on the bot staying in that room.
+#[derive(Serialize, Deserialize)]
+struct ForkOrigin {
+    room_id: OwnedRoomId,
+    #[serde(default)]
+    events: Vec<OriginalSyncRoomMessageEvent>,
+}
+
+/// A backfill `!cancel` can stop, registered for as long as the backfill runs, however it ends.
+struct Backfill<'a> {
+    backfills: &'a std::sync::Mutex<HashMap<OwnedRoomId, Arc<AtomicBool>>>,
+    room_id: OwnedRoomId,
+}
+
+impl<'a> Backfill<'a> {
+    fn register(
+        backfills: &'a std::sync::Mutex<HashMap<OwnedRoomId, Arc<AtomicBool>>>,
+        room_id: &RoomId,
+        cancelled: &Arc<AtomicBool>,
+    ) -> Self {
+        backfills
+            .lock()
+            .unwrap()
+            .insert(room_id.to_owned(), Arc::clone(cancelled));
+        Self {
+            backfills,
+            room_id: room_id.to_owned(),
+        }
+    }
+}
+
+impl Drop for Backfill<'_> {
+    fn drop(&mut self) {
+        self.backfills.lock().unwrap().remove(&self.room_id);
+    }
+}
+
 #[derive(Debug)]
 
 pub enum Processed {
     Continue(OwnedEventId, OpenAIMessage),
     Stop,
+    /// A `!reset`, or a reply from before one: nothing earlier belongs to the conversation.
+    Boundary,
 }
 
 pub struct ConversationStore {
-    inner: RwLock<HashMap<OwnedUserId, HashMap<OwnedRoomId, Vec<OwnedEventId>>>>,
+    config: Config,
+    store: Arc<dyn Store>,
+    /// Short-lived values only this instance needs, such as full tool results, kept out of the store since account
+    /// data keeps expired values around until they're overwritten.
+    scratch: Arc<dyn Store>,
+    cluster: Option<Arc<Cluster>>,
+    /// Prompt content derived from media events, which can't be rebuilt from the event body alone.
+    attachments: RwLock<HashMap<OwnedEventId, MessageContent>>,
     client: reqwest::Client,
+    chat: OpenAICompatible,
+    gemini: Option<Gemini>,
+    http: reqwest::Client,
+    web: reqwest::Client,
+    homeserver: Homeserver,
+    puppets: Puppets,
+    tools: ToolRegistry,
+    metrics: Arc<Metrics>,
+    pii: Pii,
+    model_limiter: Limiter,
+    tool_limiter: Limiter,
+    catch_up: CatchUp,
+    menus: Menus,
+    style: Style,
+    filter: MessageFilter,
+    output_filter: OutputFilter,
+    injection: InjectionGuard,
+    locales: Locales,
+    moderation: Moderation,
+    participants: Participants,
+    room_details: RoomDetails,
+    images: Box<dyn ImageProvider>,
+    transcriber: Box<dyn Transcriber>,
+    synthesizer: Box<dyn Synthesizer>,
+    api_log: ApiLog,
+    actors: RoomActors,
+    /// Cancellation flags of history being read, by room, for `!cancel`.
+    backfills: std::sync::Mutex<HashMap<OwnedRoomId, Arc<AtomicBool>>>,
 }
 #[derive(Deserialize)]
 struct ExtractType<'a> {
@@ -50,28 +192,428 @@ struct ExtractType<'a> {
 }
 
 impl ConversationStore {
-    pub fn new(config: &OpenAIConfig) -> anyhow::Result<Arc<Self>> {
-        let token = format!("Bearer {}", &config.api_key);
+    pub async fn new(config: &Config, tools: ToolRegistry) -> anyhow::Result<Arc<Self>> {
+        let token = format!("Bearer {}", &config.openai.api_key);
         let mut headers = HeaderMap::new();
         let mut token = HeaderValue::from_str(&token)?;
         token.set_sensitive(true);
         headers.insert(AUTHORIZATION, token);
+        if let ApiFlavor::OpenRouter = config.openai.flavor {
+            headers.extend(config.openai.openrouter.headers()?);
+        }
 
-        let client = Client::builder().use_rustls_tls().default_headers(headers).build()?;
+        let client = client::provider_builder(&config.client, headers)?.build()?;
+
+        let http = client::builder(&config.client, HeaderMap::new()).build()?;
+        let metrics = Arc::new(Metrics::default());
+        let homeserver = Homeserver::new(config, Arc::clone(&metrics))?;
+        let store = store::from_config(config, &homeserver).await?;
+        let cluster = match config.cluster.enabled {
+            // Memory isn't shared and account data only lists keys this instance wrote, so instances would never
+            // see each other and each would claim every room.
+            true if !matches!(config.storage.backend, StorageBackend::Redis) => {
+                anyhow::bail!("Cluster mode requires the redis storage backend")
+            }
+            true => Some(Cluster::join(Arc::clone(&store), &config.cluster).await?),
+            false => None,
+        };
 
         Ok(Arc::new(Self {
-            inner: RwLock::new(HashMap::new()),
-            client,
+            config: config.clone(),
+            store,
+            cluster,
+            scratch: Arc::new(MemoryStore::default()),
+            attachments: RwLock::new(HashMap::new()),
+            client: client.clone(),
+            chat: OpenAICompatible::new(&config.openai, client.clone()),
+            gemini: Gemini::from_config(&config.gemini, &config.client)?,
+            http: http.clone(),
+            web: client::public_only(client::builder(&config.client, HeaderMap::new())).build()?,
+            puppets: Puppets::new(config, homeserver.clone()),
+            homeserver,
+            tools,
+            model_limiter: Limiter::new("model", config.limits.model_requests, Arc::clone(&metrics)),
+            tool_limiter: Limiter::new("tool", config.limits.tool_runs, Arc::clone(&metrics)),
+            metrics,
+            pii: Pii::new(&config.pii, http.clone()),
+            catch_up: CatchUp::new(config.behavior.catch_up),
+            menus: Menus::new(&config.behavior),
+            style: Style::new(&config.style)?,
+            filter: MessageFilter::new(&config.filter)?,
+            output_filter: OutputFilter::new(&config.output_filter)?,
+            injection: InjectionGuard::new(&config.injection)?,
+            locales: Locales::load(&config.i18n)?,
+            moderation: Moderation::new(&config.moderation, &config.openai, client.clone())?,
+            participants: Participants::default(),
+            room_details: RoomDetails::default(),
+            images: images::from_config(
+                &config.images,
+                &config.openai,
+                &config.limits,
+                client.clone(),
+                http.clone(),
+            )?,
+            transcriber: speech::transcriber(
+                &config.speech.transcription,
+                &config.openai,
+                client.clone(),
+                http.clone(),
+            )?,
+            synthesizer: speech::synthesizer(&config.speech.synthesis, &config.openai, client.clone(), http.clone())?,
+            api_log: ApiLog::new(&config.api_log, &config.openai.api_key),
+            actors: RoomActors::default(),
+            backfills: std::sync::Mutex::new(HashMap::new()),
         }))
     }
 
-    pub async fn clear(&self, user_id: &UserId, room_id: &RoomId) {
-        let mut lock = self.inner.write().await;
-        lock.entry(user_id.to_owned())
-            .or_default()
-            .entry(room_id.to_owned())
-            .or_default()
-            .clear();
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Plain HTTP client, without the API credentials attached.
+    pub fn http(&self) -> &Client {
+        &self.http
+    }
+
+    /// Plain HTTP client limited to public hosts, for URLs picked by the model or users.
+    pub fn web(&self) -> &Client {
+        &self.web
+    }
+
+    /// HTTP client with the OpenAI API credentials attached.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn homeserver(&self) -> &Homeserver {
+        &self.homeserver
+    }
+
+    pub fn puppets(&self) -> &Puppets {
+        &self.puppets
+    }
+
+    /// Stop reading the history of a room. Returns whether it was being read.
+    pub fn cancel_backfill(&self, room_id: &RoomId) -> bool {
+        match self.backfills.lock().unwrap().get(room_id) {
+            Some(cancelled) => {
+                cancelled.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Per-room tasks that prompts and other work on a conversation are run on, one at a time.
+    pub fn actors(&self) -> &RoomActors {
+        &self.actors
+    }
+
+    pub fn catch_up(&self) -> &CatchUp {
+        &self.catch_up
+    }
+
+    pub fn menus(&self) -> &Menus {
+        &self.menus
+    }
+
+    pub fn model_limiter(&self) -> &Limiter {
+        &self.model_limiter
+    }
+
+    pub fn tool_limiter(&self) -> &Limiter {
+        &self.tool_limiter
+    }
+
+    pub fn tools(&self) -> &ToolRegistry {
+        &self.tools
+    }
+
+    pub fn images(&self) -> &dyn ImageProvider {
+        self.images.as_ref()
+    }
+
+    pub fn transcriber(&self) -> &dyn Transcriber {
+        self.transcriber.as_ref()
+    }
+
+    pub fn synthesizer(&self) -> &dyn Synthesizer {
+        self.synthesizer.as_ref()
+    }
+
+    pub fn participants(&self) -> &Participants {
+        &self.participants
+    }
+
+    pub fn moderation(&self) -> &Moderation {
+        &self.moderation
+    }
+
+    pub fn style(&self) -> &Style {
+        &self.style
+    }
+
+    pub fn filter(&self) -> &MessageFilter {
+        &self.filter
+    }
+
+    pub fn output_filter(&self) -> &OutputFilter {
+        &self.output_filter
+    }
+
+    pub fn injection(&self) -> &InjectionGuard {
+        &self.injection
+    }
+
+    pub fn locales(&self) -> &Locales {
+        &self.locales
+    }
+
+    /// Locale of the bot's own text in a room.
+    pub fn locale<'a>(&'a self, settings: &'a RoomSettings) -> &'a str {
+        settings.locale.as_deref().unwrap_or(&self.config.prompt.locale)
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn api_log(&self) -> &ApiLog {
+        &self.api_log
+    }
+
+    pub fn store(&self) -> &dyn Store {
+        self.store.as_ref()
+    }
+
+    /// In-memory store for short-lived values of this instance, see [`ConversationStore::scratch`].
+    pub fn scratch(&self) -> &dyn Store {
+        self.scratch.as_ref()
+    }
+
+    /// Whether this instance should handle events in the room. Always true unless sharding is enabled.
+    pub async fn owns_room(&self, room_id: &RoomId) -> bool {
+        match &self.cluster {
+            Some(cluster) => cluster.owns(room_id).await,
+            None => true,
+        }
+    }
+
+    /// One-off completion of a single prompt outside of any conversation, for housekeeping such as
+    /// judging answers or naming rooms.
+    pub async fn complete(&self, prompt: String) -> anyhow::Result<String> {
+        self.chat(vec![OpenAIMessage::new(Role::User, MessageContent::Text(prompt))])
+            .await
+    }
+
+    /// One-off completion of a prompt taken from a room, such as a message to translate. Personal data is masked
+    /// when the room's settings ask for that.
+    pub async fn complete_in(&self, settings: &RoomSettings, prompt: String) -> anyhow::Result<String> {
+        let scrub = settings.pii_scrubbing.unwrap_or(self.config.pii.enabled);
+        self.chat_scrubbed(
+            vec![OpenAIMessage::new(Role::User, MessageContent::Text(prompt))],
+            scrub,
+        )
+        .await
+    }
+
+    /// One-off completion of a list of messages kept by the caller, such as the sides of a debate. Like answers to
+    /// prompts, personal data is masked when that's configured and the answer has to pass the output filter.
+    pub async fn chat(&self, messages: Vec<OpenAIMessage>) -> anyhow::Result<String> {
+        self.chat_scrubbed(messages, self.config.pii.enabled).await
+    }
+
+    async fn chat_scrubbed(&self, mut messages: Vec<OpenAIMessage>, scrub: bool) -> anyhow::Result<String> {
+        let mut scrubber = scrub.then(|| self.pii.scrubber());
+        if let Some(scrubber) = &mut scrubber {
+            for message in &mut messages {
+                if let Some(content) = &message.content {
+                    message.content = Some(scrubber.scrub_content(content).await?);
+                }
+            }
+        }
+
+        let request = ChatRequest::new(self.config.openai.model.clone(), messages);
+        let response = self.post_completion(&request).await?;
+        let answer = response.choices.first().map(OpenAIChoice::text).unwrap_or_default();
+        let answer = match &scrubber {
+            Some(scrubber) => scrubber.restore(answer),
+            None => answer.to_string(),
+        };
+
+        if let Some(violation) = self.output_filter.check(self, &answer).await? {
+            return Err(anyhow::anyhow!("The answer violated the output filter: {violation}"));
+        }
+        Ok(answer)
+    }
+
+    /// Provider serving a model: Gemini for Gemini models when it's configured, the OpenAI compatible API otherwise.
+    pub fn chat_provider(&self, model: &str) -> &dyn ChatProvider {
+        match &self.gemini {
+            Some(gemini) if self.config.gemini.serves(model) => gemini,
+            _ => &self.chat,
+        }
+    }
+
+    /// Send a chat completion request, waiting for a free slot, and log the exchange when API logging is on.
+    pub async fn post_completion(&self, request: &ChatRequest) -> anyhow::Result<OpenAIResponse> {
+        let provider = self.chat_provider(&request.model);
+        let endpoint = &provider.endpoint(request)?;
+        let body = provider.body(request)?;
+        let request_id = client::request_id();
+        let span = tracing::info_span!("chat_completion", request_id);
+        let (status, text) = async {
+            let _permit = self.model_limiter.acquire().await?;
+            tracing::debug!("Requesting chat completion");
+            let response = provider
+                .post(endpoint.clone())
+                .header(REQUEST_ID_HEADER, &request_id)
+                .json(&body)
+                .send()
+                .await
+                .inspect_err(|error| tracing::warn!(request_id, "Chat completion request failed: {error}"))?;
+            anyhow::Ok((response.status(), response.text().await?))
+        }
+        .instrument(span)
+        .await?;
+        // Keep bodies that aren't JSON, such as a proxy's error page, so they can still be logged and reported.
+        let response = serde_json::from_str(&text).unwrap_or(Value::String(text));
+        self.api_log.record(endpoint, &body, &response).await;
+
+        if let Some(error) = OpenAIError::from_response(status, &response) {
+            tracing::warn!(request_id, "Chat completion failed: {error}");
+            return Err(error.into());
+        }
+        let response = provider.completion(response)?;
+        if response.choices.is_empty() {
+            return Err(OpenAIError::EmptyResponse.into());
+        }
+        Ok(response)
+    }
+
+    pub async fn event_ids(&self, user_id: &UserId, room_id: &RoomId) -> anyhow::Result<Vec<OwnedEventId>> {
+        let key = store::conversation_key(room_id, user_id);
+        Ok(self.store().load(&key).await?.unwrap_or_default())
+    }
+
+    /// Identifies the current conversation by its first event, which changes after a reset.
+    pub async fn conversation_id(&self, user_id: &UserId, room_id: &RoomId) -> anyhow::Result<Option<OwnedEventId>> {
+        Ok(self.event_ids(user_id, room_id).await?.first().cloned())
+    }
+
+    pub async fn insert_attachment(&self, event_id: OwnedEventId, content: MessageContent) {
+        self.attachments.write().await.insert(event_id, content);
+    }
+
+    /// Drop the message content kept for a room, after it switched to a retention mode that doesn't keep content:
+    /// content derived from its media, full tool results and the messages copied into a fork.
+    pub async fn forget_content(&self, room_id: &RoomId) -> anyhow::Result<()> {
+        let mut event_ids = Vec::new();
+        for key in self.store().keys(&store::room_key(room_id, "conversation/")).await? {
+            event_ids.extend(self.store().load::<Vec<OwnedEventId>>(&key).await?.unwrap_or_default());
+        }
+        {
+            let mut attachments = self.attachments.write().await;
+            for event_id in &event_ids {
+                attachments.remove(event_id);
+            }
+        }
+
+        for key in self.scratch().keys(&store::room_key(room_id, "tool_result/")).await? {
+            self.scratch().delete(&key).await?;
+        }
+        // Prompts suspended on a question hold the conversation so far, they are dropped rather than resumed.
+        for key in self.store().keys(&store::room_key(room_id, "questions/")).await? {
+            self.store().delete(&key).await?;
+        }
+
+        let key = store::room_key(room_id, "fork");
+        if let Some(mut fork) = self.store().load::<ForkOrigin>(&key).await? {
+            fork.events.clear();
+            self.store().save(&key, &fork).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn clear(&self, user_id: &UserId, room_id: &RoomId) -> anyhow::Result<()> {
+        let epoch = self.epoch(user_id, room_id).await?;
+        self.store()
+            .save(&store::epoch_key(room_id, user_id), &(epoch + 1))
+            .await?;
+        self.store().delete(&store::room_key(room_id, "fork")).await?;
+        self.store().delete(&store::conversation_key(room_id, user_id)).await
+    }
+
+    /// Epoch of the conversation, counting the resets so far. Replies carry it in their `bot_response` relation.
+    pub async fn epoch(&self, user_id: &UserId, room_id: &RoomId) -> anyhow::Result<u64> {
+        Ok(self
+            .store()
+            .load(&store::epoch_key(room_id, user_id))
+            .await?
+            .unwrap_or_default())
+    }
+
+    /// Carry the conversation in `from` over to `to`, where it continues on top of the messages so far, including
+    /// those `from` was forked from itself. Returns the number of messages carried over.
+    pub async fn fork(&self, user_id: &UserId, from: &Room, device: &Device, to: &RoomId) -> anyhow::Result<usize> {
+        let mut events = self.forked_events(from.id()).await?;
+        let event_ids = self.event_ids(user_id, from.id()).await?;
+        events.extend(load_messages(from, device, event_ids, self).await?);
+        let count = events.len();
+        let origin = ForkOrigin {
+            room_id: from.id().to_owned(),
+            events,
+        };
+        self.store().save(&store::room_key(to, "fork"), &origin).await?;
+        Ok(count)
+    }
+
+    /// Messages a room's conversation was forked with, if it's a fork.
+    async fn forked_events(&self, room_id: &RoomId) -> anyhow::Result<Vec<OriginalSyncRoomMessageEvent>> {
+        let fork: Option<ForkOrigin> = self.store().load(&store::room_key(room_id, "fork")).await?;
+        Ok(fork.map(|fork| fork.events).unwrap_or_default())
+    }
+
+    /// Carry a room's state over to the room replacing it after an upgrade. Settings, quotas and other room state
+    /// move along, and the conversation continues in the new room on top of the old room's messages, like a fork.
+    pub async fn migrate_room(&self, from: &Room, device: &Device, to: &RoomId) -> anyhow::Result<()> {
+        // A tombstone delivered again finds the new room set up already, which must not be overwritten.
+        for name in ["fork", "settings"] {
+            if self.store().get(&store::room_key(to, name)).await?.is_some() {
+                tracing::info!("{to} already has state, not migrating {} to it again", from.id());
+                return Ok(());
+            }
+        }
+        let count = self.fork(self.homeserver.user_id(), from, device, to).await?;
+
+        let from = from.id();
+        let prefix = store::room_key(from, "");
+        // Not every store can list all keys, account data only knows those seen since startup.
+        let mut keys = self.store().keys(&prefix).await?;
+        for name in MIGRATED_KEYS.iter().chain(&["fork"]) {
+            let key = store::room_key(from, name);
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        // The conversation went along with the fork, the rest is copied as is. The old room keeps nothing.
+        let mut stale = Vec::new();
+        for key in keys {
+            let Some(value) = self.store().get(&key).await? else {
+                continue;
+            };
+            let name = &key[prefix.len()..];
+            if name != "fork" && !name.starts_with("conversation/") {
+                self.store().set(&store::room_key(to, name), value).await?;
+            }
+            stale.push(key);
+        }
+        for key in &stale {
+            self.store().delete(key).await?;
+        }
+
+        tracing::info!("Migrated {} keys and {count} messages from {from} to {to}", stale.len());
+        Ok(())
     }
 
     pub async fn insert_events(
@@ -79,123 +621,226 @@ impl ConversationStore {
         user_id: &UserId,
         room_id: &RoomId,
         event_ids: impl IntoIterator<Item = OwnedEventId>,
-    ) {
-        let mut lock = self.inner.write().await;
-        lock.entry(user_id.to_owned())
-            .or_default()
-            .entry(room_id.to_owned())
-            .or_default()
-            .extend(event_ids);
+        retention_days: Option<u32>,
+    ) -> anyhow::Result<()> {
+        let _lock = self.store().lock(&store::conversation_key(room_id, user_id)).await;
+        let mut stored = self.event_ids(user_id, room_id).await?;
+        stored.extend(event_ids);
+        self.set(user_id, room_id, stored, retention_days).await
     }
 
-    pub async fn set(&self, user_id: &UserId, room_id: &RoomId, event_ids: Vec<OwnedEventId>) {
-        let mut lock = self.inner.write().await;
-        lock.entry(user_id.to_owned())
-            .or_default()
-            .entry(room_id.to_owned())
-            .insert_entry(event_ids);
+    /// Replace the stored conversation. In rooms with a retention period, the room's `retention_days`, it expires
+    /// after that long without activity.
+    pub async fn set(
+        &self,
+        user_id: &UserId,
+        room_id: &RoomId,
+        event_ids: Vec<OwnedEventId>,
+        retention_days: Option<u32>,
+    ) -> anyhow::Result<()> {
+        let key = store::conversation_key(room_id, user_id);
+        match retention_days {
+            Some(days) => {
+                let ttl = Duration::from_secs(u64::from(days) * 24 * 60 * 60);
+                self.store().save_expiring(&key, &event_ids, ttl).await
+            }
+            None => self.store().save(&key, &event_ids).await,
+        }
     }
 
-    pub async fn get_conversation<'a>(
+    pub async fn get_conversation(
         &self,
-        appservice: &'a ApplicationService<State<Arc<ConversationStore>>>,
-        user: &'a Arc<User>,
-        room: &'a Arc<Room>,
-    ) -> anyhow::Result<Conversation<'a>> {
-        let event_ids = {
-            let mut lock = self.inner.write().await;
-            lock.entry(user.id().to_owned())
-                .or_default()
-                .entry(room.id().to_owned())
-                .or_insert_with(|| Vec::new())
-                .clone()
-        };
+        appservice: &ApplicationService<State<Arc<ConversationStore>>>,
+        user: &Arc<User>,
+        room: &Arc<Room>,
+    ) -> anyhow::Result<Conversation> {
+        let event_ids = self.event_ids(user.id(), room.id()).await?;
 
         let device = user.get_device().await.context("Device not found")?;
-        let events = futures::stream::iter(event_ids)
-            .map(|event_id| {
-                let device = Arc::clone(&device);
-                async move {
-                    let raw_event = room.get_raw_event(&event_id).await?;
-                    let extracted = raw_event.deserialize_as::<ExtractType<'_>>()?;
-                    match extracted.event_type.as_ref() {
-                        "m.room.message" => Ok(raw_event.deserialize_as::<OriginalSyncRoomMessageEvent>()?),
-                        "m.room.encrypted" => {
-                            let decrypted = device.decrypt_event(raw_event.cast(), room.id()).await?;
-                            Ok(decrypted.event.deserialize_as::<OriginalSyncRoomMessageEvent>()?)
-                        }
-                        _ => Err(anyhow::anyhow!("Invalid event type provided")),
-                    }
-                }
-            })
-            .buffered(3)
-            .try_collect::<Vec<_>>()
-            .await?;
+        // A forked conversation starts with the messages of the room it was forked from.
+        let mut events = self.forked_events(room.id()).await?;
+        events.extend(load_messages(room, &device, event_ids, self).await?);
 
-        Ok(Conversation::from_events(appservice, user, room, device, &events)?)
+        let settings = RoomSettings::load(self.store(), room.id()).await?;
+        if let Some(cutoff) = settings.retention_cutoff() {
+            events.retain(|event| u64::from(event.origin_server_ts.0) >= cutoff);
+        }
+
+        let attachments = self.attachments.read().await;
+        Ok(Conversation::from_events(
+            appservice,
+            user,
+            room,
+            device,
+            &events,
+            &attachments,
+            settings,
+        )?)
     }
 }
 
-pub struct Conversation<'a> {
-    appservice: &'a ApplicationService<State<Arc<ConversationStore>>>,
-    config: OpenAIConfig,
-    user: &'a User,
-    room: &'a Room,
+pub struct Conversation {
+    appservice: ApplicationService<State<Arc<ConversationStore>>>,
+    settings: RoomSettings,
+    user: Arc<User>,
+    room: Arc<Room>,
     device: Arc<Device>,
     messages: Mutex<Vec<OpenAIMessage>>,
+    /// When the last message of the conversation was sent, `None` for a new conversation.
+    last_activity: Option<MilliSecondsSinceUnixEpoch>,
+    /// Who sent the prompt being answered, for tools and context tied to a person. `None` for prompts not sent
+    /// by anyone, such as scheduled ones.
+    sender: Option<OwnedUserId>,
+    /// Event of the prompt being answered, left out of history searches.
+    prompt_id: Option<OwnedEventId>,
 }
 
-impl Conversation<'_> {
-    pub fn from_events<'a>(
-        appservice: &'a ApplicationService<State<Arc<ConversationStore>>>,
-        user: &'a User,
-        room: &'a Room,
+impl Conversation {
+    pub fn from_events(
+        appservice: &ApplicationService<State<Arc<ConversationStore>>>,
+        user: &Arc<User>,
+        room: &Arc<Room>,
         device: Arc<Device>,
         events: &[OriginalSyncRoomMessageEvent],
-    ) -> anyhow::Result<Conversation<'a>> {
-        let messages = events.iter().map(|event| create_message(user.id(), event)).collect();
+        attachments: &HashMap<OwnedEventId, MessageContent>,
+        settings: RoomSettings,
+    ) -> anyhow::Result<Conversation> {
+        let messages = events
+            .iter()
+            .map(|event| {
+                let mut message = create_message(user.id(), event);
+                if let Some(content) = attachments.get(&event.event_id) {
+                    message.content = Some(content.clone());
+                }
+                message
+            })
+            .collect();
 
-        let config = appservice.get_user_fields::<Config>()?.openai;
         let conversation = Conversation {
-            appservice,
-            config,
-            user,
-            room,
+            appservice: appservice.clone(),
+            settings,
+            user: Arc::clone(user),
+            room: Arc::clone(room),
             device,
             messages: Mutex::new(messages),
+            last_activity: events.last().map(|event| event.origin_server_ts),
+            sender: None,
+            prompt_id: None,
         };
 
         Ok(conversation)
     }
 
     fn client(&self) -> &Client {
-        &self.appservice.state().client
+        self.appservice.state().client()
+    }
+
+    /// Answer prompts on behalf of `sender`.
+    pub fn with_sender(mut self, sender: &UserId) -> Self {
+        self.sender = Some(sender.to_owned());
+        self
+    }
+
+    /// Answer the prompt sent in `prompt_id`.
+    pub fn with_prompt(mut self, prompt_id: &EventId) -> Self {
+        self.prompt_id = Some(prompt_id.to_owned());
+        self
+    }
+
+    pub fn settings(&self) -> &RoomSettings {
+        &self.settings
     }
 
     pub async fn is_empty(&self) -> bool {
         self.messages.lock().await.is_empty()
     }
 
-    pub async fn backfill(&self) -> anyhow::Result<()> {
-        let (event_ids, mut messages): (Vec<_>, Vec<_>) = self
-            .room
-            .get_raw_message_stream(Direction::Backward)
-            .then(|raw| async { self.process_raw_event(raw?).await })
-            .try_filter_map(|maybe| future::ready(Ok(maybe)))
-            .scan((), |_, result| {
-                future::ready(match result {
-                    Ok(Processed::Continue(id, message)) => Some((id, message)),
-                    Ok(Processed::Stop) | Err(_) => None,
-                })
+    /// Epoch of the conversation, see [`ConversationStore::epoch`].
+    pub async fn epoch(&self) -> anyhow::Result<u64> {
+        self.appservice.state().epoch(self.user.id(), self.room.id()).await
+    }
+
+    pub fn last_activity(&self) -> Option<MilliSecondsSinceUnixEpoch> {
+        self.last_activity
+    }
+
+    /// The text of the conversation so far, one line per message, leaving out tool calls and media.
+    pub async fn transcript(&self) -> String {
+        let messages = self.messages.lock().await;
+        messages
+            .iter()
+            .filter(|message| message.role == "user" || message.role == "assistant")
+            .filter_map(|message| match &message.content {
+                Some(MessageContent::Text(text)) if !text.is_empty() => Some(format!("{}: {text}", message.role)),
+                _ => None,
             })
             .collect::<Vec<_>>()
-            .await
-            .into_iter()
-            .rev()
-            .unzip();
+            .join("\n")
+    }
+
+    /// Read the room's history into the conversation. Reading a long history posts a notice showing progress,
+    /// and can be stopped with `!cancel`, keeping the most recent messages read so far.
+    pub async fn backfill(&self) -> anyhow::Result<()> {
+        let state = self.appservice.state();
+        let locale = state.locale(&self.settings);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let _backfill = Backfill::register(&state.backfills, self.room.id(), &cancelled);
+
+        let started = Instant::now();
+        let mut last_notice = started;
+        let mut notice: Option<OwnedEventId> = None;
+        let mut read = Vec::new();
+        // Epoch of the conversation as far as the store knows it, otherwise that of the newest reply read.
+        let mut epoch = state
+            .store()
+            .load::<u64>(&store::epoch_key(self.room.id(), self.user.id()))
+            .await?;
+        let mut stream = std::pin::pin!(self.room.get_raw_message_stream(Direction::Backward));
+        while let Some(Ok(raw)) = stream.next().await {
+            if cancelled.load(Ordering::Relaxed) {
+                break;
+            }
+            match self.process_raw_event(raw, &mut epoch).await {
+                Ok(Some(Processed::Continue(id, message))) => read.push((id, message)),
+                Ok(Some(Processed::Stop | Processed::Boundary)) | Err(_) => break,
+                Ok(None) => continue,
+            }
+
+            let due = match notice {
+                None => started.elapsed() >= BACKFILL_NOTICE_DELAY,
+                Some(_) => last_notice.elapsed() >= BACKFILL_NOTICE_INTERVAL,
+            };
+            if due {
+                let text = state
+                    .locales()
+                    .text(locale, "backfill.progress", &[("count", &read.len())]);
+                notice = Some(self.post_status(notice, &text).await?);
+                last_notice = Instant::now();
+            }
+        }
+        state.metrics().increment("openai_bot_backfills_total", &[]);
+        state
+            .metrics()
+            .add("openai_bot_backfill_seconds_sum", &[], started.elapsed().as_secs_f64());
+        state
+            .metrics()
+            .add("openai_bot_backfill_messages_sum", &[], read.len() as f64);
+
+        if notice.is_some() {
+            let text = match cancelled.load(Ordering::Relaxed) {
+                true => state
+                    .locales()
+                    .text(locale, "backfill.stopped", &[("count", &read.len())]),
+                false => state.locales().text(locale, "backfill.done", &[("count", &read.len())]),
+            };
+            self.post_status(notice, &text).await?;
+        }
 
-        let store = Arc::clone(self.appservice.state());
-        store.set(self.user.id(), self.room.id(), event_ids).await;
+        let (event_ids, mut messages): (Vec<_>, Vec<_>) = read.into_iter().rev().unzip();
+        let store = Arc::clone(state);
+        store
+            .set(self.user.id(), self.room.id(), event_ids, self.settings.retention_days)
+            .await?;
 
         let mut lock = self.messages.lock().await;
         messages.append(&mut *lock);
@@ -204,63 +849,594 @@ impl Conversation<'_> {
         Ok(())
     }
 
-    pub async fn send_prompt(&self, prompt: String) -> anyhow::Result<String> {
+    /// Post a status notice, or edit the one posted before. Returns the ID of the original notice.
+    async fn post_status(&self, previous: Option<OwnedEventId>, text: &str) -> anyhow::Result<OwnedEventId> {
+        let content = self.appservice.state().config().behavior.notice(text);
+        match previous {
+            Some(previous) => {
+                let content = content.make_replacement(ReplacementMetadata::new(previous.clone(), None));
+                self.device.send_message(self.room.id(), content).await?;
+                Ok(previous)
+            }
+            None => Ok(self.device.send_message(self.room.id(), content).await?),
+        }
+    }
+
+    async fn insert_system_prompt(&self, messages: &mut Vec<OpenAIMessage>) -> anyhow::Result<()> {
+        if let Some(system) = prompt::system_prompt(
+            &self.appservice.state().config().prompt,
+            &self.settings,
+            &self.room_context().await?,
+        )? {
+            messages.insert(0, OpenAIMessage::new(Role::System, MessageContent::Text(system)));
+        }
+        Ok(())
+    }
+
+    /// The request body the next prompt would be sent with, minus the prompt itself: the system prompt, the
+    /// conversation so far after PII scrubbing, and the tools offered.
+    pub async fn preview(&self) -> anyhow::Result<ChatRequest> {
+        let mut messages = self.messages.lock().await.clone();
+        self.insert_system_prompt(&mut messages).await?;
+
+        let state = self.appservice.state();
+        let mut scrubber = self
+            .settings
+            .pii_scrubbing
+            .unwrap_or(state.config().pii.enabled)
+            .then(|| state.pii.scrubber());
+        self.create_prompt_request(&messages, &InlineDirectives::default(), scrubber.as_mut())
+            .await
+    }
+
+    pub async fn send_prompt(
+        &self,
+        prompt: MessageContent,
+        directives: &InlineDirectives,
+    ) -> anyhow::Result<Completion> {
         let mut messages = self.messages.lock().await;
-        messages.push(OpenAIMessage {
-            role: "user".to_string(),
-            content: Some(MessageContent::Text(prompt)),
-            tool_calls: Vec::new(),
-        });
+        messages.push(OpenAIMessage::new(Role::User, prompt));
+        self.insert_system_prompt(&mut messages).await?;
+        self.run_tools(&mut messages, directives, Progress::default()).await
+    }
 
-        let body = self.create_prompt_body(&messages)?;
-        let request = self
-            .client()
-            .post(self.config.endpoint.clone())
-            .json(&body)
-            .send()
-            .await?;
+    /// Continue a prompt suspended on a follow-up question where it left off, with the answer as the result of
+    /// the question. Without an answer, the model carries on with its best assumption.
+    pub async fn resume(&self, question: Question, answer: Option<String>) -> anyhow::Result<Completion> {
+        let mut messages = self.messages.lock().await;
+        *messages = question.messages;
+        let result = match answer {
+            Some(answer) => format!("The user answered: {answer}"),
+            None => "The user did not answer in time. Continue with your best assumption, and mention it.".to_string(),
+        };
+        messages.push(OpenAIMessage::tool_result(&question.call_id, result));
+        if !question.images.is_empty() {
+            messages.push(OpenAIMessage::new(Role::User, MessageContent::Parts(question.images)));
+        }
+        self.run_tools(&mut messages, &question.directives, question.progress)
+            .await
+    }
 
-        let response: OpenAIResponse = request.json().await?;
-        let action = into_actions(&response.choices.first().unwrap().message)?;
+    /// Let the model call tools until it replies, or until it asks a follow-up question, which suspends the prompt.
+    async fn run_tools(
+        &self,
+        messages: &mut Vec<OpenAIMessage>,
+        directives: &InlineDirectives,
+        progress: Progress,
+    ) -> anyhow::Result<Completion> {
+        let state = self.appservice.state();
+        let citations = Citations::default();
+        let context = ToolContext {
+            http: &state.http,
+            web: &state.web,
+            config: &state.config,
+            device: &self.device,
+            room: &self.room,
+            state,
+            citations: &citations,
+            sender: self.sender.as_deref(),
+            prompt_id: self.prompt_id.as_deref(),
+        };
+        let Progress {
+            mut usage,
+            mut tool_calls,
+            mut interim,
+            mut continuations,
+            mut stitched,
+            mut rounds,
+        } = progress;
 
-        if let Some(action) = action.first() {
-            match action {
-                AssistantAction::Reply(message) => return Ok(message.to_owned()),
-                AssistantAction::ToolCall(_) => {
-                    // let message = tool.run().await?;
-                    // messages.push(message);
-                    // // make_openai_request(messages, config).await
+        let scrub = self.settings.pii_scrubbing.unwrap_or(state.config().pii.enabled);
+        let announce = self
+            .settings
+            .announce_tools
+            .unwrap_or(state.config().behavior.announce_tools);
+        let mut scrubber = scrub.then(|| state.pii.scrubber());
+        let mut failures = 0;
+        let puppets = announce && state.puppets.active(self.room.id(), &self.settings).await?;
+
+        while rounds < MAX_TOOL_ROUNDS {
+            rounds += 1;
+            let request = self
+                .create_prompt_request(&messages, directives, scrubber.as_mut())
+                .await?;
+            let response = state.post_completion(&request).await?;
+            usage += &response.usage;
+
+            let choice = self
+                .select_choice(response.choices, &messages, scrubber.as_mut())
+                .await?;
+            if let Some(reason) = &choice.finish_reason {
+                state
+                    .metrics
+                    .increment("openai_bot_finish_reasons_total", &[("reason", reason)]);
+            }
+            let requested = choice.message.tool_calls.len();
+            if requested > 0
+                && let Some(reason) = state.config().limits.exceeded(tool_calls.len() + requested, &usage)
+            {
+                state.metrics.increment("openai_bot_budget_exceeded_total", &[]);
+                return Ok(Completion {
+                    content: format!("I stopped working on this because {reason}. Try asking something narrower."),
+                    citations: Vec::new(),
+                    sources: Vec::new(),
+                    model: response.model,
+                    usage,
+                    finish_reason: Some(BUDGET_EXCEEDED.to_string()),
+                    tool_calls,
+                    replaces: interim,
+                    continuations,
+                    question: None,
+                });
+            }
+            let actions = into_actions(&choice.message, &self.appservice.state().tools)?;
+
+            let mut reply = None;
+            let mut images = Vec::new();
+            let mut tool_results = Vec::new();
+            let mut asked = None;
+            for action in actions {
+                match action {
+                    AssistantAction::Reply(content) => {
+                        // Text sent along with tool calls is posted before they run, and edited once they're done.
+                        if !choice.message.tool_calls.is_empty()
+                            && state.config().behavior.interim_replies
+                            && interim.is_none()
+                            && !content.trim().is_empty()
+                        {
+                            let content = match &scrubber {
+                                Some(scrubber) => scrubber.restore(&content),
+                                None => content.clone(),
+                            };
+                            let message = state.config().behavior.reply(state.style().apply(&content));
+                            interim = Some(retry::send(state, &self.device, self.room.id(), message).await?);
+                        }
+                        reply = Some(content);
+                    }
+                    AssistantAction::Invalid(id, name, error) => {
+                        tracing::warn!("Model made an invalid call to {name}: {error}");
+                        state
+                            .metrics
+                            .increment("openai_bot_tool_errors_total", &[("tool", &name)]);
+                        failures += 1;
+                        let output = ToolOutput::error(&name, "invalid_call", error);
+                        tool_results.push(OpenAIMessage::tool_result(&id, output.text));
+                    }
+                    AssistantAction::ToolCall(id, tool) => {
+                        // A question suspends the prompt once the other tools of the round ran, see below. Prompts
+                        // nobody sent have nobody to ask, the tool tells the model so.
+                        if let Some(question) = tool.question()
+                            && let (Some(sender), Some(prompt_id)) = (&self.sender, &self.prompt_id)
+                        {
+                            match asked {
+                                None => asked = Some((id, question.to_string(), sender, prompt_id)),
+                                Some(_) => tool_results.push(OpenAIMessage::tool_result(
+                                    &id,
+                                    "Ask one question at a time.".to_string(),
+                                )),
+                            }
+                            continue;
+                        }
+                        tracing::debug!("Running tool {tool:?}");
+                        if announce {
+                            let notice = state.config().behavior.notice(tool.describe());
+                            // With puppets, each tool announces itself under its own ghost.
+                            match choice.message.tool_calls.iter().find(|call| puppets && call.id() == id) {
+                                Some(call) => state.puppets.send(self.room.id(), call.name(), &notice).await?,
+                                None => {
+                                    retry::send(state, &self.device, self.room.id(), notice).await?;
+                                }
+                            }
+                        }
+                        // Tools waiting on people don't hold a slot other rooms' tools could use meanwhile.
+                        let _permit = match tool.waits_for_people() {
+                            true => None,
+                            false => Some(self.appservice.state().tool_limiter.acquire().await?),
+                        };
+                        // Failures go back to the model as the result, so it can try another way or explain.
+                        let output = match tool.run(&context).await {
+                            Ok(output) => {
+                                failures = 0;
+                                output
+                            }
+                            Err(error) => {
+                                let name = tool.name();
+                                tracing::warn!("Tool {name} failed: {error:#}");
+                                state
+                                    .metrics
+                                    .increment("openai_bot_tool_errors_total", &[("tool", &name)]);
+                                failures += 1;
+                                ToolOutput::error(&name, failure_kind(&error), format!("{error:#}"))
+                            }
+                        };
+                        tool_results.push(OpenAIMessage::tool_result(&id, output.text));
+                        images.extend(output.images);
+                    }
                 }
             }
+
+            if let Some((call_id, text, sender, prompt_id)) = asked {
+                tool_calls.extend(choice.message.tool_calls.iter().map(|call| call.name().to_string()));
+                messages.push(choice.message);
+                messages.extend(tool_results);
+
+                let question_id =
+                    questions::post(state, &self.device, self.room.id(), self.room.is_direct().await, &text).await?;
+                let question = Question {
+                    prompt_id: prompt_id.clone(),
+                    question_id: question_id.clone(),
+                    call_id,
+                    messages: messages.clone(),
+                    images,
+                    directives: directives.clone(),
+                    progress: Progress {
+                        usage: usage.clone(),
+                        tool_calls: tool_calls.clone(),
+                        interim: interim.clone(),
+                        continuations,
+                        stitched,
+                        rounds,
+                    },
+                };
+                let timeout = Duration::from_secs(state.config().behavior.choice_timeout);
+                questions::save(state, &self.settings, self.room.id(), sender, &question, timeout).await?;
+                return Ok(Completion {
+                    content: String::new(),
+                    citations: Vec::new(),
+                    sources: Vec::new(),
+                    model: response.model,
+                    usage,
+                    finish_reason: None,
+                    tool_calls,
+                    replaces: interim,
+                    continuations,
+                    question: Some(question_id),
+                });
+            }
+
+            if tool_results.is_empty() {
+                let content = reply.context("Response contained neither a reply nor tool calls")?;
+                stitched.push_str(&content);
+
+                // Cut off at the token limit, ask the model to pick up where it stopped and stitch the parts.
+                if choice.finish_reason.as_deref() == Some("length") && continuations < MAX_CONTINUATIONS {
+                    continuations += 1;
+                    messages.push(OpenAIMessage::new(Role::Assistant, MessageContent::Text(content)));
+                    messages.push(OpenAIMessage::new(
+                        Role::User,
+                        MessageContent::Text(CONTINUE_PROMPT.to_string()),
+                    ));
+                    continue;
+                }
+
+                let content = match &scrubber {
+                    Some(scrubber) => scrubber.restore(&stitched),
+                    None => stitched,
+                };
+                let cited = citations.referenced(&content);
+                return Ok(Completion {
+                    sources: match state.config().behavior.sources {
+                        true => citations.consulted(&cited),
+                        false => Vec::new(),
+                    },
+                    citations: cited,
+                    content,
+                    model: response.model,
+                    usage,
+                    finish_reason: choice.finish_reason,
+                    tool_calls,
+                    replaces: interim,
+                    continuations,
+                    question: None,
+                });
+            }
+
+            tool_calls.extend(choice.message.tool_calls.iter().map(|call| call.name().to_string()));
+            let max_failures = state.config().limits.max_tool_failures;
+            if max_failures > 0 && failures >= max_failures {
+                return Ok(Completion {
+                    content: format!(
+                        "I stopped working on this because {failures} tool calls in a row failed. Try again later or \
+                         ask in a different way."
+                    ),
+                    citations: Vec::new(),
+                    sources: Vec::new(),
+                    model: response.model,
+                    usage,
+                    finish_reason: Some(TOOL_FAILURES.to_string()),
+                    tool_calls,
+                    replaces: interim,
+                    continuations,
+                    question: None,
+                });
+            }
+            messages.push(choice.message);
+            messages.extend(tool_results);
+            if !images.is_empty() {
+                messages.push(OpenAIMessage::new(Role::User, MessageContent::Parts(images)));
+            }
         }
 
-        Err(anyhow::anyhow!("Unable to parse message"))
+        Err(OpenAIError::ToolRounds(MAX_TOOL_ROUNDS).into())
+    }
+
+    /// Answer the last prompt again with an extra instruction, without storing anything, e.g. when the first answer
+    /// was withheld. Answers calling tools come back empty.
+    pub async fn regenerate(&self, instruction: &str) -> anyhow::Result<String> {
+        let mut messages = self.messages.lock().await.clone();
+        messages.push(OpenAIMessage::new(
+            Role::System,
+            MessageContent::Text(instruction.to_string()),
+        ));
+
+        let state = self.appservice.state();
+        let mut scrubber = self
+            .settings
+            .pii_scrubbing
+            .unwrap_or(state.config().pii.enabled)
+            .then(|| state.pii.scrubber());
+        let request = ChatRequest {
+            n: None,
+            ..self
+                .create_prompt_request(&messages, &InlineDirectives::default(), scrubber.as_mut())
+                .await?
+        };
+        let response = state.post_completion(&request).await?;
+        let answer = response.choices.first().map(OpenAIChoice::text).unwrap_or_default();
+
+        Ok(match &scrubber {
+            Some(scrubber) => scrubber.restore(answer),
+            None => answer.to_string(),
+        })
     }
 
-    pub async fn insert_dialog(&self, prompt_id: OwnedEventId, response_id: OwnedEventId) {
+    pub async fn insert_dialog(&self, prompt_id: OwnedEventId, response_id: OwnedEventId) -> anyhow::Result<()> {
         self.appservice
             .state()
-            .insert_events(self.user.id(), self.room.id(), [prompt_id, response_id])
+            .insert_events(
+                self.user.id(),
+                self.room.id(),
+                [prompt_id, response_id],
+                self.settings.retention_days,
+            )
             .await
     }
 
-    fn create_prompt_body(&self, messages: &[OpenAIMessage]) -> anyhow::Result<Value> {
-        Ok(json!({
-            "model": &self.config.model,
-            "messages": messages,
-            "tools": Tool::schemas()?,
-        }))
+    async fn room_context(&self) -> anyhow::Result<RoomContext> {
+        let state = self.appservice.state();
+        let mut context = RoomContext::default();
+        if state.config().prompt.room_details {
+            let details = state.room_details.get(state.homeserver(), self.room.id()).await;
+            context.name = details.name;
+            context.topic = details.topic;
+        }
+        if state.config().prompt.participants {
+            context.participants = state.participants.get(state.homeserver(), self.room.id()).await?;
+        }
+        if let Some(sender) = &self.sender {
+            // Other members could ask the bot what it knows about the sender, so facts stay in DMs.
+            if self.room.is_direct().await {
+                let memories = Memories::load(state.store(), &state.config().memory, sender).await?;
+                context.facts = memories.facts.into_iter().map(|fact| fact.text).collect();
+            }
+            context.sender = Some(sender.clone());
+        }
+        // Display names come from the member list, only fetched when the system prompt asks for one.
+        let system = state.config().prompt.system.as_deref().unwrap_or_default();
+        if let Some(sender) = &self.sender
+            && system.contains("user_display_name")
+        {
+            context.sender_name = state
+                .participants
+                .get(state.homeserver(), self.room.id())
+                .await?
+                .into_iter()
+                .find(|participant| participant.user_id == *sender)
+                .and_then(|participant| participant.display_name);
+        }
+
+        Ok(context)
+    }
+
+    async fn create_prompt_request(
+        &self,
+        messages: &[OpenAIMessage],
+        directives: &InlineDirectives,
+        scrubber: Option<&mut Scrubber<'_>>,
+    ) -> anyhow::Result<ChatRequest> {
+        let messages = match scrubber {
+            Some(scrubber) => {
+                let mut scrubbed = Vec::with_capacity(messages.len());
+                for message in messages {
+                    let mut message = message.clone();
+                    if let Some(content) = &message.content {
+                        message.content = Some(scrubber.scrub_content(content).await?);
+                    }
+                    scrubbed.push(message);
+                }
+                scrubbed
+            }
+            None => messages.to_vec(),
+        };
+
+        let has_images = messages
+            .iter()
+            .any(|message| message.content.as_ref().is_some_and(MessageContent::has_images));
+        let state = self.appservice.state();
+        let config = &state.config().openai;
+        let model = match (&directives.model, &config.vision_model) {
+            (Some(model), _) => model,
+            (None, Some(vision_model)) if has_images => vision_model,
+            _ => self.settings.model.as_ref().unwrap_or(&config.model),
+        };
+
+        Ok(ChatRequest {
+            tools: state
+                .tools
+                .schemas(state.config(), self.room.id(), self.sender.as_deref())?,
+            n: self.settings.completions.filter(|n| *n > 1),
+            temperature: directives.temperature,
+            max_tokens: directives.max_tokens,
+            ..ChatRequest::new(model.clone(), messages)
+        })
     }
 
-    async fn process_raw_event(&self, raw_event: Raw<AnySyncTimelineEvent>) -> anyhow::Result<Option<Processed>> {
-        fn handle_event(user_id: &UserId, event: OriginalSyncRoomMessageEvent) -> anyhow::Result<Option<Processed>> {
-            if let Some(command) = Command::parse(event.content.body()) {
-                return Ok(command.into_processed());
+    /// Pick the answer to continue with when several completions were requested. Responses calling
+    /// tools always continue with the first choice.
+    async fn select_choice(
+        &self,
+        mut choices: Vec<OpenAIChoice>,
+        messages: &[OpenAIMessage],
+        mut scrubber: Option<&mut Scrubber<'_>>,
+    ) -> anyhow::Result<OpenAIChoice> {
+        if choices.len() <= 1 || choices.iter().any(|choice| !choice.message.tool_calls.is_empty()) {
+            return choices.into_iter().next().context("Response contained no choices");
+        }
+
+        let index = match self.settings.choice_selection.unwrap_or_default() {
+            ChoiceSelection::Heuristic => best_by_heuristic(&choices),
+            ChoiceSelection::Judge => self
+                .judge(&choices, messages, scrubber.as_deref_mut())
+                .await
+                .unwrap_or_else(|error| {
+                    tracing::warn!("Judging completions failed, falling back to heuristic: {error}");
+                    best_by_heuristic(&choices)
+                }),
+            ChoiceSelection::React => {
+                let options = choices
+                    .iter()
+                    .take(2)
+                    .map(|choice| {
+                        let text = choice.text();
+                        match scrubber.as_deref() {
+                            Some(scrubber) => scrubber.restore(text),
+                            None => text.to_string(),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                let state = self.appservice.state();
+                let timeout = Duration::from_secs(state.config().behavior.choice_timeout);
+                state
+                    .menus()
+                    .ask(
+                        &self.device,
+                        self.room.id(),
+                        self.sender.as_deref(),
+                        "Which answer do you prefer?",
+                        &options,
+                        timeout,
+                    )
+                    .await?
+                    .unwrap_or_default()
             }
+        };
 
-            let message = create_message(user_id, &event);
-            Ok(Some(Processed::Continue(event.event_id, message)))
+        Ok(choices.swap_remove(index))
+    }
+
+    /// Ask the model which candidate answers the latest prompt best, masking personal data the same way as in the
+    /// prompt.
+    async fn judge(
+        &self,
+        choices: &[OpenAIChoice],
+        messages: &[OpenAIMessage],
+        scrubber: Option<&mut Scrubber<'_>>,
+    ) -> anyhow::Result<usize> {
+        let question = messages
+            .iter()
+            .rev()
+            .find(|message| message.role == Role::User.to_string())
+            .and_then(|message| match &message.content {
+                Some(MessageContent::Text(text)) => Some(text.as_str()),
+                _ => None,
+            })
+            .unwrap_or_default();
+        let mut texts = [question]
+            .into_iter()
+            .chain(choices.iter().map(OpenAIChoice::text))
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        if let Some(scrubber) = scrubber {
+            for text in &mut texts {
+                *text = scrubber.scrub(text).await?;
+            }
         }
+        let (question, answers) = texts.split_first().context("Question is missing")?;
+        let candidates = answers
+            .iter()
+            .enumerate()
+            .map(|(index, answer)| format!("Answer {}:\n{answer}", index + 1))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let prompt = format!(
+            "Question:\n{question}\n\n{candidates}\n\nWhich answer is the most accurate and helpful? Reply with only its number."
+        );
+
+        let verdict = self.appservice.state().complete(prompt).await?;
+        let number = verdict
+            .chars()
+            .skip_while(|c| !c.is_ascii_digit())
+            .take_while(char::is_ascii_digit)
+            .collect::<String>()
+            .parse::<usize>()?;
+
+        if !(1..=choices.len()).contains(&number) {
+            return Err(anyhow::anyhow!("Judge picked answer {number} out of {}", choices.len()));
+        }
+        Ok(number - 1)
+    }
+
+    async fn process_raw_event(
+        &self,
+        raw_event: Raw<AnySyncTimelineEvent>,
+        epoch: &mut Option<u64>,
+    ) -> anyhow::Result<Option<Processed>> {
+        let cutoff = self.settings.retention_cutoff();
+        let notice_replies = self.appservice.state().config().behavior.reply_msgtype == Some(ReplyMsgtype::Notice);
+        let puppets = self.appservice.state().puppets();
+        let handle_event =
+            |user_id: &UserId, event: OriginalSyncRoomMessageEvent| -> anyhow::Result<Option<Processed>> {
+                // History before the retention period is off limits.
+                if cutoff.is_some_and(|cutoff| u64::from(event.origin_server_ts.0) < cutoff) {
+                    return Ok(Some(Processed::Stop));
+                }
+                // Ghosts post debate turns and tool announcements for the bot, which aren't part of the conversation.
+                if puppets.is_puppet(&event.sender) {
+                    return Ok(None);
+                }
+                // Notices of the bot are status updates, tool announcements and command responses rather than
+                // answers, unless answers are sent as notices too.
+                let notice = matches!(event.content.msgtype, MessageType::Notice(_));
+                if event.sender == user_id && notice && !notice_replies {
+                    return Ok(None);
+                }
+                if let Some(command) = Command::parse(event.content.body()) {
+                    return Ok(command.into_processed());
+                }
+
+                let message = create_message(user_id, &event);
+                Ok(Some(Processed::Continue(event.event_id, message)))
+            };
 
         let extracted = match raw_event.deserialize_as::<ExtractType<'_>>() {
             Ok(extracted_type) => extracted_type,
@@ -270,6 +1446,19 @@ impl Conversation<'_> {
             }
         };
 
+        // Replies from an earlier epoch mark a reset, even when the `!reset` itself can't be read anymore.
+        if let Some(response) = BotResponse::from_event(&raw_event) {
+            match *epoch {
+                Some(current) if response.epoch < current => return Ok(Some(Processed::Boundary)),
+                Some(_) => (),
+                None => *epoch = Some(response.epoch),
+            }
+            // Responses to commands are tagged as such, whatever their message type.
+            if response.model.is_none() {
+                return Ok(None);
+            }
+        }
+
         match extracted.event_type.as_ref() {
             "m.room.member" => {
                 let event = raw_event.deserialize_as::<StrippedRoomMemberEvent>()?;
@@ -283,8 +1472,8 @@ impl Conversation<'_> {
                 handle_event(self.user.id(), event)
             }
             "m.room.encrypted" => {
-                let decrypted = self.device.decrypt_event(raw_event.cast(), self.room.id()).await?;
-                let event = decrypted.event.deserialize_as::<OriginalSyncRoomMessageEvent>()?;
+                let metrics = self.appservice.state().metrics();
+                let event = decrypt(&self.room, &self.device, raw_event, metrics).await?;
                 handle_event(self.user.id(), event)
             }
             _ => return Ok(None),
@@ -292,6 +1481,81 @@ impl Conversation<'_> {
     }
 }
 
+/// Fetch a message event from a room, decrypting it if needed.
+pub async fn load_message(
+    room: &Room,
+    device: &Device,
+    event_id: &EventId,
+    state: &ConversationStore,
+) -> anyhow::Result<OriginalSyncRoomMessageEvent> {
+    let raw_event = retry(
+        state,
+        "get_event",
+        || async move { Ok(room.get_raw_event(event_id).await?) },
+    )
+    .await?;
+    parse_message(room, device, raw_event, state.metrics())
+        .await?
+        .context("Invalid event type provided")
+}
+
+async fn load_messages(
+    room: &Room,
+    device: &Device,
+    event_ids: Vec<OwnedEventId>,
+    state: &ConversationStore,
+) -> anyhow::Result<Vec<OriginalSyncRoomMessageEvent>> {
+    futures::stream::iter(event_ids)
+        .map(|event_id| async move { load_message(room, device, &event_id, state).await })
+        .buffered(3)
+        .try_collect()
+        .await
+}
+
+/// Deserialize a timeline event as a message, decrypting it if needed. Returns `None` for other event types.
+pub async fn parse_message(
+    room: &Room,
+    device: &Device,
+    raw_event: Raw<AnySyncTimelineEvent>,
+    metrics: &Metrics,
+) -> anyhow::Result<Option<OriginalSyncRoomMessageEvent>> {
+    let extracted = raw_event.deserialize_as::<ExtractType<'_>>()?;
+    match extracted.event_type.as_ref() {
+        "m.room.message" => Ok(Some(raw_event.deserialize_as::<OriginalSyncRoomMessageEvent>()?)),
+        "m.room.encrypted" => Ok(Some(decrypt(room, device, raw_event, metrics).await?)),
+        _ => Ok(None),
+    }
+}
+
+/// Decrypt a message event, counting failures so unable-to-decrypt problems show up in the metrics.
+async fn decrypt(
+    room: &Room,
+    device: &Device,
+    raw_event: Raw<AnySyncTimelineEvent>,
+    metrics: &Metrics,
+) -> anyhow::Result<OriginalSyncRoomMessageEvent> {
+    match device.decrypt_event(raw_event.cast(), room.id()).await {
+        Ok(decrypted) => Ok(decrypted.event.deserialize_as::<OriginalSyncRoomMessageEvent>()?),
+        Err(error) => {
+            metrics.increment("openai_bot_decrypt_failures_total", &[]);
+            Err(error.into())
+        }
+    }
+}
+
+/// Prefer answers that finished on their own over truncated ones, then the most thorough.
+fn best_by_heuristic(choices: &[OpenAIChoice]) -> usize {
+    choices
+        .iter()
+        .enumerate()
+        .max_by_key(|(index, choice)| {
+            let finished = choice.finish_reason.as_deref() == Some("stop");
+            (finished, choice.text().len(), std::cmp::Reverse(*index))
+        })
+        .map(|(index, _)| index)
+        .unwrap_or_default()
+}
+
 fn create_message(bot_id: &UserId, event: &OriginalSyncRoomMessageEvent) -> OpenAIMessage {
     let role = if event.sender == bot_id {
         Role::Assistant
@@ -299,26 +1563,69 @@ fn create_message(bot_id: &UserId, event: &OriginalSyncRoomMessageEvent) -> Open
         Role::User
     };
 
-    let message = OpenAIMessage {
-        role: role.to_string(),
-        content: Some(MessageContent::Text(event.content.body().to_string())),
-        tool_calls: Vec::new(),
+    // Edits carry the full replacement text in `m.new_content`, the body is only a fallback.
+    let body = match &event.content.relates_to {
+        Some(Relation::Replacement(replacement)) => replacement.new_content.msgtype.body(),
+        _ => event.content.body(),
+    };
+    let (_, body) = InlineDirectives::parse(body);
+    let body = match event.sender == bot_id {
+        true => citations::strip_footer(strip_debug_footer(body)),
+        false => body,
+    };
+    // The bot's own emotes are shown the way it writes them, so it keeps using the same form.
+    let body = match &event.content.msgtype {
+        MessageType::Emote(_) if event.sender == bot_id => format!("/me {body}"),
+        MessageType::Emote(_) => frame_emote(&event.sender, body),
+        _ => body.to_string(),
     };
 
-    message
+    OpenAIMessage::new(role, MessageContent::Text(body))
 }
 
-pub fn into_actions(message: &OpenAIMessage) -> anyhow::Result<Vec<AssistantAction>> {
+/// Rough category of a tool failure, so the model can tell a missing page from a bug in its own arguments.
+fn failure_kind(error: &anyhow::Error) -> &'static str {
+    if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+        return match error.status() {
+            Some(status) if status == reqwest::StatusCode::NOT_FOUND => "not_found",
+            Some(_) => "http_status",
+            None if error.is_timeout() => "timeout",
+            None => "network",
+        };
+    }
+    if error.downcast_ref::<tokio::time::error::Elapsed>().is_some() {
+        return "timeout";
+    }
+    match error.downcast_ref::<serde_json::Error>() {
+        Some(_) => "parse",
+        None => "failed",
+    }
+}
+
+/// An `m.emote` as a third-person action, e.g. "*alice shrugs*".
+pub fn frame_emote(sender: &UserId, action: &str) -> String {
+    format!("*{} {action}*", sender.localpart())
+}
+
+/// What the model asked for in a message. A refusal is shown as the reply, a message with neither content nor tool
+/// calls had its content withheld by the provider's filter.
+pub fn into_actions(message: &OpenAIMessage, tools: &ToolRegistry) -> anyhow::Result<Vec<AssistantAction>> {
     let mut actions = Vec::new();
 
-    match &message.content {
-        Some(MessageContent::Text(body)) => actions.push(AssistantAction::Reply(body.clone())),
-        _ => return Err(anyhow::anyhow!("unknown type")),
+    match (&message.content, &message.refusal) {
+        (Some(MessageContent::Text(body)), _) => actions.push(AssistantAction::Reply(body.clone())),
+        (None, Some(refusal)) => actions.push(AssistantAction::Reply(refusal.clone())),
+        (None, None) if !message.tool_calls.is_empty() => (),
+        (None, None) => return Err(OpenAIError::FilteredResponse.into()),
+        (Some(MessageContent::Parts(_)), _) => return Err(anyhow::anyhow!("unknown type")),
     }
 
     for tool_call in &message.tool_calls {
-        let tool = tool_call.try_into()?;
-        actions.push(AssistantAction::ToolCall(tool));
+        let id = tool_call.id().to_string();
+        actions.push(match tools.resolve(tool_call) {
+            Ok(tool) => AssistantAction::ToolCall(id, tool),
+            Err(error) => AssistantAction::Invalid(id, tool_call.name().to_string(), format!("{error:#}")),
+        });
     }
 
     Ok(actions)
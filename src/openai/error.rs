@@ -0,0 +1,111 @@
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use super::api::{ApiError, ErrorEnvelope};
+
+/// Failed chat completion request, classified so the user can be told what went wrong.
+#[derive(Debug)]
+pub enum OpenAIError {
+    RateLimited(ApiError),
+    /// The account ran out of credits or hit its spending limit.
+    QuotaExceeded(ApiError),
+    ContextLength(ApiError),
+    Authentication(ApiError),
+    ContentFilter(ApiError),
+    Server(ApiError),
+    Other(ApiError),
+    /// A successful response without any choices.
+    EmptyResponse,
+    /// A choice without content or tool calls, its content withheld by the provider's filter.
+    FilteredResponse,
+    /// The model kept calling tools for this many rounds without replying.
+    ToolRounds(usize),
+}
+
+impl OpenAIError {
+    /// The error described by a response, if any. Some proxies send an error envelope with a 200 status, so the body
+    /// is checked regardless of the status.
+    pub fn from_response(status: StatusCode, body: &Value) -> Option<Self> {
+        if let Ok(envelope) = serde_json::from_value::<ErrorEnvelope>(body.clone()) {
+            return Some(Self::classify(status, envelope.error));
+        }
+        if status.is_success() {
+            return None;
+        }
+
+        let message = match body {
+            Value::String(text) if !text.is_empty() => text.clone(),
+            _ => status.to_string(),
+        };
+        let error = ApiError {
+            message,
+            kind: None,
+            code: None,
+            param: None,
+        };
+        Some(Self::classify(status, error))
+    }
+
+    fn classify(status: StatusCode, error: ApiError) -> Self {
+        let code = match &error.code {
+            Some(Value::String(code)) => code.as_str(),
+            _ => "",
+        };
+        let kind = error.kind.as_deref().unwrap_or_default();
+        let message = error.message.to_lowercase();
+
+        if code == "insufficient_quota" || kind == "insufficient_quota" {
+            Self::QuotaExceeded(error)
+        } else if code == "context_length_exceeded" || message.contains("context length") {
+            Self::ContextLength(error)
+        } else if code == "content_filter" || code == "content_policy_violation" {
+            Self::ContentFilter(error)
+        } else if status == StatusCode::TOO_MANY_REQUESTS || code == "rate_limit_exceeded" {
+            Self::RateLimited(error)
+        } else if matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+            || kind == "authentication_error"
+            || code == "invalid_api_key"
+        {
+            Self::Authentication(error)
+        } else if status.is_server_error() || kind == "server_error" {
+            Self::Server(error)
+        } else {
+            Self::Other(error)
+        }
+    }
+
+    /// Locale key of the message shown in the room instead of a reply. Details stay in the logs.
+    pub fn message_key(&self) -> &'static str {
+        match self {
+            Self::RateLimited(_) => "error.rate_limited",
+            Self::QuotaExceeded(_) => "error.quota_exceeded",
+            Self::ContextLength(_) => "error.context_length",
+            Self::Authentication(_) => "error.authentication",
+            Self::ContentFilter(_) => "error.request_blocked",
+            Self::Server(_) => "error.server",
+            Self::Other(_) => "error.other",
+            Self::EmptyResponse => "error.empty_response",
+            Self::FilteredResponse => "error.content_filter",
+            Self::ToolRounds(_) => "error.tool_rounds",
+        }
+    }
+}
+
+impl std::fmt::Display for OpenAIError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RateLimited(error) => write!(f, "Rate limited: {error}"),
+            Self::QuotaExceeded(error) => write!(f, "Quota exceeded: {error}"),
+            Self::ContextLength(error) => write!(f, "Context length exceeded: {error}"),
+            Self::Authentication(error) => write!(f, "Authentication failed: {error}"),
+            Self::ContentFilter(error) => write!(f, "Blocked by content filter: {error}"),
+            Self::Server(error) => write!(f, "Provider error: {error}"),
+            Self::Other(error) => write!(f, "Request failed: {error}"),
+            Self::EmptyResponse => f.write_str("Response contained no choices"),
+            Self::FilteredResponse => f.write_str("Response was withheld by content filter"),
+            Self::ToolRounds(rounds) => write!(f, "Exceeded {rounds} tool rounds without a reply"),
+        }
+    }
+}
+
+impl std::error::Error for OpenAIError {}
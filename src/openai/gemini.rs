@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use reqwest::{
+    Client, RequestBuilder,
+    header::{HeaderMap, HeaderValue},
+};
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use url::Url;
+
+use super::{
+    ChatProvider, ChatRequest, ContentPart, MessageContent, OpenAIError, OpenAIResponse,
+    api::{ApiError, ResponseFormat, ToolChoice},
+};
+use crate::client::{self, ClientConfig};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GeminiConfig {
+    /// Gemini API key. When set, requests for `gemini-…` models, e.g. through a room's `model` setting, go to the
+    /// Gemini API instead of `openai.endpoint`.
+    pub api_key: Option<String>,
+    /// Base URL of the Gemini API.
+    pub endpoint: Url,
+}
+
+impl Default for GeminiConfig {
+    fn default() -> Self {
+        Self {
+            api_key: None,
+            endpoint: Url::parse("https://generativelanguage.googleapis.com/v1beta/").unwrap(),
+        }
+    }
+}
+
+impl GeminiConfig {
+    /// Whether requests for a model are sent to the Gemini API.
+    pub fn serves(&self, model: &str) -> bool {
+        self.api_key.is_some() && model.trim_start_matches("models/").starts_with("gemini")
+    }
+}
+
+/// Google's Gemini `generateContent` API.
+pub struct Gemini {
+    endpoint: Url,
+    client: Client,
+}
+
+impl Gemini {
+    /// The Gemini API, if an API key is configured.
+    pub fn from_config(config: &GeminiConfig, client_config: &ClientConfig) -> anyhow::Result<Option<Self>> {
+        let Some(api_key) = &config.api_key else {
+            return Ok(None);
+        };
+
+        let mut key = HeaderValue::from_str(api_key)?;
+        key.set_sensitive(true);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-goog-api-key", key);
+
+        Ok(Some(Self {
+            endpoint: config.endpoint.clone(),
+            client: client::provider_builder(client_config, headers)?.build()?,
+        }))
+    }
+}
+
+impl ChatProvider for Gemini {
+    fn name(&self) -> &'static str {
+        "Gemini API"
+    }
+
+    fn base_url(&self) -> &Url {
+        &self.endpoint
+    }
+
+    fn endpoint(&self, request: &ChatRequest) -> anyhow::Result<Url> {
+        let model = request.model.trim_start_matches("models/");
+        Ok(self.endpoint.join(&format!("models/{model}:generateContent"))?)
+    }
+
+    fn body(&self, request: &ChatRequest) -> anyhow::Result<Value> {
+        let mut system = Vec::new();
+        let mut contents: Vec<Value> = Vec::new();
+        // Tool results refer to their call by ID, Gemini's function responses by the function's name.
+        let mut names = HashMap::new();
+
+        for message in &request.messages {
+            let (role, parts) = match message.role.as_str() {
+                "system" => {
+                    system.extend(parts(message.content.as_ref()));
+                    continue;
+                }
+                "assistant" => {
+                    let mut parts = parts(message.content.as_ref());
+                    for call in &message.tool_calls {
+                        names.insert(call.id(), call.name());
+                        let args: Value = serde_json::from_str(call.arguments()).unwrap_or_else(|_| json!({}));
+                        parts.push(json!({ "function_call": { "name": call.name(), "args": args } }));
+                    }
+                    ("model", parts)
+                }
+                "tool" => {
+                    let id = message.tool_call_id.as_deref().unwrap_or_default();
+                    let output = match &message.content {
+                        Some(MessageContent::Text(text)) => text.as_str(),
+                        _ => "",
+                    };
+                    let response = json!({
+                        "function_response": {
+                            "name": names.get(id).copied().unwrap_or(id),
+                            "response": { "output": output },
+                        }
+                    });
+                    ("user", vec![response])
+                }
+                _ => ("user", parts(message.content.as_ref())),
+            };
+            if parts.is_empty() {
+                continue;
+            }
+
+            // Turns have to alternate, so consecutive messages of one side are merged.
+            if let Some(last) = contents.last_mut()
+                && last["role"] == role
+                && let Some(previous) = last["parts"].as_array_mut()
+            {
+                previous.extend(parts);
+            } else {
+                contents.push(json!({ "role": role, "parts": parts }));
+            }
+        }
+
+        let mut body = json!({ "contents": contents });
+        if !system.is_empty() {
+            body["system_instruction"] = json!({ "parts": system });
+        }
+
+        let declarations: Vec<Value> = request
+            .tools
+            .iter()
+            .filter_map(|tool| tool.get("function"))
+            .map(|function| {
+                json!({
+                    "name": function["name"],
+                    "description": function["description"],
+                    "parameters_json_schema": function["parameters"],
+                })
+            })
+            .collect();
+        if !declarations.is_empty() {
+            body["tools"] = json!([{ "function_declarations": declarations }]);
+        }
+        if let Some(choice) = &request.tool_choice {
+            let config = match choice {
+                ToolChoice::Auto => json!({ "mode": "AUTO" }),
+                ToolChoice::None => json!({ "mode": "NONE" }),
+                ToolChoice::Required => json!({ "mode": "ANY" }),
+                ToolChoice::Function(name) => json!({ "mode": "ANY", "allowed_function_names": [name] }),
+            };
+            body["tool_config"] = json!({ "function_calling_config": config });
+        }
+
+        let mut generation = Map::new();
+        if let Some(temperature) = request.temperature {
+            generation.insert("temperature".to_string(), json!(temperature));
+        }
+        if let Some(max_tokens) = request.max_tokens {
+            generation.insert("max_output_tokens".to_string(), json!(max_tokens));
+        }
+        if let Some(n) = request.n {
+            generation.insert("candidate_count".to_string(), json!(n));
+        }
+        match &request.response_format {
+            Some(ResponseFormat::JsonObject) => {
+                generation.insert("response_mime_type".to_string(), json!("application/json"));
+            }
+            Some(ResponseFormat::JsonSchema { json_schema }) => {
+                generation.insert("response_mime_type".to_string(), json!("application/json"));
+                generation.insert("response_json_schema".to_string(), json_schema["schema"].clone());
+            }
+            Some(ResponseFormat::Text) | None => {}
+        }
+        if !generation.is_empty() {
+            body["generation_config"] = Value::Object(generation);
+        }
+
+        Ok(body)
+    }
+
+    fn post(&self, endpoint: Url) -> RequestBuilder {
+        self.client.post(endpoint)
+    }
+
+    fn completion(&self, response: Value) -> anyhow::Result<OpenAIResponse> {
+        let response: GenerateContentResponse = serde_json::from_value(response)?;
+        if response.candidates.is_empty()
+            && let Some(reason) = response.prompt_feedback.and_then(|feedback| feedback.block_reason)
+        {
+            return Err(OpenAIError::ContentFilter(ApiError {
+                message: format!("Prompt blocked: {reason}"),
+                kind: None,
+                code: Some(Value::String("content_filter".to_string())),
+                param: None,
+            })
+            .into());
+        }
+
+        let choices: Vec<Value> = response
+            .candidates
+            .into_iter()
+            .enumerate()
+            .map(|(index, candidate)| {
+                let parts = candidate.content.map(|content| content.parts).unwrap_or_default();
+                let text: String = parts
+                    .iter()
+                    .filter(|part| !part.thought)
+                    .filter_map(|part| part.text.as_deref())
+                    .collect();
+                let tool_calls: Vec<Value> = parts
+                    .iter()
+                    .filter_map(|part| part.function_call.as_ref())
+                    .enumerate()
+                    .map(|(index, call)| {
+                        json!({
+                            "id": call.id.clone().unwrap_or_else(|| format!("call_{index}")),
+                            "type": "function",
+                            "function": { "name": call.name, "arguments": call.args.to_string() },
+                        })
+                    })
+                    .collect();
+                let finish_reason = match candidate.finish_reason.as_deref() {
+                    _ if !tool_calls.is_empty() => Some("tool_calls".to_string()),
+                    Some(reason) => Some(finish_reason(reason)),
+                    None => None,
+                };
+
+                json!({
+                    "index": index,
+                    "message": {
+                        "role": "assistant",
+                        "content": (!text.is_empty()).then_some(text),
+                        "tool_calls": tool_calls,
+                    },
+                    "finish_reason": finish_reason,
+                })
+            })
+            .collect();
+
+        let usage = response.usage_metadata;
+        Ok(serde_json::from_value(json!({
+            "id": response.response_id,
+            "object": "chat.completion",
+            "created": Utc::now().timestamp(),
+            "model": response.model_version,
+            "choices": choices,
+            "usage": {
+                "prompt_tokens": usage.prompt_token_count,
+                "completion_tokens": usage.candidates_token_count,
+                "total_tokens": usage.total_token_count,
+            },
+        }))?)
+    }
+}
+
+/// Gemini parts of a message's content. Images are sent inline when given as a data URL.
+fn parts(content: Option<&MessageContent>) -> Vec<Value> {
+    match content {
+        Some(MessageContent::Text(text)) if !text.is_empty() => vec![json!({ "text": text })],
+        Some(MessageContent::Parts(parts)) => parts
+            .iter()
+            .map(|part| match part {
+                ContentPart::Text { text } => json!({ "text": text }),
+                ContentPart::ImageUrl { image_url } => {
+                    let url = image_url.url();
+                    match url.strip_prefix("data:").and_then(|data| data.split_once(";base64,")) {
+                        Some((mime_type, data)) => json!({ "inline_data": { "mime_type": mime_type, "data": data } }),
+                        None => json!({ "file_data": { "file_uri": url } }),
+                    }
+                }
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Finish reason in the terms of the chat completions API.
+fn finish_reason(reason: &str) -> String {
+    match reason {
+        "STOP" => "stop".to_string(),
+        "MAX_TOKENS" => "length".to_string(),
+        "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" | "IMAGE_SAFETY" => {
+            "content_filter".to_string()
+        }
+        other => other.to_lowercase(),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentResponse {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+    #[serde(default)]
+    prompt_feedback: Option<PromptFeedback>,
+    #[serde(default)]
+    usage_metadata: UsageMetadata,
+    #[serde(default)]
+    model_version: String,
+    #[serde(default)]
+    response_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Candidate {
+    #[serde(default)]
+    content: Option<CandidateContent>,
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CandidateContent {
+    #[serde(default)]
+    parts: Vec<Part>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Part {
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    function_call: Option<FunctionCall>,
+    /// Set on summaries of the model's reasoning, which aren't part of the answer.
+    #[serde(default)]
+    thought: bool,
+}
+
+#[derive(Deserialize)]
+struct FunctionCall {
+    #[serde(default)]
+    id: Option<String>,
+    name: String,
+    #[serde(default)]
+    args: Value,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PromptFeedback {
+    #[serde(default)]
+    block_reason: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct UsageMetadata {
+    prompt_token_count: u32,
+    candidates_token_count: u32,
+    total_token_count: u32,
+}
@@ -0,0 +1,66 @@
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+use super::api::ChatRequest;
+
+/// Settings used when `openai.flavor` is `openrouter`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct OpenRouterConfig {
+    /// Site URL sent as `HTTP-Referer`, identifying the bot in OpenRouter's app rankings.
+    pub referer: Option<String>,
+    /// App name sent as `X-Title`.
+    pub title: Option<String>,
+    /// Models to try, in order, when the configured model is unavailable or refuses the request.
+    pub fallback_models: Vec<String>,
+    /// Which providers may serve requests, see <https://openrouter.ai/docs/features/provider-routing>.
+    pub provider: Option<ProviderPreferences>,
+}
+
+/// Provider routing preferences of an OpenRouter request.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProviderPreferences {
+    /// Providers to try first, in order, e.g. `["anthropic", "openai"]`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub order: Vec<String>,
+    /// Providers never to use.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+    /// Whether other providers may be used when the ones in `order` are unavailable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_fallbacks: Option<bool>,
+    /// Only use providers supporting every parameter of the request, such as tools.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_parameters: Option<bool>,
+    /// `deny` to only use providers that don't store prompts or train on them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_collection: Option<String>,
+    /// Prefer providers by `price`, `throughput` or `latency` rather than balancing load between them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>,
+}
+
+impl OpenRouterConfig {
+    /// Headers attributing requests to the bot.
+    pub fn headers(&self) -> anyhow::Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        if let Some(referer) = &self.referer {
+            headers.insert("HTTP-Referer", HeaderValue::from_str(referer)?);
+        }
+        if let Some(title) = &self.title {
+            headers.insert("X-Title", HeaderValue::from_str(title)?);
+        }
+        Ok(headers)
+    }
+
+    /// Add the fallback models and provider preferences to a request that doesn't set its own.
+    pub fn route(&self, request: &mut ChatRequest) {
+        if request.models.is_empty() {
+            request.models = self.fallback_models.clone();
+        }
+        if request.provider.is_none() {
+            request.provider = self.provider.clone();
+        }
+    }
+}
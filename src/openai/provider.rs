@@ -0,0 +1,79 @@
+use reqwest::{Client, RequestBuilder};
+use serde_json::Value;
+use url::Url;
+
+use super::{Api, ApiFlavor, ChatRequest, OpenAIConfig, OpenAIResponse};
+
+/// A backend serving chat completions. Requests and completions are exchanged in the format of OpenAI's chat
+/// completions API, which providers with an API of their own translate from and to.
+pub trait ChatProvider: Send + Sync {
+    /// Name of the provider, as told to people asking where their prompts go.
+    fn name(&self) -> &'static str;
+
+    /// Base URL of the provider's API.
+    fn base_url(&self) -> &Url;
+
+    /// URL the request is posted to.
+    fn endpoint(&self, request: &ChatRequest) -> anyhow::Result<Url>;
+
+    /// Body of the request in the provider's format.
+    fn body(&self, request: &ChatRequest) -> anyhow::Result<Value>;
+
+    /// POST request to an endpoint, with the provider's credentials attached.
+    fn post(&self, endpoint: Url) -> RequestBuilder;
+
+    /// The completion in the body of a successful response.
+    fn completion(&self, response: Value) -> anyhow::Result<OpenAIResponse>;
+}
+
+/// OpenAI's chat completions API, or that of a provider compatible with it.
+pub struct OpenAICompatible {
+    config: OpenAIConfig,
+    client: Client,
+}
+
+impl OpenAICompatible {
+    /// `client` has to carry the API key.
+    pub fn new(config: &OpenAIConfig, client: Client) -> Self {
+        Self {
+            config: config.clone(),
+            client,
+        }
+    }
+}
+
+impl ChatProvider for OpenAICompatible {
+    fn name(&self) -> &'static str {
+        match self.config.flavor {
+            ApiFlavor::OpenAI => "OpenAI compatible API",
+            ApiFlavor::OpenRouter => "OpenRouter",
+        }
+    }
+
+    fn base_url(&self) -> &Url {
+        &self.config.endpoint
+    }
+
+    fn endpoint(&self, _request: &ChatRequest) -> anyhow::Result<Url> {
+        self.config.url(Api::ChatCompletions)
+    }
+
+    fn body(&self, request: &ChatRequest) -> anyhow::Result<Value> {
+        match self.config.flavor {
+            ApiFlavor::OpenAI => Ok(serde_json::to_value(request)?),
+            ApiFlavor::OpenRouter => {
+                let mut request = request.clone();
+                self.config.openrouter.route(&mut request);
+                Ok(serde_json::to_value(&request)?)
+            }
+        }
+    }
+
+    fn post(&self, endpoint: Url) -> RequestBuilder {
+        self.client.post(endpoint)
+    }
+
+    fn completion(&self, response: Value) -> anyhow::Result<OpenAIResponse> {
+        Ok(serde_json::from_value(response)?)
+    }
+}
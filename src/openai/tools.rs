@@ -1,14 +1,49 @@
-use std::str::FromStr;
+use std::{str::FromStr, sync::Arc, time::Duration};
 
 use anyhow::Context;
+use async_trait::async_trait;
+use futures::StreamExt;
+use matrix_appservice::{
+    Device, Direction, Room,
+    exports::matrix_sdk::ruma::{
+        EventId, OwnedUserId, RoomAliasId, RoomId, UserId, events::room::message::MessageType,
+    },
+};
 use schemars::{JsonSchema, schema_for};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 use url::Url;
 
-use crate::openai::{ImageUrl, MessageContent, OpenAIImageContent, OpenAIMessage};
+use crate::{
+    calendar::{self, CalendarAccount},
+    citations::{self, Citations},
+    client::{self, Identified},
+    config::Config,
+    convert, dice,
+    email::Mailer,
+    home_assistant::HomeAssistant,
+    images::{self, ImageOptions},
+    issues::Forges,
+    kubernetes, media,
+    memory::Memories,
+    openai::{ContentPart, ConversationStore, load_message, parse_message},
+    paste, prometheus,
+    settings::RoomSettings,
+    shell, sql, store,
+};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// How many events back `search_history` looks, and how many matches it returns.
+const HISTORY_SEARCH_DEPTH: usize = 250;
+const HISTORY_SEARCH_RESULTS: usize = 10;
+/// Rough number of characters per token, to turn the tool result budget into a length.
+const CHARS_PER_TOKEN: usize = 4;
+/// How long full tool results stay available to `read_more`.
+const TOOL_RESULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Bytes of a fetched page read at most, the rest isn't downloaded.
+const MAX_FETCH_BYTES: usize = 2 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolCall {
     id: String,
     #[serde(rename = "type")]
@@ -16,15 +51,281 @@ pub struct ToolCall {
     function: FunctionCall,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct FunctionCall {
     pub name: String,
     pub arguments: String,
 }
 
+impl ToolCall {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.function.name
+    }
+
+    /// Arguments as the JSON text the model wrote.
+    pub fn arguments(&self) -> &str {
+        &self.function.arguments
+    }
+}
+
+#[derive(Debug)]
 pub enum AssistantAction {
     Reply(String),
-    ToolCall(Tool),
+    ToolCall(String, Invocation),
+    /// A tool call that couldn't be resolved, such as an unknown tool or malformed arguments: call id, tool name
+    /// and the error.
+    Invalid(String, String, String),
+}
+
+/// A tool provided by an embedding application, offered to the model next to the built-in tools.
+#[async_trait]
+pub trait CustomTool: Send + Sync {
+    fn name(&self) -> &str;
+    fn description(&self) -> &str;
+    /// JSON schema of the arguments object.
+    fn parameters(&self) -> Value;
+    async fn run(&self, arguments: Value, context: &ToolContext<'_>) -> anyhow::Result<ToolOutput>;
+}
+
+/// A tool call resolved against the registry, ready to run.
+pub enum Invocation {
+    Builtin(Tool),
+    Custom(Arc<dyn CustomTool>, Value),
+}
+
+impl Invocation {
+    /// Short in-room announcement of what the tool is about to do.
+    pub fn describe(&self) -> String {
+        match self {
+            Invocation::Builtin(tool) => tool.describe(),
+            Invocation::Custom(tool, _) => format!("🔧 Running {}…", tool.name()),
+        }
+    }
+
+    /// Name the model calls the tool by.
+    pub fn name(&self) -> String {
+        match self {
+            Invocation::Builtin(tool) => serde_json::to_value(tool)
+                .ok()
+                .and_then(|tool| tool.get("name")?.as_str().map(str::to_string))
+                .unwrap_or_default(),
+            Invocation::Custom(tool, _) => tool.name().to_string(),
+        }
+    }
+
+    /// Run the tool, truncating long output to the configured budget. Pages read through `read_more` are
+    /// already cut to size. A tool running past the timeout is cancelled and fails with `Elapsed`.
+    pub async fn run(&self, context: &ToolContext<'_>) -> anyhow::Result<ToolOutput> {
+        let run = async {
+            match self {
+                Invocation::Builtin(tool) => tool.run(context).await,
+                Invocation::Custom(tool, arguments) => tool.run(arguments.clone(), context).await,
+            }
+        };
+        let mut output = match self.timeout(context.config) {
+            Some(timeout) => match tokio::time::timeout(timeout, run).await {
+                Ok(output) => output?,
+                Err(elapsed) => {
+                    let name = self.name();
+                    context
+                        .state
+                        .metrics()
+                        .increment("openai_bot_tool_timeouts_total", &[("tool", &name)]);
+                    return Err(anyhow::Error::new(elapsed).context(format!(
+                        "The tool didn't finish within {} seconds and was cancelled. Try again with a simpler \
+                         request, use another tool, or answer without it",
+                        timeout.as_secs()
+                    )));
+                }
+            },
+            None => run.await?,
+        };
+        if !matches!(self, Invocation::Builtin(Tool::ReadMore { .. })) {
+            output.text = truncate_result(context, output.text).await?;
+        }
+        if let Some(source) = self.untrusted_source() {
+            output.text = context.state.injection().sanitize(context, source, output.text).await?;
+        }
+        if let Some(source) = self.source() {
+            context.citations.consult(source);
+        }
+
+        Ok(output)
+    }
+
+    /// Whether the tool waits for someone in the room to answer or approve, which can take minutes.
+    pub fn waits_for_people(&self) -> bool {
+        matches!(
+            self,
+            Invocation::Builtin(Tool::AskChoice { .. } | Tool::CallHomeService { .. } | Tool::SendEmail { .. })
+        )
+    }
+
+    /// The follow-up question, for calls asking the user one. Those don't run, but suspend the prompt until the
+    /// question is answered.
+    pub fn question(&self) -> Option<&str> {
+        match self {
+            Invocation::Builtin(Tool::AskUser { question }) => Some(question),
+            _ => None,
+        }
+    }
+
+    /// How long the tool may run, `None` without a limit. Tools waiting for people to answer get the time they have
+    /// to answer on top.
+    fn timeout(&self, config: &Config) -> Option<Duration> {
+        let limit = Duration::from_secs(config.limits.tool_timeout);
+        match (config.limits.tool_timeout, self.waits_for_people()) {
+            (0, _) => None,
+            (_, true) => Some(limit + Duration::from_secs(config.behavior.choice_timeout)),
+            (_, false) => Some(limit),
+        }
+    }
+
+    /// What the tool read, for the sources listed below the reply. Tools citing their results by number, such as
+    /// issue and history searches, and tools that only act or compute have none.
+    fn source(&self) -> Option<String> {
+        match self {
+            Invocation::Builtin(tool) => match tool {
+                Tool::FetchUrl { url } => Some(url.clone()),
+                Tool::ListHomeEntities {} => Some("Home Assistant".to_string()),
+                Tool::GetHomeState { entity_id } => Some(entity_id.clone()),
+                Tool::GetAgenda { .. } => Some("calendar".to_string()),
+                Tool::QueryDatabase { .. } => Some("database".to_string()),
+                Tool::RunCommand { command } => Some(command.clone()),
+                Tool::ListPods { namespace } | Tool::GetKubernetesEvents { namespace, .. } => {
+                    Some(format!("kubernetes/{namespace}"))
+                }
+                Tool::DescribeDeployment { namespace, name } => Some(format!("kubernetes/{namespace}/{name}")),
+                Tool::PromQL { query, .. } => Some(query.clone()),
+                _ => None,
+            },
+            Invocation::Custom(tool, _) => Some(tool.name().to_string()),
+        }
+    }
+
+    /// Name of the tool when its output comes from outside the bot's control, such as web pages, issues and
+    /// calendar invites, and may carry prompt injection. Custom tools are assumed to fetch such content.
+    fn untrusted_source(&self) -> Option<&str> {
+        match self {
+            Invocation::Builtin(Tool::FetchUrl { .. }) => Some("fetch_url"),
+            Invocation::Builtin(Tool::ReadMore { .. }) => Some("read_more"),
+            Invocation::Builtin(Tool::SearchHistory { .. }) => Some("search_history"),
+            Invocation::Builtin(Tool::FetchIssue { .. }) => Some("fetch_issue"),
+            Invocation::Builtin(Tool::SearchIssues { .. }) => Some("search_issues"),
+            Invocation::Builtin(Tool::GetAgenda { .. }) => Some("get_agenda"),
+            Invocation::Builtin(_) => None,
+            Invocation::Custom(tool, _) => Some(tool.name()),
+        }
+    }
+}
+
+impl std::fmt::Debug for Invocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Invocation::Builtin(tool) => tool.fmt(f),
+            Invocation::Custom(tool, arguments) => write!(f, "{}({arguments})", tool.name()),
+        }
+    }
+}
+
+/// Built-in tools plus any custom tools registered by the embedding application.
+#[derive(Default, Clone)]
+pub struct ToolRegistry {
+    custom: Vec<Arc<dyn CustomTool>>,
+}
+
+impl ToolRegistry {
+    pub fn register(&mut self, tool: Arc<dyn CustomTool>) {
+        self.custom.push(tool);
+    }
+
+    pub fn resolve(&self, tool_call: &ToolCall) -> anyhow::Result<Invocation> {
+        match self.custom.iter().find(|tool| tool.name() == tool_call.name()) {
+            Some(tool) => Ok(Invocation::Custom(
+                Arc::clone(tool),
+                serde_json::from_str(&tool_call.function.arguments)?,
+            )),
+            None => Ok(Invocation::Builtin(tool_call.try_into()?)),
+        }
+    }
+
+    /// Names of all tools offered to the model for a prompt in this room by this sender.
+    pub fn names(&self, config: &Config, room_id: &RoomId, sender: Option<&UserId>) -> anyhow::Result<Vec<String>> {
+        Ok(self
+            .schemas(config, room_id, sender)?
+            .iter()
+            .filter_map(|schema| schema.pointer("/function/name")?.as_str().map(str::to_string))
+            .collect())
+    }
+
+    pub fn schemas(&self, config: &Config, room_id: &RoomId, sender: Option<&UserId>) -> anyhow::Result<Vec<Value>> {
+        let mut schemas = Tool::schemas(config, room_id, sender)?;
+        schemas.extend(self.custom.iter().map(|tool| {
+            json!({
+                "type": "function",
+                "function": {
+                    "name": tool.name(),
+                    "description": tool.description(),
+                    "parameters": tool.parameters(),
+                }
+            })
+        }));
+
+        Ok(schemas)
+    }
+}
+
+/// Shared resources tools may use while running.
+pub struct ToolContext<'a> {
+    /// Plain HTTP client, without the API credentials attached to the OpenAI client.
+    pub http: &'a reqwest::Client,
+    /// Client for URLs picked by the model or users, which refuses hosts that aren't public.
+    pub web: &'a reqwest::Client,
+    pub config: &'a Config,
+    pub device: &'a Device,
+    pub room: &'a Room,
+    pub state: &'a ConversationStore,
+    /// Sources the model may cite as `[n]`, linked below the reply.
+    pub citations: &'a Citations,
+    /// Who sent the prompt, if anyone.
+    pub sender: Option<&'a UserId>,
+    /// Event of the prompt, if it was sent in the room.
+    pub prompt_id: Option<&'a EventId>,
+}
+
+/// Result of a tool run. Images can't be part of a tool message, so they are sent to the model
+/// as a follow-up user message instead.
+pub struct ToolOutput {
+    pub text: String,
+    pub images: Vec<ContentPart>,
+}
+
+impl ToolOutput {
+    pub fn text(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            images: Vec::new(),
+        }
+    }
+
+    /// A failed tool run, as a structured result the model can recover from, e.g. `{"error": {"tool": "fetch_url",
+    /// "kind": "timeout", "message": "…"}}`.
+    pub fn error(tool: &str, kind: &str, message: impl Into<String>) -> Self {
+        Self::text(
+            json!({
+                "error": {
+                    "tool": tool,
+                    "kind": kind,
+                    "message": message.into(),
+                }
+            })
+            .to_string(),
+        )
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
@@ -33,6 +334,107 @@ pub enum Tool {
     #[serde(rename = "fetch_url")]
     /// Fetch the contents of a URL and return HTML or image metadata.
     FetchUrl { url: String },
+    #[serde(rename = "ask_choice")]
+    /// Ask the user to pick one of several options when a request is ambiguous. Returns the chosen option.
+    AskChoice { question: String, options: Vec<String> },
+    #[serde(rename = "ask_user")]
+    /// Ask the user a clarifying question when a request can't be answered without more information, and wait for
+    /// their answer before continuing. Prefer ask_choice when the possible answers are known.
+    AskUser { question: String },
+    #[serde(rename = "edit_image")]
+    /// Edit an image posted in the room, identified by its event ID, following an instruction such as
+    /// "make the sky purple", and post the result. Leave the instruction empty for a variation.
+    EditImage { source_event: String, instruction: String },
+    #[serde(rename = "generate_image")]
+    /// Generate an image from a description and post it in the room.
+    GenerateImage { prompt: String },
+    #[serde(rename = "resolve_room")]
+    /// Look up the room ID behind a room alias such as #rust:example.org.
+    ResolveRoom { alias: String },
+    #[serde(rename = "search_rooms")]
+    /// Search the homeserver's public room directory by name or topic. Returns aliases, topics and member counts.
+    SearchRooms { query: String },
+    #[serde(rename = "search_history")]
+    /// Search earlier messages in this room for a phrase. Results are numbered, cite the ones you rely on as [n].
+    SearchHistory { query: String },
+    #[serde(rename = "read_more")]
+    /// Continue reading a truncated tool result from the given character offset.
+    ReadMore { result_id: String, offset: usize },
+    #[serde(rename = "convert")]
+    /// Convert a value between units (e.g. 5 "mi" to "km", 20 "°C" to "°F"), or a time between timezones when
+    /// `from` and `to` are IANA timezone names (e.g. "15:00" or "2025-03-01 3pm" from "Europe/Paris" to
+    /// "Asia/Tokyo"). Always use this rather than converting yourself.
+    Convert { value: String, from: String, to: String },
+    #[serde(rename = "roll_dice")]
+    /// Roll dice in standard notation, e.g. "3d6+2", "d20-1" or "4d6kh3" to keep the highest three. Returns the
+    /// individual rolls and the total. Always roll with this tool rather than making up results.
+    RollDice { expression: String },
+    #[serde(rename = "fetch_issue")]
+    /// Fetch an issue or pull request by number. The repository is "owner/name" on GitHub, or
+    /// "gitlab:group/project" on GitLab. Cite the issue as [n] when you rely on it.
+    FetchIssue { repo: String, number: u64 },
+    #[serde(rename = "search_issues")]
+    /// Search issues and pull requests of a repository, written as for fetch_issue. Results are numbered, cite the
+    /// ones you rely on as [n].
+    SearchIssues { repo: String, query: String },
+    #[serde(rename = "list_home_entities")]
+    /// List the Home Assistant entities you can access, with their current state.
+    ListHomeEntities {},
+    #[serde(rename = "get_home_state")]
+    /// Get the current state and attributes of a Home Assistant entity, e.g. "lock.front_door".
+    GetHomeState { entity_id: String },
+    #[serde(rename = "call_home_service")]
+    /// Call a Home Assistant service on an entity, e.g. domain "light", service "turn_off", entity
+    /// "light.kitchen", with optional extra service data. The user is asked to confirm first.
+    CallHomeService {
+        domain: String,
+        service: String,
+        entity_id: String,
+        data: Option<Value>,
+    },
+    #[serde(rename = "send_email")]
+    /// Send a plain text email. The user is shown the draft and asked to approve it before it is sent, and only
+    /// some recipients are allowed.
+    SendEmail { to: String, subject: String, body: String },
+    #[serde(rename = "get_agenda")]
+    /// Read the user's calendar for a day ("2025-03-01") or an inclusive range of days ("2025-03-01/2025-03-07").
+    /// Only works in a DM with a user who connected their calendar.
+    GetAgenda { date_range: String },
+    #[serde(rename = "query_database")]
+    /// Run a read-only SQL query against the configured database and get the result as a Markdown table. Only
+    /// some tables are available, and long results are cut off.
+    QueryDatabase { sql: String },
+    #[serde(rename = "run_command")]
+    /// Run a command on the host, such as "uptime" or "systemctl status nginx". Only commands the operator allowed
+    /// can run, an attempt at anything else returns the list of allowed commands.
+    RunCommand { command: String },
+    #[serde(rename = "list_pods")]
+    /// List the pods in a Kubernetes namespace with their status, readiness and restart counts.
+    ListPods { namespace: String },
+    #[serde(rename = "get_kubernetes_events")]
+    /// Get recent events in a Kubernetes namespace, optionally only those about one object such as a pod.
+    GetKubernetesEvents { namespace: String, object: Option<String> },
+    #[serde(rename = "describe_deployment")]
+    /// Describe a Kubernetes deployment: replicas, conditions and container images.
+    DescribeDeployment { namespace: String, name: String },
+    #[serde(rename = "query_prometheus")]
+    /// Run a PromQL query against the configured Prometheus server. Without a range it returns the current values,
+    /// with a range such as "1h" or "7d" it returns a sparkline with min, max and last value per series.
+    PromQL { query: String, range: Option<String> },
+    #[serde(rename = "paste")]
+    /// Share long code or text outside of the reply, as a paste link or a file attachment, so it doesn't flood the
+    /// room. Returns where it went, mention that in your reply instead of repeating the content.
+    Paste { content: String, filename: String },
+    #[serde(rename = "remember")]
+    /// Remember a fact about the user for future conversations, when they ask you to, e.g. "remember my birthday
+    /// is May 3". Write the fact so it makes sense on its own, like "Their birthday is May 3".
+    Remember { fact: String },
+    #[serde(rename = "recall_facts")]
+    /// List the facts the user asked you to remember about them.
+    RecallFacts {},
+    #[serde(rename = "list_capabilities")]
+    /// List the tools, models and room settings you have, to answer questions about what you can do accurately.
+    ListCapabilities {},
 }
 
 impl TryFrom<&ToolCall> for Tool {
@@ -50,13 +452,134 @@ impl TryFrom<&ToolCall> for Tool {
 }
 
 impl Tool {
-    pub async fn run(&self) -> anyhow::Result<OpenAIMessage> {
+    pub fn describe(&self) -> String {
+        match self {
+            Tool::FetchUrl { url } => format!("🌐 Fetching {url}…"),
+            Tool::AskChoice { .. } => "❓ Asking for clarification…".to_string(),
+            Tool::AskUser { .. } => "❓ Asking a follow-up question…".to_string(),
+            Tool::EditImage { .. } => "🎨 Editing image…".to_string(),
+            Tool::GenerateImage { .. } => "🎨 Generating image…".to_string(),
+            Tool::ResolveRoom { alias } => format!("🔎 Resolving {alias}…"),
+            Tool::SearchRooms { query } => format!("🔎 Searching the room directory for \"{query}\"…"),
+            Tool::SearchHistory { query } => format!("🔎 Searching room history for \"{query}\"…"),
+            Tool::ReadMore { .. } => "📄 Reading on…".to_string(),
+            Tool::Convert { value, from, to } => format!("🔢 Converting {value} {from} to {to}…"),
+            Tool::RollDice { expression } => format!("🎲 Rolling {expression}…"),
+            Tool::FetchIssue { repo, number } => format!("🐛 Looking up {repo}#{number}…"),
+            Tool::SearchIssues { repo, query } => format!("🐛 Searching {repo} issues for \"{query}\"…"),
+            Tool::ListHomeEntities {} => "🏠 Listing devices…".to_string(),
+            Tool::GetHomeState { entity_id } => format!("🏠 Checking {entity_id}…"),
+            Tool::CallHomeService {
+                domain,
+                service,
+                entity_id,
+                ..
+            } => format!("🏠 Calling {domain}.{service} on {entity_id}…"),
+            Tool::SendEmail { to, .. } => format!("✉️ Drafting an email to {to}…"),
+            Tool::GetAgenda { date_range } => format!("📅 Checking the calendar for {date_range}…"),
+            Tool::QueryDatabase { .. } => "🗄️ Querying the database…".to_string(),
+            Tool::RunCommand { command } => format!("🖥️ Running `{command}`…"),
+            Tool::ListPods { namespace } => format!("☸️ Listing pods in {namespace}…"),
+            Tool::GetKubernetesEvents { namespace, .. } => format!("☸️ Reading events in {namespace}…"),
+            Tool::DescribeDeployment { namespace, name } => format!("☸️ Describing {namespace}/{name}…"),
+            Tool::PromQL { query, .. } => format!("📈 Querying `{query}`…"),
+            Tool::Paste { filename, .. } => format!("📋 Sharing {filename}…"),
+            Tool::Remember { .. } => "🧠 Remembering that…".to_string(),
+            Tool::RecallFacts {} => "🧠 Recalling what I know about you…".to_string(),
+            Tool::ListCapabilities {} => "🧰 Checking what I can do…".to_string(),
+        }
+    }
+
+    pub async fn run(&self, context: &ToolContext<'_>) -> anyhow::Result<ToolOutput> {
         match self {
-            Tool::FetchUrl { url } => fetch_url(Url::from_str(url)?).await,
+            Tool::FetchUrl { url } => fetch_url(context, Url::from_str(url)?).await,
+            Tool::AskChoice { question, options } => ask_choice(context, question, options).await,
+            Tool::AskUser { .. } => Ok(ToolOutput::text(
+                "Nobody sent this prompt, so there is nobody to ask. Continue with your best assumption, and \
+                 mention it.",
+            )),
+            Tool::EditImage {
+                source_event,
+                instruction,
+            } => edit_image(context, source_event, instruction).await,
+            Tool::GenerateImage { prompt } => generate_image(context, prompt).await,
+            Tool::ResolveRoom { alias } => resolve_room(context, alias).await,
+            Tool::SearchRooms { query } => search_rooms(context, query).await,
+            Tool::SearchHistory { query } => search_history(context, query).await,
+            Tool::ReadMore { result_id, offset } => read_more(context, result_id, *offset).await,
+            Tool::Convert { value, from, to } => Ok(ToolOutput::text(convert::convert(value, from, to)?)),
+            Tool::RollDice { expression } => Ok(ToolOutput::text(dice::roll(expression)?)),
+            Tool::FetchIssue { repo, number } => fetch_issue(context, repo, *number).await,
+            Tool::SearchIssues { repo, query } => search_issues(context, repo, query).await,
+            Tool::ListHomeEntities {} | Tool::GetHomeState { .. } | Tool::CallHomeService { .. }
+                if !context.config.home_assistant.permits(context.room.id(), context.sender) =>
+            {
+                Ok(ToolOutput::text("The home can't be read or controlled in this room."))
+            }
+            Tool::ListHomeEntities {} => {
+                let home = HomeAssistant::new(context.http, &context.config.home_assistant)?;
+                Ok(ToolOutput::text(home.entities().await?.join("\n")))
+            }
+            Tool::GetHomeState { entity_id } => {
+                let home = HomeAssistant::new(context.http, &context.config.home_assistant)?;
+                Ok(ToolOutput::text(home.state(entity_id).await?))
+            }
+            Tool::CallHomeService {
+                domain,
+                service,
+                entity_id,
+                data,
+            } => call_home_service(context, domain, service, entity_id, data.as_ref()).await,
+            Tool::SendEmail { to, subject, body } => send_email(context, to, subject, body).await,
+            Tool::GetAgenda { date_range } => get_agenda(context, date_range).await,
+            Tool::QueryDatabase { sql: query } => Ok(ToolOutput::text(sql::query(&context.config.sql, query).await?)),
+            Tool::RunCommand { command } => run_command(context, command).await,
+            Tool::ListPods { namespace } => Ok(ToolOutput::text(
+                kubernetes::list_pods(&context.config.kubernetes, namespace).await?,
+            )),
+            Tool::GetKubernetesEvents { namespace, object } => Ok(ToolOutput::text(
+                kubernetes::events(&context.config.kubernetes, namespace, object.as_deref()).await?,
+            )),
+            Tool::DescribeDeployment { namespace, name } => Ok(ToolOutput::text(
+                kubernetes::describe_deployment(&context.config.kubernetes, namespace, name).await?,
+            )),
+            Tool::PromQL { query, range } => Ok(ToolOutput::text(
+                prometheus::query(context.http, &context.config.prometheus, query, range.as_deref()).await?,
+            )),
+            Tool::Paste { content, filename } => {
+                let location =
+                    paste::share(context.state, context.device, context.room.id(), content, filename).await?;
+                Ok(ToolOutput::text(format!("The content has been {location}.")))
+            }
+            Tool::Remember { fact } => remember(context, fact).await,
+            Tool::RecallFacts {} => recall_facts(context).await,
+            Tool::ListCapabilities {} => list_capabilities(context).await,
         }
     }
 
-    pub fn schemas() -> anyhow::Result<Vec<serde_json::Value>> {
+    /// Whether a tool is offered to the model for a prompt in this room by this sender. Tools backed by optional
+    /// integrations are only offered once the integration is configured, and those acting on things beyond the chat
+    /// only where they are permitted.
+    fn available(name: &str, config: &Config, room_id: &RoomId, sender: Option<&UserId>) -> bool {
+        match name {
+            "list_home_entities" | "get_home_state" | "call_home_service" => {
+                config.home_assistant.enabled() && config.home_assistant.permits(room_id, sender)
+            }
+            "send_email" => config.email.smtp.is_some(),
+            "get_agenda" => config.calendar.key.is_some(),
+            "query_database" => config.sql.dsn.is_some(),
+            "run_command" => config.shell.enabled() && config.shell.permits(room_id, sender),
+            "list_pods" | "get_kubernetes_events" | "describe_deployment" => config.kubernetes.kubeconfig.is_some(),
+            "query_prometheus" => config.prometheus.url.is_some(),
+            _ => true,
+        }
+    }
+
+    pub fn schemas(
+        config: &Config,
+        room_id: &RoomId,
+        sender: Option<&UserId>,
+    ) -> anyhow::Result<Vec<serde_json::Value>> {
         let schema = schema_for!(Tool);
         let schema_value = serde_json::to_value(&schema)?;
 
@@ -77,6 +600,9 @@ impl Tool {
                 .and_then(|v| v.as_str())
                 .context("Variant 'name.const' missing or not a string")?
                 .to_string();
+            if !Tool::available(&name, config, room_id, sender) {
+                continue;
+            }
 
             let description = variant
                 .get("description")
@@ -100,17 +626,477 @@ impl Tool {
     }
 }
 
-async fn fetch_url(url: Url) -> anyhow::Result<OpenAIMessage> {
-    let content = OpenAIImageContent {
-        kind: "image_url".to_string(),
-        image_url: ImageUrl { url: url.to_string() },
+async fn fetch_url(context: &ToolContext<'_>, url: Url) -> anyhow::Result<ToolOutput> {
+    client::check_public(&url)?;
+    let response = context
+        .web
+        .get(url.clone())
+        .send_identified()
+        .await?
+        .error_for_status()?;
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+
+    if content_type.starts_with("image/") {
+        let length = response.content_length().unwrap_or_default();
+        return Ok(ToolOutput {
+            text: format!("Fetched an image of type {content_type} ({length} bytes), attached in the next message."),
+            images: vec![ContentPart::image_url(url)],
+        });
+    }
+
+    let (body, cut_off) = client::read_limited(response, MAX_FETCH_BYTES).await?;
+    let mut text = String::from_utf8_lossy(&body).into_owned();
+    if cut_off {
+        text.push_str("\n[Only the first part of the page was downloaded.]");
+    }
+    Ok(ToolOutput::text(text))
+}
+
+fn result_length(config: &Config) -> usize {
+    config.limits.tool_result_tokens * CHARS_PER_TOKEN
+}
+
+fn result_key(context: &ToolContext<'_>, result_id: &str) -> String {
+    store::room_key(context.room.id(), &format!("tool_result/{result_id}"))
+}
+
+/// Cut a tool result down to the configured budget. The full result is kept in memory for a while, so the model can
+/// page through the rest with `read_more`.
+async fn truncate_result(context: &ToolContext<'_>, text: String) -> anyhow::Result<String> {
+    let length = result_length(context.config);
+    if text.chars().count() <= length {
+        return Ok(text);
+    }
+    if !RoomSettings::load(context.state.store(), context.room.id())
+        .await?
+        .keeps_content()
+    {
+        let slice: String = text.chars().take(length).collect();
+        return Ok(format!("{slice}\n[Truncated, the rest can't be kept in this room.]"));
+    }
+
+    let result_id = format!("{:016x}", rand::random::<u64>());
+    context
+        .state
+        .scratch()
+        .save_expiring(&result_key(context, &result_id), &text, TOOL_RESULT_TTL)
+        .await?;
+
+    Ok(page(&text, &result_id, 0, length))
+}
+
+fn page(text: &str, result_id: &str, offset: usize, length: usize) -> String {
+    let total = text.chars().count();
+    if offset >= total {
+        return format!("[Offset {offset} is past the end of the result, which has {total} characters.]");
+    }
+    let end = (offset + length).min(total);
+    let slice: String = text.chars().skip(offset).take(length).collect();
+
+    match end < total {
+        true => format!(
+            "{slice}\n[Truncated, showing characters {offset}-{end} of {total}. \
+            Call read_more with result_id \"{result_id}\" and offset {end} to continue.]"
+        ),
+        false => format!("{slice}\n[End of result, characters {offset}-{end} of {total}.]"),
+    }
+}
+
+async fn read_more(context: &ToolContext<'_>, result_id: &str, offset: usize) -> anyhow::Result<ToolOutput> {
+    let text = context
+        .state
+        .scratch()
+        .load::<String>(&result_key(context, result_id))
+        .await?;
+
+    Ok(ToolOutput::text(match text {
+        Some(text) => page(&text, result_id, offset, result_length(context.config)),
+        None => format!("No result with ID {result_id}, it may have expired."),
+    }))
+}
+
+async fn ask_choice(context: &ToolContext<'_>, question: &str, options: &[String]) -> anyhow::Result<ToolOutput> {
+    let timeout = Duration::from_secs(context.config.behavior.choice_timeout);
+    let selection = context
+        .state
+        .menus()
+        .ask(
+            context.device,
+            context.room.id(),
+            context.sender,
+            question,
+            options,
+            timeout,
+        )
+        .await?;
+
+    Ok(ToolOutput::text(match selection {
+        Some(index) => format!("The user chose option {}: {}", index + 1, options[index]),
+        None => "The user did not choose an option in time.".to_string(),
+    }))
+}
+
+async fn edit_image(context: &ToolContext<'_>, source_event: &str, instruction: &str) -> anyhow::Result<ToolOutput> {
+    let event_id = <&EventId>::try_from(source_event)?;
+    let event = load_message(context.room, context.device, event_id, context.state).await?;
+    let MessageType::Image(image) = &event.content.msgtype else {
+        return Ok(ToolOutput::text(format!("Event {source_event} is not an image.")));
+    };
+
+    let state = context.state;
+    let original = media::download(state.homeserver(), &image.source, context.config.media.max_size).await?;
+    let edited = state.images().edit(original, instruction).await?;
+    post_image(context, edited, "edited.png").await?;
+
+    Ok(ToolOutput::text("The edited image has been posted in the room."))
+}
+
+async fn generate_image(context: &ToolContext<'_>, prompt: &str) -> anyhow::Result<ToolOutput> {
+    let options = ImageOptions::default();
+    // Images asked for by someone count towards their daily limit, like `!image`.
+    let image = match context.sender {
+        Some(sender) => {
+            let generated = images::generate_daily(
+                context.state.store(),
+                &context.config.images,
+                context.state.images(),
+                sender,
+                prompt,
+                &options,
+            )
+            .await?;
+            let Some(image) = generated else {
+                return Ok(ToolOutput::text(format!(
+                    "The user reached their limit of {} images today.",
+                    context.config.images.daily_limit
+                )));
+            };
+            image
+        }
+        None => context.state.images().generate(prompt, &options).await?,
     };
+    post_image(context, image, "generated.png").await?;
+
+    Ok(ToolOutput::text("The generated image has been posted in the room."))
+}
 
-    let message = OpenAIMessage {
-        role: "user".to_string(),
-        content: Some(MessageContent::Images(vec![content])),
-        tool_calls: Vec::new(),
+async fn post_image(context: &ToolContext<'_>, data: Vec<u8>, filename: &str) -> anyhow::Result<()> {
+    images::post(
+        context.state.homeserver(),
+        context.device,
+        context.room.id(),
+        data,
+        filename,
+    )
+    .await
+}
+
+async fn resolve_room(context: &ToolContext<'_>, alias: &str) -> anyhow::Result<ToolOutput> {
+    let Ok(alias) = <&RoomAliasId>::try_from(alias) else {
+        return Ok(ToolOutput::text(format!("{alias} is not a valid room alias.")));
     };
 
-    Ok(message)
+    Ok(ToolOutput::text(
+        match context.state.homeserver().resolve_alias(alias).await? {
+            Some(resolved) => format!(
+                "{alias} points to room {} (matrix.to link: https://matrix.to/#/{alias}), joinable via {}.",
+                resolved.room_id,
+                resolved.servers.join(", ")
+            ),
+            None => format!("No room with the alias {alias} exists."),
+        },
+    ))
+}
+
+async fn search_rooms(context: &ToolContext<'_>, query: &str) -> anyhow::Result<ToolOutput> {
+    let rooms = context.state.homeserver().public_rooms(query, 10).await?;
+    if rooms.is_empty() {
+        return Ok(ToolOutput::text(format!("No public rooms match \"{query}\".")));
+    }
+
+    let lines = rooms
+        .iter()
+        .map(|room| {
+            let address = room
+                .canonical_alias
+                .as_ref()
+                .map_or_else(|| room.room_id.to_string(), ToString::to_string);
+            format!(
+                "- {} ({address}), {} members: {}",
+                room.name.as_deref().unwrap_or("Unnamed room"),
+                room.num_joined_members,
+                room.topic.as_deref().unwrap_or("no topic"),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    Ok(ToolOutput::text(lines.join("\n")))
+}
+
+/// Search what people wrote in the room, leaving out the prompt itself and the bot's own replies.
+async fn search_history(context: &ToolContext<'_>, query: &str) -> anyhow::Result<ToolOutput> {
+    let bot_id = context.state.homeserver().user_id();
+    let needle = query.to_lowercase();
+    let mut results = Vec::new();
+    let mut events = std::pin::pin!(
+        context
+            .room
+            .get_raw_message_stream(Direction::Backward)
+            .take(HISTORY_SEARCH_DEPTH)
+    );
+
+    while let Some(raw_event) = events.next().await
+        && results.len() < HISTORY_SEARCH_RESULTS
+    {
+        let Ok(Some(event)) = parse_message(context.room, context.device, raw_event?, context.state.metrics()).await
+        else {
+            continue;
+        };
+        if event.sender == bot_id || Some(&*event.event_id) == context.prompt_id {
+            continue;
+        }
+        let body = event.content.body();
+        if !body.to_lowercase().contains(&needle) {
+            continue;
+        }
+
+        let number = context
+            .citations
+            .cite(citations::permalink(context.room.id(), &event.event_id));
+        results.push(format!("[{number}] {}: {body}", event.sender));
+    }
+
+    Ok(ToolOutput::text(match results.is_empty() {
+        true => format!("No messages in this room mention \"{query}\"."),
+        false => results.join("\n"),
+    }))
+}
+
+async fn fetch_issue(context: &ToolContext<'_>, repo: &str, number: u64) -> anyhow::Result<ToolOutput> {
+    let issue = Forges::new(context.http, &context.config.issues)
+        .fetch(repo, number)
+        .await?;
+    let citation = context.citations.cite(issue.url.clone());
+    Ok(ToolOutput::text(format!("[{citation}] {}", issue.describe())))
+}
+
+async fn search_issues(context: &ToolContext<'_>, repo: &str, query: &str) -> anyhow::Result<ToolOutput> {
+    let issues = Forges::new(context.http, &context.config.issues)
+        .search(repo, query)
+        .await?;
+    let results = issues
+        .iter()
+        .map(|issue| format!("[{}] {}", context.citations.cite(issue.url.clone()), issue.summary()))
+        .collect::<Vec<_>>();
+
+    Ok(ToolOutput::text(match results.is_empty() {
+        true => format!("No issues in {repo} match \"{query}\"."),
+        false => results.join("\n"),
+    }))
+}
+
+async fn call_home_service(
+    context: &ToolContext<'_>,
+    domain: &str,
+    service: &str,
+    entity_id: &str,
+    data: Option<&Value>,
+) -> anyhow::Result<ToolOutput> {
+    let config = &context.config.home_assistant;
+    let home = HomeAssistant::new(context.http, config)?;
+    if !config.allows(entity_id) {
+        return Ok(ToolOutput::text(format!("Entity {entity_id} is not available to you.")));
+    }
+    if !config.allows_service(domain, service) {
+        return Ok(ToolOutput::text(format!(
+            "Service {domain}.{service} is not available to you."
+        )));
+    }
+
+    if config.confirm {
+        let question = format!("Call `{domain}.{service}` on `{entity_id}`?");
+        if !confirm(context, &question).await? {
+            return Ok(ToolOutput::text(
+                "The user did not confirm, the service was not called.",
+            ));
+        }
+    }
+
+    let changed = home.call_service(domain, service, entity_id, data).await?;
+    Ok(ToolOutput::text(match changed.is_empty() {
+        true => format!("Called {domain}.{service}, no states changed."),
+        false => format!("Called {domain}.{service}. New states:\n{}", changed.join("\n")),
+    }))
+}
+
+async fn send_email(context: &ToolContext<'_>, to: &str, subject: &str, body: &str) -> anyhow::Result<ToolOutput> {
+    let config = &context.config.email;
+    if !config.allows(to) {
+        return Ok(ToolOutput::text(format!("Sending email to {to} is not allowed.")));
+    }
+
+    let quoted = body
+        .lines()
+        .map(|line| format!("> {line}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let question = format!("Send this email to {to}?\n\n**{subject}**\n\n{quoted}");
+    if !confirm(context, &question).await? {
+        return Ok(ToolOutput::text("The user did not approve the email, it was not sent."));
+    }
+
+    Mailer::new(config)?.send(to, subject, body).await?;
+    Ok(ToolOutput::text(format!("The email to {to} has been sent.")))
+}
+
+/// Ask whoever sent the prompt to approve an action before a tool carries it out.
+async fn confirm(context: &ToolContext<'_>, question: &str) -> anyhow::Result<bool> {
+    let timeout = Duration::from_secs(context.config.behavior.choice_timeout);
+    context
+        .state
+        .menus()
+        .confirm(context.device, context.room.id(), context.sender, question, timeout)
+        .await
+}
+
+async fn run_command(context: &ToolContext<'_>, command: &str) -> anyhow::Result<ToolOutput> {
+    let config = &context.config.shell;
+    if !config.permits(context.room.id(), context.sender) {
+        return Ok(ToolOutput::text("Commands can't be run in this room."));
+    }
+    Ok(ToolOutput::text(shell::run(config, command).await?))
+}
+
+async fn get_agenda(context: &ToolContext<'_>, date_range: &str) -> anyhow::Result<ToolOutput> {
+    let Some(user_id) = dm_partner(context).await? else {
+        return Ok(ToolOutput::text("Calendars can only be read in a DM with their owner."));
+    };
+    let Some(key) = context.config.calendar.key.as_deref() else {
+        return Ok(ToolOutput::text("Calendars aren't available."));
+    };
+    let Some(account) = CalendarAccount::load(context.state.store(), key, &user_id).await? else {
+        return Ok(ToolOutput::text(
+            "The user hasn't connected a calendar. They can do so with `!calendar connect`.",
+        ));
+    };
+
+    let settings = RoomSettings::load(context.state.store(), context.room.id()).await?;
+    let timezone = settings.resolved_timezone(&context.config.prompt.timezone);
+    let (start, end) = calendar::parse_range(date_range, timezone)?;
+    let events = match account.events(context.web, start, end).await {
+        Ok(events) => events,
+        Err(error) => {
+            tracing::warn!("Reading the calendar of {user_id} failed: {error}");
+            return Ok(ToolOutput::text("The calendar couldn't be read."));
+        }
+    };
+
+    Ok(ToolOutput::text(match events.is_empty() {
+        true => format!("Nothing on the calendar for {date_range}."),
+        false => events
+            .iter()
+            .map(|event| event.describe(timezone))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }))
+}
+
+async fn remember(context: &ToolContext<'_>, fact: &str) -> anyhow::Result<ToolOutput> {
+    let Some(sender) = context.sender else {
+        return Ok(ToolOutput::text("There's no user to remember this for."));
+    };
+    if !RoomSettings::load(context.state.store(), context.room.id())
+        .await?
+        .keeps_content()
+    {
+        return Ok(ToolOutput::text(
+            "Nothing said in this room may be stored, so this can't be remembered here.",
+        ));
+    }
+    let _lock = Memories::lock(context.state.store(), sender).await;
+    let mut memories = Memories::load(context.state.store(), &context.config.memory, sender).await?;
+    let Some(id) = memories.remember(&context.config.memory, fact) else {
+        return Ok(ToolOutput::text(
+            "Too many facts are remembered already. The user can remove some with `!forget <id>` first.",
+        ));
+    };
+    memories.save(context.state.store(), sender).await?;
+    Ok(ToolOutput::text(format!(
+        "Remembered as fact {id}, the user can remove it with `!forget {id}`."
+    )))
+}
+
+async fn recall_facts(context: &ToolContext<'_>) -> anyhow::Result<ToolOutput> {
+    let Some(sender) = context.sender else {
+        return Ok(ToolOutput::text("There's no user to recall facts about."));
+    };
+    if !context.room.is_direct().await {
+        return Ok(ToolOutput::text(
+            "Facts are only recalled in a DM with the user, so others in the room can't read them.",
+        ));
+    }
+    let memories = Memories::load(context.state.store(), &context.config.memory, sender).await?;
+    Ok(ToolOutput::text(match memories.facts.is_empty() {
+        true => "Nothing remembered about this user yet.".to_string(),
+        false => memories.describe(),
+    }))
+}
+
+async fn list_capabilities(context: &ToolContext<'_>) -> anyhow::Result<ToolOutput> {
+    let config = context.config;
+    let settings = RoomSettings::load(context.state.store(), context.room.id()).await?;
+
+    let tools = context
+        .state
+        .tools()
+        .schemas(config, context.room.id(), context.sender)?
+        .iter()
+        .filter_map(|schema| {
+            let name = schema.pointer("/function/name")?.as_str()?;
+            let description = schema.pointer("/function/description")?.as_str().unwrap_or_default();
+            Some(format!(
+                "- {name}: {}",
+                description.split_whitespace().collect::<Vec<_>>().join(" ")
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    let mut models = vec![format!(
+        "- Chat: {}",
+        settings.model.as_ref().unwrap_or(&config.openai.model)
+    )];
+    if let Some(vision_model) = &config.openai.vision_model {
+        models.push(format!("- Images in prompts: {vision_model}"));
+    }
+    if config.gemini.api_key.is_some() {
+        models.push("- Gemini models, when selected per room or prompt".to_string());
+    }
+    models.push(format!("- Image generation: {}", config.images.model));
+
+    Ok(ToolOutput::text(format!(
+        "Tools:\n{}\n\nModels:\n{}\n\n{}",
+        tools.join("\n"),
+        models.join("\n"),
+        settings.describe()?
+    )))
+}
+
+/// The user on the other side of a DM, for tools that read their personal data. `None` in group rooms.
+async fn dm_partner(context: &ToolContext<'_>) -> anyhow::Result<Option<OwnedUserId>> {
+    if !context.room.is_direct().await {
+        return Ok(None);
+    }
+
+    let homeserver = context.state.homeserver();
+    let members = homeserver.joined_members(context.room.id()).await?;
+    let mut others = members.into_keys().filter(|user_id| user_id != homeserver.user_id());
+    Ok(match (others.next(), others.next()) {
+        (Some(user_id), None) => Some(user_id),
+        _ => None,
+    })
 }
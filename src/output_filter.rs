@@ -0,0 +1,119 @@
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::openai::{Conversation, ConversationStore};
+
+/// Instruction the model gets when answering again after its answer was withheld.
+const STRICT_INSTRUCTION: &str = "Your previous answer to the last message was withheld for containing profanity or \
+    explicit content. Answer it again, keeping the answer free of profanity, slurs and sexual or otherwise explicit \
+    content.";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct OutputFilterConfig {
+    /// Words answers may not contain, matched case-insensitively as whole words.
+    pub words: Vec<String>,
+    /// Regexes answers may not match, e.g. `(?i)nsfw`.
+    pub patterns: Vec<String>,
+    /// Also reject answers the moderation endpoint flags. Requires `moderation.enabled` or `moderation.endpoint`.
+    pub moderation: bool,
+    /// What happens to answers that violate the filter, rooms can override this with `!set output_filter`.
+    pub policy: FilterPolicy,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterPolicy {
+    /// Post answers unfiltered.
+    #[default]
+    Off,
+    /// Ask the model once more with a stricter instruction, and refuse when that answer violates the filter too.
+    Regenerate,
+    /// Replace the answer with a refusal.
+    Refuse,
+}
+
+/// Checks answers after they're generated, against word lists, regexes and optionally the moderation endpoint.
+pub struct OutputFilter {
+    patterns: Vec<Regex>,
+    moderation: bool,
+}
+
+impl OutputFilter {
+    pub fn new(config: &OutputFilterConfig) -> anyhow::Result<Self> {
+        let mut patterns = config
+            .patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .map_err(|error| anyhow::anyhow!("Invalid output filter pattern '{pattern}': {error}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if !config.words.is_empty() {
+            let words = config.words.iter().map(|word| regex::escape(word)).collect::<Vec<_>>();
+            patterns.push(
+                RegexBuilder::new(&format!(r"\b(?:{})\b", words.join("|")))
+                    .case_insensitive(true)
+                    .build()?,
+            );
+        }
+
+        Ok(Self {
+            patterns,
+            moderation: config.moderation,
+        })
+    }
+
+    /// Why an answer violates the filter, if it does: the text that matched or the categories it was flagged for.
+    pub async fn check(&self, state: &ConversationStore, answer: &str) -> anyhow::Result<Option<String>> {
+        if let Some(found) = self.patterns.iter().find_map(|pattern| pattern.find(answer)) {
+            return Ok(Some(format!("matched '{}'", found.as_str())));
+        }
+        if self.moderation {
+            let categories = state.moderation().flagged(answer).await?;
+            if !categories.is_empty() {
+                return Ok(Some(format!("flagged for {}", categories.join(", "))));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The answer to post under the room's filter policy: the answer itself when it passes, a regenerated one when the
+/// policy allows it and that one passes, or `None` when the bot should refuse.
+pub async fn enforce(
+    state: &ConversationStore,
+    conversation: &Conversation,
+    answer: String,
+) -> anyhow::Result<Option<String>> {
+    let policy = conversation
+        .settings()
+        .output_filter
+        .unwrap_or(state.config().output_filter.policy);
+    if policy == FilterPolicy::Off {
+        return Ok(Some(answer));
+    }
+    let Some(violation) = state.output_filter().check(state, &answer).await? else {
+        return Ok(Some(answer));
+    };
+    tracing::info!("Answer violated the output filter: {violation}");
+
+    if policy == FilterPolicy::Regenerate {
+        let answer = conversation.regenerate(STRICT_INSTRUCTION).await?;
+        match state.output_filter().check(state, &answer).await? {
+            None if !answer.trim().is_empty() => {
+                state
+                    .metrics()
+                    .increment("openai_bot_output_filtered_total", &[("outcome", "regenerated")]);
+                return Ok(Some(answer));
+            }
+            Some(violation) => tracing::info!("Regenerated answer violated the output filter: {violation}"),
+            None => (),
+        }
+    }
+
+    state
+        .metrics()
+        .increment("openai_bot_output_filtered_total", &[("outcome", "refused")]);
+    Ok(None)
+}
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+
+use matrix_appservice::exports::matrix_sdk::ruma::{OwnedRoomId, OwnedUserId, RoomId};
+use tokio::sync::RwLock;
+
+use crate::homeserver::Homeserver;
+
+#[derive(Debug, Clone)]
+pub struct Participant {
+    pub user_id: OwnedUserId,
+    pub display_name: Option<String>,
+    pub power_level: i64,
+}
+
+impl Participant {
+    /// Markdown list entry, e.g. `- Alice (@alice:example.org), power level 100`.
+    pub fn describe(&self) -> String {
+        match &self.display_name {
+            Some(name) => format!("- {name} ({}), power level {}", self.user_id, self.power_level),
+            None => format!("- {}, power level {}", self.user_id, self.power_level),
+        }
+    }
+}
+
+/// Joined members per room, fetched from room state on first use and dropped again whenever
+/// membership in the room changes.
+#[derive(Default)]
+pub struct Participants {
+    rooms: RwLock<HashMap<OwnedRoomId, Vec<Participant>>>,
+}
+
+impl Participants {
+    pub async fn get(&self, homeserver: &Homeserver, room_id: &RoomId) -> anyhow::Result<Vec<Participant>> {
+        if let Some(participants) = self.rooms.read().await.get(room_id) {
+            return Ok(participants.clone());
+        }
+
+        let power_levels = homeserver.power_levels(room_id).await?;
+        let participants = homeserver
+            .joined_members(room_id)
+            .await?
+            .into_iter()
+            .map(|(user_id, display_name)| Participant {
+                power_level: power_levels.get(&user_id),
+                user_id,
+                display_name,
+            })
+            .collect::<Vec<_>>();
+
+        self.rooms
+            .write()
+            .await
+            .insert(room_id.to_owned(), participants.clone());
+        Ok(participants)
+    }
+
+    pub async fn invalidate(&self, room_id: &RoomId) {
+        self.rooms.write().await.remove(room_id);
+    }
+}
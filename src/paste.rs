@@ -0,0 +1,104 @@
+use matrix_appservice::{
+    Device,
+    exports::matrix_sdk::ruma::{
+        RoomId,
+        events::room::message::{FileMessageEventContent, MessageType, RoomMessageEventContent},
+    },
+};
+use serde::Deserialize;
+use url::Url;
+
+use crate::{client::Identified, media, openai::ConversationStore};
+
+/// Characters of a long reply shown in the room above the link to the full text.
+const PREVIEW_LENGTH: usize = 500;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PasteConfig {
+    /// Paste service taking the text as a raw POST body and answering with its URL, such as `https://paste.rs/`.
+    /// Without one, and in encrypted rooms, long text is attached to the room as a file instead.
+    pub service: Option<Url>,
+    /// Replies longer than this many characters are pasted, with a preview posted in the room. 0 disables this.
+    pub threshold: usize,
+}
+
+impl Default for PasteConfig {
+    fn default() -> Self {
+        Self {
+            service: None,
+            threshold: 8000,
+        }
+    }
+}
+
+/// Put long text somewhere out of the way: on the paste service when configured, otherwise as a file attachment
+/// in the room. Text from encrypted rooms always stays in the room, encrypted like the rest of it. Returns a
+/// description of where it went, to show to the user or the model.
+pub async fn share(
+    state: &ConversationStore,
+    device: &Device,
+    room_id: &RoomId,
+    text: &str,
+    filename: &str,
+) -> anyhow::Result<String> {
+    let homeserver = state.homeserver();
+    if let Some(service) = &state.config().paste.service
+        && !media::is_encrypted(homeserver, room_id).await?
+    {
+        let url = state
+            .http()
+            .post(service.clone())
+            .body(text.to_string())
+            .send_identified()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        return Ok(format!("pasted at {}", url.trim()));
+    }
+
+    let source = media::upload(
+        homeserver,
+        room_id,
+        text.as_bytes().to_vec(),
+        "text/plain; charset=utf-8",
+        filename,
+    )
+    .await?;
+    let content = RoomMessageEventContent::new(MessageType::File(FileMessageEventContent::new(
+        filename.to_string(),
+        source,
+    )));
+    device.send_message(room_id, content).await?;
+
+    Ok(format!("attached as {filename}"))
+}
+
+/// Replace a reply over the threshold with a preview and a pointer to the full text. Returns whether it was
+/// shortened, so the full text can be kept in the conversation instead of the preview.
+pub async fn shorten(
+    state: &ConversationStore,
+    device: &Device,
+    room_id: &RoomId,
+    reply: String,
+) -> anyhow::Result<(String, bool)> {
+    let threshold = state.config().paste.threshold;
+    if threshold == 0 || reply.chars().count() <= threshold {
+        return Ok((reply, false));
+    }
+
+    let location = share(state, device, room_id, &reply, "response.md").await?;
+    let mut preview = match reply.char_indices().nth(PREVIEW_LENGTH) {
+        Some((index, _)) => reply[..index].to_string(),
+        None => reply.clone(),
+    };
+    // Close a code block the preview cuts through.
+    if preview.matches("```").count() % 2 == 1 {
+        preview.push_str("\n```");
+    }
+    Ok((
+        format!("{preview}…\n\n*The full answer is too long to post here, so I've {location}.*"),
+        true,
+    ))
+}
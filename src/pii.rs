@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use url::Url;
+
+use crate::{
+    client::Identified,
+    openai::{ContentPart, MessageContent},
+};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PiiConfig {
+    /// Mask personal data in prompts by default, rooms can override this with `!set pii_scrubbing`.
+    pub enabled: bool,
+    /// Optional named entity recognition service, receiving `{"text": ...}` and returning a list of
+    /// `{"start", "end", "label"}` entities with character offsets.
+    pub ner_endpoint: Option<Url>,
+}
+
+/// A span of personal data in a text, as byte offsets.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entity {
+    pub start: usize,
+    pub end: usize,
+    pub label: String,
+}
+
+/// A source of personal data spans. Detectors are combined, overlapping spans are merged.
+#[async_trait]
+pub trait Detector: Send + Sync {
+    async fn detect(&self, text: &str) -> anyhow::Result<Vec<Entity>>;
+}
+
+pub struct RegexDetector {
+    patterns: Vec<(&'static str, Regex)>,
+    /// Dates and times, which look like phone numbers to the phone pattern.
+    dates: Regex,
+}
+
+impl Default for RegexDetector {
+    fn default() -> Self {
+        let patterns = [
+            ("EMAIL", r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}"),
+            ("MATRIX_ID", r"[@!#][A-Za-z0-9._=/+-]+:[A-Za-z0-9.-]+(?::\d+)?"),
+            ("PHONE", r"(?:\+|\(|\b)\d[\d ().-]{6,}\d\b"),
+        ];
+
+        Self {
+            patterns: patterns
+                .into_iter()
+                .map(|(label, pattern)| (label, Regex::new(pattern).expect("valid PII pattern")))
+                .collect(),
+            dates: Regex::new(r"\d{4}[./-]\d{1,2}[./-]\d{1,2}|\d{1,2}[./-]\d{1,2}[./-]\d{4}")
+                .expect("valid date pattern"),
+        }
+    }
+}
+
+impl RegexDetector {
+    /// Whether a match of `label`'s pattern really is one. Phone numbers have 9 to 15 digits, single separators
+    /// and no dates in them.
+    fn confirms(&self, label: &str, found: &str) -> bool {
+        if label != "PHONE" {
+            return true;
+        }
+        let digits = found.chars().filter(char::is_ascii_digit).count();
+        let doubled = found
+            .as_bytes()
+            .windows(2)
+            .any(|pair| pair.iter().all(|byte| b" .-".contains(byte)));
+        (9..=15).contains(&digits) && !doubled && !self.dates.is_match(found)
+    }
+}
+
+#[async_trait]
+impl Detector for RegexDetector {
+    async fn detect(&self, text: &str) -> anyhow::Result<Vec<Entity>> {
+        Ok(self
+            .patterns
+            .iter()
+            .flat_map(|(label, pattern)| {
+                pattern
+                    .find_iter(text)
+                    .filter(|found| self.confirms(label, found.as_str()))
+                    .map(|found| Entity {
+                        start: found.start(),
+                        end: found.end(),
+                        label: label.to_string(),
+                    })
+            })
+            .collect())
+    }
+}
+
+pub struct NerDetector {
+    client: reqwest::Client,
+    endpoint: Url,
+}
+
+#[async_trait]
+impl Detector for NerDetector {
+    async fn detect(&self, text: &str) -> anyhow::Result<Vec<Entity>> {
+        let entities: Vec<Entity> = self
+            .client
+            .post(self.endpoint.clone())
+            .json(&json!({ "text": text }))
+            .send_identified()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        // Convert character offsets into byte offsets, dropping anything out of range.
+        let offsets: Vec<usize> = text
+            .char_indices()
+            .map(|(index, _)| index)
+            .chain([text.len()])
+            .collect();
+        Ok(entities
+            .into_iter()
+            .filter_map(|entity| {
+                Some(Entity {
+                    start: *offsets.get(entity.start)?,
+                    end: *offsets.get(entity.end)?,
+                    label: entity.label.to_uppercase(),
+                })
+            })
+            .filter(|entity| entity.start < entity.end)
+            .collect())
+    }
+}
+
+/// The configured detectors, shared by all conversations.
+pub struct Pii {
+    detectors: Vec<Box<dyn Detector>>,
+}
+
+impl Pii {
+    pub fn new(config: &PiiConfig, client: reqwest::Client) -> Self {
+        let mut detectors: Vec<Box<dyn Detector>> = vec![Box::new(RegexDetector::default())];
+        if let Some(endpoint) = &config.ner_endpoint {
+            detectors.push(Box::new(NerDetector {
+                client,
+                endpoint: endpoint.clone(),
+            }));
+        }
+
+        Self { detectors }
+    }
+
+    pub fn scrubber(&self) -> Scrubber<'_> {
+        Scrubber {
+            detectors: &self.detectors,
+            placeholders: HashMap::new(),
+            counters: HashMap::new(),
+            scrubbed: HashMap::new(),
+        }
+    }
+}
+
+/// Replaces personal data with placeholders like `[EMAIL_1]`, consistently for all texts scrubbed
+/// by the same scrubber, and puts the original values back into the reply.
+pub struct Scrubber<'a> {
+    detectors: &'a [Box<dyn Detector>],
+    placeholders: HashMap<String, String>,
+    counters: HashMap<String, usize>,
+    /// Texts already scrubbed, so history sent again in each tool round isn't run through the detectors again.
+    scrubbed: HashMap<String, String>,
+}
+
+impl Scrubber<'_> {
+    pub async fn scrub(&mut self, text: &str) -> anyhow::Result<String> {
+        if let Some(scrubbed) = self.scrubbed.get(text) {
+            return Ok(scrubbed.clone());
+        }
+
+        let mut entities = Vec::new();
+        for detector in self.detectors {
+            entities.extend(detector.detect(text).await?);
+        }
+        entities.sort_by_key(|entity| (entity.start, std::cmp::Reverse(entity.end)));
+
+        let mut output = String::with_capacity(text.len());
+        let mut cursor = 0;
+        for entity in entities {
+            if entity.start < cursor || !text.is_char_boundary(entity.start) || !text.is_char_boundary(entity.end) {
+                continue;
+            }
+
+            output.push_str(&text[cursor..entity.start]);
+            output.push_str(&self.placeholder(&entity.label, &text[entity.start..entity.end]));
+            cursor = entity.end;
+        }
+        output.push_str(&text[cursor..]);
+
+        self.scrubbed.insert(text.to_string(), output.clone());
+        Ok(output)
+    }
+
+    pub async fn scrub_content(&mut self, content: &MessageContent) -> anyhow::Result<MessageContent> {
+        Ok(match content {
+            MessageContent::Text(text) => MessageContent::Text(self.scrub(text).await?),
+            MessageContent::Parts(parts) => {
+                let mut scrubbed = Vec::with_capacity(parts.len());
+                for part in parts {
+                    scrubbed.push(match part {
+                        ContentPart::Text { text } => ContentPart::text(self.scrub(text).await?),
+                        other => other.clone(),
+                    });
+                }
+                MessageContent::Parts(scrubbed)
+            }
+        })
+    }
+
+    pub fn restore(&self, text: &str) -> String {
+        self.placeholders
+            .iter()
+            .fold(text.to_string(), |text, (original, placeholder)| {
+                text.replace(placeholder, original)
+            })
+    }
+
+    fn placeholder(&mut self, label: &str, original: &str) -> String {
+        if let Some(placeholder) = self.placeholders.get(original) {
+            return placeholder.clone();
+        }
+
+        let counter = self.counters.entry(label.to_string()).or_default();
+        *counter += 1;
+        let placeholder = format!("[{label}_{counter}]");
+        self.placeholders.insert(original.to_string(), placeholder.clone());
+        placeholder
+    }
+}
@@ -0,0 +1,174 @@
+use anyhow::{Context, bail, ensure};
+use chrono::{TimeDelta, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use url::Url;
+
+use crate::client::Identified;
+
+/// Number of points a range query returns per series, and the most series shown.
+const RANGE_POINTS: i64 = 60;
+const MAX_SERIES: usize = 20;
+/// Longest range a query may look back over.
+const MAX_RANGE: TimeDelta = TimeDelta::days(366);
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PrometheusConfig {
+    /// Base URL of the Prometheus server, e.g. `http://prometheus:9090/`. The query tool is only offered when this
+    /// is set.
+    pub url: Option<Url>,
+    /// Bearer token, for servers behind authentication.
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    status: String,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    data: Option<Data>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Data {
+    result_type: String,
+    result: Value,
+}
+
+/// Run an instant query, or a range query over the last `range` (e.g. `1h`, `7d`) with a sparkline per series.
+pub async fn query(
+    http: &reqwest::Client,
+    config: &PrometheusConfig,
+    query: &str,
+    range: Option<&str>,
+) -> anyhow::Result<String> {
+    let url = config.url.as_ref().context("Prometheus is not configured")?;
+    let now = Utc::now();
+    let request = match range {
+        Some(range) => {
+            let range = parse_range(range)?;
+            let step = (range.num_seconds() / RANGE_POINTS).max(1);
+            http.get(url.join("api/v1/query_range")?).query(&[
+                ("query", query.to_string()),
+                ("start", (now - range).timestamp().to_string()),
+                ("end", now.timestamp().to_string()),
+                ("step", step.to_string()),
+            ])
+        }
+        None => http.get(url.join("api/v1/query")?).query(&[("query", query)]),
+    };
+    let request = match &config.token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    };
+
+    let response: Response = request.send_identified().await?.json().await?;
+    if response.status != "success" {
+        bail!("Query failed: {}", response.error.unwrap_or(response.status));
+    }
+    let data = response.data.context("Response without data")?;
+
+    Ok(match data.result_type.as_str() {
+        "vector" => format_vector(&data.result),
+        "matrix" => format_matrix(&data.result),
+        // Scalars and strings are a single [timestamp, value] pair.
+        _ => data.result[1].as_str().unwrap_or_default().to_string(),
+    })
+}
+
+fn parse_range(range: &str) -> anyhow::Result<TimeDelta> {
+    let range = range.trim();
+    let split = range.find(|c: char| !c.is_ascii_digit()).unwrap_or(range.len());
+    let (amount, unit) = range.split_at(split);
+    let amount: i64 = amount.parse().with_context(|| format!("Invalid range '{range}'"))?;
+    let delta = match unit {
+        "s" => TimeDelta::try_seconds(amount),
+        "m" => TimeDelta::try_minutes(amount),
+        "h" | "" => TimeDelta::try_hours(amount),
+        "d" => TimeDelta::try_days(amount),
+        "w" => TimeDelta::try_weeks(amount),
+        _ => bail!("Invalid range '{range}', use e.g. 30m, 6h or 7d"),
+    };
+    let delta = delta
+        .filter(|delta| *delta <= MAX_RANGE)
+        .with_context(|| format!("Range '{range}' is too long, look back a year at most"))?;
+    ensure!(delta > TimeDelta::zero(), "Range '{range}' is empty");
+    Ok(delta)
+}
+
+/// Series labels in PromQL notation, e.g. `up{job="node"}`.
+fn labels(metric: &Value) -> String {
+    let Some(labels) = metric.as_object() else {
+        return String::new();
+    };
+    let name = labels.get("__name__").and_then(Value::as_str).unwrap_or_default();
+    let pairs = labels
+        .iter()
+        .filter(|(key, _)| *key != "__name__")
+        .map(|(key, value)| format!("{key}=\"{}\"", value.as_str().unwrap_or_default()))
+        .collect::<Vec<_>>();
+    format!("{name}{{{}}}", pairs.join(", "))
+}
+
+fn series(result: &Value) -> impl Iterator<Item = &Value> {
+    result.as_array().into_iter().flatten()
+}
+
+fn format_vector(result: &Value) -> String {
+    let lines = series(result)
+        .take(MAX_SERIES)
+        .map(|sample| {
+            let value = sample["value"][1].as_str().unwrap_or_default();
+            format!("{} {value}", labels(&sample["metric"]))
+        })
+        .collect::<Vec<_>>();
+    with_overflow(lines, series(result).count())
+}
+
+fn format_matrix(result: &Value) -> String {
+    let lines = series(result)
+        .take(MAX_SERIES)
+        .map(|range| {
+            let values = range["values"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|point| point[1].as_str()?.parse::<f64>().ok())
+                .collect::<Vec<_>>();
+            let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            let last = values.last().copied().unwrap_or(f64::NAN);
+            format!(
+                "{}\n{} min {min}, max {max}, last {last}",
+                labels(&range["metric"]),
+                sparkline(&values, min, max)
+            )
+        })
+        .collect::<Vec<_>>();
+    with_overflow(lines, series(result).count())
+}
+
+fn sparkline(values: &[f64], min: f64, max: f64) -> String {
+    values
+        .iter()
+        .map(|value| {
+            let scaled = match max > min {
+                true => (value - min) / (max - min) * (SPARKS.len() - 1) as f64,
+                false => 0.0,
+            };
+            SPARKS[(scaled.round() as usize).min(SPARKS.len() - 1)]
+        })
+        .collect()
+}
+
+fn with_overflow(lines: Vec<String>, total: usize) -> String {
+    match (lines.is_empty(), total > lines.len()) {
+        (true, _) => "The query returned no data.".to_string(),
+        (false, true) => format!("{}\n({} more series not shown)", lines.join("\n"), total - lines.len()),
+        (false, false) => lines.join("\n"),
+    }
+}
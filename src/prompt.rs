@@ -0,0 +1,188 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use matrix_appservice::exports::matrix_sdk::ruma::OwnedUserId;
+use minijinja::{Environment, context};
+use serde::Deserialize;
+
+use crate::{memory, participants::Participant, settings::RoomSettings};
+
+/// Members listed in the system prompt. Larger rooms list the people with the highest power levels and count the rest.
+const MAX_PARTICIPANTS: usize = 50;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PromptConfig {
+    /// Base instructions sent as the system prompt with every request. A minijinja template, evaluated per request
+    /// with `{{room_name}}`, `{{room_topic}}`, `{{user_id}}`, `{{user_display_name}}`, `{{date}}`, `{{time}}`,
+    /// `{{weekday}}`, `{{timezone}}`, `{{locale}}` and `{{persona}}`. The persona of the room is added after the
+    /// system prompt, unless the template places it itself.
+    pub system: Option<String>,
+    /// Template describing the current time, with `{date}`, `{time}`, `{weekday}`, `{timezone}` and
+    /// `{locale}` placeholders. Leave empty to not tell the model the time.
+    pub clock: String,
+    /// Default IANA timezone, rooms can override this with `!set timezone`.
+    pub timezone: String,
+    /// Default locale, rooms can override this with `!set locale`.
+    pub locale: String,
+    /// List the room's members with their display names and power levels, so the model can refer to people.
+    pub participants: bool,
+    /// Mention the room's name and topic, so the model picks up on what the room is about.
+    pub room_details: bool,
+    /// Tell the model it can describe an action by starting its reply with `/me`, which is sent as an emote.
+    pub emotes: bool,
+}
+
+impl Default for PromptConfig {
+    fn default() -> Self {
+        Self {
+            system: None,
+            clock: "The current date is {weekday} {date}, the time is {time} ({timezone}). \
+                Answer in the conventions of the {locale} locale unless asked otherwise."
+                .to_string(),
+            timezone: "UTC".to_string(),
+            locale: "en-US".to_string(),
+            participants: false,
+            room_details: false,
+            emotes: true,
+        }
+    }
+}
+
+/// Details about the room a request is made in, gathered according to the prompt configuration.
+#[derive(Debug, Default)]
+pub struct RoomContext {
+    pub name: Option<String>,
+    pub topic: Option<String>,
+    pub participants: Vec<Participant>,
+    /// Facts the person sending the prompt asked to be remembered, only shared in their DMs.
+    pub facts: Vec<String>,
+    /// Who sent the prompt, if anyone, and their display name in the room.
+    pub sender: Option<OwnedUserId>,
+    pub sender_name: Option<String>,
+}
+
+/// Assemble the system prompt for a request in a room, `None` if there's nothing to tell the model.
+pub fn system_prompt(
+    config: &PromptConfig,
+    settings: &RoomSettings,
+    room: &RoomContext,
+) -> anyhow::Result<Option<String>> {
+    let timezone = settings.resolved_timezone(&config.timezone);
+    let locale = settings.locale.as_deref().unwrap_or(&config.locale);
+    let now = Utc::now().with_timezone(&timezone);
+    let timezone = timezone.name();
+
+    let mut sections = Vec::new();
+    let mut persona_placed = false;
+    if let Some(system) = &config.system {
+        let (system, placed) = render_system(system, settings, room, &now, timezone, locale)?;
+        sections.push(system);
+        persona_placed = placed;
+    }
+    if let Some(persona) = settings.persona.as_ref().filter(|_| !persona_placed) {
+        sections.push(persona.clone());
+    }
+
+    if !config.clock.is_empty() {
+        sections.push(clock(&config.clock, &now, timezone, locale));
+    }
+
+    match (&room.name, &room.topic) {
+        (Some(name), Some(topic)) => sections.push(format!(
+            "You are chatting in the room \"{name}\". Its topic is: {topic}"
+        )),
+        (Some(name), None) => sections.push(format!("You are chatting in the room \"{name}\".")),
+        (None, Some(topic)) => sections.push(format!("The topic of this room is: {topic}")),
+        (None, None) => (),
+    }
+
+    if !room.participants.is_empty() {
+        let mut participants = room.participants.iter().collect::<Vec<_>>();
+        participants.sort_by_key(|participant| std::cmp::Reverse(participant.power_level));
+        let mut lines = participants
+            .iter()
+            .take(MAX_PARTICIPANTS)
+            .map(|participant| participant.describe())
+            .collect::<Vec<_>>();
+        if let Some(others) = participants
+            .len()
+            .checked_sub(MAX_PARTICIPANTS)
+            .filter(|others| *others > 0)
+        {
+            lines.push(format!("- and {others} other members"));
+        }
+        sections.push(format!("People in this room:\n{}", lines.join("\n")));
+    }
+
+    if config.emotes {
+        sections.push(
+            "Messages like *alice waves* are actions. To perform one yourself, start your reply with /me, \
+            e.g. \"/me waves\"."
+                .to_string(),
+        );
+    }
+
+    if settings.voice_mode.unwrap_or_default() {
+        sections.push(
+            "Your replies are read aloud as voice messages. Answer in plain spoken sentences, without Markdown, \
+            links, tables or code blocks."
+                .to_string(),
+        );
+    }
+
+    if !room.facts.is_empty() {
+        sections.push(format!(
+            "Things the user asked you to remember about them:\n{}",
+            memory::describe(room.facts.iter().map(String::as_str))
+        ));
+    }
+
+    Ok((!sections.is_empty()).then(|| sections.join("\n\n")))
+}
+
+/// Render the system prompt template. Returns the prompt and whether the template places the persona itself.
+fn render_system(
+    template: &str,
+    settings: &RoomSettings,
+    room: &RoomContext,
+    now: &DateTime<Tz>,
+    timezone: &str,
+    locale: &str,
+) -> anyhow::Result<(String, bool)> {
+    let environment = Environment::new();
+    let template = environment
+        .template_from_str(template)
+        .context("Invalid system prompt template")?;
+    let places_persona = template.undeclared_variables(false).contains("persona");
+    let user_display_name = room
+        .sender_name
+        .clone()
+        .or_else(|| room.sender.as_ref().map(|sender| sender.localpart().to_string()));
+
+    let rendered = template
+        .render(context! {
+            room_name => room.name,
+            room_topic => room.topic,
+            user_id => room.sender.as_ref().map(ToString::to_string),
+            user_display_name,
+            date => now.format("%Y-%m-%d").to_string(),
+            time => now.format("%H:%M").to_string(),
+            weekday => now.format("%A").to_string(),
+            timezone => timezone,
+            locale => locale,
+            persona => settings.persona,
+        })
+        .context("Rendering the system prompt template failed")?;
+
+    Ok((rendered, places_persona))
+}
+
+fn clock(template: &str, now: &DateTime<Tz>, timezone: &str, locale: &str) -> String {
+    template
+        .replace("{date}", &now.format("%Y-%m-%d").to_string())
+        .replace("{time}", &now.format("%H:%M").to_string())
+        .replace("{weekday}", &now.format("%A").to_string())
+        .replace("{timezone}", timezone)
+        .replace("{locale}", locale)
+}
@@ -0,0 +1,109 @@
+use std::collections::HashSet;
+
+use matrix_appservice::exports::matrix_sdk::ruma::{
+    OwnedRoomId, OwnedUserId, RoomId, UserId, events::room::message::RoomMessageEventContent,
+};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use crate::{config::Config, homeserver::Homeserver, settings::RoomSettings};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PuppetsConfig {
+    /// Let rooms post debate turns and tool announcements as ghost users, one per persona and tool, so clients show
+    /// who's speaking. Rooms opt in with `!set puppets true`. Ghosts have no devices, so they post unencrypted, and
+    /// are only used in unencrypted rooms.
+    pub enabled: bool,
+    /// Localpart prefix of the ghosts, e.g. `openai_` for `@openai_search:example.org`. Has to fall within the user
+    /// namespace of the appservice registration.
+    pub prefix: String,
+}
+
+impl Default for PuppetsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            prefix: "openai_".to_string(),
+        }
+    }
+}
+
+/// Ghost users of the appservice posting on behalf of personas and tools.
+pub struct Puppets {
+    config: PuppetsConfig,
+    server_name: String,
+    homeserver: Homeserver,
+    /// Ghosts registered with their display name set since startup.
+    registered: Mutex<HashSet<OwnedUserId>>,
+    /// Rooms each ghost is known to have joined.
+    joined: Mutex<HashSet<(OwnedRoomId, OwnedUserId)>>,
+}
+
+impl Puppets {
+    pub fn new(config: &Config, homeserver: Homeserver) -> Self {
+        Self {
+            config: config.puppets.clone(),
+            server_name: config.homeserver.server_name.clone(),
+            homeserver,
+            registered: Mutex::new(HashSet::new()),
+            joined: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Whether messages in a room are posted by ghosts: it opted in, and isn't encrypted.
+    pub async fn active(&self, room_id: &RoomId, settings: &RoomSettings) -> anyhow::Result<bool> {
+        if !self.config.enabled || !settings.puppets.unwrap_or_default() {
+            return Ok(false);
+        }
+        Ok(self
+            .homeserver
+            .state_event(room_id, "m.room.encryption")
+            .await?
+            .is_none())
+    }
+
+    /// Ghost posting as `name`, a persona or tool name, e.g. `@openai_web_search:example.org` for `web_search`.
+    pub fn user_id(&self, name: &str) -> anyhow::Result<OwnedUserId> {
+        let localpart: String = name
+            .to_lowercase()
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | '0'..='9' | '.' | '_' | '=' | '-' => c,
+                _ => '_',
+            })
+            .collect();
+        Ok(UserId::parse(format!(
+            "@{}{localpart}:{}",
+            self.config.prefix, self.server_name
+        ))?)
+    }
+
+    /// Whether a user is one of the ghosts, judging by the configured prefix, whether or not ghosts are enabled.
+    pub fn is_puppet(&self, user_id: &UserId) -> bool {
+        user_id.localpart().starts_with(&self.config.prefix) && user_id.server_name().as_str() == self.server_name
+    }
+
+    /// Post a message as the ghost named `name`, registering it and joining it to the room first when needed.
+    pub async fn send(&self, room_id: &RoomId, name: &str, content: &RoomMessageEventContent) -> anyhow::Result<()> {
+        let user_id = self.user_id(name)?;
+        let ghost = self.homeserver.as_user(&user_id);
+
+        if !self.registered.lock().await.contains(&user_id) {
+            self.homeserver.register(user_id.localpart()).await?;
+            ghost.set_displayname(name).await?;
+            self.registered.lock().await.insert(user_id.clone());
+        }
+
+        let membership = (room_id.to_owned(), user_id.clone());
+        if !self.joined.lock().await.contains(&membership) {
+            if !self.homeserver.joined_members(room_id).await?.contains_key(&user_id) {
+                self.homeserver.invite(room_id, &user_id).await?;
+                ghost.join(room_id).await?;
+            }
+            self.joined.lock().await.insert(membership);
+        }
+
+        ghost.send_message(room_id, &serde_json::to_value(content)?).await
+    }
+}
@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use matrix_appservice::{
+    Device,
+    exports::matrix_sdk::ruma::{OwnedEventId, RoomId, UserId},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    directives::InlineDirectives,
+    openai::{ContentPart, ConversationStore, OpenAIMessage, Progress},
+    retry,
+    settings::RoomSettings,
+    store::{self, Store},
+};
+
+/// How long a question is kept after its answer is due, so the timeout still finds it to continue without one.
+const EXPIRY_GRACE: Duration = Duration::from_secs(60);
+
+/// A prompt suspended on a follow-up question the model asked, waiting for a free-text answer. It is kept in the
+/// store rather than holding on to the room, so other prompts are answered meanwhile and an answer still resumes
+/// the prompt after a restart. The timeout isn't kept though, so a question left unanswered across a restart
+/// expires without the prompt continuing. Rooms that don't keep message content keep their questions in process
+/// memory only. Each person has at most one open question per room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Question {
+    /// Prompt being answered when the model asked.
+    pub prompt_id: OwnedEventId,
+    /// The question as posted. In group rooms, answers have to reply to it.
+    pub question_id: OwnedEventId,
+    /// Tool call the answer is the result of.
+    pub call_id: String,
+    /// Messages of the tool loop so far, from the system prompt up to the results of the round that asked.
+    pub messages: Vec<OpenAIMessage>,
+    /// Images returned by other tools in the same round, handed to the model after the answer.
+    pub images: Vec<ContentPart>,
+    pub directives: InlineDirectives,
+    pub progress: Progress,
+}
+
+/// Post a question of the model. In group rooms it asks for a reply, so other messages of the asker aren't taken
+/// as the answer.
+pub async fn post(
+    state: &ConversationStore,
+    device: &Device,
+    room_id: &RoomId,
+    is_direct: bool,
+    question: &str,
+) -> anyhow::Result<OwnedEventId> {
+    let body = match is_direct {
+        true => format!("{question}\n\nReply to continue."),
+        false => format!("{question}\n\nReply to this message to continue."),
+    };
+    retry::send(state, device, room_id, state.config().behavior.reply(body)).await
+}
+
+/// Keep the question `asker` has to answer within `timeout`, replacing any earlier one of theirs in the room. It
+/// holds the conversation so far, so it is kept no longer than the room's retention period.
+pub async fn save(
+    state: &ConversationStore,
+    settings: &RoomSettings,
+    room_id: &RoomId,
+    asker: &UserId,
+    question: &Question,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let store = match settings.keeps_content() {
+        true => state.store(),
+        false => state.scratch(),
+    };
+    let mut ttl = timeout + EXPIRY_GRACE;
+    if let Some(days) = settings.retention_days {
+        ttl = ttl.min(Duration::from_secs(u64::from(days) * 24 * 60 * 60));
+    }
+    store.save_expiring(&question_key(room_id, asker), question, ttl).await
+}
+
+/// Take the question `asker` has open in the room, if `answers` agrees a message of theirs answers it. A taken
+/// question is removed, so it's resumed only once.
+pub async fn take(
+    state: &ConversationStore,
+    room_id: &RoomId,
+    asker: &UserId,
+    answers: impl FnOnce(&Question) -> bool,
+) -> anyhow::Result<Option<Question>> {
+    let key = question_key(room_id, asker);
+    let _lock = state.store().lock(&key).await;
+    // The room may have stopped keeping content since, so look in both places.
+    for store in [state.scratch(), state.store()] {
+        if let Some(question) = store.load::<Question>(&key).await? {
+            if !answers(&question) {
+                return Ok(None);
+            }
+            store.delete(&key).await?;
+            return Ok(Some(question));
+        }
+    }
+    Ok(None)
+}
+
+fn question_key(room_id: &RoomId, asker: &UserId) -> String {
+    store::room_key(room_id, &format!("questions/{asker}"))
+}
@@ -0,0 +1,55 @@
+use std::future::Future;
+
+use matrix_appservice::{
+    Device,
+    exports::matrix_sdk::ruma::{RoomId, events::room::message::ReplacementMetadata},
+};
+use tokio::sync::oneshot;
+
+use crate::{limiter::Limiter, openai::ConversationStore};
+
+/// Run `work`, answering a prompt. When all model requests are in use, the room is told the prompt is queued and
+/// about how long it will take, and the notice is edited once the prompt gets its turn.
+pub async fn announce<F: Future>(
+    state: &ConversationStore,
+    device: &Device,
+    room_id: &RoomId,
+    locale: &str,
+    work: F,
+) -> anyhow::Result<F::Output> {
+    let limiter = state.model_limiter();
+    if !state.config().limits.queue_notices || !limiter.saturated() {
+        return Ok(work.await);
+    }
+
+    let text = match limiter.estimated_wait() {
+        Some(wait) => state
+            .locales()
+            .text(locale, "queue.waiting", &[("seconds", &wait.as_secs().max(1))]),
+        None => state.locales().text(locale, "queue.waiting_unknown", &[]),
+    };
+    let notice_id = device
+        .send_message(room_id, state.config().behavior.notice(text))
+        .await?;
+    state.metrics().increment("openai_bot_queue_notices_total", &[]);
+
+    let (started, turn) = oneshot::channel();
+    let edit = async {
+        // The sender is dropped without a signal when the work ends before ever making a request.
+        if turn.await.is_err() {
+            return;
+        }
+        let text = state.locales().text(locale, "queue.started", &[]);
+        let content = state
+            .config()
+            .behavior
+            .notice(text)
+            .make_replacement(ReplacementMetadata::new(notice_id.clone(), None));
+        if let Err(error) = device.send_message(room_id, content).await {
+            tracing::warn!("Updating the queue notice in {room_id} failed: {error}");
+        }
+    };
+    let (output, ()) = tokio::join!(Limiter::started(started, work), edit);
+
+    Ok(output)
+}
@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use matrix_appservice::exports::matrix_sdk::ruma::MilliSecondsSinceUnixEpoch;
+use serde::Deserialize;
+
+use crate::openai::{Conversation, ConversationStore};
+
+const RECAP_PROMPT: &str = "Below is an earlier conversation between a user and an assistant, which the user is \
+    returning to. Recap in two or three sentences what was discussed and where it was left, addressing the user \
+    directly, e.g. \"Previously we discussed…\". Reply with only the recap.";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RecapConfig {
+    /// Open the reply to someone returning to a DM after a while with a short recap of the conversation so far.
+    pub enabled: bool,
+    /// Hours since the last message of the conversation after which the next prompt gets a recap.
+    pub idle_hours: u64,
+}
+
+impl Default for RecapConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            idle_hours: 72,
+        }
+    }
+}
+
+/// Recap of the conversation so far, when the prompt sent at `sent` comes after the configured idle gap.
+pub async fn recap(
+    state: &ConversationStore,
+    conversation: &Conversation,
+    sent: MilliSecondsSinceUnixEpoch,
+) -> anyhow::Result<Option<String>> {
+    let config = &state.config().recap;
+    let Some(last) = conversation.last_activity() else {
+        return Ok(None);
+    };
+    let idle = Duration::from_millis(u64::from(sent.0).saturating_sub(u64::from(last.0)));
+    if !config.enabled || idle < Duration::from_secs(config.idle_hours * 60 * 60) {
+        return Ok(None);
+    }
+
+    let transcript = conversation.transcript().await;
+    if transcript.is_empty() {
+        return Ok(None);
+    }
+    let locale = state.locale(conversation.settings());
+    let summary = state
+        .complete(format!(
+            "{RECAP_PROMPT} Write it in the language of locale {locale}.\n\n{transcript}"
+        ))
+        .await?;
+
+    Ok(Some(state.locales().text(
+        locale,
+        "recap.reply",
+        &[("summary", summary.trim())],
+    )))
+}
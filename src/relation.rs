@@ -0,0 +1,60 @@
+use matrix_appservice::{
+    Device,
+    exports::matrix_sdk::ruma::{OwnedEventId, RoomId, events::room::message::RoomMessageEventContent, serde::Raw},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+/// Relation tying a message of the bot to the event it responds to, a prompt or a command.
+pub const BOT_RESPONSE_REL_TYPE: &str = "nl.spacebased.matrix-openai-bot.bot_response";
+
+/// Content of the `bot_response` relation. Backfills use it to tell which exchanges belong to which conversation
+/// epoch, and which messages only respond to commands.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BotResponse {
+    /// The prompt or command responded to.
+    pub event_id: OwnedEventId,
+    /// Model that wrote the response, `None` for responses to commands, which backfills leave out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Conversation epoch of the exchange, increased by every `!reset`.
+    #[serde(default)]
+    pub epoch: u64,
+}
+
+impl BotResponse {
+    /// Send `content` with the relation attached. Edits already relate to the message they replace, so they are sent
+    /// as is.
+    pub async fn send(
+        &self,
+        device: &Device,
+        room_id: &RoomId,
+        content: RoomMessageEventContent,
+    ) -> anyhow::Result<OwnedEventId> {
+        Ok(device.send_message(room_id, self.attach(content)?).await?)
+    }
+
+    /// `content` with the relation attached, see [`BotResponse::send`].
+    pub fn attach(&self, content: RoomMessageEventContent) -> anyhow::Result<RoomMessageEventContent> {
+        if content.relates_to.is_some() {
+            return Ok(content);
+        }
+        let mut value = serde_json::to_value(&content)?;
+        value["m.relates_to"] = json!({
+            "rel_type": BOT_RESPONSE_REL_TYPE,
+            "event_id": self.event_id,
+            "model": self.model,
+            "epoch": self.epoch,
+        });
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// The relation of a message, if it's a response of the bot.
+    pub fn from_event<T>(raw: &Raw<T>) -> Option<Self> {
+        let relation = raw.get_field::<Value>("content").ok()??.get("m.relates_to")?.clone();
+        if relation.get("rel_type")?.as_str()? != BOT_RESPONSE_REL_TYPE {
+            return None;
+        }
+        serde_json::from_value(relation).ok()
+    }
+}
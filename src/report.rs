@@ -0,0 +1,118 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Arc};
+
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use matrix_appservice::exports::matrix_sdk::ruma::{OwnedRoomId, OwnedUserId};
+use serde::Serialize;
+
+use crate::{
+    config::Config,
+    homeserver::Homeserver,
+    metrics::Metrics,
+    store::{self, StorageBackend, Store},
+    usage::DailyUsage,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+}
+
+/// Token usage and estimated cost of a user in a room over the reported period.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReportRow {
+    pub room_id: String,
+    pub user_id: String,
+    pub messages: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Estimated cost in dollars, from `limits.prompt_price` and `limits.completion_price`.
+    pub cost: f64,
+}
+
+/// Usage per user and room since `since`, read from the configured store rather than the metrics endpoint.
+pub async fn generate(config: &Config, since: Option<NaiveDate>) -> anyhow::Result<Vec<ReportRow>> {
+    match config.storage.backend {
+        StorageBackend::Memory => tracing::warn!("The memory store doesn't outlive the bot, so the report is empty"),
+        StorageBackend::AccountData => anyhow::bail!(
+            "Account data can't be listed, so usage reports need the redis store. Usage is still kept per day in \
+             account data, but only the running bot knows which keys exist"
+        ),
+        StorageBackend::Redis => {}
+    }
+    let homeserver = Homeserver::new(config, Arc::new(Metrics::default()))?;
+    let store = store::from_config(config, &homeserver).await?;
+    collect(store.as_ref(), config, since).await
+}
+
+async fn collect(store: &dyn Store, config: &Config, since: Option<NaiveDate>) -> anyhow::Result<Vec<ReportRow>> {
+    let mut totals: BTreeMap<(OwnedRoomId, OwnedUserId), DailyUsage> = BTreeMap::new();
+    for key in store.keys("room/").await? {
+        let Some((room_id, user_id, date)) = parse_key(&key) else {
+            continue;
+        };
+        if since.is_some_and(|since| date < since) {
+            continue;
+        }
+        let Some(usage) = store.load::<DailyUsage>(&key).await? else {
+            continue;
+        };
+        let total = totals.entry((room_id, user_id)).or_default();
+        total.messages += usage.messages;
+        total.prompt_tokens += usage.prompt_tokens;
+        total.completion_tokens += usage.completion_tokens;
+    }
+
+    let limits = &config.limits;
+    Ok(totals
+        .into_iter()
+        .map(|((room_id, user_id), usage)| ReportRow {
+            room_id: room_id.to_string(),
+            user_id: user_id.to_string(),
+            messages: usage.messages,
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            cost: (usage.prompt_tokens as f64 * limits.prompt_price
+                + usage.completion_tokens as f64 * limits.completion_price)
+                / 1_000_000.0,
+        })
+        .collect())
+}
+
+/// Room, user and date of a daily usage key, `room/{room_id}/usage/{date}/{user_id}`.
+fn parse_key(key: &str) -> Option<(OwnedRoomId, OwnedUserId, NaiveDate)> {
+    let (room_id, rest) = key.strip_prefix("room/")?.split_once("/usage/")?;
+    let (date, user_id) = rest.split_once('/')?;
+    Some((room_id.try_into().ok()?, user_id.try_into().ok()?, date.parse().ok()?))
+}
+
+pub fn render(rows: &[ReportRow], format: ReportFormat) -> anyhow::Result<String> {
+    match format {
+        ReportFormat::Json => Ok(serde_json::to_string_pretty(rows)?),
+        ReportFormat::Csv => {
+            let mut csv = "room_id,user_id,messages,prompt_tokens,completion_tokens,cost\n".to_string();
+            for row in rows {
+                writeln!(
+                    csv,
+                    "{},{},{},{},{},{:.6}",
+                    csv_field(&row.room_id),
+                    csv_field(&row.user_id),
+                    row.messages,
+                    row.prompt_tokens,
+                    row.completion_tokens,
+                    row.cost
+                )?;
+            }
+            Ok(csv)
+        }
+    }
+}
+
+/// Quote a field when it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string(),
+    }
+}
@@ -0,0 +1,163 @@
+use std::{future::Future, time::Duration};
+
+use matrix_appservice::{
+    Device,
+    exports::matrix_sdk::ruma::{OwnedEventId, OwnedRoomId, RoomId, events::room::message::RoomMessageEventContent},
+};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{homeserver, media, openai::ConversationStore};
+
+/// How failures a retry may fix show up in errors of the Matrix SDK, which only carry the message of the underlying
+/// HTTP error: rate limiting, timeouts, connection problems and gateway errors.
+const TRANSIENT_ERRORS: &[&str] = &[
+    "M_LIMIT_EXCEEDED",
+    "timed out",
+    "error sending request",
+    "500 Internal Server Error",
+    "502 Bad Gateway",
+    "503 Service Unavailable",
+    "504 Gateway Timeout",
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Attempts of a homeserver call before giving up, the first one included.
+    pub attempts: u32,
+    /// Milliseconds to wait before the first retry, doubled for every retry after it.
+    pub backoff_ms: u64,
+    /// Room told about homeserver calls that keep failing. Falls back to `updates.admin_room`.
+    pub admin_room: Option<OwnedRoomId>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            attempts: 4,
+            backoff_ms: 500,
+            admin_room: None,
+        }
+    }
+}
+
+/// Run a homeserver call, retrying transient failures such as federation timeouts with exponential backoff. `call`
+/// names the call in logs and metrics. A call that keeps failing, or is refused by a server ACL, is reported in the
+/// admin room rather than only dropping the prompt.
+pub async fn retry<T, F, Fut>(state: &ConversationStore, call: &'static str, attempt: F) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    retry_if(state, call, is_transient, attempt).await
+}
+
+/// Send a message, retrying transient failures like [`retry`]. A send that timed out may have arrived after all, so
+/// in unencrypted rooms every attempt carries the same transaction ID, which the homeserver doesn't post twice.
+/// Encrypted messages go through the device, which picks a new transaction ID for each attempt, so they are only
+/// retried when the homeserver can't have received them.
+pub async fn send(
+    state: &ConversationStore,
+    device: &Device,
+    room_id: &RoomId,
+    content: RoomMessageEventContent,
+) -> anyhow::Result<OwnedEventId> {
+    if media::is_encrypted(state.homeserver(), room_id).await? {
+        let content = &content;
+        return retry_if(state, "send", is_unsent, || async move {
+            Ok(device.send_message(room_id, content.clone()).await?)
+        })
+        .await;
+    }
+
+    let content = &serde_json::to_value(&content)?;
+    let transaction = &homeserver::transaction_id();
+    retry(state, "send", || async move {
+        state.homeserver().send_event(room_id, content, transaction).await
+    })
+    .await
+}
+
+/// Like [`retry`], retrying the failures `retryable` accepts.
+async fn retry_if<T, F, Fut>(
+    state: &ConversationStore,
+    call: &'static str,
+    retryable: fn(&anyhow::Error) -> bool,
+    mut attempt: F,
+) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let config = &state.config().retry;
+    let mut delay = Duration::from_millis(config.backoff_ms);
+    let mut attempts = 1;
+    loop {
+        let error = match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+
+        let transient = retryable(&error);
+        if transient && attempts < config.attempts {
+            tracing::debug!("Homeserver call {call} failed, retrying in {delay:?}: {error:#}");
+            state
+                .metrics()
+                .increment("openai_bot_homeserver_retries_total", &[("call", call)]);
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+            attempts += 1;
+            continue;
+        }
+
+        if transient || format!("{error:#}").contains("banned from room") {
+            report(state, call, attempts, &error).await;
+        }
+        return Err(error);
+    }
+}
+
+/// Whether a failure may go away on its own: timeouts, connection problems, rate limiting and server errors, which
+/// is how an unreachable federated server shows up.
+fn is_transient(error: &anyhow::Error) -> bool {
+    if let Some(error) = reqwest_error(error) {
+        return error.is_timeout()
+            || error.is_connect()
+            || error
+                .status()
+                .is_some_and(|status| status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS);
+    }
+    let message = format!("{error:#}");
+    TRANSIENT_ERRORS.iter().any(|marker| message.contains(marker))
+}
+
+/// Whether a request failed before the homeserver handled it: it couldn't connect, or was rate limited.
+fn is_unsent(error: &anyhow::Error) -> bool {
+    if let Some(error) = reqwest_error(error) {
+        return error.is_connect() || error.status() == Some(StatusCode::TOO_MANY_REQUESTS);
+    }
+    format!("{error:#}").contains("M_LIMIT_EXCEEDED")
+}
+
+fn reqwest_error(error: &anyhow::Error) -> Option<&reqwest::Error> {
+    error.chain().find_map(|error| error.downcast_ref())
+}
+
+/// Post a notice about a failed call in the admin room. Failing to do so is only logged.
+async fn report(state: &ConversationStore, call: &str, attempts: u32, error: &anyhow::Error) {
+    tracing::warn!("Homeserver call {call} failed after {attempts} attempts: {error:#}");
+    let config = state.config();
+    let Some(room_id) = config.retry.admin_room.as_ref().or(config.updates.admin_room.as_ref()) else {
+        return;
+    };
+
+    let content = json!({
+        "msgtype": "m.notice",
+        "body": format!("Homeserver call `{call}` failed after {attempts} attempts: {error:#}"),
+    });
+    if let Err(error) = state.homeserver().send_message(room_id, &content).await {
+        tracing::warn!("Reporting the failure in {room_id} failed: {error}");
+    }
+}
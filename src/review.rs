@@ -0,0 +1,132 @@
+use futures::{StreamExt, TryStreamExt, stream};
+use matrix_appservice::exports::matrix_sdk::ruma::events::room::message::{MessageType, OriginalSyncRoomMessageEvent};
+
+use crate::{media, openai::ConversationStore};
+
+/// Largest piece of a diff reviewed in one request. Bigger file diffs are split between hunks.
+const MAX_CHUNK_LENGTH: usize = 12_000;
+/// Pieces of a diff reviewed at the same time.
+const CONCURRENT_REVIEWS: usize = 4;
+
+const FILE_PROMPT: &str = "You are reviewing part of a code change. Point out bugs, security issues, unclear code \
+    and missing tests, each as a bullet starting with the line it is about. Skip praise and nitpicks. \
+    Reply with just \"No comments.\" when there's nothing worth mentioning.";
+
+const SUMMARY_PROMPT: &str = "Below are review comments on the files of a code change. Write a short summary of the \
+    change and the most important issues, followed by a verdict: approve, approve with suggestions or request \
+    changes.";
+
+/// Whether text looks like a unified diff.
+pub fn is_diff(text: &str) -> bool {
+    let has = |prefix: &str| text.lines().any(|line| line.starts_with(prefix));
+    has("diff --git ") || (has("--- ") && has("+++ ") && has("@@ "))
+}
+
+/// The diff in a message and any request written above it, if the message is one: either pasted as text or
+/// uploaded as a `.patch` or `.diff` file.
+pub async fn extract(
+    state: &ConversationStore,
+    event: &OriginalSyncRoomMessageEvent,
+) -> anyhow::Result<Option<(String, String)>> {
+    match &event.content.msgtype {
+        MessageType::Text(text) if is_diff(&text.body) => {
+            let start = text
+                .body
+                .find("diff --git ")
+                .or_else(|| text.body.find("--- "))
+                .unwrap_or_default();
+            let (request, diff) = text.body.split_at(start);
+            Ok(Some((diff.to_string(), request.trim().to_string())))
+        }
+        MessageType::File(file) => {
+            let name = file.filename.as_deref().unwrap_or(&file.body).to_lowercase();
+            if !name.ends_with(".patch") && !name.ends_with(".diff") {
+                return Ok(None);
+            }
+            let data = media::download(state.homeserver(), &file.source, state.config().media.max_size).await?;
+            Ok(Some((String::from_utf8_lossy(&data).into_owned(), String::new())))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Split a diff into the changes per file, keyed by path.
+fn split_files(diff: &str) -> Vec<(String, String)> {
+    let mut files: Vec<(String, String)> = Vec::new();
+    for line in diff.lines() {
+        let starts_file = line.starts_with("diff --git ")
+            || (line.starts_with("--- ") && files.last().is_none_or(|(_, chunk)| chunk.contains("\n@@ ")));
+        if starts_file || files.is_empty() {
+            files.push((String::new(), String::new()));
+        }
+
+        let (path, chunk) = files.last_mut().expect("a file was pushed");
+        if let Some(new_path) = line.strip_prefix("+++ ") {
+            *path = new_path.trim_start_matches("b/").to_string();
+        }
+        chunk.push_str(line);
+        chunk.push('\n');
+    }
+
+    files.retain(|(_, chunk)| chunk.contains("\n@@ ") || chunk.starts_with("@@ "));
+    for (path, _) in &mut files {
+        if path.is_empty() {
+            *path = "changes".to_string();
+        }
+    }
+    files
+}
+
+/// Split a file's diff between hunks into pieces of at most `MAX_CHUNK_LENGTH`, as far as hunks allow.
+fn split_hunks(chunk: &str) -> Vec<String> {
+    let mut pieces = vec![String::new()];
+    for line in chunk.lines() {
+        let current = pieces.last_mut().expect("there is a piece");
+        if line.starts_with("@@ ") && current.len() + line.len() > MAX_CHUNK_LENGTH && !current.is_empty() {
+            pieces.push(String::new());
+        }
+        let current = pieces.last_mut().expect("there is a piece");
+        current.push_str(line);
+        current.push('\n');
+    }
+    pieces
+}
+
+/// Review a diff file by file, and summarize the comments. `request` is the message the diff came with.
+pub async fn review(state: &ConversationStore, diff: &str, request: &str) -> anyhow::Result<String> {
+    let files = split_files(diff);
+    if files.is_empty() {
+        return Ok("That looks like a diff, but I couldn't find any changes in it.".to_string());
+    }
+
+    let request = match request.is_empty() {
+        true => String::new(),
+        false => format!("\n\nThe author asked: {request}"),
+    };
+    let pieces = files
+        .iter()
+        .flat_map(|(path, chunk)| split_hunks(chunk).into_iter().map(move |piece| (path, piece)))
+        .collect::<Vec<_>>();
+    let comments: Vec<String> = stream::iter(&pieces)
+        .map(|(path, piece)| state.complete(format!("{FILE_PROMPT}{request}\n\nFile: {path}\n\n```diff\n{piece}```")))
+        .buffered(CONCURRENT_REVIEWS)
+        .try_collect()
+        .await?;
+
+    // Pieces of a file follow each other, so the comments can be grouped under the file they're about.
+    let mut reviews: Vec<(&String, Vec<String>)> = Vec::new();
+    for ((path, _), comment) in pieces.iter().zip(comments) {
+        match reviews.last_mut() {
+            Some((last, file_comments)) if last == path => file_comments.push(comment),
+            _ => reviews.push((path, vec![comment])),
+        }
+    }
+    let comments = reviews
+        .iter()
+        .map(|(path, comments)| format!("### `{path}`\n\n{}", comments.join("\n\n")))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let summary = state.complete(format!("{SUMMARY_PROMPT}\n\n{comments}")).await?;
+
+    Ok(format!("## Review\n\n{summary}\n\n## Files\n\n{comments}"))
+}
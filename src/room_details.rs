@@ -0,0 +1,61 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use matrix_appservice::exports::matrix_sdk::ruma::{OwnedRoomId, RoomId};
+use serde_json::Value;
+use tokio::sync::RwLock;
+
+use crate::homeserver::Homeserver;
+
+/// How long the name and topic of a room are reused before asking the homeserver again.
+const TTL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Default)]
+pub struct RoomDetail {
+    pub name: Option<String>,
+    pub topic: Option<String>,
+}
+
+/// Name and topic per room, fetched from room state on first use and kept for a while.
+#[derive(Default)]
+pub struct RoomDetails {
+    rooms: RwLock<HashMap<OwnedRoomId, (Instant, RoomDetail)>>,
+}
+
+impl RoomDetails {
+    /// Name and topic of the room. State the bot can't read, e.g. because it isn't allowed to, counts as unset.
+    pub async fn get(&self, homeserver: &Homeserver, room_id: &RoomId) -> RoomDetail {
+        if let Some((fetched, details)) = self.rooms.read().await.get(room_id)
+            && fetched.elapsed() < TTL
+        {
+            return details.clone();
+        }
+
+        let details = RoomDetail {
+            name: field(homeserver, room_id, "m.room.name", "name").await,
+            topic: field(homeserver, room_id, "m.room.topic", "topic").await,
+        };
+        self.rooms
+            .write()
+            .await
+            .insert(room_id.to_owned(), (Instant::now(), details.clone()));
+        details
+    }
+}
+
+async fn field(homeserver: &Homeserver, room_id: &RoomId, event_type: &str, field: &str) -> Option<String> {
+    let content = match homeserver.state_event(room_id, event_type).await {
+        Ok(content) => content?,
+        Err(error) => {
+            tracing::debug!("Reading {event_type} of {room_id} failed: {error}");
+            return None;
+        }
+    };
+    content
+        .get(field)
+        .and_then(Value::as_str)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
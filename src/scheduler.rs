@@ -0,0 +1,156 @@
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use matrix_appservice::{ApplicationService, State, exports::matrix_sdk::ruma::RoomId};
+use serde::{Deserialize, Serialize};
+
+use crate::{handlers, openai::ConversationStore, settings::RoomSettings};
+
+/// How often schedules are checked. Runs happen at most this long after they are due.
+const TICK: Duration = Duration::from_secs(30);
+
+/// A prompt run on a cron schedule, with its result posted to the room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    /// Cron expression in the room's timezone, e.g. `0 9 * * MON`. Five field expressions number the days of the
+    /// week like classic cron, from 0 for Sunday.
+    pub cron: String,
+    pub prompt: String,
+    /// Unix timestamp of the last run, or of when the schedule was added.
+    pub last_run: i64,
+}
+
+impl Schedule {
+    pub fn new(cron: &str, prompt: &str) -> anyhow::Result<Self> {
+        parse_cron(cron)?;
+        Ok(Self {
+            cron: cron.to_string(),
+            prompt: prompt.to_string(),
+            last_run: Utc::now().timestamp(),
+        })
+    }
+
+    /// First occurrence after the last run.
+    pub fn next_run(&self, timezone: Tz) -> anyhow::Result<Option<DateTime<Tz>>> {
+        let last_run = DateTime::from_timestamp(self.last_run, 0)
+            .context("Invalid last run timestamp")?
+            .with_timezone(&timezone);
+        Ok(parse_cron(&self.cron)?.after(&last_run).next())
+    }
+}
+
+/// Parse a cron expression, accepting the classic five fields as well as the six or seven field form
+/// with seconds and years. The latter numbers the days of the week from 1 for Sunday.
+fn parse_cron(expression: &str) -> anyhow::Result<cron::Schedule> {
+    let fields = expression.split_whitespace().collect::<Vec<_>>();
+    let expression = match fields.as_slice() {
+        [minute, hour, day, month, weekday] => {
+            format!("0 {minute} {hour} {day} {month} {}", classic_weekdays(weekday)?)
+        }
+        _ => expression.to_string(),
+    };
+    cron::Schedule::from_str(&expression).map_err(|error| anyhow::anyhow!("Invalid cron expression: {error}"))
+}
+
+/// Translate a day of week field numbered from 0 (or 7) for Sunday to the numbering from 1 for Sunday the cron
+/// crate uses. Names such as `MON` are left alone.
+fn classic_weekdays(field: &str) -> anyhow::Result<String> {
+    let day = |day: &str| match day.parse::<u8>() {
+        Ok(day @ 0..=7) => Ok(Some(day % 7 + 1)),
+        Ok(_) => Err(anyhow::anyhow!(
+            "Invalid cron expression: day of week {day} is out of range"
+        )),
+        Err(_) => Ok(None),
+    };
+    let mut parts = Vec::new();
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step)),
+            None => (part, None),
+        };
+        let range = match range.split_once('-') {
+            Some((start, end)) => match (day(start)?, day(end)?) {
+                // Ranges ending on Sunday as 7 wrap around, e.g. `5-7` is Friday through Sunday.
+                (Some(start), Some(1)) if start > 1 && step.is_none() => format!("{start}-7,1"),
+                (Some(start), Some(end)) => format!("{start}-{end}"),
+                _ => range.to_string(),
+            },
+            None => match day(range)? {
+                Some(day) => day.to_string(),
+                None => range.to_string(),
+            },
+        };
+        parts.push(match step {
+            Some(step) => format!("{range}/{step}"),
+            None => range,
+        });
+    }
+    Ok(parts.join(","))
+}
+
+/// Run due schedules in all rooms this instance owns, until the process exits.
+pub async fn run(appservice: ApplicationService<State<Arc<ConversationStore>>>) {
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+        if let Err(error) = tick(&appservice).await {
+            tracing::warn!("Running schedules failed: {error}");
+        }
+    }
+}
+
+/// Run due schedules in the rooms the bot is joined to. Rooms are listed by the homeserver rather than the store,
+/// since not every store can enumerate its keys.
+async fn tick(appservice: &ApplicationService<State<Arc<ConversationStore>>>) -> anyhow::Result<()> {
+    let now = Utc::now();
+    for room_id in appservice.state().homeserver().joined_rooms().await? {
+        if !appservice.state().owns_room(&room_id).await {
+            continue;
+        }
+        if let Err(error) = run_due(appservice, &room_id, now).await {
+            tracing::warn!("Running schedules in {room_id} failed: {error}");
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_due(
+    appservice: &ApplicationService<State<Arc<ConversationStore>>>,
+    room_id: &RoomId,
+    now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let state = appservice.state();
+    let due = {
+        let _lock = RoomSettings::lock(state.store(), room_id).await;
+        let mut settings = RoomSettings::load(state.store(), room_id).await?;
+        let timezone = settings.resolved_timezone(&state.config().prompt.timezone);
+
+        // Schedules due while the room is paused are skipped, rather than all run once it resumes.
+        let paused = settings.paused.unwrap_or_default();
+        let mut due = Vec::new();
+        for schedule in &mut settings.schedules {
+            if schedule.next_run(timezone)?.is_some_and(|next| next <= now) {
+                schedule.last_run = now.timestamp();
+                due.push(schedule.prompt.clone());
+            }
+        }
+        // Save before running, so a failing prompt isn't retried on every tick.
+        if !due.is_empty() {
+            settings.save(state.store(), room_id).await?;
+        }
+        if paused {
+            return Ok(());
+        }
+        due
+    };
+
+    for prompt in due {
+        if let Err(error) = handlers::prompt_room(appservice, room_id, &prompt).await {
+            tracing::warn!("Scheduled prompt in {room_id} failed: {error}");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use matrix_appservice::{ApplicationService, State, exports::matrix_sdk::ruma::OwnedRoomId};
+use serde::Deserialize;
+
+use crate::openai::{ChatRequest, ConversationStore, MessageContent, OpenAIMessage, Role};
+
+/// Store key written and read back to check the store.
+const PROBE_KEY: &str = "self_test";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SelfTestConfig {
+    /// Check the homeserver, model and store on startup, and post the results.
+    pub enabled: bool,
+    /// Room the results are posted in. Falls back to `updates.admin_room`.
+    pub admin_room: Option<OwnedRoomId>,
+}
+
+/// Run the startup checks and post a summary in the admin room. Failing checks are reported, not returned.
+pub async fn run(appservice: ApplicationService<State<Arc<ConversationStore>>>) -> anyhow::Result<()> {
+    let state = appservice.state();
+    let config = state.config();
+
+    let checks = [
+        ("Homeserver", check(homeserver(state)).await),
+        ("Model", check(model(state)).await),
+        ("Store", check(store(state)).await),
+    ];
+    let passed = checks.iter().all(|(_, result)| result.is_ok());
+    let mut lines = vec![format!(
+        "**Self-test {}** for {} v{}",
+        if passed { "passed" } else { "failed" },
+        env!("CARGO_PKG_NAME"),
+        crate::version::VERSION
+    )];
+    lines.push("- ✅ Configuration: loaded".to_string());
+    for (name, result) in &checks {
+        lines.push(match result {
+            Ok(elapsed) => format!("- ✅ {name}: {} ms", elapsed.as_millis()),
+            Err(error) => format!("- ❌ {name}: {error:#}"),
+        });
+    }
+    let summary = lines.join("\n");
+    match passed {
+        true => tracing::info!("{summary}"),
+        false => tracing::warn!("{summary}"),
+    }
+
+    let Some(room_id) = config
+        .self_test
+        .admin_room
+        .as_ref()
+        .or(config.updates.admin_room.as_ref())
+    else {
+        return Ok(());
+    };
+    let device = appservice
+        .get_bot()
+        .await?
+        .get_device()
+        .await
+        .context("Device not found")?;
+    device.send_message(room_id, config.behavior.notice(summary)).await?;
+
+    Ok(())
+}
+
+async fn check(test: impl Future<Output = anyhow::Result<()>>) -> anyhow::Result<Duration> {
+    let started = Instant::now();
+    test.await?;
+    Ok(started.elapsed())
+}
+
+/// The homeserver answers and accepts the appservice token.
+async fn homeserver(state: &ConversationStore) -> anyhow::Result<()> {
+    let user_id = state.homeserver().whoami().await?;
+    if &*user_id != state.homeserver().user_id() {
+        return Err(anyhow::anyhow!("Token belongs to {user_id}"));
+    }
+    Ok(())
+}
+
+/// The model answers a one-token ping.
+async fn model(state: &ConversationStore) -> anyhow::Result<()> {
+    let request = ChatRequest {
+        max_tokens: Some(1),
+        ..ChatRequest::new(
+            state.config().openai.model.clone(),
+            vec![OpenAIMessage::new(Role::User, MessageContent::Text("ping".to_string()))],
+        )
+    };
+    state.post_completion(&request).await?;
+    Ok(())
+}
+
+/// A value written to the store can be read back.
+async fn store(state: &ConversationStore) -> anyhow::Result<()> {
+    let written = chrono::Utc::now().timestamp_millis();
+    state.store().save(PROBE_KEY, &written).await?;
+    let read: Option<i64> = state.store().load(PROBE_KEY).await?;
+    state.store().delete(PROBE_KEY).await?;
+    match read == Some(written) {
+        true => Ok(()),
+        false => Err(anyhow::anyhow!("Read back {read:?} after writing {written}")),
+    }
+}
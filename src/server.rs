@@ -0,0 +1,77 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, State as AxumState},
+    http::StatusCode,
+    routing::post,
+};
+use matrix_appservice::{ApplicationService, State};
+use serde::Deserialize;
+use serde_json::Value;
+use subtle::ConstantTimeEq;
+use tokio::sync::Semaphore;
+
+use crate::{admin, handlers, openai::ConversationStore, webhooks};
+
+pub(crate) type AppState = ApplicationService<State<Arc<ConversationStore>>>;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Address to serve the bot's own HTTP endpoints on, such as inbound webhooks. Disabled when unset.
+    pub listen: Option<SocketAddr>,
+    /// Bearer token for the admin API under `/admin`, which is only served when this is set.
+    pub admin_token: Option<String>,
+}
+
+/// Serve the bot's HTTP endpoints until the process exits.
+pub async fn serve(appservice: AppState, listen: SocketAddr) -> anyhow::Result<()> {
+    let pending = Arc::new(Semaphore::new(appservice.state().config().webhooks.max_pending.max(1)));
+    let mut router = Router::new()
+        .route("/hooks/{token}", post(inbound_hook))
+        .layer(Extension(pending));
+    if let Some(token) = &appservice.state().config().http.admin_token {
+        router = router.merge(admin::router(token));
+    }
+    let router = router.with_state(appservice);
+
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    tracing::info!("Serving HTTP endpoints on {listen}");
+    axum::serve(listener, router).await?;
+    Ok(())
+}
+
+async fn inbound_hook(
+    AxumState(appservice): AxumState<AppState>,
+    Extension(pending): Extension<Arc<Semaphore>>,
+    Path(token): Path<String>,
+    Json(payload): Json<Value>,
+) -> StatusCode {
+    // Compare in constant time, so response timing doesn't give away how much of a token was right.
+    let Some(hook) = appservice
+        .state()
+        .config()
+        .webhooks
+        .inbound
+        .iter()
+        .find(|hook| bool::from(hook.token.as_bytes().ct_eq(token.as_bytes())))
+        .cloned()
+    else {
+        return StatusCode::NOT_FOUND;
+    };
+    let Ok(permit) = pending.try_acquire_owned() else {
+        return StatusCode::TOO_MANY_REQUESTS;
+    };
+
+    // Answer right away, the model may take longer than the sender is willing to wait.
+    let prompt = webhooks::render(&hook.template, &payload);
+    tokio::spawn(async move {
+        let _permit = permit;
+        if let Err(error) = handlers::prompt_room(&appservice, &hook.room_id, &prompt).await {
+            tracing::warn!("Inbound webhook for {} failed: {error}", hook.room_id);
+        }
+    });
+
+    StatusCode::ACCEPTED
+}
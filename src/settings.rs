@@ -0,0 +1,188 @@
+use chrono::{TimeDelta, Utc};
+use chrono_tz::Tz;
+use matrix_appservice::exports::matrix_sdk::ruma::RoomId;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::OwnedMutexGuard;
+
+use crate::{
+    output_filter::FilterPolicy,
+    scheduler::Schedule,
+    store::{self, Store},
+};
+
+/// Longest retention period a room can set, a century.
+const MAX_RETENTION_DAYS: u32 = 36_500;
+
+/// Minimum power level required to change room settings.
+pub const MODERATOR_POWER_LEVEL: i64 = 50;
+
+/// Per-room overrides of the global configuration, managed with `!set`. Unset fields fall back to
+/// the configuration file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoomSettings {
+    /// Mask emails, phone numbers and Matrix IDs before prompts leave the homeserver.
+    pub pii_scrubbing: Option<bool>,
+    /// Append model, token counts, latency and tool calls to every reply.
+    pub debug: Option<bool>,
+    /// Post a notice describing each tool call while it runs.
+    pub announce_tools: Option<bool>,
+    /// Hide replies the moderation endpoint flags behind a spoiler.
+    pub spoilers: Option<bool>,
+    /// Number of completions to request for each prompt.
+    pub completions: Option<u8>,
+    /// How to pick between completions when more than one is requested.
+    pub choice_selection: Option<ChoiceSelection>,
+    /// IANA timezone the model is told the current time in, e.g. `Europe/Amsterdam`.
+    pub timezone: Option<String>,
+    /// Locale the model should follow for dates, numbers and units, e.g. `nl-NL`.
+    pub locale: Option<String>,
+    /// Model used in this room instead of the configured one.
+    pub model: Option<String>,
+    /// Extra instructions for the model in this room, e.g. "You are a pirate".
+    pub persona: Option<String>,
+    /// Reply to images posted by anyone with a generated description, for people using a screen reader.
+    pub alt_text: Option<bool>,
+    /// What happens to answers violating the output filter.
+    pub output_filter: Option<FilterPolicy>,
+    /// Answer with synthesized voice messages only, for rooms used hands-free or by people who'd rather listen.
+    pub voice_mode: Option<bool>,
+    /// Post debate turns and tool announcements as ghost users, when the operator enabled `puppets`.
+    pub puppets: Option<bool>,
+    /// Stop responding in the room, set with `!pause` and cleared with `!resume`.
+    pub paused: Option<bool>,
+    /// Keep adding messages addressed to the bot to the conversation while paused.
+    pub record_while_paused: Option<bool>,
+    /// What the bot may keep of the messages in this room.
+    pub retention: Option<RetentionMode>,
+    /// Days the conversation is kept, at most a century. Older messages are left out of the conversation and of
+    /// backfill.
+    pub retention_days: Option<u32>,
+    /// Prompts run on a cron schedule, managed with `!schedule`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub schedules: Vec<Schedule>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChoiceSelection {
+    /// Prefer finished and more thorough answers.
+    #[default]
+    Heuristic,
+    /// Let the model judge which answer is best.
+    Judge,
+    /// Present the top two answers and let the user pick one with a reaction.
+    React,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionMode {
+    /// Keep content needed to work well, such as long tool results and text derived from media.
+    #[default]
+    Full,
+    /// Never store message content. Only event IDs are kept to follow the conversation.
+    NoContent,
+    /// Like `no_content`, but records handed to other systems carry SHA-256 hashes of the content.
+    HashOnly,
+}
+
+impl RoomSettings {
+    /// Whether message content may be stored for this room.
+    pub fn keeps_content(&self) -> bool {
+        self.retention.unwrap_or_default() == RetentionMode::Full
+    }
+
+    /// Timestamp in milliseconds before which messages fall outside the retention period, if there is one.
+    pub fn retention_cutoff(&self) -> Option<u64> {
+        let days = self.retention_days?;
+        let cutoff = Utc::now().checked_sub_signed(TimeDelta::try_days(days.into())?)?;
+        Some(cutoff.timestamp_millis().max(0) as u64)
+    }
+
+    /// Timezone of the room, falling back to `default` and then UTC when the name isn't a known IANA timezone.
+    pub fn resolved_timezone(&self, default: &str) -> Tz {
+        if let Some(timezone) = &self.timezone {
+            match timezone.parse() {
+                Ok(timezone) => return timezone,
+                Err(_) => tracing::warn!("Unknown room timezone '{timezone}', using '{default}'"),
+            }
+        }
+        default.parse().unwrap_or_else(|_| {
+            tracing::warn!("Unknown timezone '{default}' in the configuration, using UTC");
+            Tz::UTC
+        })
+    }
+
+    pub async fn load(store: &dyn Store, room_id: &RoomId) -> anyhow::Result<Self> {
+        Ok(store.load(&settings_key(room_id)).await?.unwrap_or_default())
+    }
+
+    /// Hold off other updates of the room's settings while loading, changing and saving them.
+    pub async fn lock(store: &dyn Store, room_id: &RoomId) -> OwnedMutexGuard<()> {
+        store.lock(&settings_key(room_id)).await
+    }
+
+    pub async fn save(&self, store: &dyn Store, room_id: &RoomId) -> anyhow::Result<()> {
+        store.save(&settings_key(room_id), self).await
+    }
+
+    /// Update a single setting by name. The value is parsed as JSON where possible, so `true` and
+    /// `3` become a boolean and a number, and `unset` restores the configured default.
+    pub fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        let value = match value.trim() {
+            "unset" | "" => Value::Null,
+            value => serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string())),
+        };
+        self.apply(key, value)
+    }
+
+    /// Update a single setting by name to a JSON value, `null` restores the configured default.
+    pub fn apply(&mut self, key: &str, value: Value) -> anyhow::Result<()> {
+        let mut settings = serde_json::to_value(&*self)?;
+        let object = settings.as_object_mut().expect("settings serialize to an object");
+        if !object.contains_key(key) {
+            return Err(anyhow::anyhow!("Unknown setting '{key}'"));
+        }
+        if let Some(timezone) = value
+            .as_str()
+            .filter(|timezone| key == "timezone" && timezone.parse::<Tz>().is_err())
+        {
+            return Err(anyhow::anyhow!(
+                "Unknown timezone '{timezone}', use an IANA name such as Europe/Amsterdam"
+            ));
+        }
+        object.insert(key.to_string(), value);
+
+        let settings: Self =
+            serde_json::from_value(settings).map_err(|error| anyhow::anyhow!("Invalid value for '{key}': {error}"))?;
+        if settings.retention_days.is_some_and(|days| days > MAX_RETENTION_DAYS) {
+            return Err(anyhow::anyhow!(
+                "Invalid value for '{key}': at most {MAX_RETENTION_DAYS} days"
+            ));
+        }
+        *self = settings;
+        Ok(())
+    }
+
+    /// Markdown listing of all settings and their current values.
+    pub fn describe(&self) -> anyhow::Result<String> {
+        let settings = serde_json::to_value(self)?;
+        let lines = settings
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(key, value)| match value {
+                Value::Null => format!("- `{key}`: default"),
+                value => format!("- `{key}`: `{value}`"),
+            })
+            .collect::<Vec<_>>();
+
+        Ok(format!("**Room settings**\n{}", lines.join("\n")))
+    }
+}
+
+fn settings_key(room_id: &RoomId) -> String {
+    store::room_key(room_id, "settings")
+}
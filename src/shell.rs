@@ -0,0 +1,131 @@
+use matrix_appservice::exports::matrix_sdk::ruma::{OwnedRoomId, OwnedUserId, RoomId, UserId};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ShellConfig {
+    /// Commands the model may run. Requires the `shell` feature.
+    pub allowed: Vec<AllowedCommand>,
+    /// Rooms in which commands can be run, e.g. an operators' room.
+    pub rooms: Vec<OwnedRoomId>,
+    /// Users who can have commands run in any room. The tool isn't offered unless rooms or users are set.
+    pub users: Vec<OwnedUserId>,
+    /// Seconds a command may run before it is killed.
+    pub timeout: u64,
+    /// Maximum number of characters of output handed back to the model.
+    pub max_output: usize,
+}
+
+impl Default for ShellConfig {
+    fn default() -> Self {
+        Self {
+            allowed: Vec::new(),
+            rooms: Vec::new(),
+            users: Vec::new(),
+            timeout: 10,
+            max_output: 10_000,
+        }
+    }
+}
+
+impl ShellConfig {
+    pub fn enabled(&self) -> bool {
+        !self.allowed.is_empty() && (!self.rooms.is_empty() || !self.users.is_empty())
+    }
+
+    /// Whether commands can be run for a prompt in this room by this sender.
+    pub fn permits(&self, room_id: &RoomId, sender: Option<&UserId>) -> bool {
+        self.rooms.iter().any(|room| room == room_id)
+            || sender.is_some_and(|sender| self.users.iter().any(|user| user == sender))
+    }
+
+    pub fn describe_allowed(&self) -> String {
+        self.allowed
+            .iter()
+            .map(|allowed| format!("`{}`", allowed.command))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// A command template such as `systemctl status {unit}`. Words in braces are filled in by the model with a single
+/// argument, anything else has to match exactly. Commands run directly, not through a shell.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AllowedCommand {
+    pub command: String,
+}
+
+impl AllowedCommand {
+    /// The program and arguments to run, if `command` fits this template.
+    fn matches(&self, command: &str) -> Option<Vec<String>> {
+        let template = self.command.split_whitespace().collect::<Vec<_>>();
+        let words = command.split_whitespace().collect::<Vec<_>>();
+        if template.len() != words.len() {
+            return None;
+        }
+
+        template
+            .iter()
+            .zip(&words)
+            .all(
+                |(expected, word)| match expected.starts_with('{') && expected.ends_with('}') {
+                    true => is_safe_argument(word),
+                    false => expected == word,
+                },
+            )
+            .then(|| words.iter().map(|word| word.to_string()).collect())
+    }
+}
+
+/// Placeholder values are single plain words, so they can't add options, reach outside the given argument or walk up
+/// a path.
+fn is_safe_argument(word: &str) -> bool {
+    !word.starts_with('-')
+        && !word.contains("..")
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '@' | ':'))
+}
+
+/// Run a command if it matches one of the allowed templates, returning its exit status and output.
+pub async fn run(config: &ShellConfig, command: &str) -> anyhow::Result<String> {
+    let Some(words) = config.allowed.iter().find_map(|allowed| allowed.matches(command)) else {
+        return Ok(format!(
+            "`{command}` is not allowed. Allowed commands: {}",
+            config.describe_allowed()
+        ));
+    };
+
+    execute(config, &words).await
+}
+
+#[cfg(feature = "shell")]
+async fn execute(config: &ShellConfig, words: &[String]) -> anyhow::Result<String> {
+    use std::time::Duration;
+
+    let output = tokio::time::timeout(
+        Duration::from_secs(config.timeout),
+        tokio::process::Command::new(&words[0])
+            .args(&words[1..])
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .map_err(|_| anyhow::anyhow!("Command timed out after {} seconds", config.timeout))??;
+
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    if let Some((index, _)) = text.char_indices().nth(config.max_output) {
+        text.truncate(index);
+        text.push_str("\n[truncated]");
+    }
+
+    Ok(format!("Exit status: {}\n\n{text}", output.status))
+}
+
+#[cfg(not(feature = "shell"))]
+async fn execute(_config: &ShellConfig, _words: &[String]) -> anyhow::Result<String> {
+    Err(anyhow::anyhow!(
+        "Running commands requires building with the 'shell' feature"
+    ))
+}
@@ -0,0 +1,208 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use matrix_appservice::{
+    Device,
+    exports::matrix_sdk::ruma::{
+        OwnedEventId, RoomId, UInt,
+        events::room::message::{
+            AudioInfo, AudioMessageEventContent, MessageType, RoomMessageEventContent, UnstableVoiceContentBlock,
+        },
+    },
+};
+use serde::Deserialize;
+use url::Url;
+
+use crate::{
+    homeserver::Homeserver,
+    media,
+    openai::{Api, OpenAIConfig},
+};
+
+pub use self::{
+    openai::{OpenAISpeech, OpenAITranscriber},
+    piper::Piper,
+    whisper_cpp::WhisperCpp,
+};
+
+mod openai;
+mod piper;
+mod whisper_cpp;
+
+/// Speech to text.
+#[async_trait]
+pub trait Transcriber: Send + Sync {
+    async fn transcribe(&self, audio: Vec<u8>, mimetype: &str) -> anyhow::Result<String>;
+}
+
+/// Text to speech.
+#[async_trait]
+pub trait Synthesizer: Send + Sync {
+    async fn synthesize(&self, text: &str) -> anyhow::Result<SynthesizedAudio>;
+}
+
+pub struct SynthesizedAudio {
+    pub data: Vec<u8>,
+    pub mimetype: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptionBackend {
+    #[default]
+    #[serde(rename = "openai")]
+    OpenAI,
+    /// The example server shipped with whisper.cpp.
+    WhisperCpp,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SynthesisBackend {
+    #[default]
+    #[serde(rename = "openai")]
+    OpenAI,
+    /// Piper's HTTP server.
+    Piper,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TranscriptionConfig {
+    /// Transcribe voice messages sent to the bot and answer the transcript. The audio is sent to the provider.
+    pub enabled: bool,
+    pub provider: TranscriptionBackend,
+    /// Base URL of the provider's API. Defaults to the audio API below `openai.endpoint` for the OpenAI provider.
+    pub endpoint: Option<Url>,
+    pub model: String,
+}
+
+impl Default for TranscriptionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            provider: TranscriptionBackend::default(),
+            endpoint: None,
+            model: "whisper-1".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SynthesisConfig {
+    pub provider: SynthesisBackend,
+    /// Base URL of the provider's API, see [`TranscriptionConfig::endpoint`].
+    pub endpoint: Option<Url>,
+    pub model: String,
+    pub voice: String,
+}
+
+impl Default for SynthesisConfig {
+    fn default() -> Self {
+        Self {
+            provider: SynthesisBackend::default(),
+            endpoint: None,
+            model: "tts-1".to_string(),
+            voice: "alloy".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SpeechConfig {
+    pub transcription: TranscriptionConfig,
+    pub synthesis: SynthesisConfig,
+}
+
+/// Build the configured transcriber. `client` carries the OpenAI credentials, `http` is a plain client
+/// for self-hosted backends.
+pub fn transcriber(
+    config: &TranscriptionConfig,
+    openai: &OpenAIConfig,
+    client: reqwest::Client,
+    http: reqwest::Client,
+) -> anyhow::Result<Box<dyn Transcriber>> {
+    Ok(match config.provider {
+        TranscriptionBackend::OpenAI => Box::new(OpenAITranscriber::new(
+            config,
+            endpoint(&config.endpoint, openai)?,
+            client,
+        )),
+        TranscriptionBackend::WhisperCpp => {
+            let endpoint = config
+                .endpoint
+                .clone()
+                .context("whisper.cpp requires speech.transcription.endpoint")?;
+            Box::new(WhisperCpp::new(endpoint, http))
+        }
+    })
+}
+
+/// Build the configured synthesizer, see [`transcriber`].
+pub fn synthesizer(
+    config: &SynthesisConfig,
+    openai: &OpenAIConfig,
+    client: reqwest::Client,
+    http: reqwest::Client,
+) -> anyhow::Result<Box<dyn Synthesizer>> {
+    Ok(match config.provider {
+        SynthesisBackend::OpenAI => Box::new(OpenAISpeech::new(config, endpoint(&config.endpoint, openai)?, client)),
+        SynthesisBackend::Piper => {
+            let endpoint = config
+                .endpoint
+                .clone()
+                .context("Piper requires speech.synthesis.endpoint")?;
+            Box::new(Piper::new(config, endpoint, http))
+        }
+    })
+}
+
+/// The configured endpoint of an OpenAI speech backend, or the audio API below `openai.endpoint`.
+fn endpoint(configured: &Option<Url>, openai: &OpenAIConfig) -> anyhow::Result<Url> {
+    match configured {
+        Some(endpoint) => Ok(endpoint.clone()),
+        None => openai.url(Api::Audio),
+    }
+}
+
+/// Upload synthesized speech and post it in the room as a voice message, returning the event ID. Clients show it as
+/// a recording rather than an audio file through the MSC3245 voice flag.
+pub async fn post(
+    homeserver: &Homeserver,
+    device: &Device,
+    room_id: &RoomId,
+    audio: SynthesizedAudio,
+) -> anyhow::Result<OwnedEventId> {
+    let filename = file_name(&audio.mimetype);
+    let mut info = AudioInfo::new();
+    info.mimetype = Some(audio.mimetype.clone());
+    info.size = UInt::new(audio.data.len() as u64);
+
+    let source = media::upload(homeserver, room_id, audio.data, &audio.mimetype, &filename).await?;
+    let mut content = AudioMessageEventContent::new(filename, source);
+    content.info = Some(Box::new(info));
+    content.voice = Some(UnstableVoiceContentBlock::new());
+    device
+        .send_message(room_id, RoomMessageEventContent::new(MessageType::Audio(content)))
+        .await
+}
+
+/// File name with an extension matching the MIME type, some servers detect the format from it.
+fn file_name(mimetype: &str) -> String {
+    let extension = match mimetype {
+        "audio/ogg" | "audio/opus" => "ogg",
+        "audio/mpeg" | "audio/mp3" => "mp3",
+        "audio/mp4" | "audio/m4a" | "audio/x-m4a" => "m4a",
+        "audio/wav" | "audio/x-wav" => "wav",
+        "audio/webm" => "webm",
+        "audio/flac" => "flac",
+        _ => "ogg",
+    };
+    format!("audio.{extension}")
+}
+
+#[derive(Deserialize)]
+struct TranscriptionResponse {
+    text: String,
+}
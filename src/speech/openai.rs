@@ -0,0 +1,95 @@
+use async_trait::async_trait;
+use reqwest::multipart::{Form, Part};
+use serde_json::json;
+use url::Url;
+
+use crate::{
+    client::Identified,
+    speech::{
+        SynthesisConfig, SynthesizedAudio, Synthesizer, Transcriber, TranscriptionConfig, TranscriptionResponse,
+        file_name,
+    },
+};
+
+/// OpenAI compatible `transcriptions` endpoint.
+pub struct OpenAITranscriber {
+    client: reqwest::Client,
+    endpoint: Url,
+    model: String,
+}
+
+impl OpenAITranscriber {
+    pub fn new(config: &TranscriptionConfig, endpoint: Url, client: reqwest::Client) -> Self {
+        Self {
+            client,
+            endpoint,
+            model: config.model.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Transcriber for OpenAITranscriber {
+    async fn transcribe(&self, audio: Vec<u8>, mimetype: &str) -> anyhow::Result<String> {
+        let file = Part::bytes(audio).file_name(file_name(mimetype)).mime_str(mimetype)?;
+        let form = Form::new().text("model", self.model.clone()).part("file", file);
+
+        let response: TranscriptionResponse = self
+            .client
+            .post(self.endpoint.join("transcriptions")?)
+            .multipart(form)
+            .send_identified()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(response.text)
+    }
+}
+
+/// OpenAI compatible `speech` endpoint, producing Ogg Opus like Matrix voice messages.
+pub struct OpenAISpeech {
+    client: reqwest::Client,
+    endpoint: Url,
+    model: String,
+    voice: String,
+}
+
+impl OpenAISpeech {
+    pub fn new(config: &SynthesisConfig, endpoint: Url, client: reqwest::Client) -> Self {
+        Self {
+            client,
+            endpoint,
+            model: config.model.clone(),
+            voice: config.voice.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Synthesizer for OpenAISpeech {
+    async fn synthesize(&self, text: &str) -> anyhow::Result<SynthesizedAudio> {
+        let body = json!({
+            "model": self.model,
+            "voice": self.voice,
+            "input": text,
+            "response_format": "opus",
+        });
+
+        let data = self
+            .client
+            .post(self.endpoint.join("speech")?)
+            .json(&body)
+            .send_identified()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+
+        Ok(SynthesizedAudio {
+            data: data.to_vec(),
+            mimetype: "audio/ogg".to_string(),
+        })
+    }
+}
@@ -0,0 +1,45 @@
+use async_trait::async_trait;
+use serde_json::json;
+use url::Url;
+
+use crate::{
+    client::Identified,
+    speech::{SynthesisConfig, SynthesizedAudio, Synthesizer},
+};
+
+/// Piper's HTTP server, synthesizing WAV audio locally.
+pub struct Piper {
+    http: reqwest::Client,
+    endpoint: Url,
+    voice: String,
+}
+
+impl Piper {
+    pub fn new(config: &SynthesisConfig, endpoint: Url, http: reqwest::Client) -> Self {
+        Self {
+            http,
+            endpoint,
+            voice: config.voice.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Synthesizer for Piper {
+    async fn synthesize(&self, text: &str) -> anyhow::Result<SynthesizedAudio> {
+        let data = self
+            .http
+            .post(self.endpoint.clone())
+            .json(&json!({ "text": text, "voice": self.voice }))
+            .send_identified()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+
+        Ok(SynthesizedAudio {
+            data: data.to_vec(),
+            mimetype: "audio/wav".to_string(),
+        })
+    }
+}
@@ -0,0 +1,40 @@
+use async_trait::async_trait;
+use reqwest::multipart::{Form, Part};
+use url::Url;
+
+use crate::{
+    client::Identified,
+    speech::{Transcriber, TranscriptionResponse, file_name},
+};
+
+/// The whisper.cpp example server, transcribing locally with whatever model it was started with.
+pub struct WhisperCpp {
+    http: reqwest::Client,
+    endpoint: Url,
+}
+
+impl WhisperCpp {
+    pub fn new(endpoint: Url, http: reqwest::Client) -> Self {
+        Self { http, endpoint }
+    }
+}
+
+#[async_trait]
+impl Transcriber for WhisperCpp {
+    async fn transcribe(&self, audio: Vec<u8>, mimetype: &str) -> anyhow::Result<String> {
+        let file = Part::bytes(audio).file_name(file_name(mimetype)).mime_str(mimetype)?;
+        let form = Form::new().text("response_format", "json").part("file", file);
+
+        let response: TranscriptionResponse = self
+            .http
+            .post(self.endpoint.join("inference")?)
+            .multipart(form)
+            .send_identified()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(response.text.trim().to_string())
+    }
+}
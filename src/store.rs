@@ -0,0 +1,141 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Weak},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use matrix_appservice::exports::matrix_sdk::ruma::{RoomId, UserId};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use crate::{config::Config, homeserver::Homeserver};
+
+#[cfg(feature = "redis")]
+pub use self::redis::{RedisConfig, RedisStore};
+pub use self::{account_data::AccountDataStore, memory::MemoryStore};
+
+mod account_data;
+mod memory;
+#[cfg(feature = "redis")]
+mod redis;
+
+/// Locks of keys being read, changed and saved, so concurrent updates in this process don't overwrite each other.
+static LOCKS: LazyLock<std::sync::Mutex<HashMap<String, Weak<Mutex<()>>>>> = LazyLock::new(Default::default);
+
+/// Key-value persistence for bot state. Values are JSON documents.
+///
+/// Keys are `/`-separated paths. Keys scoped to a room start with `room/<room id>/`, see [`room_key`].
+#[async_trait]
+pub trait Store: Send + Sync {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Value>>;
+    async fn set(&self, key: &str, value: Value) -> anyhow::Result<()>;
+    async fn delete(&self, key: &str) -> anyhow::Result<()>;
+    /// List all keys starting with `prefix`.
+    async fn keys(&self, prefix: &str) -> anyhow::Result<Vec<String>>;
+
+    /// Store a value that expires after `ttl`. Backends without expiry support keep it indefinitely.
+    async fn set_expiring(&self, key: &str, value: Value, ttl: Duration) -> anyhow::Result<()> {
+        let _ = ttl;
+        self.set(key, value).await
+    }
+}
+
+impl dyn Store {
+    pub async fn load<T: DeserializeOwned>(&self, key: &str) -> anyhow::Result<Option<T>> {
+        match self.get(key).await? {
+            Some(value) => Ok(Some(serde_json::from_value(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn save<T: Serialize>(&self, key: &str, value: &T) -> anyhow::Result<()> {
+        self.set(key, serde_json::to_value(value)?).await
+    }
+
+    pub async fn save_expiring<T: Serialize>(&self, key: &str, value: &T, ttl: Duration) -> anyhow::Result<()> {
+        self.set_expiring(key, serde_json::to_value(value)?, ttl).await
+    }
+
+    /// Hold off other updates of a key in this process while reading, changing and saving it. Across instances,
+    /// each room is handled by a single instance, see [`crate::cluster`].
+    pub async fn lock(&self, key: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = LOCKS.lock().unwrap();
+            locks.retain(|_, lock| lock.strong_count() > 0);
+            match locks.get(key).and_then(Weak::upgrade) {
+                Some(lock) => lock,
+                None => {
+                    let lock = Arc::new(Mutex::new(()));
+                    locks.insert(key.to_string(), Arc::downgrade(&lock));
+                    lock
+                }
+            }
+        };
+        lock.lock_owned().await
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    /// Keep state in memory only, it is lost on restart.
+    #[default]
+    Memory,
+    /// Persist state in the bot user's account data on the homeserver.
+    AccountData,
+    /// Share state between multiple instances through Redis. Requires the `redis` feature.
+    Redis,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    /// Maximum size in bytes of a single account data event, larger values are split into chunks.
+    pub chunk_size: usize,
+    /// Maximum total size in bytes of a single value stored in account data.
+    pub max_size: usize,
+    #[cfg(feature = "redis")]
+    pub redis: RedisConfig,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::default(),
+            chunk_size: 32 * 1024,
+            max_size: 1024 * 1024,
+            #[cfg(feature = "redis")]
+            redis: RedisConfig::default(),
+        }
+    }
+}
+
+pub async fn from_config(config: &Config, homeserver: &Homeserver) -> anyhow::Result<Arc<dyn Store>> {
+    Ok(match config.storage.backend {
+        StorageBackend::Memory => Arc::new(MemoryStore::default()),
+        StorageBackend::AccountData => Arc::new(AccountDataStore::new(homeserver.clone(), &config.storage)),
+        #[cfg(feature = "redis")]
+        StorageBackend::Redis => Arc::new(RedisStore::connect(&config.storage.redis).await?),
+        #[cfg(not(feature = "redis"))]
+        StorageBackend::Redis => {
+            return Err(anyhow::anyhow!(
+                "Redis storage requires building with the 'redis' feature"
+            ));
+        }
+    })
+}
+
+pub fn room_key(room_id: &RoomId, name: &str) -> String {
+    format!("room/{room_id}/{name}")
+}
+
+pub fn conversation_key(room_id: &RoomId, user_id: &UserId) -> String {
+    room_key(room_id, &format!("conversation/{user_id}"))
+}
+
+pub fn epoch_key(room_id: &RoomId, user_id: &UserId) -> String {
+    room_key(room_id, &format!("epoch/{user_id}"))
+}
@@ -0,0 +1,226 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{TimeDelta, Utc};
+use reqwest::Method;
+use serde_json::{Value, json};
+use tokio::sync::RwLock;
+
+use crate::{
+    homeserver::Homeserver,
+    store::{StorageConfig, Store},
+};
+
+const EVENT_TYPE_PREFIX: &str = "nl.spacebased.matrix-openai-bot.store";
+
+/// Stores values in the bot user's account data, so state survives restarts without a database.
+///
+/// Room scoped keys go into the room account data of that room, everything else into global
+/// account data. Values larger than the configured chunk size are split over multiple events.
+///
+/// Account data doesn't expire, so expiring values carry their expiry time in milliseconds and read as missing
+/// after it. Their content stays on the homeserver until the key is written again.
+pub struct AccountDataStore {
+    homeserver: Homeserver,
+    chunk_size: usize,
+    max_size: usize,
+    cache: RwLock<HashMap<String, (Option<Value>, Option<i64>)>>,
+}
+
+impl AccountDataStore {
+    pub fn new(homeserver: Homeserver, config: &StorageConfig) -> Self {
+        Self {
+            homeserver,
+            chunk_size: config.chunk_size.max(1024),
+            max_size: config.max_size,
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    async fn read_event(&self, room_id: Option<&str>, event_type: &str) -> anyhow::Result<Option<Value>> {
+        let request = self
+            .homeserver
+            .request_segments(Method::GET, &account_data_path(&self.homeserver, room_id, event_type))?;
+        match self.homeserver.send_optional("account_data", request).await? {
+            Some(response) => Ok(Some(response.json().await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn write_event(&self, room_id: Option<&str>, event_type: &str, content: &Value) -> anyhow::Result<()> {
+        let request = self
+            .homeserver
+            .request_segments(Method::PUT, &account_data_path(&self.homeserver, room_id, event_type))?
+            .json(content);
+        self.homeserver.send("account_data", request).await?;
+
+        Ok(())
+    }
+
+    async fn decode(&self, room_id: Option<&str>, event_type: &str, content: Value) -> anyhow::Result<Option<Value>> {
+        if expired(content.get("expires").and_then(Value::as_i64)) {
+            return Ok(None);
+        }
+        if let Some(value) = content.get("value") {
+            return Ok(Some(value.clone()));
+        }
+
+        let Some(chunks) = content.get("chunks").and_then(Value::as_u64) else {
+            return Ok(None);
+        };
+
+        let mut data = String::new();
+        for index in 0..chunks {
+            let chunk = self
+                .read_event(room_id, &chunk_type(event_type, index))
+                .await?
+                .context("Missing account data chunk")?;
+            data.push_str(
+                chunk
+                    .get("data")
+                    .and_then(Value::as_str)
+                    .context("Invalid account data chunk")?,
+            );
+        }
+
+        Ok(Some(serde_json::from_str(&data)?))
+    }
+
+    async fn write(&self, key: &str, value: Value, expires: Option<i64>) -> anyhow::Result<()> {
+        let (room_id, event_type) = location(key);
+        let data = serde_json::to_string(&value)?;
+        if data.len() > self.max_size {
+            return Err(anyhow::anyhow!(
+                "Value for '{key}' exceeds the account data size limit of {} bytes",
+                self.max_size
+            ));
+        }
+
+        if data.len() <= self.chunk_size {
+            self.write_event(room_id, &event_type, &json!({ "value": value, "expires": expires }))
+                .await?;
+        } else {
+            let chunks = split_chunks(&data, self.chunk_size);
+            for (index, chunk) in chunks.iter().enumerate() {
+                self.write_event(
+                    room_id,
+                    &chunk_type(&event_type, index as u64),
+                    &json!({ "data": chunk }),
+                )
+                .await?;
+            }
+
+            // Written last, so readers never see a chunk count without the chunks.
+            self.write_event(
+                room_id,
+                &event_type,
+                &json!({ "chunks": chunks.len(), "expires": expires }),
+            )
+            .await?;
+        }
+
+        self.cache.write().await.insert(key.to_string(), (Some(value), expires));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Store for AccountDataStore {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Value>> {
+        if let Some((value, expires)) = self.cache.read().await.get(key) {
+            return Ok(value.clone().filter(|_| !expired(*expires)));
+        }
+
+        let (room_id, event_type) = location(key);
+        let content = self.read_event(room_id, &event_type).await?;
+        let expires = content
+            .as_ref()
+            .and_then(|content| content.get("expires"))
+            .and_then(Value::as_i64);
+        let value = match content {
+            Some(content) => self.decode(room_id, &event_type, content).await?,
+            None => None,
+        };
+
+        self.cache
+            .write()
+            .await
+            .insert(key.to_string(), (value.clone(), expires));
+        Ok(value)
+    }
+
+    async fn set(&self, key: &str, value: Value) -> anyhow::Result<()> {
+        self.write(key, value, None).await
+    }
+
+    async fn set_expiring(&self, key: &str, value: Value, ttl: Duration) -> anyhow::Result<()> {
+        let ttl = TimeDelta::from_std(ttl)?;
+        let expires = Utc::now().checked_add_signed(ttl).context("Expiry out of range")?;
+        self.write(key, value, Some(expires.timestamp_millis())).await
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        // Account data can't be removed, an empty object marks the key as deleted.
+        let (room_id, event_type) = location(key);
+        self.write_event(room_id, &event_type, &json!({})).await?;
+
+        self.cache.write().await.insert(key.to_string(), (None, None));
+        Ok(())
+    }
+
+    async fn keys(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        // Account data can't be enumerated by type, so only keys seen since startup are known.
+        let cache = self.cache.read().await;
+        Ok(cache
+            .iter()
+            .filter(|(key, (value, expires))| key.starts_with(prefix) && value.is_some() && !expired(*expires))
+            .map(|(key, _)| key.clone())
+            .collect())
+    }
+}
+
+fn expired(expires: Option<i64>) -> bool {
+    expires.is_some_and(|expires| expires <= Utc::now().timestamp_millis())
+}
+
+/// Map a key onto the room it belongs to, if any, and the account data event type holding it.
+fn location(key: &str) -> (Option<&str>, String) {
+    if let Some(rest) = key.strip_prefix("room/")
+        && let Some((room_id, name)) = rest.split_once('/')
+    {
+        return (Some(room_id), format!("{EVENT_TYPE_PREFIX}.{name}"));
+    }
+
+    (None, format!("{EVENT_TYPE_PREFIX}.{key}"))
+}
+
+fn chunk_type(event_type: &str, index: u64) -> String {
+    format!("{event_type}.chunk.{index}")
+}
+
+fn account_data_path<'a>(homeserver: &'a Homeserver, room_id: Option<&'a str>, event_type: &'a str) -> Vec<&'a str> {
+    let mut segments = vec!["_matrix", "client", "v3", "user", homeserver.user_id().as_str()];
+    if let Some(room_id) = room_id {
+        segments.extend(["rooms", room_id]);
+    }
+    segments.extend(["account_data", event_type]);
+    segments
+}
+
+fn split_chunks(data: &str, chunk_size: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let mut end = chunk_size.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let (chunk, remainder) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = remainder;
+    }
+
+    chunks
+}
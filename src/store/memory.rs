@@ -0,0 +1,55 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use serde_json::Value;
+use tokio::sync::RwLock;
+
+use crate::store::Store;
+
+#[derive(Default)]
+pub struct MemoryStore {
+    inner: RwLock<HashMap<String, (Value, Option<Instant>)>>,
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Value>> {
+        let lock = self.inner.read().await;
+        Ok(match lock.get(key) {
+            Some((_, Some(expires))) if *expires <= Instant::now() => None,
+            Some((value, _)) => Some(value.clone()),
+            None => None,
+        })
+    }
+
+    async fn set(&self, key: &str, value: Value) -> anyhow::Result<()> {
+        self.inner.write().await.insert(key.to_string(), (value, None));
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        self.inner.write().await.remove(key);
+        Ok(())
+    }
+
+    async fn keys(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let lock = self.inner.read().await;
+        let now = Instant::now();
+        Ok(lock
+            .iter()
+            .filter(|(key, (_, expires))| key.starts_with(prefix) && expires.is_none_or(|expires| expires > now))
+            .map(|(key, _)| key.clone())
+            .collect())
+    }
+
+    async fn set_expiring(&self, key: &str, value: Value, ttl: Duration) -> anyhow::Result<()> {
+        let mut lock = self.inner.write().await;
+        let now = Instant::now();
+        lock.retain(|_, (_, expires)| expires.is_none_or(|expires| expires > now));
+        lock.insert(key.to_string(), (value, Some(now + ttl)));
+        Ok(())
+    }
+}
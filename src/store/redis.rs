@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use redis::{AsyncCommands, Client, IntoConnectionInfo, aio::ConnectionManager};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::store::Store;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RedisConfig {
+    /// Connection URL, use the `rediss://` scheme for TLS.
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Force TLS, even when the URL uses the `redis://` scheme.
+    pub tls: bool,
+    /// Prefix for all keys, so multiple deployments can share a Redis database.
+    pub key_prefix: String,
+    /// Expiry in seconds applied to every key that doesn't set its own.
+    pub ttl: Option<u64>,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        Self {
+            url: "redis://127.0.0.1:6379".to_string(),
+            username: None,
+            password: None,
+            tls: false,
+            key_prefix: "matrix-openai-bot:".to_string(),
+            ttl: None,
+        }
+    }
+}
+
+/// Stores values in Redis, so multiple instances behind a load balancer can share state.
+pub struct RedisStore {
+    connection: ConnectionManager,
+    key_prefix: String,
+    ttl: Option<Duration>,
+}
+
+impl RedisStore {
+    pub async fn connect(config: &RedisConfig) -> anyhow::Result<Self> {
+        let url = match config.url.strip_prefix("redis://") {
+            Some(rest) if config.tls => format!("rediss://{rest}"),
+            _ => config.url.clone(),
+        };
+
+        let mut info = url.into_connection_info()?;
+        if let Some(username) = &config.username {
+            info.redis.username = Some(username.clone());
+        }
+        if let Some(password) = &config.password {
+            info.redis.password = Some(password.clone());
+        }
+
+        let connection = ConnectionManager::new(Client::open(info)?).await?;
+
+        Ok(Self {
+            connection,
+            key_prefix: config.key_prefix.clone(),
+            ttl: config.ttl.map(Duration::from_secs),
+        })
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{key}", self.key_prefix)
+    }
+}
+
+#[async_trait]
+impl Store for RedisStore {
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Value>> {
+        let mut connection = self.connection.clone();
+        let data: Option<String> = connection.get(self.key(key)).await?;
+        Ok(data.map(|data| serde_json::from_str(&data)).transpose()?)
+    }
+
+    async fn set(&self, key: &str, value: Value) -> anyhow::Result<()> {
+        match self.ttl {
+            Some(ttl) => self.set_expiring(key, value, ttl).await,
+            None => {
+                let mut connection = self.connection.clone();
+                let _: () = connection.set(self.key(key), serde_json::to_string(&value)?).await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let mut connection = self.connection.clone();
+        let _: () = connection.del(self.key(key)).await?;
+        Ok(())
+    }
+
+    async fn keys(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let mut connection = self.connection.clone();
+        let pattern = format!("{}*", self.key(prefix));
+        let mut keys = Vec::new();
+        let mut cursor = 0u64;
+        loop {
+            let (next, batch): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(100)
+                .query_async(&mut connection)
+                .await?;
+
+            keys.extend(
+                batch
+                    .into_iter()
+                    .filter_map(|key| key.strip_prefix(&self.key_prefix).map(str::to_string)),
+            );
+            if next == 0 {
+                return Ok(keys);
+            }
+            cursor = next;
+        }
+    }
+
+    async fn set_expiring(&self, key: &str, value: Value, ttl: Duration) -> anyhow::Result<()> {
+        let mut connection = self.connection.clone();
+        let _: () = connection
+            .set_ex(self.key(key), serde_json::to_string(&value)?, ttl.as_secs().max(1))
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use regex::Regex;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct StyleConfig {
+    /// Turn markdown headings into plain bold lines, which read better in chat.
+    pub strip_headers: bool,
+    /// Maximum reply length in characters, longer replies are cut off with an ellipsis.
+    pub max_length: Option<usize>,
+    /// Regex replacements applied in order, e.g. to drop "As an AI language model" boilerplate.
+    pub rules: Vec<StyleRule>,
+    /// Line appended to every reply.
+    pub signature: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StyleRule {
+    pub pattern: String,
+    #[serde(default)]
+    pub replacement: String,
+}
+
+/// Post-processing of model output before it is sent to Matrix.
+pub struct Style {
+    strip_headers: bool,
+    max_length: Option<usize>,
+    rules: Vec<(Regex, String)>,
+    signature: Option<String>,
+}
+
+impl Style {
+    pub fn new(config: &StyleConfig) -> anyhow::Result<Self> {
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| {
+                let regex = Regex::new(&rule.pattern)
+                    .map_err(|error| anyhow::anyhow!("Invalid style rule '{}': {error}", rule.pattern))?;
+                Ok((regex, rule.replacement.clone()))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            strip_headers: config.strip_headers,
+            max_length: config.max_length,
+            rules,
+            signature: config.signature.clone(),
+        })
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for (regex, replacement) in &self.rules {
+            text = regex.replace_all(&text, replacement.as_str()).into_owned();
+        }
+
+        if self.strip_headers {
+            // Lines starting with # inside code blocks are code, such as comments or preprocessor directives.
+            let mut fence = None;
+            text = text
+                .lines()
+                .map(|line| {
+                    if fence.is_none()
+                        && let Some(heading) = line.trim_start().strip_prefix('#')
+                    {
+                        return format!("**{}**", heading.trim_start_matches('#').trim());
+                    }
+                    fence = next_fence(fence, line);
+                    line.to_string()
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+        }
+
+        let mut text = text.trim().to_string();
+        if let Some(max_length) = self.max_length
+            && let Some((index, _)) = text.char_indices().nth(max_length)
+        {
+            text.truncate(index);
+            text.push('…');
+            // A code block cut off halfway would swallow everything after it, like the signature.
+            if let Some(fence) = text.lines().fold(None, next_fence).map(str::to_string) {
+                text.push('\n');
+                text.push_str(&fence);
+            }
+        }
+
+        if let Some(signature) = &self.signature {
+            text.push_str("\n\n");
+            text.push_str(signature);
+        }
+
+        text
+    }
+}
+
+/// The fence of the code block open after `line`, given the one open before it.
+fn next_fence<'a>(open: Option<&'a str>, line: &'a str) -> Option<&'a str> {
+    let line = line.trim_start();
+    let fence_length = |marker: char| line.len() - line.trim_start_matches(marker).len();
+    let fence = match (fence_length('`'), fence_length('~')) {
+        (length, _) if length >= 3 => &line[..length],
+        (_, length) if length >= 3 => &line[..length],
+        _ => return open,
+    };
+    match open {
+        None => Some(fence),
+        // A block is closed by a bare fence of the same kind, at least as long as the one opening it.
+        Some(open) if fence.starts_with(open) && line.trim_end() == fence => None,
+        Some(open) => Some(open),
+    }
+}
@@ -0,0 +1,128 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use chrono::NaiveDate;
+use matrix_appservice::exports::matrix_sdk::ruma::{RoomId, UserId};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    metrics::Metrics,
+    openai::Completion,
+    store::{self, Store},
+};
+
+/// Running totals of the exchanges handled in a room.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoomStats {
+    pub messages: u64,
+    pub total_latency_ms: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub tool_calls: BTreeMap<String, u64>,
+}
+
+/// Usage of a single user in a room on a single day, kept for usage reports.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DailyUsage {
+    pub messages: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl RoomStats {
+    pub async fn load(store: &dyn Store, room_id: &RoomId) -> anyhow::Result<Self> {
+        Ok(store.load(&stats_key(room_id)).await?.unwrap_or_default())
+    }
+
+    /// Account a completed exchange in the room statistics, the daily usage of the sender and the process metrics.
+    /// The daily usage expires after `usage_days`, or the room's retention period when that's shorter.
+    pub async fn record(
+        store: &dyn Store,
+        metrics: &Metrics,
+        room_id: &RoomId,
+        sender: &UserId,
+        completion: &Completion,
+        latency: Duration,
+        usage_days: u32,
+    ) -> anyhow::Result<()> {
+        metrics.increment("openai_bot_messages_total", &[]);
+        metrics.add("openai_bot_latency_seconds_sum", &[], latency.as_secs_f64());
+        metrics.add(
+            "openai_bot_tokens_total",
+            &[("kind", "prompt")],
+            completion.usage.prompt_tokens.into(),
+        );
+        metrics.add(
+            "openai_bot_tokens_total",
+            &[("kind", "completion")],
+            completion.usage.completion_tokens.into(),
+        );
+
+        let _lock = store.lock(&stats_key(room_id)).await;
+        let mut stats = Self::load(store, room_id).await?;
+        stats.messages += 1;
+        stats.total_latency_ms += latency.as_millis() as u64;
+        stats.prompt_tokens += u64::from(completion.usage.prompt_tokens);
+        stats.completion_tokens += u64::from(completion.usage.completion_tokens);
+        for tool in &completion.tool_calls {
+            metrics.increment("openai_bot_tool_calls_total", &[("tool", tool)]);
+            *stats.tool_calls.entry(tool.clone()).or_default() += 1;
+        }
+
+        store.save(&stats_key(room_id), &stats).await?;
+
+        let key = usage_key(room_id, chrono::Utc::now().date_naive(), sender);
+        let _lock = store.lock(&key).await;
+        let mut usage: DailyUsage = store.load(&key).await?.unwrap_or_default();
+        usage.messages += 1;
+        usage.prompt_tokens += u64::from(completion.usage.prompt_tokens);
+        usage.completion_tokens += u64::from(completion.usage.completion_tokens);
+        let ttl = Duration::from_secs(u64::from(usage_days) * 24 * 60 * 60);
+        store.save_expiring(&key, &usage, ttl).await
+    }
+
+    pub fn average_latency(&self) -> Duration {
+        match self.messages {
+            0 => Duration::ZERO,
+            messages => Duration::from_millis(self.total_latency_ms / messages),
+        }
+    }
+
+    pub fn to_markdown(&self, conversation_length: usize) -> String {
+        let tool_calls = match self.tool_calls.is_empty() {
+            true => "none".to_string(),
+            false => self
+                .tool_calls
+                .iter()
+                .map(|(tool, count)| format!("`{tool}` × {count}"))
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+
+        format!(
+            "| Statistic | Value |\n\
+             |---|---|\n\
+             | Messages handled | {} |\n\
+             | Average latency | {:.2} s |\n\
+             | Prompt tokens | {} |\n\
+             | Completion tokens | {} |\n\
+             | Tool calls | {} |\n\
+             | Conversation length | {} events |",
+            self.messages,
+            self.average_latency().as_secs_f64(),
+            self.prompt_tokens,
+            self.completion_tokens,
+            tool_calls,
+            conversation_length,
+        )
+    }
+}
+
+fn stats_key(room_id: &RoomId) -> String {
+    store::room_key(room_id, "stats")
+}
+
+pub fn usage_key(room_id: &RoomId, date: NaiveDate, user_id: &UserId) -> String {
+    store::room_key(room_id, &format!("usage/{date}/{user_id}"))
+}
@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use matrix_appservice::{ApplicationService, State, exports::matrix_sdk::ruma::OwnedRoomId};
+use serde::Deserialize;
+use url::Url;
+
+use crate::{client::Identified, openai::ConversationStore};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_COMMIT: &str = env!("GIT_COMMIT");
+pub const BUILD_DATE: &str = env!("BUILD_DATE");
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UpdatesConfig {
+    /// Check for a newer release on startup.
+    pub check: bool,
+    /// Release endpoint returning a JSON object with a `tag_name`, such as GitHub's latest release API.
+    pub endpoint: Url,
+    /// Room to announce new releases in.
+    pub admin_room: Option<OwnedRoomId>,
+}
+
+impl Default for UpdatesConfig {
+    fn default() -> Self {
+        Self {
+            check: false,
+            endpoint: Url::parse("https://api.github.com/repos/bleumink/matrix-openai-bot/releases/latest")
+                .expect("valid default URL"),
+            admin_room: None,
+        }
+    }
+}
+
+/// Version line for `!version`.
+pub fn describe() -> String {
+    format!(
+        "{} v{VERSION} ({GIT_COMMIT}, built {BUILD_DATE})",
+        env!("CARGO_PKG_NAME")
+    )
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    html_url: Option<String>,
+}
+
+/// Look for a newer release and announce it in the admin room.
+pub async fn check(appservice: ApplicationService<State<Arc<ConversationStore>>>) -> anyhow::Result<()> {
+    let state = appservice.state();
+    let config = &state.config().updates;
+    let release: Release = state
+        .http()
+        .get(config.endpoint.clone())
+        .send_identified()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let latest = release.tag_name.trim_start_matches('v');
+    if parse(latest) <= parse(VERSION) {
+        return Ok(());
+    }
+
+    tracing::info!("A newer version is available: {latest}");
+    let Some(room_id) = &config.admin_room else {
+        return Ok(());
+    };
+
+    let mut notice = format!(
+        "A new version of {} is available: {latest} (running {VERSION}).",
+        env!("CARGO_PKG_NAME")
+    );
+    if let Some(url) = &release.html_url {
+        notice.push_str(&format!(" Release notes: {url}"));
+    }
+    let device = appservice
+        .get_bot()
+        .await?
+        .get_device()
+        .await
+        .context("Device not found")?;
+    device
+        .send_message(room_id, state.config().behavior.notice(notice))
+        .await?;
+
+    Ok(())
+}
+
+/// Numeric components of a version, so `0.10.0` sorts after `0.9.1`.
+fn parse(version: &str) -> Vec<u64> {
+    version
+        .split(['.', '-', '+'])
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
@@ -0,0 +1,172 @@
+use std::sync::LazyLock;
+
+use hmac::{Hmac, Mac};
+use matrix_appservice::exports::matrix_sdk::ruma::{OwnedRoomId, RoomId, UserId};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::{client::Identified, openai::Usage, settings::RetentionMode};
+
+/// Header carrying the hex encoded HMAC-SHA256 of the body, prefixed with `sha256=`.
+pub const SIGNATURE_HEADER: &str = "X-Signature-256";
+
+static PLACEHOLDER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{([A-Za-z0-9_.]+)\}").expect("valid placeholder pattern"));
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WebhooksConfig {
+    /// Endpoints at `POST /hooks/<token>` turning JSON payloads into prompts for a room.
+    pub inbound: Vec<InboundHook>,
+    /// Inbound payloads being answered at once. Further payloads are refused with 429 Too Many Requests.
+    pub max_pending: usize,
+    /// URLs receiving a JSON record of every completed exchange.
+    pub outbound: Vec<OutboundHook>,
+    /// Key to pseudonymize senders in exchange records with, see [`ExchangeRecord::user`]. Records carry no user
+    /// without it.
+    pub user_key: Option<String>,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            inbound: Vec::new(),
+            max_pending: 16,
+            outbound: Vec::new(),
+            user_key: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OutboundHook {
+    pub url: Url,
+    /// Key to sign deliveries with, see [`SIGNATURE_HEADER`].
+    pub secret: Option<String>,
+}
+
+/// A completed exchange, as delivered to outbound webhooks.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExchangeRecord {
+    pub room_id: String,
+    /// HMAC-SHA256 of the sender's user ID keyed with the configured user key, so records can be grouped by user
+    /// without identifying them. A plain hash of a user ID is easily reversed by hashing candidate IDs.
+    pub user: Option<String>,
+    pub prompt: String,
+    pub response: String,
+    pub model: String,
+    pub usage: Usage,
+    pub timestamp: i64,
+}
+
+impl ExchangeRecord {
+    pub fn new(
+        room_id: &RoomId,
+        sender: &UserId,
+        user_key: Option<&str>,
+        prompt: &str,
+        response: &str,
+        model: &str,
+        usage: &Usage,
+    ) -> Self {
+        Self {
+            room_id: room_id.to_string(),
+            user: user_key.map(|key| sign(key, sender.as_bytes())),
+            prompt: prompt.to_string(),
+            response: response.to_string(),
+            model: model.to_string(),
+            usage: usage.clone(),
+            timestamp: chrono::Utc::now().timestamp(),
+        }
+    }
+
+    /// Strip or hash the content according to the room's retention policy.
+    pub fn retain(mut self, mode: RetentionMode) -> Self {
+        match mode {
+            RetentionMode::Full => (),
+            RetentionMode::NoContent => {
+                self.prompt.clear();
+                self.response.clear();
+            }
+            RetentionMode::HashOnly => {
+                self.prompt = hex(&Sha256::digest(self.prompt.as_bytes()));
+                self.response = hex(&Sha256::digest(self.response.as_bytes()));
+            }
+        }
+        self
+    }
+}
+
+/// POST the record to every outbound hook. Failures are logged, not returned, so one broken receiver
+/// doesn't affect the others.
+pub async fn deliver(http: &reqwest::Client, hooks: &[OutboundHook], record: &ExchangeRecord) {
+    let body = match serde_json::to_vec(record) {
+        Ok(body) => body,
+        Err(error) => {
+            tracing::warn!("Serializing exchange record failed: {error}");
+            return;
+        }
+    };
+
+    for hook in hooks {
+        let mut request = http
+            .post(hook.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.clone());
+        if let Some(secret) = &hook.secret {
+            request = request.header(SIGNATURE_HEADER, format!("sha256={}", sign(secret, &body)));
+        }
+
+        let result = request
+            .send_identified()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(error) = result {
+            tracing::warn!("Delivering exchange to {} failed: {error}", hook.url);
+        }
+    }
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    hex(&mac.finalize().into_bytes())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InboundHook {
+    /// Secret part of the URL identifying the hook.
+    pub token: String,
+    pub room_id: OwnedRoomId,
+    /// Prompt template. `{payload}` is replaced by the whole payload, `{a.b.0}` by the value at that path.
+    pub template: String,
+}
+
+/// Fill in a template from a JSON payload. Unknown paths render as an empty string.
+pub fn render(template: &str, payload: &Value) -> String {
+    PLACEHOLDER
+        .replace_all(template, |captures: &Captures| {
+            let path = &captures[1];
+            let value = match path {
+                "payload" => Some(payload),
+                path => path.split('.').try_fold(payload, |value, segment| match value {
+                    Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+                    value => value.get(segment),
+                }),
+            };
+
+            match value {
+                Some(Value::String(text)) => text.clone(),
+                Some(Value::Null) | None => String::new(),
+                Some(value) => serde_json::to_string_pretty(value).unwrap_or_default(),
+            }
+        })
+        .into_owned()
+}
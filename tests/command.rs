@@ -0,0 +1,40 @@
+use matrix_appservice::exports::matrix_sdk::ruma::{
+    OwnedEventId, events::room::message::RoomMessageEventContent, serde::Raw,
+};
+use matrix_openai_bot::{command::Command, relation::BotResponse};
+use serde_json::json;
+
+#[test]
+fn dm_waits_for_consent() {
+    for input in ["!dm why is the sky blue?", "!dm"] {
+        assert!(Command::parse(input).unwrap().needs_consent(), "{input}");
+    }
+}
+
+#[test]
+fn dm_seed_is_a_command_response() {
+    let seed = BotResponse {
+        event_id: OwnedEventId::try_from("$command").unwrap(),
+        model: None,
+        epoch: 2,
+    };
+    let content = seed
+        .attach(RoomMessageEventContent::notice_markdown(
+            "Continuing privately:\n\n> why?",
+        ))
+        .unwrap();
+    let event: Raw<()> = serde_json::from_value(json!({
+        "type": "m.room.message",
+        "event_id": "$seed",
+        "sender": "@bot:example.org",
+        "origin_server_ts": 0,
+        "content": content,
+    }))
+    .unwrap();
+
+    // Backfills leave out responses without a model, so the quoted question isn't read back as the bot's.
+    let relation = BotResponse::from_event(&event).unwrap();
+    assert_eq!(relation.event_id, "$command");
+    assert!(relation.model.is_none());
+    assert_eq!(relation.epoch, 2);
+}
@@ -0,0 +1,42 @@
+use matrix_openai_bot::convert::convert;
+
+#[test]
+fn converts_between_units() {
+    assert_eq!(convert("5", "mi", "km").unwrap(), "5 mi = 8.04672 km");
+    assert_eq!(convert("12", "inches", "cm").unwrap(), "12 inches = 30.48 cm");
+    assert_eq!(convert("1,000", "g", "kg").unwrap(), "1000 g = 1 kg");
+    assert_eq!(convert("3", "miles", "feet").unwrap(), "3 miles = 15840 feet");
+}
+
+#[test]
+fn converts_temperatures() {
+    assert_eq!(convert("20", "°C", "°F").unwrap(), "20 °C = 68 °F");
+    assert_eq!(convert("0", "kelvin", "celsius").unwrap(), "0 kelvin = -273.15 celsius");
+}
+
+#[test]
+fn tells_bits_from_bytes() {
+    assert_eq!(convert("100", "Mb", "MB").unwrap(), "100 Mb = 12.5 MB");
+    assert_eq!(convert("1", "GiB", "MiB").unwrap(), "1 GiB = 1024 MiB");
+    assert_eq!(convert("8", "kbit", "kB").unwrap(), "8 kbit = 1 kB");
+}
+
+#[test]
+fn keeps_milliseconds_apart_from_seconds() {
+    assert_eq!(convert("1500", "ms", "s").unwrap(), "1500 ms = 1.5 s");
+}
+
+#[test]
+fn rejects_mismatched_and_unknown_units() {
+    assert!(convert("1", "kg", "km").is_err());
+    assert!(convert("1", "furlong", "m").is_err());
+    assert!(convert("many", "m", "km").is_err());
+}
+
+#[test]
+fn converts_times_between_timezones() {
+    assert_eq!(
+        convert("2025-03-01 15:00", "Europe/Amsterdam", "America/New_York").unwrap(),
+        "Saturday 2025-03-01 15:00 CET (+01:00) = Saturday 2025-03-01 09:00 EST (-05:00)"
+    );
+}
@@ -0,0 +1,38 @@
+use matrix_openai_bot::dice::roll;
+
+#[test]
+fn rolls_within_bounds() {
+    for _ in 0..100 {
+        let result = roll("3d6+2").unwrap();
+        let total: i64 = result.rsplit(" = ").next().unwrap().parse().unwrap();
+        assert!((5..=20).contains(&total), "{result}");
+    }
+}
+
+#[test]
+fn keeps_highest_and_lowest() {
+    let result = roll("4d6kh3").unwrap();
+    assert!(result.contains("keeping"), "{result}");
+    let total: i64 = result.rsplit(" = ").next().unwrap().parse().unwrap();
+    assert!((3..=18).contains(&total), "{result}");
+
+    let result = roll("-1 + 2d20kl1").unwrap();
+    let total: i64 = result.rsplit(" = ").next().unwrap().parse().unwrap();
+    assert!((0..=19).contains(&total), "{result}");
+}
+
+#[test]
+fn rejects_oversized_rolls() {
+    assert!(roll("99999999d99999999").is_err());
+    assert!(roll("101d6").is_err());
+    assert!(roll("1d1001").is_err());
+    assert!(roll("9223372036854775807+9223372036854775807").is_err());
+    assert!(roll(&vec!["1"; 21].join("+")).is_err());
+}
+
+#[test]
+fn rejects_malformed_expressions() {
+    for expression in ["", "d", "3d", "2d6+", "1++2", "4d6kh5", "abc"] {
+        assert!(roll(expression).is_err(), "{expression}");
+    }
+}
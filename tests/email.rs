@@ -0,0 +1,17 @@
+use matrix_openai_bot::email::EmailConfig;
+
+#[test]
+fn allows_recipients_by_delivery_address() {
+    let config = EmailConfig {
+        recipients: vec!["boss@example.com".to_string(), "@example.org".to_string()],
+        ..EmailConfig::default()
+    };
+    assert!(config.allows("boss@example.com"));
+    assert!(config.allows("The Boss <BOSS@example.com>"));
+    assert!(config.allows("alice@example.org"));
+    assert!(!config.allows("alice@notexample.org"));
+    assert!(!config.allows("alice@sub.example.org"));
+    assert!(!config.allows("boss@example.com <attacker@evil.test>"));
+    assert!(!config.allows("attacker@evil.test, boss@example.com"));
+    assert!(!config.allows("not an address"));
+}
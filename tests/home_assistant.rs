@@ -0,0 +1,72 @@
+use matrix_appservice::exports::matrix_sdk::ruma::{RoomId, UserId};
+use matrix_openai_bot::{config::Config, home_assistant::HomeAssistantConfig, openai::Tool};
+use serde_json::json;
+
+fn config() -> HomeAssistantConfig {
+    HomeAssistantConfig {
+        entities: vec!["lock.front_door".to_string(), "light.*".to_string()],
+        services: vec!["light.turn_on".to_string(), "lock.*".to_string()],
+        ..HomeAssistantConfig::default()
+    }
+}
+
+#[test]
+fn allows_listed_entities_only() {
+    let config = config();
+    assert!(config.allows("lock.front_door"));
+    assert!(config.allows("light.kitchen"));
+    assert!(!config.allows("lock.back_door"));
+    assert!(!config.allows("light.x/../../config"));
+    assert!(!config.allows("light.Kitchen"));
+}
+
+#[test]
+fn allows_listed_services_only() {
+    let config = config();
+    assert!(config.allows_service("light", "turn_on"));
+    assert!(config.allows_service("lock", "unlock"));
+    assert!(!config.allows_service("light", "turn_off"));
+    assert!(!config.allows_service("homeassistant", "restart"));
+    assert!(!config.allows_service("lock", "unlock/../../restart"));
+}
+
+/// Names of the tools offered to `sender` in `room_id`, with Home Assistant opened up to one room and one user.
+fn offered(room_id: &str, sender: &str) -> Vec<String> {
+    let config: Config = serde_json::from_value(json!({
+        "homeserver": { "server_name": "example.org", "url": "http://localhost:8008" },
+        "appservice": { "username": "bot", "as_token": "as-token" },
+        "openai": { "endpoint": "https://api.openai.com/v1", "api_key": "sk-test", "model": "gpt-test" },
+        "home_assistant": {
+            "url": "http://homeassistant.local:8123/",
+            "rooms": ["!home:example.org"],
+            "users": ["@owner:example.org"],
+        },
+    }))
+    .unwrap();
+    let room_id = <&RoomId>::try_from(room_id).unwrap();
+    let sender = <&UserId>::try_from(sender).unwrap();
+    Tool::schemas(&config, room_id, Some(sender))
+        .unwrap()
+        .iter()
+        .filter_map(|schema| schema.pointer("/function/name")?.as_str().map(str::to_string))
+        .collect()
+}
+
+#[test]
+fn offers_tools_only_where_permitted() {
+    let home_tools = ["list_home_entities", "get_home_state", "call_home_service"];
+    for (room_id, sender, permitted) in [
+        ("!home:example.org", "@guest:example.org", true),
+        ("!public:example.org", "@owner:example.org", true),
+        ("!public:example.org", "@guest:example.org", false),
+    ] {
+        let offered = offered(room_id, sender);
+        for tool in home_tools {
+            assert_eq!(
+                offered.iter().any(|name| name == tool),
+                permitted,
+                "{tool} for {sender} in {room_id}"
+            );
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use matrix_openai_bot::issues::{Forges, IssuesConfig};
+
+#[tokio::test]
+async fn rejects_malformed_repositories() {
+    let http = reqwest::Client::new();
+    let config = IssuesConfig::default();
+    let forges = Forges::new(&http, &config);
+    for repo in [
+        "owner",
+        "owner/name/extra",
+        "../admin",
+        "owner/..",
+        "owner/name?x=1",
+        "gitlab:group",
+        "a b/c",
+    ] {
+        let error = forges.fetch(repo, 1).await.unwrap_err();
+        assert!(
+            error.to_string().starts_with("Expected a repository"),
+            "{repo}: {error}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn only_reads_allowed_repositories() {
+    let http = reqwest::Client::new();
+    let config = IssuesConfig {
+        repositories: vec!["bleumink/matrix-openai-bot".to_string()],
+        ..IssuesConfig::default()
+    };
+    let forges = Forges::new(&http, &config);
+    for repo in ["someone/else", "gitlab:bleumink/matrix-openai-bot"] {
+        let error = forges.search(repo, "crash").await.unwrap_err();
+        assert!(error.to_string().contains("not in the list"), "{repo}: {error}");
+    }
+}
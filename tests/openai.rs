@@ -0,0 +1,177 @@
+mod support;
+
+use matrix_appservice::exports::matrix_sdk::ruma::RoomId;
+use matrix_openai_bot::{
+    dice,
+    openai::{
+        Api, AssistantAction, ChatRequest, ConversationStore, Invocation, MessageContent, OpenAIConfig, OpenAIError,
+        OpenAIMessage, Role, Tool, ToolRegistry, into_actions,
+    },
+};
+use serde_json::json;
+
+use support::MockOpenAI;
+
+#[tokio::test]
+async fn complete_sends_prompt_and_returns_reply() {
+    let openai = MockOpenAI::replaying(&["reply"]).await;
+    let state = ConversationStore::new(&openai.config(), ToolRegistry::default())
+        .await
+        .unwrap();
+
+    let reply = state.complete("Hi there".to_string()).await.unwrap();
+    assert_eq!(reply, "Hello! How can I help?");
+
+    let requests = openai.requests().await;
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0]["model"], "gpt-test");
+    assert_eq!(
+        requests[0]["messages"],
+        json!([{ "role": "user", "content": "Hi there" }])
+    );
+}
+
+/// The requests of a tool round as `Conversation::run_tools` makes them: ask with the tools offered, run the
+/// requested tool, hand back the call and its result and get the final answer. A `Conversation` needs a room the bot
+/// joined on a homeserver, which the stub doesn't provide, so the rounds are driven here and checked against what
+/// the stub received.
+#[tokio::test]
+async fn tool_call_then_final_answer() {
+    let openai = MockOpenAI::replaying(&["tool_call", "final_answer"]).await;
+    let config = openai.config();
+    let state = ConversationStore::new(&config, ToolRegistry::default()).await.unwrap();
+    let room_id = <&RoomId>::try_from("!room:example.org").unwrap();
+    let tools = state.tools().schemas(&config, room_id, None).unwrap();
+
+    let mut messages = vec![OpenAIMessage::new(
+        Role::User,
+        MessageContent::Text("Roll 2d6 for me".to_string()),
+    )];
+    let request = ChatRequest {
+        tools: tools.clone(),
+        ..ChatRequest::new("gpt-test", messages.clone())
+    };
+    let response = state.post_completion(&request).await.unwrap();
+    let message = response.choices.into_iter().next().unwrap().message;
+
+    let actions = into_actions(&message, state.tools()).unwrap();
+    let [AssistantAction::ToolCall(id, Invocation::Builtin(Tool::RollDice { expression }))] = actions.as_slice() else {
+        panic!("Expected a single roll_dice call, got {actions:?}");
+    };
+    assert_eq!(expression, "2d6");
+    let output = dice::roll(expression).unwrap();
+
+    messages.push(message.clone());
+    messages.push(OpenAIMessage::tool_result(id, output.clone()));
+    let request = ChatRequest {
+        tools,
+        ..ChatRequest::new("gpt-test", messages)
+    };
+    let response = state.post_completion(&request).await.unwrap();
+    let actions = into_actions(&response.choices[0].message, state.tools()).unwrap();
+    let [AssistantAction::Reply(reply)] = actions.as_slice() else {
+        panic!("Expected a final answer, got {actions:?}");
+    };
+    assert_eq!(reply, "You rolled the dice, see the result above.");
+
+    let requests = openai.requests().await;
+    assert_eq!(requests.len(), 2);
+    assert!(
+        requests[0]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|tool| tool["function"]["name"] == "roll_dice")
+    );
+    assert_eq!(requests[1]["messages"][0], requests[0]["messages"][0]);
+    let call_message = &requests[1]["messages"][1];
+    assert_eq!(call_message["role"], "assistant");
+    assert_eq!(call_message["tool_calls"][0]["id"], "call_1");
+    assert_eq!(call_message["tool_calls"][0]["function"]["name"], "roll_dice");
+    let tool_message = &requests[1]["messages"][2];
+    assert_eq!(tool_message["role"], "tool");
+    assert_eq!(tool_message["tool_call_id"], "call_1");
+    assert_eq!(tool_message["content"], output);
+}
+
+/// Some proxies answer with an error envelope and a 200 status.
+#[tokio::test]
+async fn error_envelope_is_classified() {
+    let openai = MockOpenAI::replaying(&["rate_limited"]).await;
+    let state = ConversationStore::new(&openai.config(), ToolRegistry::default())
+        .await
+        .unwrap();
+
+    let error = state.complete("Hi there".to_string()).await.unwrap_err();
+    let error = error.downcast_ref::<OpenAIError>().expect("classified API error");
+    assert!(matches!(error, OpenAIError::RateLimited(_)), "got {error:?}");
+}
+
+/// Filtered choices come back without content or tool calls, which is shown as the content filter notice.
+#[tokio::test]
+async fn filtered_choice_is_classified() {
+    let openai = MockOpenAI::replaying(&["content_filter"]).await;
+    let state = ConversationStore::new(&openai.config(), ToolRegistry::default())
+        .await
+        .unwrap();
+
+    let request = ChatRequest::new(
+        "gpt-test",
+        vec![OpenAIMessage::new(
+            Role::User,
+            MessageContent::Text("Hi there".to_string()),
+        )],
+    );
+    let response = state.post_completion(&request).await.unwrap();
+    let error = into_actions(&response.choices[0].message, state.tools()).unwrap_err();
+    let error = error.downcast_ref::<OpenAIError>().expect("classified API error");
+    assert!(matches!(error, OpenAIError::FilteredResponse), "got {error:?}");
+    assert_eq!(error.message_key(), "error.content_filter");
+}
+
+#[tokio::test]
+async fn refusal_is_the_reply() {
+    let openai = MockOpenAI::replaying(&["refusal"]).await;
+    let state = ConversationStore::new(&openai.config(), ToolRegistry::default())
+        .await
+        .unwrap();
+
+    let request = ChatRequest::new(
+        "gpt-test",
+        vec![OpenAIMessage::new(
+            Role::User,
+            MessageContent::Text("Hi there".to_string()),
+        )],
+    );
+    let response = state.post_completion(&request).await.unwrap();
+    let actions = into_actions(&response.choices[0].message, state.tools()).unwrap();
+    let [AssistantAction::Reply(reply)] = actions.as_slice() else {
+        panic!("Expected the refusal as the reply, got {actions:?}");
+    };
+    assert_eq!(reply, "I can't help with that.");
+}
+
+#[test]
+fn endpoint_accepts_base_or_completions_url() {
+    for endpoint in [
+        "https://api.example.org/v1",
+        "https://api.example.org/v1/",
+        "https://api.example.org/v1/chat/completions",
+    ] {
+        let config: OpenAIConfig = serde_json::from_value(json!({
+            "endpoint": endpoint,
+            "api_key": "key",
+            "model": "gpt-test",
+        }))
+        .unwrap();
+
+        assert_eq!(
+            config.url(Api::ChatCompletions).unwrap().as_str(),
+            "https://api.example.org/v1/chat/completions"
+        );
+        assert_eq!(
+            config.url(Api::Images).unwrap().join("generations").unwrap().as_str(),
+            "https://api.example.org/v1/images/generations"
+        );
+    }
+}
@@ -0,0 +1,40 @@
+use matrix_openai_bot::pii::{Pii, PiiConfig};
+
+async fn scrub(text: &str) -> String {
+    let pii = Pii::new(&PiiConfig::default(), reqwest::Client::new());
+    pii.scrubber().scrub(text).await.unwrap()
+}
+
+#[tokio::test]
+async fn masks_phone_numbers() {
+    assert_eq!(scrub("Call me at +31 6 12345678.").await, "Call me at [PHONE_1].");
+    assert_eq!(scrub("Office: (555) 123-4567").await, "Office: [PHONE_1]");
+    assert_eq!(
+        scrub("Mobile 06-12345678 or 020 123 4567").await,
+        "Mobile [PHONE_1] or [PHONE_2]"
+    );
+}
+
+#[tokio::test]
+async fn leaves_dates_and_times_alone() {
+    for text in [
+        "Today is Friday 2026-10-16, it is 14:30 in Europe/Amsterdam.",
+        "The meeting moved to 2026-10-16 14:30.",
+        "Born on 16-10-1990, paid on 16.10.2026 and 2026/10/16.",
+        "Order 12345 shipped.",
+    ] {
+        assert_eq!(scrub(text).await, text);
+    }
+}
+
+#[tokio::test]
+async fn masks_addresses_consistently_and_restores_them() {
+    let pii = Pii::new(&PiiConfig::default(), reqwest::Client::new());
+    let mut scrubber = pii.scrubber();
+    let scrubbed = scrubber
+        .scrub("Mail alice@example.org or ping @alice:example.org, alice@example.org again")
+        .await
+        .unwrap();
+    assert_eq!(scrubbed, "Mail [EMAIL_1] or ping [MATRIX_ID_1], [EMAIL_1] again");
+    assert_eq!(scrubber.restore("Sent to [EMAIL_1]."), "Sent to alice@example.org.");
+}
@@ -0,0 +1,54 @@
+mod support;
+
+use std::time::Duration;
+
+use matrix_appservice::exports::matrix_sdk::ruma::{OwnedEventId, RoomId, UserId};
+use matrix_openai_bot::{
+    directives::InlineDirectives,
+    openai::{ConversationStore, Progress, ToolRegistry},
+    questions::{self, Question},
+    settings::{RetentionMode, RoomSettings},
+};
+
+use support::MockOpenAI;
+
+fn question() -> Question {
+    Question {
+        prompt_id: OwnedEventId::try_from("$prompt").unwrap(),
+        question_id: OwnedEventId::try_from("$question").unwrap(),
+        call_id: "call_1".to_string(),
+        messages: Vec::new(),
+        images: Vec::new(),
+        directives: InlineDirectives::default(),
+        progress: Progress::default(),
+    }
+}
+
+#[tokio::test]
+async fn keeps_questions_of_rooms_without_content_out_of_the_store() {
+    let openai = MockOpenAI::replaying(&[]).await;
+    let state = ConversationStore::new(&openai.config(), ToolRegistry::default())
+        .await
+        .unwrap();
+    let room_id = <&RoomId>::try_from("!room:example.org").unwrap();
+    let asker = <&UserId>::try_from("@alice:example.org").unwrap();
+    let settings = RoomSettings {
+        retention: Some(RetentionMode::NoContent),
+        ..RoomSettings::default()
+    };
+
+    questions::save(&state, &settings, room_id, asker, &question(), Duration::from_secs(300))
+        .await
+        .unwrap();
+    let keys = state.store().keys("room/").await.unwrap();
+    assert!(!keys.iter().any(|key| key.contains("/questions/")), "{keys:?}");
+
+    let taken = questions::take(&state, room_id, asker, |_| true).await.unwrap();
+    assert_eq!(taken.unwrap().call_id, "call_1");
+    assert!(
+        questions::take(&state, room_id, asker, |_| true)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
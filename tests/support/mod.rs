@@ -0,0 +1,76 @@
+//! Stub OpenAI server replaying recorded responses from `tests/fixtures`, so the model round-trip can be tested
+//! without an API key.
+
+use std::path::Path;
+
+use matrix_openai_bot::config::Config;
+use serde_json::{Value, json};
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{header, method, path},
+};
+
+pub const API_KEY: &str = "sk-test";
+const COMPLETIONS_PATH: &str = "/v1/chat/completions";
+
+/// A recorded response from `tests/fixtures/<name>.json`.
+pub fn fixture(name: &str) -> Value {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(format!("{name}.json"));
+    let text = std::fs::read_to_string(&path).unwrap_or_else(|error| panic!("Reading {path:?} failed: {error}"));
+    serde_json::from_str(&text).unwrap_or_else(|error| panic!("Parsing {path:?} failed: {error}"))
+}
+
+pub struct MockOpenAI {
+    server: MockServer,
+}
+
+impl MockOpenAI {
+    /// Start a stub answering chat completion requests with the given fixtures, one per request, in order.
+    pub async fn replaying(fixtures: &[&str]) -> Self {
+        let server = MockServer::start().await;
+        for name in fixtures {
+            Mock::given(method("POST"))
+                .and(path(COMPLETIONS_PATH))
+                .and(header("authorization", format!("Bearer {API_KEY}").as_str()))
+                .respond_with(ResponseTemplate::new(200).set_body_json(fixture(name)))
+                .up_to_n_times(1)
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        Self { server }
+    }
+
+    /// Minimal bot configuration talking to this stub. The homeserver is never contacted.
+    pub fn config(&self) -> Config {
+        serde_json::from_value(json!({
+            "homeserver": {
+                "server_name": "example.org",
+                "url": "http://localhost:8008",
+            },
+            "appservice": {
+                "username": "bot",
+                "as_token": "as-token",
+            },
+            "openai": {
+                "endpoint": format!("{}{COMPLETIONS_PATH}", self.server.uri()),
+                "api_key": API_KEY,
+                "model": "gpt-test",
+            },
+        }))
+        .expect("valid test configuration")
+    }
+
+    /// Bodies of the requests received so far.
+    pub async fn requests(&self) -> Vec<Value> {
+        self.server
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .iter()
+            .map(|request| request.body_json().expect("JSON request body"))
+            .collect()
+    }
+}
@@ -0,0 +1,33 @@
+use matrix_openai_bot::webhooks::render;
+use serde_json::json;
+
+#[test]
+fn renders_paths_into_the_template() {
+    let payload = json!({
+        "alert": { "name": "DiskFull", "labels": { "host": "db1" } },
+        "values": [90, 95],
+    });
+    assert_eq!(
+        render("{alert.name} on {alert.labels.host} at {values.1}%", &payload),
+        "DiskFull on db1 at 95%"
+    );
+}
+
+#[test]
+fn renders_objects_as_json_and_missing_paths_as_empty() {
+    let payload = json!({ "status": { "code": 3 }, "note": null });
+    assert_eq!(render("{status}", &payload), "{\n  \"code\": 3\n}");
+    assert_eq!(
+        render("[{note}] [{missing.path}] [{status.code.0}]", &payload),
+        "[] [] []"
+    );
+}
+
+#[test]
+fn renders_the_whole_payload() {
+    let payload = json!("text");
+    assert_eq!(
+        render("Got {payload}, {not a placeholder}", &payload),
+        "Got text, {not a placeholder}"
+    );
+}